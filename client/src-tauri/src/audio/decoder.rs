@@ -0,0 +1,345 @@
+//! Streaming decode of stem files into interleaved stereo `f32`.
+//!
+//! Packets are decoded on demand as samples are read, so only a packet's
+//! worth of audio per stem is held in memory regardless of file length.
+//! Opus streams go through [`super::opus`], with their encoder pre-skip and
+//! end padding trimmed here since the Ogg demuxer leaves both in.
+
+use super::opus::{self, SEEK_PREROLL};
+use crate::error::{CommandError, CommandResult};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{
+    CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS,
+};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo, Track};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::{Time, TimeBase};
+
+/// Map a symphonia error for the file at `path` to a command error
+pub fn decode_error(path: &Path, error: SymphoniaError) -> CommandError {
+    match error {
+        SymphoniaError::IoError(e) => CommandError::io(path, e),
+        other => CommandError::Decode {
+            path: path.to_string_lossy().to_string(),
+            message: other.to_string(),
+        },
+    }
+}
+
+/// Open `path` and find its first audio track
+fn open_track(path: &Path) -> CommandResult<(Box<dyn FormatReader>, Track)> {
+    let file = File::open(path).map_err(|e| CommandError::io(path, e))?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| decode_error(path, e))?;
+    let format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .cloned()
+        .ok_or_else(|| CommandError::Decode {
+            path: path.to_string_lossy().to_string(),
+            message: "no audio track".to_string(),
+        })?;
+    Ok((format, track))
+}
+
+pub struct StreamingDecoder {
+    path: PathBuf,
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    sample_rate: u32,
+    frames: Option<u64>,
+    /// Decoded frames before the first real one (Opus pre-skip)
+    lead: u64,
+    /// Frames decoded ahead of a seek target and thrown away
+    preroll: u64,
+    /// Frames past the end are encoder padding, not audio
+    trim_end: bool,
+    /// Next frame `read` hands out
+    position: u64,
+    /// Decoded stereo samples not yet handed out, starting at `pending_pos`
+    pending: Vec<f32>,
+    pending_pos: usize,
+    /// Frames still to discard after a seek landed before the target
+    skip_frames: u64,
+    sample_buf: Option<SampleBuffer<f32>>,
+    finished: bool,
+}
+
+impl StreamingDecoder {
+    pub fn open(path: &Path) -> CommandResult<Self> {
+        let (format, track) = open_track(path)?;
+        let params = &track.codec_params;
+        let sample_rate = params.sample_rate.ok_or_else(|| CommandError::Decode {
+            path: path.to_string_lossy().to_string(),
+            message: "unknown sample rate".to_string(),
+        })?;
+        let decoder = opus::codecs()
+            .make(params, &DecoderOptions::default())
+            .map_err(|e| decode_error(path, e))?;
+        let is_opus = params.codec == CODEC_TYPE_OPUS;
+        // The demuxer leaves Opus pre-skip in and counts the last page's
+        // padding into `n_frames`
+        let (lead, padding) = if is_opus {
+            (
+                opus::pre_skip(params).unwrap_or(0),
+                params.padding.unwrap_or(0) as u64,
+            )
+        } else {
+            (0, 0)
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            track_id: track.id,
+            time_base: params.time_base,
+            sample_rate,
+            frames: params
+                .n_frames
+                .map(|frames| frames.saturating_sub(lead + padding)),
+            lead,
+            preroll: if is_opus { SEEK_PREROLL } else { 0 },
+            trim_end: is_opus,
+            position: 0,
+            format,
+            decoder,
+            pending: Vec::new(),
+            pending_pos: 0,
+            skip_frames: lead,
+            sample_buf: None,
+            finished: false,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Length in frames, if the container declares it
+    pub fn frames(&self) -> Option<u64> {
+        self.frames
+    }
+
+    pub fn codec_params(&self) -> &CodecParameters {
+        self.decoder.codec_params()
+    }
+
+    /// Fill `out` with interleaved stereo frames, returning how many frames
+    /// were written. Fewer than requested means the stream has ended.
+    pub fn read(&mut self, out: &mut [f32]) -> usize {
+        let mut wanted = out.len() / 2;
+        if let Some(frames) = self.frames.filter(|_| self.trim_end) {
+            wanted = wanted.min(frames.saturating_sub(self.position) as usize);
+        }
+        let mut written = 0;
+        while written < wanted {
+            if self.pending_pos >= self.pending.len() && !self.decode_next() {
+                break;
+            }
+            let available = (self.pending.len() - self.pending_pos) / 2;
+            let count = available.min(wanted - written);
+            out[written * 2..(written + count) * 2]
+                .copy_from_slice(&self.pending[self.pending_pos..self.pending_pos + count * 2]);
+            self.pending_pos += count * 2;
+            written += count;
+        }
+        self.position += written as u64;
+        written
+    }
+
+    /// Position the stream so the next `read` starts exactly at `frame`
+    pub fn seek(&mut self, frame: u64) -> CommandResult<()> {
+        let target = frame + self.lead;
+        let ts = self.to_timestamp(target.saturating_sub(self.preroll));
+        self.pending.clear();
+        self.pending_pos = 0;
+        self.finished = false;
+        self.position = frame;
+
+        let seeked = self.format.seek(
+            SeekMode::Accurate,
+            SeekTo::TimeStamp {
+                ts,
+                track_id: self.track_id,
+            },
+        );
+        self.decoder.reset();
+        match seeked {
+            Ok(seeked) => {
+                self.skip_frames = target.saturating_sub(self.to_frame(seeked.actual_ts));
+                Ok(())
+            }
+            // Seeking past the end just ends the stream
+            Err(SymphoniaError::SeekError(_)) if self.frames.is_some_and(|n| frame >= n) => {
+                self.finished = true;
+                Ok(())
+            }
+            Err(e) => Err(decode_error(&self.path, e)),
+        }
+    }
+
+    fn to_timestamp(&self, frame: u64) -> u64 {
+        match self.time_base {
+            Some(time_base) => {
+                time_base.calc_timestamp(Time::from(frame as f64 / self.sample_rate as f64))
+            }
+            None => frame,
+        }
+    }
+
+    fn to_frame(&self, ts: u64) -> u64 {
+        match self.time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(ts);
+                ((time.seconds as f64 + time.frac) * self.sample_rate as f64).round() as u64
+            }
+            None => ts,
+        }
+    }
+
+    /// Decode the next packet of our track into `pending`; false at the end
+    /// of the stream or on an unrecoverable error
+    fn decode_next(&mut self) -> bool {
+        while !self.finished {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::ResetRequired) => {
+                    self.decoder.reset();
+                    continue;
+                }
+                Err(_) => {
+                    self.finished = true;
+                    break;
+                }
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt packet is skipped rather than ending playback
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(_) => {
+                    self.finished = true;
+                    break;
+                }
+            };
+
+            let spec = *decoded.spec();
+            let capacity = decoded.capacity() as u64;
+            let sample_buf = match &mut self.sample_buf {
+                Some(buf) if buf.capacity() as u64 >= capacity * spec.channels.count() as u64 => {
+                    buf
+                }
+                slot => slot.insert(SampleBuffer::new(capacity, spec)),
+            };
+            sample_buf.copy_interleaved_ref(decoded);
+            let samples = sample_buf.samples();
+            let channels = spec.channels.count().max(1);
+
+            self.pending.clear();
+            self.pending_pos = 0;
+            for frame in samples.chunks_exact(channels) {
+                let (left, right) = match frame {
+                    [mono] => (*mono, *mono),
+                    [left, right, ..] => (*left, *right),
+                    [] => (0.0, 0.0),
+                };
+                self.pending.push(left);
+                self.pending.push(right);
+            }
+
+            if self.skip_frames > 0 {
+                let skipped = (self.skip_frames as usize).min(self.pending.len() / 2);
+                self.pending_pos = skipped * 2;
+                self.skip_frames -= skipped as u64;
+            }
+            if self.pending_pos < self.pending.len() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Write a 16-bit stereo WAV whose left channel counts frames (modulo
+    /// 1000) and whose right channel is `right`
+    pub fn write_test_wav(path: &Path, sample_rate: u32, frames: usize, right: i16) {
+        let data_len = (frames * 4) as u32;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * 4).to_le_bytes());
+        bytes.extend_from_slice(&4u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for frame in 0..frames {
+            bytes.extend_from_slice(&((frame % 1000) as i16).to_le_bytes());
+            bytes.extend_from_slice(&right.to_le_bytes());
+        }
+        File::create(path).unwrap().write_all(&bytes).unwrap();
+    }
+
+    /// Sample value of frame `n`'s left channel in a test WAV
+    pub fn counter_sample(n: usize) -> f32 {
+        (n % 1000) as f32 / 32768.0
+    }
+
+    #[test]
+    fn test_streams_and_seeks_sample_accurately() {
+        let path =
+            std::env::temp_dir().join(format!("music-tutor-decode-{}.wav", std::process::id()));
+        write_test_wav(&path, 8000, 20_000, 0);
+
+        let mut decoder = StreamingDecoder::open(&path).unwrap();
+        assert_eq!(decoder.sample_rate(), 8000);
+        assert_eq!(decoder.frames(), Some(20_000));
+
+        let mut out = vec![0.0; 2 * 5000];
+        assert_eq!(decoder.read(&mut out), 5000);
+        assert_eq!(out[2 * 4321], counter_sample(4321));
+
+        decoder.seek(12_345).unwrap();
+        assert_eq!(decoder.read(&mut out[..2]), 1);
+        assert_eq!(out[0], counter_sample(12_345));
+
+        // Reads stop at the end of the stream
+        decoder.seek(19_000).unwrap();
+        assert_eq!(decoder.read(&mut out), 1000);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,511 @@
+//! Writing interleaved stereo `f32` to WAV or FLAC.
+//!
+//! Both writers stream to disk and patch their headers once the length is
+//! known. The FLAC encoder is deliberately simple: fixed-size blocks,
+//! independent channels and the best of FLAC's fixed polynomial predictors
+//! per subframe with partitioned Rice residuals. It won't match the
+//! reference encoder's ratios, but that matters little for a practice mix.
+
+use crate::error::{CommandError, CommandResult};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const CHANNELS: usize = 2;
+
+/// Samples per channel in each FLAC frame
+const FLAC_BLOCK: usize = 4096;
+/// Highest Rice partition order tried per subframe
+const MAX_PARTITION_ORDER: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AudioFormat {
+    Wav,
+    Flac,
+}
+
+/// Streams stereo audio to a file in one of the export formats
+pub struct AudioWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    format: AudioFormat,
+    sample_rate: u32,
+    bits: u32,
+    frames: u64,
+    /// FLAC only: samples waiting for a full block, per channel
+    pending: [Vec<i64>; CHANNELS],
+    flac_frames: u64,
+    frame_sizes: (u32, u32),
+}
+
+impl AudioWriter {
+    /// Create `path`; `bits` is 16 or 24
+    pub fn create(
+        path: &Path,
+        format: AudioFormat,
+        sample_rate: u32,
+        bits: u32,
+    ) -> CommandResult<Self> {
+        if bits != 16 && bits != 24 {
+            return Err(CommandError::invalid(format!(
+                "Bit depth must be 16 or 24, not {}",
+                bits
+            )));
+        }
+        let file = File::create(path).map_err(|e| CommandError::io(path, e))?;
+        let mut writer = Self {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            format,
+            sample_rate,
+            bits,
+            frames: 0,
+            pending: [Vec::new(), Vec::new()],
+            flac_frames: 0,
+            frame_sizes: (u32::MAX, 0),
+        };
+        let header = writer.header();
+        writer.put(&header)?;
+        Ok(writer)
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> CommandResult<()> {
+        self.file
+            .write_all(bytes)
+            .map_err(|e| CommandError::io(&self.path, e))
+    }
+
+    /// Sample value of +1.0; the same scale decoders divide by
+    fn full_scale(&self) -> f32 {
+        (1i64 << (self.bits - 1)) as f32
+    }
+
+    /// Append interleaved stereo frames; samples beyond ±1.0 are clipped
+    pub fn write(&mut self, samples: &[f32]) -> CommandResult<()> {
+        let scale = self.full_scale();
+        let quantize = |sample: f32| {
+            let value = (sample.clamp(-1.0, 1.0) * scale).round() as i64;
+            value.min(scale as i64 - 1)
+        };
+        self.frames += (samples.len() / CHANNELS) as u64;
+
+        match self.format {
+            AudioFormat::Wav => {
+                let width = (self.bits / 8) as usize;
+                let mut bytes = Vec::with_capacity(samples.len() * width);
+                for &sample in samples {
+                    bytes.extend_from_slice(&quantize(sample).to_le_bytes()[..width]);
+                }
+                self.put(&bytes)
+            }
+            AudioFormat::Flac => {
+                for frame in samples.chunks_exact(CHANNELS) {
+                    for (channel, &sample) in frame.iter().enumerate() {
+                        self.pending[channel].push(quantize(sample));
+                    }
+                    if self.pending[0].len() == FLAC_BLOCK {
+                        self.flush_flac_block()?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Flush remaining audio and fill in the header
+    pub fn finish(mut self) -> CommandResult<()> {
+        if self.format == AudioFormat::Flac && !self.pending[0].is_empty() {
+            self.flush_flac_block()?;
+        }
+        let header = self.header();
+        let path = self.path.clone();
+        let io = |e| CommandError::io(&path, e);
+        self.file.seek(SeekFrom::Start(0)).map_err(io)?;
+        self.file.write_all(&header).map_err(io)?;
+        self.file.flush().map_err(io)
+    }
+
+    fn header(&self) -> Vec<u8> {
+        match self.format {
+            AudioFormat::Wav => self.wav_header(),
+            AudioFormat::Flac => self.flac_header(),
+        }
+    }
+
+    fn wav_header(&self) -> Vec<u8> {
+        let block_align = CHANNELS as u32 * self.bits / 8;
+        let data_len = (self.frames * block_align as u64).min(u32::MAX as u64 - 36) as u32;
+        let mut bytes = Vec::with_capacity(44);
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        bytes.extend_from_slice(&(CHANNELS as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(self.sample_rate * block_align).to_le_bytes());
+        bytes.extend_from_slice(&(block_align as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.bits as u16).to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        bytes
+    }
+
+    /// `fLaC` marker plus a STREAMINFO block (MD5 left unset)
+    fn flac_header(&self) -> Vec<u8> {
+        let (min_frame, max_frame) = match self.frame_sizes {
+            (u32::MAX, _) => (0, 0),
+            sizes => sizes,
+        };
+        let mut bits = BitWriter::default();
+        bits.write(FLAC_BLOCK as u64, 16);
+        bits.write(FLAC_BLOCK as u64, 16);
+        bits.write(min_frame as u64, 24);
+        bits.write(max_frame as u64, 24);
+        bits.write(self.sample_rate as u64, 20);
+        bits.write(CHANNELS as u64 - 1, 3);
+        bits.write(self.bits as u64 - 1, 5);
+        bits.write(self.frames.min((1 << 36) - 1), 36);
+        let mut bytes = b"fLaC".to_vec();
+        // Last metadata block, type 0 (STREAMINFO), 34 bytes
+        bytes.extend_from_slice(&[0x80, 0, 0, 34]);
+        bytes.extend_from_slice(&bits.into_bytes());
+        bytes.extend_from_slice(&[0; 16]);
+        bytes
+    }
+
+    fn flush_flac_block(&mut self) -> CommandResult<()> {
+        let block = std::mem::take(&mut self.pending);
+        let frame = encode_flac_frame(&block, self.flac_frames, self.bits);
+        self.flac_frames += 1;
+        let size = frame.len() as u32;
+        self.frame_sizes = (self.frame_sizes.0.min(size), self.frame_sizes.1.max(size));
+        self.put(&frame)
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    /// Append the low `bits` bits of `value`, most significant first
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits > 32 {
+            self.write(value >> 32, bits - 32);
+            self.write(value & 0xFFFF_FFFF, 32);
+            return;
+        }
+        let mask = (1u64 << bits) - 1;
+        self.acc = (self.acc << bits) | (value & mask);
+        self.len += bits;
+        while self.len >= 8 {
+            self.len -= 8;
+            self.bytes.push((self.acc >> self.len) as u8);
+        }
+        self.acc &= (1 << self.len) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// `count` zeros followed by a one
+    fn write_unary(&mut self, mut count: u64) {
+        while count >= 32 {
+            self.write(0, 32);
+            count -= 32;
+        }
+        self.write(1, count as u32 + 1);
+    }
+
+    /// Zero-pad to a byte boundary and return the bytes
+    fn into_bytes(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            let pad = 8 - self.len;
+            self.write(0, pad);
+        }
+        self.bytes
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// FLAC's UTF-8-style variable length coding of the frame number
+fn write_utf8_number(bits: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        bits.write(value, 8);
+        return;
+    }
+    let mut continuation = 1;
+    while value >> (6 * continuation) >= 1 << (6 - continuation) {
+        continuation += 1;
+    }
+    let lead_ones = (0xFF00u64 >> (continuation + 1)) & 0xFF;
+    bits.write(lead_ones | (value >> (6 * continuation)), 8);
+    for i in (0..continuation).rev() {
+        bits.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+    }
+}
+
+fn encode_flac_frame(block: &[Vec<i64>; CHANNELS], number: u64, sample_bits: u32) -> Vec<u8> {
+    let len = block[0].len();
+    let mut bits = BitWriter::default();
+    bits.write(0b11_1111_1111_1110, 14); // sync
+    bits.write(0, 1);
+    bits.write(0, 1); // fixed block size
+    bits.write(0b0111, 4); // block size as 16 bits at the end of the header
+    bits.write(0b0000, 4); // sample rate from STREAMINFO
+    bits.write(0b0001, 4); // independent left/right
+    bits.write(if sample_bits == 16 { 0b100 } else { 0b110 }, 3);
+    bits.write(0, 1);
+    write_utf8_number(&mut bits, number);
+    bits.write(len as u64 - 1, 16);
+    let mut header = bits.into_bytes();
+    header.push(crc8(&header));
+
+    let mut bits = BitWriter {
+        bytes: header,
+        ..Default::default()
+    };
+    for samples in block {
+        write_subframe(&mut bits, samples, sample_bits);
+    }
+    let mut frame = bits.into_bytes();
+    let crc = crc16(&frame);
+    frame.extend_from_slice(&crc.to_be_bytes());
+    frame
+}
+
+/// Residuals of FLAC's fixed predictor of `order` (0–4)
+fn fixed_residuals(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|n| {
+            let x = |k: usize| samples[n - k];
+            match order {
+                0 => x(0),
+                1 => x(0) - x(1),
+                2 => x(0) - 2 * x(1) + x(2),
+                3 => x(0) - 3 * x(1) + 3 * x(2) - x(3),
+                _ => x(0) - 4 * x(1) + 6 * x(2) - 4 * x(3) + x(4),
+            }
+        })
+        .collect()
+}
+
+fn zigzag(residual: i64) -> u64 {
+    ((residual << 1) ^ (residual >> 63)) as u64
+}
+
+/// Cheapest Rice parameter for `values` and the bits it takes
+fn rice_parameter(values: &[u64], max_parameter: u32) -> (u32, u64) {
+    let cost = |k: u32| {
+        values
+            .iter()
+            .map(|&value| (value >> k) + 1 + k as u64)
+            .sum::<u64>()
+    };
+    let mean = values.iter().sum::<u64>() / values.len().max(1) as u64;
+    let guess = (64 - mean.leading_zeros()).min(max_parameter);
+    (guess.saturating_sub(1)..=(guess + 1).min(max_parameter))
+        .map(|k| (k, cost(k)))
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap_or((0, cost(0)))
+}
+
+struct ResidualPlan {
+    partition_order: u32,
+    /// Rice parameter per partition
+    parameters: Vec<u32>,
+    /// Whether 5-bit parameters (RICE2) are needed
+    wide: bool,
+    bits: u64,
+}
+
+/// Choose the partition order and Rice parameters that code `residuals`
+/// (for a block of `block_len` with a predictor of `order`) in the fewest
+/// bits
+fn plan_residuals(residuals: &[u64], block_len: usize, order: usize) -> ResidualPlan {
+    let mut best: Option<ResidualPlan> = None;
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1usize << partition_order;
+        if !block_len.is_multiple_of(partitions) || block_len / partitions <= order {
+            break;
+        }
+        let size = block_len / partitions;
+        let mut parameters = Vec::with_capacity(partitions);
+        let mut bits = 0;
+        let mut start = 0;
+        for partition in 0..partitions {
+            let count = if partition == 0 { size - order } else { size };
+            let (k, cost) = rice_parameter(&residuals[start..start + count], 30);
+            parameters.push(k);
+            bits += cost;
+            start += count;
+        }
+        let wide = parameters.iter().any(|&k| k > 14);
+        bits += partitions as u64 * if wide { 5 } else { 4 };
+        if best.as_ref().is_none_or(|best| bits < best.bits) {
+            best = Some(ResidualPlan {
+                partition_order,
+                parameters,
+                wide,
+                bits,
+            });
+        }
+    }
+    best.expect("partition order 0 always fits")
+}
+
+fn write_subframe(bits: &mut BitWriter, samples: &[i64], sample_bits: u32) {
+    if samples.iter().all(|&sample| sample == samples[0]) {
+        bits.write(0b0000_0000, 8); // CONSTANT
+        bits.write_signed(samples[0], sample_bits);
+        return;
+    }
+
+    let verbatim_bits = samples.len() as u64 * sample_bits as u64;
+    let best = (0..=4.min(samples.len() - 1))
+        .map(|order| {
+            let residuals: Vec<u64> = fixed_residuals(samples, order)
+                .into_iter()
+                .map(zigzag)
+                .collect();
+            let plan = plan_residuals(&residuals, samples.len(), order);
+            let total = order as u64 * sample_bits as u64 + 6 + plan.bits;
+            (order, residuals, plan, total)
+        })
+        .min_by_key(|(_, _, _, total)| *total);
+
+    match best {
+        Some((order, residuals, plan, total)) if total < verbatim_bits => {
+            bits.write(0b0001_0000 | (order as u64) << 1, 8); // FIXED
+            for &sample in &samples[..order] {
+                bits.write_signed(sample, sample_bits);
+            }
+            bits.write(plan.wide as u64, 2);
+            bits.write(plan.partition_order as u64, 4);
+            let partitions = 1usize << plan.partition_order;
+            let size = samples.len() / partitions;
+            let mut start = 0;
+            for (partition, &k) in plan.parameters.iter().enumerate() {
+                bits.write(k as u64, if plan.wide { 5 } else { 4 });
+                let count = if partition == 0 { size - order } else { size };
+                for &value in &residuals[start..start + count] {
+                    bits.write_unary(value >> k);
+                    bits.write(value, k);
+                }
+                start += count;
+            }
+        }
+        _ => {
+            bits.write(0b0000_0010, 8); // VERBATIM
+            for &sample in samples {
+                bits.write_signed(sample, sample_bits);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::decoder::StreamingDecoder;
+    use super::*;
+
+    /// A chord with some noise, to exercise every subframe type
+    fn signal(frames: usize) -> Vec<f32> {
+        let mut seed = 1u32;
+        (0..frames)
+            .flat_map(|n| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let noise = (seed >> 16) as f32 / 65536.0 - 0.5;
+                let t = n as f32 / 8000.0;
+                let left = (std::f32::consts::TAU * 220.0 * t).sin() * 0.4 + noise * 0.05;
+                let right = if n < 5000 {
+                    0.0
+                } else {
+                    (std::f32::consts::TAU * 330.0 * t).sin() * 0.9
+                };
+                [left, right]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trips_through_decoder() {
+        let input = signal(10_000);
+        for (format, bits, name) in [
+            (AudioFormat::Flac, 16, "a.flac"),
+            (AudioFormat::Flac, 24, "b.flac"),
+            (AudioFormat::Wav, 24, "c.wav"),
+        ] {
+            let path = std::env::temp_dir().join(format!(
+                "music-tutor-encode-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let mut writer = AudioWriter::create(&path, format, 8000, bits).unwrap();
+            // Uneven writes straddle block boundaries
+            for chunk in input.chunks(2 * 1500) {
+                writer.write(chunk).unwrap();
+            }
+            writer.finish().unwrap();
+
+            let mut decoder = StreamingDecoder::open(&path).unwrap();
+            assert_eq!(decoder.sample_rate(), 8000);
+            assert_eq!(decoder.frames(), Some(10_000));
+            let mut output = vec![0.0; input.len() + 100];
+            assert_eq!(decoder.read(&mut output), 10_000);
+
+            let step = 1.0 / (1 << (bits - 1)) as f32;
+            for (a, b) in input.iter().zip(&output) {
+                assert!((a - b).abs() <= step, "{} vs {} in {}", a, b, name);
+            }
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_frame_numbers_use_utf8_coding() {
+        for (value, expected) in [
+            (0x45u64, vec![0x45]),
+            (0x80, vec![0xC2, 0x80]),
+            (0x1234, vec![0xE1, 0x88, 0xB4]),
+        ] {
+            let mut bits = BitWriter::default();
+            write_utf8_number(&mut bits, value);
+            assert_eq!(bits.into_bytes(), expected);
+        }
+    }
+}
@@ -0,0 +1,423 @@
+//! Rendering a practice mix to an audio file.
+//!
+//! The export mixes like playback, offline in a [`Mixer`]: the selected
+//! stems at one speed with their gain/mute/solo resolved to a fixed gain
+//! each, optionally only a loop
+//! range and preceded by a count-in click. With peak normalization the mix
+//! is rendered twice, once to measure the peak and once to write it.
+
+use super::decoder::StreamingDecoder;
+use super::encode::{AudioFormat, AudioWriter};
+use super::mixer::{self, Mixer};
+use super::probe::{parse_speed, to_db};
+use crate::error::{CommandError, CommandResult};
+use crate::{read_analysis, sandbox, SongAnalysis};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Frames rendered per pass
+const BLOCK_FRAMES: usize = 4096;
+
+/// Count-in click: a short decaying sine, higher on the downbeat
+const CLICK_SECONDS: f64 = 0.03;
+const CLICK_HZ: f64 = 1000.0;
+const ACCENT_HZ: f64 = 1500.0;
+const CLICK_LEVEL: f64 = 0.5;
+
+fn unity() -> f32 {
+    1.0
+}
+
+/// Mix settings for one stem; stems not listed are left out
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StemMix {
+    pub name: String,
+    #[serde(default = "unity")]
+    pub gain: f32,
+    #[serde(default)]
+    pub muted: bool,
+    #[serde(default)]
+    pub solo: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MixExport {
+    pub output_path: String,
+    /// Taken from the output extension when missing
+    pub format: Option<AudioFormat>,
+    /// Which rendering of the stems to mix (`"1.0x"` by default)
+    pub speed: Option<String>,
+    pub stems: Vec<StemMix>,
+    /// Seconds into the files at `speed`; both or neither
+    pub loop_start: Option<f64>,
+    pub loop_end: Option<f64>,
+    /// Beats of click before the music, at the song's tempo
+    #[serde(default)]
+    pub count_in: u32,
+    /// Scale the mix so its peak lands here (dBFS, e.g. -1.0)
+    pub normalize_db: Option<f64>,
+    /// 16 (default) or 24
+    pub bit_depth: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MixExportResult {
+    pub path: String,
+    /// Seconds written, count-in included
+    pub duration: f64,
+    /// Peak of the written file in dBFS
+    pub peak_db: f64,
+    /// Gain applied by normalization in dB (0 without it)
+    pub gain_db: f64,
+    /// Samples that exceeded full scale and were clipped
+    pub clipped_samples: u64,
+}
+
+/// Everything needed to render the mix, checked up front
+struct Plan {
+    stems: Vec<(StemMix, PathBuf)>,
+    range: Option<(f64, f64)>,
+    count_in: u32,
+    /// Beats per second at the exported speed, when there is a count-in
+    beat_rate: f64,
+    beats_per_measure: u32,
+}
+
+impl Plan {
+    fn new(analysis: &SongAnalysis, song_dir: &Path, export: &MixExport) -> CommandResult<Self> {
+        let speed = export.speed.as_deref().unwrap_or("1.0x");
+        if export.stems.is_empty() {
+            return Err(CommandError::invalid("No stems selected for export"));
+        }
+        let mut stems = Vec::new();
+        for mix in &export.stems {
+            mixer::check_gain(mix.gain)?;
+            let relative = analysis
+                .stems
+                .get(&mix.name)
+                .and_then(|info| info.paths.get(speed))
+                .ok_or_else(|| {
+                    CommandError::invalid(format!("Stem {} has no {} rendering", mix.name, speed))
+                })?;
+            stems.push((
+                mix.clone(),
+                sandbox::stem_path(song_dir, analysis, relative)?,
+            ));
+        }
+
+        let range = match (export.loop_start, export.loop_end) {
+            (Some(start), Some(end)) => {
+                if !(start.is_finite() && start >= 0.0 && end > start) {
+                    return Err(CommandError::invalid(format!(
+                        "Invalid export range {}–{}",
+                        start, end
+                    )));
+                }
+                Some((start, end))
+            }
+            (None, None) => None,
+            _ => {
+                return Err(CommandError::invalid(
+                    "Export range needs both a start and an end",
+                ))
+            }
+        };
+
+        let mut beat_rate = 0.0;
+        if export.count_in > 0 {
+            let tempo = analysis
+                .tempo_bpm
+                .filter(|tempo| *tempo > 0.0)
+                .ok_or_else(|| {
+                    CommandError::invalid("A count-in needs the song's tempo, which is unknown")
+                })?;
+            let factor = parse_speed(speed).ok_or_else(|| {
+                CommandError::invalid(format!("Cannot tell the tempo of the {} rendering", speed))
+            })?;
+            beat_rate = tempo * factor / 60.0;
+        }
+
+        Ok(Self {
+            stems,
+            range,
+            count_in: export.count_in,
+            beat_rate,
+            beats_per_measure: analysis
+                .time_signature
+                .map(|(numerator, _)| numerator.max(1) as u32)
+                .unwrap_or(4),
+        })
+    }
+
+    fn open_mixer(&self) -> CommandResult<Mixer> {
+        let decoders = self
+            .stems
+            .iter()
+            .map(|(mix, path)| Ok((mix.name.clone(), StreamingDecoder::open(path)?)))
+            .collect::<CommandResult<Vec<_>>>()?;
+        let any_solo = self.stems.iter().any(|(mix, _)| mix.solo);
+        let gains = self
+            .stems
+            .iter()
+            .map(|(mix, _)| mixer::target_gain(mix.gain, mix.muted, mix.solo, any_solo))
+            .collect();
+        Mixer::new(decoders, gains)
+    }
+
+    /// Click sample at `frame` of the count-in. Beats are accented as if
+    /// the music starts on a downbeat.
+    fn click(&self, frame: u64, sample_rate: u32) -> f32 {
+        let beat_frames = sample_rate as f64 / self.beat_rate;
+        let beat = (frame as f64 / beat_frames).floor();
+        let t = (frame as f64 - beat * beat_frames) / sample_rate as f64;
+        if t >= CLICK_SECONDS {
+            return 0.0;
+        }
+        let beats_left = (self.count_in as u64).saturating_sub(beat as u64);
+        let hz = if beats_left.is_multiple_of(self.beats_per_measure as u64) {
+            ACCENT_HZ
+        } else {
+            CLICK_HZ
+        };
+        let envelope = (-t / (CLICK_SECONDS / 5.0)).exp();
+        (CLICK_LEVEL * envelope * (std::f64::consts::TAU * hz * t).sin()) as f32
+    }
+
+    /// Render the count-in and then `mixer`, handing each block of
+    /// interleaved stereo to `sink`. Returns the frames rendered.
+    fn render(
+        &self,
+        mut mixer: Mixer,
+        mut sink: impl FnMut(&[f32]) -> CommandResult<()>,
+    ) -> CommandResult<u64> {
+        let sample_rate = mixer.sample_rate();
+        let to_frame = |seconds: f64| (seconds * sample_rate as f64).round() as u64;
+        let (start, end) = match self.range {
+            Some((start, end)) => (to_frame(start), to_frame(end).min(mixer.length())),
+            None => (0, mixer.length()),
+        };
+        if start >= end {
+            return Err(CommandError::invalid(
+                "Export range is past the end of the song",
+            ));
+        }
+
+        let mut buf = vec![0.0f32; BLOCK_FRAMES * 2];
+        let mut total = 0u64;
+        let count_in_frames = if self.count_in > 0 {
+            (self.count_in as f64 * sample_rate as f64 / self.beat_rate).round() as u64
+        } else {
+            0
+        };
+        while total < count_in_frames {
+            let frames = (count_in_frames - total).min(BLOCK_FRAMES as u64) as usize;
+            for (i, frame) in buf[..frames * 2].chunks_exact_mut(2).enumerate() {
+                let sample = self.click(total + i as u64, sample_rate);
+                frame[0] = sample;
+                frame[1] = sample;
+            }
+            sink(&buf[..frames * 2])?;
+            total += frames as u64;
+        }
+
+        mixer.seek(start)?;
+        let mut position = start;
+        while position < end {
+            let frames = (end - position).min(BLOCK_FRAMES as u64) as usize;
+            let rendered = mixer.render(&mut buf[..frames * 2])?;
+            if rendered == 0 {
+                break;
+            }
+            sink(&buf[..rendered * 2])?;
+            position += rendered as u64;
+            total += rendered as u64;
+        }
+        Ok(total)
+    }
+}
+
+fn peak_of(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()))
+}
+
+/// Write the mix described by `export` for the song in `song_dir`
+pub fn export(song_dir: &Path, export: &MixExport) -> CommandResult<MixExportResult> {
+    let analysis = read_analysis(&song_dir.join("analysis.json"))?;
+    let plan = Plan::new(&analysis, song_dir, export)?;
+    let path = PathBuf::from(&export.output_path);
+    let format = match export.format {
+        Some(format) => format,
+        None => match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("flac") => AudioFormat::Flac,
+            Some(ext) if ext.eq_ignore_ascii_case("wav") => AudioFormat::Wav,
+            _ => {
+                return Err(CommandError::invalid(
+                    "Export needs a format or a .wav/.flac file name",
+                ))
+            }
+        },
+    };
+
+    let mut gain = 1.0f32;
+    if let Some(target_db) = export.normalize_db {
+        if !(target_db.is_finite() && target_db <= 0.0) {
+            return Err(CommandError::invalid(format!(
+                "Normalization target must be at most 0 dBFS, not {}",
+                target_db
+            )));
+        }
+        let mut peak = 0.0f32;
+        plan.render(plan.open_mixer()?, |block| {
+            peak = peak.max(peak_of(block));
+            Ok(())
+        })?;
+        if peak > 0.0 {
+            gain = 10f32.powf(target_db as f32 / 20.0) / peak;
+        }
+    }
+
+    let mixer = plan.open_mixer()?;
+    let sample_rate = mixer.sample_rate();
+    let mut writer =
+        AudioWriter::create(&path, format, sample_rate, export.bit_depth.unwrap_or(16))?;
+    let mut peak = 0.0f32;
+    let mut clipped = 0u64;
+    let mut scaled = Vec::new();
+    let rendered = plan.render(mixer, |block| {
+        scaled.clear();
+        scaled.extend(block.iter().map(|sample| sample * gain));
+        clipped += scaled.iter().filter(|s| s.abs() > 1.0).count() as u64;
+        peak = peak.max(peak_of(&scaled).min(1.0));
+        writer.write(&scaled)
+    });
+
+    let result = rendered.and_then(|frames| {
+        writer.finish()?;
+        Ok(MixExportResult {
+            path: export.output_path.clone(),
+            duration: frames as f64 / sample_rate as f64,
+            peak_db: to_db(peak as f64),
+            gain_db: to_db(gain as f64),
+            clipped_samples: clipped,
+        })
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&path);
+    }
+    result
+}
+
+/// Render a mix of a song's stems to a WAV or FLAC file, e.g. a backing
+/// track without the bass
+#[tauri::command]
+pub fn export_mix(song_dir: &str, export: MixExport) -> CommandResult<MixExportResult> {
+    self::export(Path::new(song_dir), &export)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::decoder::tests::{counter_sample, write_test_wav};
+    use super::*;
+    use crate::{test_analysis, write_analysis};
+
+    #[test]
+    fn test_exports_minus_one_mix() {
+        let dir = std::env::temp_dir().join(format!("music-tutor-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_test_wav(&dir.join("a.wav"), 8000, 8000, 1000);
+        write_test_wav(&dir.join("b.wav"), 8000, 6000, 2000);
+        let stem = |name: &str| {
+            serde_json::json!({
+                "name": name,
+                "paths": {"1.0x": format!("{}.wav", name)},
+                "hasNotes": false,
+                "peakDb": 0.0
+            })
+        };
+        let mut analysis = test_analysis();
+        analysis.original_duration = 1.0;
+        analysis.sample_rate = 8000;
+        analysis.tempo_bpm = Some(120.0);
+        analysis.stems =
+            serde_json::from_value(serde_json::json!({"a": stem("a"), "b": stem("b")})).unwrap();
+        write_analysis(&dir.join("analysis.json"), &analysis).unwrap();
+
+        let mixes: Vec<StemMix> = serde_json::from_value(serde_json::json!([
+            {"name": "a"},
+            {"name": "b", "muted": true}
+        ]))
+        .unwrap();
+        let wav = dir.join("minus-b.wav");
+        let result = export(
+            &dir,
+            &MixExport {
+                output_path: wav.to_string_lossy().to_string(),
+                format: None,
+                speed: None,
+                stems: mixes.clone(),
+                loop_start: Some(0.25),
+                loop_end: Some(0.5),
+                count_in: 0,
+                normalize_db: None,
+                bit_depth: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(result.duration, 0.25);
+        assert_eq!(result.clipped_samples, 0);
+
+        // Only "a" from frame 2000 on, with no fade-in from the muted stem
+        let mut decoder = StreamingDecoder::open(&wav).unwrap();
+        let mut out = vec![0.0; 2 * 3000];
+        assert_eq!(decoder.read(&mut out), 2000);
+        assert_eq!(out[0], counter_sample(2000));
+        assert_eq!(out[1], 1000.0 / 32768.0);
+        assert_eq!(out[2 * 1999], counter_sample(3999));
+
+        // Two beats of click at 120 bpm ahead of the whole song, normalized
+        let flac = dir.join("count-in.flac");
+        let result = export(
+            &dir,
+            &MixExport {
+                output_path: flac.to_string_lossy().to_string(),
+                format: None,
+                speed: Some("1.0x".to_string()),
+                stems: mixes,
+                loop_start: None,
+                loop_end: None,
+                count_in: 2,
+                normalize_db: Some(-6.0),
+                bit_depth: Some(24),
+            },
+        )
+        .unwrap();
+        assert_eq!(result.duration, 2.0);
+        assert!((result.peak_db - -6.0).abs() < 0.01);
+        let mut decoder = StreamingDecoder::open(&flac).unwrap();
+        let mut out = vec![0.0; 2 * 20_000];
+        assert_eq!(decoder.read(&mut out), 16_000);
+        assert!(out[..2 * 240].iter().any(|s| s.abs() > 0.1));
+        assert!(out[2 * 1000..2 * 4000].iter().all(|s| *s == 0.0));
+
+        assert!(export(
+            &dir,
+            &MixExport {
+                output_path: dir.join("mix.ogg").to_string_lossy().to_string(),
+                format: None,
+                speed: None,
+                stems: vec![],
+                loop_start: None,
+                loop_end: None,
+                count_in: 0,
+                normalize_db: None,
+                bit_depth: None,
+            },
+        )
+        .is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
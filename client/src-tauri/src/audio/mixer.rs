@@ -0,0 +1,349 @@
+//! Sums the stems of one song into a stereo stream.
+//!
+//! A [`Transport`] reads every stem's decoder by the same number of frames
+//! on each pass, so stems stay sample-aligned through seeks and loops, and
+//! each stem is ramped from its previous gain to avoid clicks. Live
+//! playback splits the two across threads (see [`super::player`]) and owns
+//! the gain/mute/solo controls; the [`Mixer`] here is the offline
+//! counterpart for export, mixing at fixed gains straight to the end.
+
+use super::decoder::StreamingDecoder;
+use crate::error::{CommandError, CommandResult};
+
+/// Frames read per pass; bounds the per-stem buffers and the loop/seek
+/// granularity of a single render
+pub const CHUNK_FRAMES: usize = 1024;
+
+pub fn check_gain(gain: f32) -> CommandResult<()> {
+    if gain.is_finite() && gain >= 0.0 {
+        Ok(())
+    } else {
+        Err(CommandError::invalid(format!("Invalid gain: {}", gain)))
+    }
+}
+
+pub fn check_loop(range: Option<(u64, u64)>) -> CommandResult<()> {
+    match range {
+        Some((start, end)) if start >= end => {
+            Err(CommandError::invalid("Loop start must be before loop end"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Gain a stem should play at, given whether any stem is soloed
+pub fn target_gain(gain: f32, muted: bool, solo: bool, any_solo: bool) -> f32 {
+    let audible = !muted && (!any_solo || solo);
+    if audible {
+        gain
+    } else {
+        0.0
+    }
+}
+
+/// Add interleaved stereo `samples` into `out`, starting at gain `from`
+/// and moving by `step` per frame
+pub fn add_ramped(out: &mut [f32], samples: &[f32], from: f32, step: f32) {
+    for (i, (out, sample)) in out
+        .chunks_exact_mut(2)
+        .zip(samples.chunks_exact(2))
+        .enumerate()
+    {
+        let gain = from + step * i as f32;
+        out[0] += sample[0] * gain;
+        out[1] += sample[1] * gain;
+    }
+}
+
+/// The decoders of one song, read in lockstep from a shared position
+pub struct Transport {
+    decoders: Vec<StreamingDecoder>,
+    sample_rate: u32,
+    /// Length of the longest stem; shrinks if a stream ends early
+    length: u64,
+    position: u64,
+    loop_range: Option<(u64, u64)>,
+}
+
+impl Transport {
+    /// Read `stems`, which must all share a sample rate. Returns the stem
+    /// names in the order passes are read
+    pub fn new(stems: Vec<(String, StreamingDecoder)>) -> CommandResult<(Vec<String>, Self)> {
+        let sample_rate = match stems.first() {
+            Some((_, decoder)) => decoder.sample_rate(),
+            None => return Err(CommandError::invalid("No stems to play")),
+        };
+        if let Some((name, decoder)) = stems
+            .iter()
+            .find(|(_, decoder)| decoder.sample_rate() != sample_rate)
+        {
+            return Err(CommandError::invalid(format!(
+                "Stem {} is {} Hz but the other stems are {} Hz",
+                name,
+                decoder.sample_rate(),
+                sample_rate
+            )));
+        }
+
+        // Streams without a declared length play until every decoder runs dry
+        let length = stems
+            .iter()
+            .map(|(_, decoder)| decoder.frames().unwrap_or(u64::MAX))
+            .max()
+            .unwrap_or(0);
+        let (names, decoders) = stems.into_iter().unzip();
+        Ok((
+            names,
+            Self {
+                decoders,
+                sample_rate,
+                length,
+                position: 0,
+                loop_range: None,
+            },
+        ))
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Move every stem to `frame` (clamped to the song length)
+    pub fn seek(&mut self, frame: u64) -> CommandResult<()> {
+        let frame = frame.min(self.length);
+        for decoder in &mut self.decoders {
+            decoder.seek(frame)?;
+        }
+        self.position = frame;
+        Ok(())
+    }
+
+    /// Repeat `start..end` (in frames, checked by [`check_loop`]) once the
+    /// position reaches `end`, or stop looping with `None`
+    pub fn set_loop(&mut self, range: Option<(u64, u64)>) {
+        self.loop_range = range;
+    }
+
+    /// Read the next pass of at most `max_frames` from every stem into the
+    /// matching buffer, zero-padded where a stem ends early, wrapping at
+    /// the loop end. Returns the first frame and the frame count, or
+    /// `None` at the end of the song
+    pub fn read_chunk(
+        &mut self,
+        buffers: &mut [Vec<f32>],
+        max_frames: usize,
+    ) -> CommandResult<Option<(u64, usize)>> {
+        if let Some((start, end)) = self.loop_range {
+            if self.position == end {
+                self.seek(start)?;
+            }
+        }
+
+        let end = match self.loop_range {
+            Some((_, end)) if self.position < end => end.min(self.length),
+            _ => self.length,
+        };
+        if self.position >= end {
+            return Ok(None);
+        }
+
+        let frames = max_frames.min((end - self.position) as usize);
+        // Silent stems are read too so they stay in step
+        let mut read = 0;
+        for (decoder, buffer) in self.decoders.iter_mut().zip(buffers.iter_mut()) {
+            buffer.resize(frames * 2, 0.0);
+            let count = decoder.read(buffer);
+            buffer[count * 2..].fill(0.0);
+            read = read.max(count);
+        }
+        if read < frames {
+            // Every stream ran out before the declared length
+            self.length = self.position + read as u64;
+        }
+        if read == 0 {
+            return Ok(None);
+        }
+        let start = self.position;
+        self.position += read as u64;
+        Ok(Some((start, read)))
+    }
+}
+
+/// Offline mix of a [`Transport`] at a fixed gain per stem, for export
+pub struct Mixer {
+    transport: Transport,
+    gains: Vec<f32>,
+    buffers: Vec<Vec<f32>>,
+}
+
+impl Mixer {
+    /// Mix `stems`, which must all share a sample rate, at `gains` (one per
+    /// stem, in the same order)
+    pub fn new(stems: Vec<(String, StreamingDecoder)>, gains: Vec<f32>) -> CommandResult<Self> {
+        let (names, transport) = Transport::new(stems)?;
+        Ok(Self {
+            buffers: vec![Vec::new(); names.len()],
+            transport,
+            gains,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.transport.sample_rate()
+    }
+
+    pub fn length(&self) -> u64 {
+        self.transport.length()
+    }
+
+    pub fn seek(&mut self, frame: u64) -> CommandResult<()> {
+        self.transport.seek(frame)
+    }
+
+    /// Fill `out` with interleaved stereo frames from the current position.
+    /// Returns the frames rendered, fewer than asked only at the end of the
+    /// song
+    pub fn render(&mut self, out: &mut [f32]) -> CommandResult<usize> {
+        out.fill(0.0);
+        let total = out.len() / 2;
+        let mut done = 0;
+
+        while done < total {
+            let max_frames = (total - done).min(CHUNK_FRAMES);
+            let Some((_, frames)) = self.transport.read_chunk(&mut self.buffers, max_frames)?
+            else {
+                break;
+            };
+
+            let out = &mut out[done * 2..(done + frames) * 2];
+            for (gain, buffer) in self.gains.iter().zip(&self.buffers) {
+                if *gain != 0.0 {
+                    add_ramped(out, buffer, *gain, 0.0);
+                }
+            }
+            done += frames;
+        }
+        Ok(done)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::super::decoder::tests::{counter_sample, write_test_wav};
+    use super::super::opus::tests::{sine_sample, write_test_opus};
+    use super::*;
+    use std::path::PathBuf;
+
+    pub struct Fixture {
+        dir: PathBuf,
+    }
+
+    impl Fixture {
+        /// Two 8 kHz stems: both count frames on the left; the right channel
+        /// is a constant 1000 for "a" and 2000 for "b"
+        pub fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "music-tutor-mixer-{}-{}",
+                name,
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            write_test_wav(&dir.join("a.wav"), 8000, 8000, 1000);
+            write_test_wav(&dir.join("b.wav"), 8000, 6000, 2000);
+            Self { dir }
+        }
+
+        pub fn stems(&self) -> Vec<(String, StreamingDecoder)> {
+            ["a", "b"]
+                .iter()
+                .map(|name| {
+                    let path = self.dir.join(format!("{}.wav", name));
+                    (name.to_string(), StreamingDecoder::open(&path).unwrap())
+                })
+                .collect()
+        }
+
+        fn mixer(&self, gains: Vec<f32>) -> Mixer {
+            Mixer::new(self.stems(), gains).unwrap()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Right channel of each fixture stem
+    pub const A: f32 = 1000.0 / 32768.0;
+    pub const B: f32 = 2000.0 / 32768.0;
+
+    fn render(mixer: &mut Mixer, frames: usize) -> Vec<f32> {
+        let mut out = vec![0.0; frames * 2];
+        let rendered = mixer.render(&mut out).unwrap();
+        out.truncate(rendered * 2);
+        out
+    }
+
+    #[test]
+    fn test_mixes_stems_in_sync() {
+        let fixture = Fixture::new("sync");
+        let mut mixer = fixture.mixer(vec![1.0, 1.0]);
+        assert_eq!(mixer.length(), 8000);
+
+        let out = render(&mut mixer, 3000);
+        assert_eq!(out[2 * 2500], 2.0 * counter_sample(2500));
+        assert!((out[2 * 2500 + 1] - (A + B)).abs() < 1e-6);
+
+        // Both stems land on the same frame after a seek
+        mixer.seek(4321).unwrap();
+        let out = render(&mut mixer, 10);
+        assert_eq!(out[0], 2.0 * counter_sample(4321));
+
+        // Past the end of the shorter stem only the longer one plays, and
+        // rendering stops at the end of the song
+        mixer.seek(7000).unwrap();
+        let out = render(&mut mixer, 2000);
+        assert_eq!(out.len(), 2 * 1000);
+        assert!((out[1] - A).abs() < 1e-6);
+        assert!(render(&mut mixer, 10).is_empty());
+    }
+
+    #[test]
+    fn test_mixes_at_fixed_gains() {
+        let fixture = Fixture::new("gain");
+        let mut mixer = fixture.mixer(vec![0.5, 0.0]);
+        let out = render(&mut mixer, 10);
+        assert!((out[1] - 0.5 * A).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_plays_opus_stems() {
+        let path = std::env::temp_dir().join(format!(
+            "music-tutor-mixer-opus-{}.opus",
+            std::process::id()
+        ));
+        write_test_opus(&path, 24_000, 0.5);
+        let stems = vec![("vocals".to_string(), StreamingDecoder::open(&path).unwrap())];
+        let mut mixer = Mixer::new(stems, vec![1.0]).unwrap();
+        assert_eq!(mixer.sample_rate(), 48_000);
+        assert_eq!(mixer.length(), 24_000);
+
+        mixer.seek(12_000).unwrap();
+        let out = render(&mut mixer, 100);
+        for (i, frame) in out.chunks_exact(2).enumerate() {
+            assert!((frame[0] - sine_sample(12_000 + i, 0.5)).abs() < 0.05);
+        }
+
+        assert_eq!(render(&mut mixer, 20_000).len(), 2 * 11_900);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,318 @@
+//! Native stem playback.
+//!
+//! Stems are streamed from disk by [`decoder`] (Opus through [`opus`]) on
+//! a feeder thread, handed to the device callback through lock-free rings
+//! by [`player`], summed sample-aligned with the helpers in [`mixer`],
+//! time-stretched or transposed by [`stretch`] when asked, and played
+//! through the default device by [`output`]. [`probe`] checks stem files
+//! against analysis.json, [`waveform`] serves cached peaks for
+//! drawing them and [`export`] renders mixes to WAV or FLAC. The frontend
+//! drives playback through the commands below and follows the
+//! `playback-position` event instead of decoding audio in the webview;
+//! failures outside a command arrive as `playback-error`.
+
+mod decoder;
+mod encode;
+pub mod export;
+mod mixer;
+mod opus;
+mod output;
+mod player;
+pub mod probe;
+mod stretch;
+pub mod waveform;
+
+use crate::error::{CommandError, CommandResult};
+use crate::{read_analysis, sandbox};
+use decoder::StreamingDecoder;
+use output::Output;
+use player::{ErrorSink, Playback, Player};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+
+pub const POSITION_EVENT: &str = "playback-position";
+pub const ERROR_EVENT: &str = "playback-error";
+
+/// How often `playback-position` is emitted while playing
+const POSITION_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Payload of the `playback-position` event
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaybackPosition {
+    /// Seconds into the loaded files
+    pub position: f64,
+    pub duration: f64,
+    pub playing: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StemState {
+    pub name: String,
+    pub gain: f32,
+    pub muted: bool,
+    pub solo: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaybackState {
+    pub position: f64,
+    pub duration: f64,
+    pub playing: bool,
+    pub sample_rate: u32,
+    pub loop_start: Option<f64>,
+    pub loop_end: Option<f64>,
+    /// Real-time speed on top of the loaded files' own speed
+    pub speed: f64,
+    /// Transposition in semitones
+    pub pitch_shift: f64,
+    pub stems: Vec<StemState>,
+}
+
+fn to_seconds(playback: &Playback, frame: u64) -> f64 {
+    frame as f64 / playback.sample_rate() as f64
+}
+
+fn to_frame(playback: &Playback, seconds: f64) -> CommandResult<u64> {
+    if !(seconds.is_finite() && seconds >= 0.0) {
+        return Err(CommandError::invalid(format!(
+            "Invalid position: {}",
+            seconds
+        )));
+    }
+    Ok((seconds * playback.sample_rate() as f64).round() as u64)
+}
+
+fn position_of(playback: &Playback) -> PlaybackPosition {
+    PlaybackPosition {
+        position: to_seconds(playback, playback.position()),
+        duration: to_seconds(playback, playback.length()),
+        playing: playback.is_playing(),
+    }
+}
+
+fn state_of(playback: &Playback) -> PlaybackState {
+    let position = position_of(playback);
+    PlaybackState {
+        position: position.position,
+        duration: position.duration,
+        playing: position.playing,
+        sample_rate: playback.sample_rate(),
+        loop_start: playback
+            .loop_range()
+            .map(|(start, _)| to_seconds(playback, start)),
+        loop_end: playback
+            .loop_range()
+            .map(|(_, end)| to_seconds(playback, end)),
+        speed: playback.speed(),
+        pitch_shift: playback.pitch_shift(),
+        stems: playback.stems(),
+    }
+}
+
+/// Send errors from the feeder and the device to the frontend
+fn error_sink(app: &AppHandle) -> ErrorSink {
+    let app = app.clone();
+    Arc::new(move |error: CommandError| {
+        let _ = app.emit(ERROR_EVENT, error);
+    })
+}
+
+/// Managed state: the command side of the loaded song and the output
+/// stream once it has been opened. The device callback never touches
+/// either lock
+#[derive(Default)]
+pub struct PlaybackEngine {
+    playback: Arc<Mutex<Option<Playback>>>,
+    output: Mutex<Option<Output>>,
+}
+
+impl PlaybackEngine {
+    fn with_playback<T>(
+        &self,
+        f: impl FnOnce(&mut Playback) -> CommandResult<T>,
+    ) -> CommandResult<T> {
+        let mut playback = self.playback.lock().unwrap_or_else(|e| e.into_inner());
+        match playback.as_mut() {
+            Some(playback) => f(playback),
+            None => Err(CommandError::invalid("No song is loaded for playback")),
+        }
+    }
+
+    /// Hand `player` to the output, opening the device the first time
+    fn set_player(&self, app: &AppHandle, player: Player) -> CommandResult<()> {
+        let mut output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        if output.is_none() {
+            *output = Some(Output::start(error_sink(app))?);
+            spawn_position_events(app.clone(), self.playback.clone());
+        }
+        match output.as_mut() {
+            Some(output) => output.set_player(player),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Emit `playback-position` while playing, plus once when playback stops
+fn spawn_position_events(app: AppHandle, playback: Arc<Mutex<Option<Playback>>>) {
+    thread::spawn(move || {
+        let mut last: Option<PlaybackPosition> = None;
+        loop {
+            thread::sleep(POSITION_INTERVAL);
+            let current = playback
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .as_ref()
+                .map(position_of);
+            let Some(current) = current else {
+                continue;
+            };
+            if current.playing || last != Some(current) {
+                let _ = app.emit(POSITION_EVENT, current);
+                last = Some(current);
+            }
+        }
+    });
+}
+
+/// Load a song's stems for playback at one of its pre-rendered speeds
+/// (`"1.0x"` by default). Gain, mute and solo carry over from the
+/// previously loaded stems of the same name, as does the pitch shift;
+/// real-time speed resets to 1.0 and playback starts paused at 0.
+#[tauri::command]
+pub fn load_playback(
+    app: AppHandle,
+    song_dir: &str,
+    speed: Option<String>,
+) -> CommandResult<PlaybackState> {
+    let song_dir = Path::new(song_dir);
+    let analysis = read_analysis(&song_dir.join("analysis.json"))?;
+    let speed = speed.unwrap_or_else(|| "1.0x".to_string());
+
+    let mut names: Vec<&String> = analysis.stems.keys().collect();
+    names.sort();
+    let mut stems = Vec::new();
+    for name in names {
+        let Some(relative) = analysis.stems[name].paths.get(&speed) else {
+            continue;
+        };
+        let path = sandbox::stem_path(song_dir, &analysis, relative)?;
+        stems.push((name.clone(), StreamingDecoder::open(&path)?));
+    }
+    if stems.is_empty() {
+        return Err(CommandError::invalid(format!(
+            "No stems rendered at {}",
+            speed
+        )));
+    }
+    let (mut playback, player) = Playback::new(stems, error_sink(&app))?;
+
+    let engine = app.state::<PlaybackEngine>();
+    let mut current = engine.playback.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(previous) = current.as_ref() {
+        playback.set_stretch(1.0, previous.pitch_shift())?;
+        for stem in previous.stems() {
+            // Stems missing from the new set are simply skipped
+            let _ = playback.set_gain(&stem.name, stem.gain);
+            let _ = playback.set_muted(&stem.name, stem.muted);
+            let _ = playback.set_solo(&stem.name, stem.solo);
+        }
+    }
+    engine.set_player(&app, player)?;
+    let state = state_of(&playback);
+    *current = Some(playback);
+    Ok(state)
+}
+
+#[tauri::command]
+pub fn get_playback_state(app: AppHandle) -> CommandResult<PlaybackState> {
+    app.state::<PlaybackEngine>()
+        .with_playback(|playback| Ok(state_of(playback)))
+}
+
+#[tauri::command]
+pub fn start_playback(app: AppHandle) -> CommandResult<()> {
+    app.state::<PlaybackEngine>().with_playback(Playback::play)
+}
+
+#[tauri::command]
+pub fn pause_playback(app: AppHandle) -> CommandResult<()> {
+    app.state::<PlaybackEngine>().with_playback(|playback| {
+        playback.pause();
+        Ok(())
+    })
+}
+
+/// Jump to `position` seconds; all stems move together
+#[tauri::command]
+pub fn seek_playback(app: AppHandle, position: f64) -> CommandResult<()> {
+    app.state::<PlaybackEngine>().with_playback(|playback| {
+        let frame = to_frame(playback, position)?;
+        playback.seek(frame)
+    })
+}
+
+/// Loop between `start` and `end` seconds, or clear the loop when either
+/// is missing
+#[tauri::command]
+pub fn set_playback_loop(
+    app: AppHandle,
+    start: Option<f64>,
+    end: Option<f64>,
+) -> CommandResult<()> {
+    app.state::<PlaybackEngine>().with_playback(|playback| {
+        let range = match (start, end) {
+            (Some(start), Some(end)) => {
+                Some((to_frame(playback, start)?, to_frame(playback, end)?))
+            }
+            _ => None,
+        };
+        playback.set_loop(range)
+    })
+}
+
+/// Set a stem's linear gain (1.0 = unity)
+#[tauri::command]
+pub fn set_stem_gain(app: AppHandle, stem: &str, gain: f32) -> CommandResult<()> {
+    app.state::<PlaybackEngine>()
+        .with_playback(|playback| playback.set_gain(stem, gain))
+}
+
+#[tauri::command]
+pub fn set_stem_muted(app: AppHandle, stem: &str, muted: bool) -> CommandResult<()> {
+    app.state::<PlaybackEngine>()
+        .with_playback(|playback| playback.set_muted(stem, muted))
+}
+
+/// Solo or unsolo a stem; any number of stems can be soloed at once
+#[tauri::command]
+pub fn set_stem_solo(app: AppHandle, stem: &str, solo: bool) -> CommandResult<()> {
+    app.state::<PlaybackEngine>()
+        .with_playback(|playback| playback.set_solo(stem, solo))
+}
+
+/// Stretch playback to `speed` (0.25–2.0) without changing pitch. Applies
+/// to whatever files are loaded, so 1.0x stems give any speed in range.
+#[tauri::command]
+pub fn set_playback_speed(app: AppHandle, speed: f64) -> CommandResult<()> {
+    app.state::<PlaybackEngine>().with_playback(|playback| {
+        let semitones = playback.pitch_shift();
+        playback.set_stretch(speed, semitones)
+    })
+}
+
+/// Transpose playback by `semitones` plus `cents` (up to two octaves either
+/// way) without changing speed
+#[tauri::command]
+pub fn set_pitch_shift(app: AppHandle, semitones: f64, cents: Option<f64>) -> CommandResult<()> {
+    app.state::<PlaybackEngine>().with_playback(|playback| {
+        let speed = playback.speed();
+        playback.set_stretch(speed, semitones + cents.unwrap_or(0.0) / 100.0)
+    })
+}
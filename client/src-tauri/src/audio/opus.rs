@@ -0,0 +1,263 @@
+//! Opus decoding for symphonia.
+//!
+//! symphonia demuxes Ogg Opus but ships no Opus decoder, so this wraps
+//! libopus (through `audiopus`) in symphonia's [`Decoder`] trait and
+//! registers it next to the built-in codecs. Only mono and stereo streams
+//! (channel mapping family 0) are supported, which covers every stem the
+//! pipeline writes.
+
+use audiopus::coder::{Decoder as LibopusDecoder, GenericCtl};
+use audiopus::packet::Packet as OpusPacket;
+use audiopus::{Channels as OpusChannels, MutSignals, SampleRate};
+use std::sync::{Mutex, OnceLock};
+use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer, AudioBufferRef, Signal, SignalSpec};
+use symphonia::core::codecs::{
+    CodecDescriptor, CodecParameters, CodecRegistry, Decoder, DecoderOptions, FinalizeResult,
+    CODEC_TYPE_OPUS,
+};
+use symphonia::core::errors::{decode_error, unsupported_error, Result};
+use symphonia::core::formats::Packet;
+use symphonia::core::support_codec;
+
+/// Opus always decodes at 48 kHz
+const OPUS_RATE: u32 = 48_000;
+
+/// Longest Opus packet: 120 ms at 48 kHz
+const MAX_PACKET_FRAMES: usize = 5760;
+
+/// Frames to decode ahead of a seek target so the decoder converges, as
+/// RFC 7845 recommends
+pub const SEEK_PREROLL: u64 = 3840;
+
+/// Frames the encoder asks to be dropped from the start of the stream,
+/// from the OpusHead packet. symphonia's `delay` can't be relied on: it is
+/// replaced when the first page looks short
+pub fn pre_skip(params: &CodecParameters) -> Option<u64> {
+    let head = params.extra_data.as_deref()?;
+    let bytes = head.get(10..12)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]) as u64)
+}
+
+/// Default symphonia codecs plus Opus
+pub fn codecs() -> &'static CodecRegistry {
+    static CODECS: OnceLock<CodecRegistry> = OnceLock::new();
+    CODECS.get_or_init(|| {
+        let mut registry = CodecRegistry::new();
+        symphonia::default::register_enabled_codecs(&mut registry);
+        registry.register_all::<OpusDecoder>();
+        registry
+    })
+}
+
+pub struct OpusDecoder {
+    params: CodecParameters,
+    /// libopus state is `Send` but not `Sync`, which symphonia's trait
+    /// requires. Only ever reached through `get_mut`, so never locked
+    decoder: Mutex<LibopusDecoder>,
+    channels: usize,
+    /// Interleaved output of the last packet
+    samples: Vec<f32>,
+    buf: AudioBuffer<f32>,
+}
+
+impl OpusDecoder {
+    fn libopus(&mut self) -> &mut LibopusDecoder {
+        self.decoder
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Decoder for OpusDecoder {
+    fn try_new(params: &CodecParameters, _options: &DecoderOptions) -> Result<Self> {
+        let channels = match params.channels {
+            Some(channels) => channels,
+            None => return unsupported_error("opus: unknown channel layout"),
+        };
+        let opus_channels = match channels.count() {
+            1 => OpusChannels::Mono,
+            2 => OpusChannels::Stereo,
+            _ => return unsupported_error("opus: only mono and stereo are supported"),
+        };
+        let decoder = LibopusDecoder::new(SampleRate::Hz48000, opus_channels)
+            .or_else(|_| unsupported_error("opus: cannot create decoder"))?;
+
+        Ok(Self {
+            params: params.clone(),
+            decoder: Mutex::new(decoder),
+            channels: channels.count(),
+            samples: vec![0.0; MAX_PACKET_FRAMES * channels.count()],
+            buf: AudioBuffer::new(
+                MAX_PACKET_FRAMES as u64,
+                SignalSpec::new(OPUS_RATE, channels),
+            ),
+        })
+    }
+
+    fn supported_codecs() -> &'static [CodecDescriptor] {
+        &[support_codec!(CODEC_TYPE_OPUS, "opus", "Opus (libopus)")]
+    }
+
+    fn reset(&mut self) {
+        // Only fails for a bad decoder pointer, which `new` rules out
+        let _ = self.libopus().reset_state();
+    }
+
+    fn codec_params(&self) -> &CodecParameters {
+        &self.params
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Result<AudioBufferRef<'_>> {
+        let mut samples = std::mem::take(&mut self.samples);
+        let decoded = match (
+            OpusPacket::try_from(packet.buf()),
+            MutSignals::try_from(&mut samples[..]),
+        ) {
+            (Ok(input), Ok(output)) => self.libopus().decode_float(Some(input), output, false),
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+        self.samples = samples;
+        let frames = match decoded {
+            Ok(frames) => frames,
+            Err(_) => {
+                self.buf.clear();
+                return decode_error("opus: corrupt packet");
+            }
+        };
+
+        self.buf.clear();
+        self.buf.render_reserved(Some(frames));
+        for channel in 0..self.channels {
+            let plane = self.buf.chan_mut(channel);
+            for (frame, sample) in plane.iter_mut().enumerate() {
+                *sample = self.samples[frame * self.channels + channel];
+            }
+        }
+        self.buf
+            .trim(packet.trim_start as usize, packet.trim_end as usize);
+        Ok(self.buf.as_audio_buffer_ref())
+    }
+
+    fn finalize(&mut self) -> FinalizeResult {
+        FinalizeResult::default()
+    }
+
+    fn last_decoded(&self) -> AudioBufferRef<'_> {
+        self.buf.as_audio_buffer_ref()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::super::decoder::StreamingDecoder;
+    use audiopus::coder::Encoder;
+    use audiopus::{Application, Channels, SampleRate};
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+    use std::f32::consts::TAU;
+    use std::fs::File;
+    use std::path::Path;
+
+    /// Frames per encoded packet (20 ms)
+    const PACKET_FRAMES: usize = 960;
+
+    /// Left channel of frame `n` in a test Opus file: a 440 Hz sine at
+    /// `amplitude`; the right channel is silent
+    pub fn sine_sample(n: usize, amplitude: f32) -> f32 {
+        amplitude * (TAU * 440.0 * n as f32 / 48_000.0).sin()
+    }
+
+    /// Write a 48 kHz stereo Ogg Opus file of `frames` frames of
+    /// [`sine_sample`]
+    pub fn write_test_opus(path: &Path, frames: usize, amplitude: f32) {
+        let encoder =
+            Encoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio).unwrap();
+        let pre_skip = encoder.lookahead().unwrap() as usize;
+        let mut writer = PacketWriter::new(File::create(path).unwrap());
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // version
+        head.push(2); // channels
+        head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+        head.extend_from_slice(&48_000u32.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+        writer
+            .write_packet(head.into(), 1, PacketWriteEndInfo::EndPage, 0)
+            .unwrap();
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&4u32.to_le_bytes());
+        tags.extend_from_slice(b"test");
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        writer
+            .write_packet(tags.into(), 1, PacketWriteEndInfo::EndPage, 0)
+            .unwrap();
+
+        // The encoder lags by `pre_skip`, so feed silence until it has
+        // flushed every real frame
+        let total = frames + pre_skip;
+        let mut input = vec![0.0f32; PACKET_FRAMES * 2];
+        let mut output = vec![0u8; 4000];
+        let mut encoded = 0;
+        while encoded < total {
+            for (i, frame) in input.chunks_exact_mut(2).enumerate() {
+                let n = encoded + i;
+                frame[0] = if n < frames {
+                    sine_sample(n, amplitude)
+                } else {
+                    0.0
+                };
+                frame[1] = 0.0;
+            }
+            let len = encoder.encode_float(&input, &mut output).unwrap();
+            encoded += PACKET_FRAMES;
+            // Pages of half a second, as encoders write them
+            let end = if encoded >= total {
+                PacketWriteEndInfo::EndStream
+            } else if encoded % (PACKET_FRAMES * 25) == 0 {
+                PacketWriteEndInfo::EndPage
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            writer
+                .write_packet(output[..len].into(), 1, end, encoded.min(total) as u64)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_decodes_and_seeks_opus() {
+        let path =
+            std::env::temp_dir().join(format!("music-tutor-opus-{}.opus", std::process::id()));
+        write_test_opus(&path, 48_000, 0.5);
+
+        let mut decoder = StreamingDecoder::open(&path).unwrap();
+        assert_eq!(decoder.sample_rate(), 48_000);
+        // Pre-skip and end padding are trimmed
+        assert_eq!(decoder.frames(), Some(48_000));
+        let mut all = vec![0.0; 2 * 50_000];
+        assert_eq!(decoder.read(&mut all), 48_000);
+
+        // Lossy, but the encoder delay is gone: the sine lines up
+        for n in (1000..47_000).step_by(997) {
+            assert!(
+                (all[2 * n] - sine_sample(n, 0.5)).abs() < 0.05,
+                "frame {}",
+                n
+            );
+            assert!(all[2 * n + 1].abs() < 0.05);
+        }
+
+        decoder.seek(30_001).unwrap();
+        let mut out = vec![0.0; 2 * 100];
+        assert_eq!(decoder.read(&mut out), 100);
+        for (i, frame) in out.chunks_exact(2).enumerate() {
+            assert!((frame[0] - all[2 * (30_001 + i)]).abs() < 0.02);
+        }
+
+        decoder.seek(47_900).unwrap();
+        assert_eq!(decoder.read(&mut out), 100);
+        assert_eq!(decoder.read(&mut out), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+//! Plays the loaded song through the default output device.
+//!
+//! The device stream lives on its own thread (cpal streams aren't `Send` on
+//! every platform) and renders the current [`Player`] in its callback.
+//! Players are handed over and back through lock-free rings so loading a
+//! song never blocks the callback, and a replaced player is dropped on the
+//! loading thread rather than the audio one. When the device runs at a
+//! different rate than the stems, the mix is resampled with linear
+//! interpolation.
+
+use super::player::{ErrorSink, Player};
+use crate::error::{CommandError, CommandResult};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::sync::mpsc;
+use std::thread;
+
+/// Frames pulled from the player at a time while resampling
+const BLOCK_FRAMES: usize = 256;
+
+/// Players queued for the callback to pick up or give back
+const HANDOFF_SLOTS: usize = 4;
+
+fn device_error(message: impl std::fmt::Display) -> CommandError {
+    CommandError::AudioDevice {
+        message: message.to_string(),
+    }
+}
+
+/// Handle to the running output stream; dropping it stops the stream
+pub struct Output {
+    players: Producer<Player>,
+    retired: Consumer<Player>,
+    _stop: mpsc::Sender<()>,
+}
+
+/// The callback's ends of the player handoff
+struct Handoff {
+    players: Consumer<Player>,
+    retired: Producer<Player>,
+    current: Option<Player>,
+}
+
+impl Output {
+    pub fn start(on_error: ErrorSink) -> CommandResult<Self> {
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let (players, players_rx) = RingBuffer::new(HANDOFF_SLOTS);
+        let (retired_tx, retired) = RingBuffer::new(HANDOFF_SLOTS);
+        let handoff = Handoff {
+            players: players_rx,
+            retired: retired_tx,
+            current: None,
+        };
+
+        thread::spawn(move || match open_stream(handoff, on_error) {
+            Ok(_stream) => {
+                let _ = ready_tx.send(Ok(()));
+                // Returns once the Output (and so the sender) is dropped,
+                // which drops the stream
+                let _ = stop_rx.recv();
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+            }
+        });
+
+        ready_rx
+            .recv()
+            .map_err(|_| device_error("audio thread exited"))??;
+        Ok(Self {
+            players,
+            retired,
+            _stop: stop_tx,
+        })
+    }
+
+    /// Play `player` in place of the current one
+    pub fn set_player(&mut self, player: Player) -> CommandResult<()> {
+        while self.retired.pop().is_ok() {}
+        self.players
+            .push(player)
+            .map_err(|_| device_error("the output stream stopped pulling audio"))
+    }
+}
+
+fn open_stream(handoff: Handoff, on_error: ErrorSink) -> CommandResult<Stream> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or_else(|| device_error("no output device available"))?;
+    let supported = device.default_output_config().map_err(device_error)?;
+    let config = supported.config();
+
+    let stream = match supported.sample_format() {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, handoff, on_error),
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, handoff, on_error),
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, handoff, on_error),
+        other => {
+            return Err(device_error(format!(
+                "unsupported sample format {:?}",
+                other
+            )))
+        }
+    }?;
+    stream.play().map_err(device_error)?;
+    Ok(stream)
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    mut handoff: Handoff,
+    on_error: ErrorSink,
+) -> CommandResult<Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let mut adapter = RateAdapter::new(config.sample_rate.0);
+    let mut stereo = Vec::new();
+
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                while let Ok(next) = handoff.players.pop() {
+                    if let Some(previous) = handoff.current.replace(next) {
+                        let _ = handoff.retired.push(previous);
+                    }
+                    adapter.reset();
+                }
+
+                let frames = data.len() / channels;
+                stereo.resize(frames * 2, 0.0);
+                match handoff.current.as_mut() {
+                    Some(player) => {
+                        if player.sync() {
+                            adapter.reset();
+                        }
+                        adapter.render(player, &mut stereo);
+                    }
+                    None => stereo.fill(0.0),
+                }
+
+                for (frame, sample) in data.chunks_exact_mut(channels).zip(stereo.chunks_exact(2)) {
+                    if channels == 1 {
+                        frame[0] = T::from_sample((sample[0] + sample[1]) * 0.5);
+                        continue;
+                    }
+                    frame[0] = T::from_sample(sample[0]);
+                    frame[1] = T::from_sample(sample[1]);
+                    for extra in &mut frame[2..] {
+                        *extra = T::from_sample(0.0);
+                    }
+                }
+            },
+            move |e| on_error(device_error(e)),
+            None,
+        )
+        .map_err(device_error)
+}
+
+/// Converts the player's sample rate to the device's
+pub struct RateAdapter {
+    device_rate: u32,
+    /// Source frames before and after the current output position
+    prev: [f32; 2],
+    next: [f32; 2],
+    /// Position between `prev` and `next`, in source frames
+    frac: f64,
+    block: Vec<f32>,
+    block_pos: usize,
+}
+
+impl RateAdapter {
+    pub fn new(device_rate: u32) -> Self {
+        Self {
+            device_rate,
+            prev: [0.0; 2],
+            next: [0.0; 2],
+            frac: 1.0,
+            block: Vec::new(),
+            block_pos: 0,
+        }
+    }
+
+    /// Forget buffered frames, e.g. after a seek
+    pub fn reset(&mut self) {
+        self.prev = [0.0; 2];
+        self.next = [0.0; 2];
+        self.frac = 1.0;
+        self.block.clear();
+        self.block_pos = 0;
+    }
+
+    /// Fill `out` (interleaved stereo at the device rate) from `player`
+    pub fn render(&mut self, player: &mut Player, out: &mut [f32]) {
+        if player.sample_rate() == self.device_rate {
+            player.render(out);
+            return;
+        }
+
+        let step = player.sample_rate() as f64 / self.device_rate as f64;
+        for frame in out.chunks_exact_mut(2) {
+            while self.frac >= 1.0 {
+                self.prev = self.next;
+                self.next = self.pull(player);
+                self.frac -= 1.0;
+            }
+            let t = self.frac as f32;
+            frame[0] = self.prev[0] + (self.next[0] - self.prev[0]) * t;
+            frame[1] = self.prev[1] + (self.next[1] - self.prev[1]) * t;
+            self.frac += step;
+        }
+    }
+
+    fn pull(&mut self, player: &mut Player) -> [f32; 2] {
+        if self.block_pos >= self.block.len() {
+            self.block.resize(BLOCK_FRAMES * 2, 0.0);
+            player.render(&mut self.block);
+            self.block_pos = 0;
+        }
+        let frame = [self.block[self.block_pos], self.block[self.block_pos + 1]];
+        self.block_pos += 2;
+        frame
+    }
+}
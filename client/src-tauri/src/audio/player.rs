@@ -0,0 +1,779 @@
+//! Live playback of a song's stems.
+//!
+//! The device callback must never block or touch the disk, so a feeder
+//! thread owns the [`Transport`] and decodes ahead into one lock-free ring
+//! per stem, plus a ring of [`Segment`]s saying which song frames those
+//! samples are. The callback's [`Player`] only pops from the rings and
+//! mixes, reading gains, play state and speed from atomics that
+//! [`Playback`] sets for the commands. Seeks and loop changes go to the
+//! feeder over a channel and bump a generation counter; the player drops
+//! whatever was decoded for an older generation.
+
+use super::decoder::StreamingDecoder;
+use super::mixer::{self, Transport, CHUNK_FRAMES};
+use super::stretch::{self, Stretch};
+use super::StemState;
+use crate::error::{CommandError, CommandResult};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Frames decoded ahead of the callback per stem
+const RING_FRAMES: usize = 8 * CHUNK_FRAMES;
+
+/// Segments queued ahead; more than the stem rings need unless a short
+/// loop splits passes
+const SEGMENT_SLOTS: usize = 64;
+
+/// How long the feeder waits for commands when the rings are full or the
+/// song has ended
+const FEED_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Receives errors from the feeder and the device, which no command is
+/// waiting on
+pub type ErrorSink = Arc<dyn Fn(CommandError) + Send + Sync>;
+
+struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+    fn new(value: f64) -> Self {
+        Self(AtomicU64::new(value.to_bits()))
+    }
+
+    fn load(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn store(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+struct StemControl {
+    name: String,
+    /// `f32` bits
+    gain: AtomicU32,
+    muted: AtomicBool,
+    solo: AtomicBool,
+}
+
+impl StemControl {
+    fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+}
+
+/// Shared by the commands, the feeder and the callback
+struct Controls {
+    stems: Vec<StemControl>,
+    playing: AtomicBool,
+    /// Bumped by every seek; audio decoded before it is stale
+    generation: AtomicU64,
+    /// Generation of the audio the callback last played, and the frame
+    /// after it
+    heard_generation: AtomicU64,
+    position: AtomicU64,
+    length: AtomicU64,
+    speed: AtomicF64,
+    /// Pitch ratio
+    pitch: AtomicF64,
+}
+
+/// Frames the feeder pushed to every stem ring. `frames == 0` marks the
+/// end of the song
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    generation: u64,
+    start: u64,
+    frames: usize,
+}
+
+enum FeederCommand {
+    Seek {
+        frame: u64,
+        generation: u64,
+    },
+    Loop {
+        range: Option<(u64, u64)>,
+        frame: u64,
+        generation: u64,
+    },
+}
+
+/// The commands' side of a loaded song
+pub struct Playback {
+    controls: Arc<Controls>,
+    commands: Sender<FeederCommand>,
+    sample_rate: u32,
+    loop_range: Option<(u64, u64)>,
+    speed: f64,
+    /// Transposition in semitones (fractional for cents)
+    pitch_shift: f64,
+    /// Where the last seek went, reported until the callback plays from it
+    seek_target: u64,
+}
+
+impl Playback {
+    /// Start decoding `stems` (all at one sample rate) on a feeder thread.
+    /// Returns the handle for commands and the [`Player`] to give the
+    /// output callback; playback starts paused at 0.
+    pub fn new(
+        stems: Vec<(String, StreamingDecoder)>,
+        on_error: ErrorSink,
+    ) -> CommandResult<(Self, Player)> {
+        let (names, transport) = Transport::new(stems)?;
+        let sample_rate = transport.sample_rate();
+        let controls = Arc::new(Controls {
+            stems: names
+                .into_iter()
+                .map(|name| StemControl {
+                    name,
+                    gain: AtomicU32::new(1f32.to_bits()),
+                    muted: AtomicBool::new(false),
+                    solo: AtomicBool::new(false),
+                })
+                .collect(),
+            playing: AtomicBool::new(false),
+            generation: AtomicU64::new(0),
+            heard_generation: AtomicU64::new(0),
+            position: AtomicU64::new(0),
+            length: AtomicU64::new(transport.length()),
+            speed: AtomicF64::new(1.0),
+            pitch: AtomicF64::new(1.0),
+        });
+
+        let (producers, consumers) = controls
+            .stems
+            .iter()
+            .map(|_| RingBuffer::new(RING_FRAMES * 2))
+            .unzip();
+        let (segment_producer, segment_consumer) = RingBuffer::new(SEGMENT_SLOTS);
+        let (commands, receiver) = mpsc::channel();
+
+        let feeder = Feeder {
+            buffers: vec![Vec::with_capacity(CHUNK_FRAMES * 2); controls.stems.len()],
+            transport,
+            controls: controls.clone(),
+            commands: receiver,
+            stems: producers,
+            segments: segment_producer,
+            generation: 0,
+            ended: false,
+            on_error,
+        };
+        thread::spawn(move || feeder.run());
+
+        let player = Player {
+            applied: vec![0.0; controls.stems.len()],
+            controls: controls.clone(),
+            sample_rate,
+            stems: consumers,
+            segments: segment_consumer,
+            current: None,
+            generation: 0,
+            settled: false,
+            stretch: Some(Stretch::new(sample_rate)),
+            stretch_settings: (1.0, 1.0),
+        };
+        let playback = Self {
+            controls,
+            commands,
+            sample_rate,
+            loop_range: None,
+            speed: 1.0,
+            pitch_shift: 0.0,
+            seek_target: 0,
+        };
+        Ok((playback, player))
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Frame the listener is at
+    pub fn position(&self) -> u64 {
+        let generation = self.controls.generation.load(Ordering::Acquire);
+        if self.controls.heard_generation.load(Ordering::Acquire) >= generation {
+            self.controls.position.load(Ordering::Relaxed)
+        } else {
+            self.seek_target
+        }
+    }
+
+    pub fn length(&self) -> u64 {
+        self.controls.length.load(Ordering::Relaxed)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.controls.playing.load(Ordering::Relaxed)
+    }
+
+    pub fn loop_range(&self) -> Option<(u64, u64)> {
+        self.loop_range
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn pitch_shift(&self) -> f64 {
+        self.pitch_shift
+    }
+
+    pub fn stems(&self) -> Vec<StemState> {
+        self.controls
+            .stems
+            .iter()
+            .map(|stem| StemState {
+                name: stem.name.clone(),
+                gain: stem.gain(),
+                muted: stem.muted.load(Ordering::Relaxed),
+                solo: stem.solo.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Start playing, from the beginning if the end had been reached
+    pub fn play(&mut self) -> CommandResult<()> {
+        if self.position() >= self.length() {
+            self.seek(0)?;
+        }
+        self.controls.playing.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn pause(&mut self) {
+        self.controls.playing.store(false, Ordering::Relaxed);
+    }
+
+    /// Move every stem to `frame` (clamped to the song length)
+    pub fn seek(&mut self, frame: u64) -> CommandResult<()> {
+        let frame = frame.min(self.length());
+        let generation = self.restart_at(frame);
+        self.send(FeederCommand::Seek { frame, generation })
+    }
+
+    /// Repeat `start..end` (in frames) once playback reaches `end`, or
+    /// stop looping with `None`
+    pub fn set_loop(&mut self, range: Option<(u64, u64)>) -> CommandResult<()> {
+        mixer::check_loop(range)?;
+        // Audio past the old loop end may already be decoded, so decoding
+        // starts over from where the listener is
+        let frame = self.position();
+        let generation = self.restart_at(frame);
+        self.loop_range = range;
+        self.send(FeederCommand::Loop {
+            range,
+            frame,
+            generation,
+        })
+    }
+
+    fn restart_at(&mut self, frame: u64) -> u64 {
+        self.seek_target = frame;
+        self.controls.generation.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    fn send(&self, command: FeederCommand) -> CommandResult<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| CommandError::AudioDevice {
+                message: "playback thread exited".to_string(),
+            })
+    }
+
+    fn stem(&self, name: &str) -> CommandResult<&StemControl> {
+        self.controls
+            .stems
+            .iter()
+            .find(|stem| stem.name == name)
+            .ok_or_else(|| CommandError::invalid(format!("Unknown stem: {}", name)))
+    }
+
+    pub fn set_gain(&mut self, name: &str, gain: f32) -> CommandResult<()> {
+        mixer::check_gain(gain)?;
+        self.stem(name)?
+            .gain
+            .store(gain.to_bits(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn set_muted(&mut self, name: &str, muted: bool) -> CommandResult<()> {
+        self.stem(name)?.muted.store(muted, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Soloing any stem silences every stem that isn't soloed
+    pub fn set_solo(&mut self, name: &str, solo: bool) -> CommandResult<()> {
+        self.stem(name)?.solo.store(solo, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Play at `speed` (0.25–2.0) with pitch preserved, transposed by
+    /// `semitones`. Positions and loop points stay in file frames.
+    pub fn set_stretch(&mut self, speed: f64, semitones: f64) -> CommandResult<()> {
+        let pitch = stretch::validate(speed, semitones)?;
+        self.speed = speed;
+        self.pitch_shift = semitones;
+        self.controls.speed.store(speed);
+        self.controls.pitch.store(pitch);
+        Ok(())
+    }
+}
+
+/// Decodes ahead of the callback until the rings are full. Exits once the
+/// [`Playback`] is dropped
+struct Feeder {
+    transport: Transport,
+    controls: Arc<Controls>,
+    commands: Receiver<FeederCommand>,
+    stems: Vec<Producer<f32>>,
+    segments: Producer<Segment>,
+    buffers: Vec<Vec<f32>>,
+    generation: u64,
+    /// Nothing more to decode until the next seek
+    ended: bool,
+    on_error: ErrorSink,
+}
+
+impl Feeder {
+    fn run(mut self) {
+        loop {
+            let command = if self.can_feed() {
+                match self.commands.try_recv() {
+                    Ok(command) => Some(command),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            } else {
+                match self.commands.recv_timeout(FEED_INTERVAL) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            };
+            match command {
+                Some(command) => self.apply(command),
+                None if self.can_feed() => self.feed(),
+                None => {}
+            }
+        }
+    }
+
+    fn can_feed(&self) -> bool {
+        !self.ended
+            && self.segments.slots() > 0
+            && self
+                .stems
+                .iter()
+                .all(|ring| ring.slots() >= CHUNK_FRAMES * 2)
+    }
+
+    fn apply(&mut self, command: FeederCommand) {
+        let (frame, generation) = match command {
+            FeederCommand::Seek { frame, generation } => (frame, generation),
+            FeederCommand::Loop {
+                range,
+                frame,
+                generation,
+            } => {
+                self.transport.set_loop(range);
+                (frame, generation)
+            }
+        };
+        self.generation = generation;
+        self.ended = false;
+        if let Err(e) = self.transport.seek(frame) {
+            self.fail(e);
+        }
+    }
+
+    /// Decode one pass into the rings
+    fn feed(&mut self) {
+        match self.transport.read_chunk(&mut self.buffers, CHUNK_FRAMES) {
+            Ok(Some((start, frames))) => {
+                // Samples go in before their segment, so the callback
+                // never sees a segment without its audio
+                for (ring, buffer) in self.stems.iter_mut().zip(&self.buffers) {
+                    if let Ok(mut chunk) = ring.write_chunk(frames * 2) {
+                        let (first, second) = chunk.as_mut_slices();
+                        let split = first.len();
+                        first.copy_from_slice(&buffer[..split]);
+                        second.copy_from_slice(&buffer[split..frames * 2]);
+                        chunk.commit_all();
+                    }
+                }
+                let _ = self.segments.push(Segment {
+                    generation: self.generation,
+                    start,
+                    frames,
+                });
+            }
+            Ok(None) => {
+                self.ended = true;
+                let _ = self.segments.push(Segment {
+                    generation: self.generation,
+                    start: self.transport.position(),
+                    frames: 0,
+                });
+            }
+            Err(e) => self.fail(e),
+        }
+        self.controls
+            .length
+            .store(self.transport.length(), Ordering::Relaxed);
+    }
+
+    /// Stop playback after a failed seek
+    fn fail(&mut self, error: CommandError) {
+        self.ended = true;
+        self.controls.playing.store(false, Ordering::Relaxed);
+        (self.on_error)(error);
+    }
+}
+
+/// The output callback's side of a loaded song. Never blocks or allocates
+/// while rendering
+pub struct Player {
+    controls: Arc<Controls>,
+    sample_rate: u32,
+    stems: Vec<Consumer<f32>>,
+    segments: Consumer<Segment>,
+    /// Rest of the segment being played
+    current: Option<Segment>,
+    generation: u64,
+    /// Gain each stem ended the last pass at; changes are ramped from here
+    applied: Vec<f32>,
+    /// False until the first pass, which starts at the set gains
+    settled: bool,
+    /// Always present except while it is pulling from the rings
+    stretch: Option<Stretch>,
+    /// Speed and pitch ratio `stretch` is set to; (1.0, 1.0) bypasses it
+    stretch_settings: (f64, f64),
+}
+
+impl Player {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Catch up with seeks made since the last call. Returns true when the
+    /// stream jumped, so audio buffered further down should be dropped
+    pub fn sync(&mut self) -> bool {
+        let generation = self.controls.generation.load(Ordering::Acquire);
+        if generation == self.generation {
+            return false;
+        }
+        self.generation = generation;
+        if let Some(segment) = self.current.take() {
+            self.skip(segment.frames);
+        }
+        if let Some(stretch) = &mut self.stretch {
+            stretch.reset();
+        }
+        true
+    }
+
+    /// Fill `out` with interleaved stereo frames; silence while paused,
+    /// past the end or while the feeder catches up after a seek
+    pub fn render(&mut self, out: &mut [f32]) {
+        let settings = (self.controls.speed.load(), self.controls.pitch.load());
+        if settings != self.stretch_settings {
+            if let Some(stretch) = &mut self.stretch {
+                if self.stretch_settings == (1.0, 1.0) {
+                    stretch.reset();
+                }
+                stretch.set(settings.0, settings.1);
+            }
+            self.stretch_settings = settings;
+        }
+
+        let stretching = self.stretch_settings != (1.0, 1.0);
+        match self.stretch.take() {
+            Some(mut stretch) if stretching && self.controls.playing.load(Ordering::Relaxed) => {
+                stretch.process(
+                    &mut |buf: &mut [f32]| {
+                        self.render_source(buf);
+                    },
+                    out,
+                );
+                self.stretch = Some(stretch);
+            }
+            stretch => {
+                self.stretch = stretch;
+                self.render_source(out);
+            }
+        }
+    }
+
+    /// Mix the unstretched stems from the rings, returning how many frames
+    /// came from the song rather than silence
+    fn render_source(&mut self, out: &mut [f32]) -> usize {
+        out.fill(0.0);
+        let total = out.len() / 2;
+        let mut done = 0;
+
+        while done < total && self.controls.playing.load(Ordering::Relaxed) {
+            let Some(segment) = self.current.take().or_else(|| self.segments.pop().ok()) else {
+                break;
+            };
+            if segment.generation < self.generation {
+                self.skip(segment.frames);
+                continue;
+            }
+            if segment.generation > self.generation {
+                // Seeked mid-render; picked up after the next `sync`
+                self.current = Some(segment);
+                break;
+            }
+            if segment.frames == 0 {
+                self.publish(segment.start);
+                self.controls.playing.store(false, Ordering::Relaxed);
+                break;
+            }
+
+            let frames = segment.frames.min(total - done);
+            self.mix(&mut out[done * 2..(done + frames) * 2]);
+            done += frames;
+            self.publish(segment.start + frames as u64);
+            if frames < segment.frames {
+                self.current = Some(Segment {
+                    start: segment.start + frames as u64,
+                    frames: segment.frames - frames,
+                    ..segment
+                });
+            }
+        }
+        done
+    }
+
+    /// Add the next `out.len() / 2` frames of every stem into `out`
+    fn mix(&mut self, out: &mut [f32]) {
+        let frames = out.len() / 2;
+        let stems = &self.controls.stems;
+        let any_solo = stems.iter().any(|stem| stem.solo.load(Ordering::Relaxed));
+        for ((control, ring), applied) in stems.iter().zip(&mut self.stems).zip(&mut self.applied) {
+            let target = mixer::target_gain(
+                control.gain(),
+                control.muted.load(Ordering::Relaxed),
+                control.solo.load(Ordering::Relaxed),
+                any_solo,
+            );
+            let from = if self.settled { *applied } else { target };
+            *applied = target;
+
+            let Ok(chunk) = ring.read_chunk(frames * 2) else {
+                continue;
+            };
+            if from != 0.0 || target != 0.0 {
+                let step = (target - from) / frames as f32;
+                let (first, second) = chunk.as_slices();
+                let split = first.len();
+                mixer::add_ramped(&mut out[..split], first, from, step);
+                let from = from + step * (split / 2) as f32;
+                mixer::add_ramped(&mut out[split..], second, from, step);
+            }
+            chunk.commit_all();
+        }
+        self.settled = true;
+    }
+
+    /// Drop `frames` of every stem
+    fn skip(&mut self, frames: usize) {
+        for ring in &mut self.stems {
+            if let Ok(chunk) = ring.read_chunk(frames * 2) {
+                chunk.commit_all();
+            }
+        }
+    }
+
+    fn publish(&self, position: u64) {
+        self.controls.position.store(position, Ordering::Relaxed);
+        self.controls
+            .heard_generation
+            .store(self.generation, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::decoder::tests::counter_sample;
+    use super::super::mixer::tests::{Fixture, A, B};
+    use super::super::output::RateAdapter;
+    use super::*;
+    use std::time::Instant;
+
+    fn start(fixture: &Fixture) -> (Playback, Player) {
+        Playback::new(fixture.stems(), Arc::new(|e| panic!("{}", e))).unwrap()
+    }
+
+    fn wait_until(mut ready: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !ready() {
+            assert!(Instant::now() < deadline, "feeder stalled");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Wait until audio decoded after the last seek is queued, dropping
+    /// stale audio ahead of it
+    fn wait_for_current(player: &mut Player) {
+        player.sync();
+        wait_until(|| match player.segments.peek() {
+            Ok(segment) if segment.generation < player.generation => {
+                let frames = segment.frames;
+                let _ = player.segments.pop();
+                player.skip(frames);
+                false
+            }
+            Ok(_) => true,
+            Err(_) => false,
+        });
+    }
+
+    /// Render `frames` unstretched, waiting for the feeder instead of
+    /// playing silence when it falls behind
+    fn render(player: &mut Player, frames: usize) -> Vec<f32> {
+        player.sync();
+        let mut out = vec![0.0; frames * 2];
+        let mut done = 0;
+        wait_until(|| {
+            done += player.render_source(&mut out[done * 2..]);
+            done == frames || !player.controls.playing.load(Ordering::Relaxed)
+        });
+        out
+    }
+
+    #[test]
+    fn test_plays_stems_in_sync() {
+        let fixture = Fixture::new("player-sync");
+        let (mut playback, mut player) = start(&fixture);
+        assert_eq!(playback.length(), 8000);
+
+        // Paused output is silent and doesn't advance
+        let mut out = vec![1.0; 200];
+        player.render(&mut out);
+        assert!(out.iter().all(|s| *s == 0.0));
+        assert_eq!(playback.position(), 0);
+
+        playback.play().unwrap();
+        let out = render(&mut player, 3000);
+        assert_eq!(out[2 * 2500], 2.0 * counter_sample(2500));
+        assert!((out[2 * 2500 + 1] - (A + B)).abs() < 1e-6);
+        assert_eq!(playback.position(), 3000);
+
+        // Audio decoded before a seek is dropped
+        playback.seek(4321).unwrap();
+        assert_eq!(playback.position(), 4321);
+        let out = render(&mut player, 10);
+        assert_eq!(out[0], 2.0 * counter_sample(4321));
+
+        // Past the end of the shorter stem only the longer one plays
+        playback.seek(7000).unwrap();
+        let out = render(&mut player, 2000);
+        assert!((out[1] - A).abs() < 1e-6);
+        assert!(!playback.is_playing());
+        assert_eq!(playback.position(), 8000);
+        assert!(out[2 * 1000..].iter().all(|s| *s == 0.0));
+
+        // Playing again starts over
+        playback.play().unwrap();
+        assert_eq!(render(&mut player, 10)[2 * 5], 2.0 * counter_sample(5));
+    }
+
+    #[test]
+    fn test_gain_mute_and_solo() {
+        let fixture = Fixture::new("player-gain");
+        let (mut playback, mut player) = start(&fixture);
+        playback.set_muted("b", true).unwrap();
+        playback.play().unwrap();
+
+        // Settings made before playing apply from the first frame
+        assert!((render(&mut player, 10)[1] - A).abs() < 1e-6);
+
+        playback.set_muted("b", false).unwrap();
+        playback.set_gain("a", 0.5).unwrap();
+        playback.set_solo("a", true).unwrap();
+        render(&mut player, 1024); // ramp
+        assert!((render(&mut player, 10)[1] - 0.5 * A).abs() < 1e-6);
+
+        playback.set_solo("a", false).unwrap();
+        render(&mut player, 1024);
+        assert!((render(&mut player, 10)[1] - (0.5 * A + B)).abs() < 1e-6);
+
+        let stems = playback.stems();
+        assert_eq!((stems[0].name.as_str(), stems[0].gain), ("a", 0.5));
+        assert!(playback.set_gain("missing", 1.0).is_err());
+        assert!(playback.set_gain("a", f32::NAN).is_err());
+    }
+
+    #[test]
+    fn test_loops_between_points() {
+        let fixture = Fixture::new("player-loop");
+        let (mut playback, mut player) = start(&fixture);
+        playback.set_loop(Some((1000, 1500))).unwrap();
+        playback.seek(1400).unwrap();
+        playback.play().unwrap();
+
+        let out = render(&mut player, 300);
+        assert_eq!(out[2 * 99], 2.0 * counter_sample(1499));
+        assert_eq!(out[2 * 100], 2.0 * counter_sample(1000));
+        assert_eq!(playback.position(), 1200);
+
+        // Clearing the loop drops the wrapped audio already decoded
+        playback.set_loop(None).unwrap();
+        let out = render(&mut player, 400);
+        assert_eq!(out[2 * 399], 2.0 * counter_sample(1599));
+        assert!(playback.set_loop(Some((10, 10))).is_err());
+    }
+
+    #[test]
+    fn test_stretch_changes_rate_of_progress() {
+        let fixture = Fixture::new("player-stretch");
+        let (mut playback, mut player) = start(&fixture);
+        playback.set_stretch(0.5, 0.0).unwrap();
+        playback.play().unwrap();
+        // The whole song fits in the rings
+        wait_until(|| player.stems[0].slots() == 8000 * 2);
+
+        // Half a second of output reads about a quarter second of the files,
+        // plus the stretcher's lookahead
+        let mut out = vec![0.0; 4000 * 2];
+        player.render(&mut out);
+        let position = playback.position();
+        assert!((2000..3600).contains(&position), "{}", position);
+
+        // Back at 1.0x the mix is passed through untouched
+        playback.set_stretch(1.0, 0.0).unwrap();
+        playback.seek(4321).unwrap();
+        assert_eq!(render(&mut player, 10)[0], 2.0 * counter_sample(4321));
+
+        assert!(playback.set_stretch(3.0, 0.0).is_err());
+        assert_eq!(playback.speed(), 1.0);
+    }
+
+    #[test]
+    fn test_seek_clears_resampler() {
+        let fixture = Fixture::new("player-resample");
+        let (mut playback, mut player) = start(&fixture);
+        let mut adapter = RateAdapter::new(16_000);
+        playback.play().unwrap();
+        wait_for_current(&mut player);
+        let mut out = vec![0.0; 2 * 100];
+        adapter.render(&mut player, &mut out);
+
+        playback.seek(4000).unwrap();
+        assert!(player.sync());
+        adapter.reset();
+        wait_for_current(&mut player);
+        adapter.render(&mut player, &mut out);
+        // Source frames land on every other device frame after the ramp in
+        // from silence, starting at the seek target
+        assert_eq!(out[2 * 2], 2.0 * counter_sample(4000));
+        assert_eq!(out[2 * 4], 2.0 * counter_sample(4001));
+    }
+}
@@ -0,0 +1,307 @@
+//! Probing stem files and checking them against analysis.json.
+//!
+//! The pipeline records what it rendered (`originalDuration`, `sampleRate`,
+//! per-stem `peakDb` and one file per speed) but nothing verifies that the
+//! files on disk still match. [`probe`] reads a file's container info and
+//! decodes it once for peak and RMS levels; [`check_stems`] compares every
+//! stem against the analysis, including whether each speed's file is as
+//! long as that speed implies.
+
+use super::decoder::StreamingDecoder;
+use super::opus;
+use crate::error::CommandResult;
+use crate::validation::{Severity, ValidationIssue};
+use crate::{read_analysis, sandbox, SongAnalysis};
+use serde::Serialize;
+use std::path::Path;
+
+/// Level reported for digital silence instead of -inf
+const SILENCE_DB: f64 = -120.0;
+
+/// Allowed difference between a file's duration and the one its speed
+/// implies: a fixed slack for encoder padding plus a fraction of the length
+const DURATION_SLACK: f64 = 0.25;
+const DURATION_TOLERANCE: f64 = 0.01;
+
+/// Allowed difference between measured and recorded peak level
+const PEAK_TOLERANCE_DB: f64 = 1.0;
+
+/// Frames decoded at a time while measuring levels
+const MEASURE_FRAMES: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioInfo {
+    /// Codec short name, e.g. `flac`, `pcm_s16le`, `mp3`, `opus`
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: Option<usize>,
+    /// Decoded length in seconds
+    pub duration: f64,
+    /// dBFS of the loudest sample
+    pub peak_db: f64,
+    pub rms_db: f64,
+}
+
+/// One stem file as probed by `probe_stems`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StemProbe {
+    pub stem: String,
+    pub speed: String,
+    pub path: String,
+    /// Missing when the file couldn't be opened; see the issues
+    pub info: Option<AudioInfo>,
+}
+
+/// Result of `probe_stems`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeReport {
+    pub stems: Vec<StemProbe>,
+    pub issues: Vec<ValidationIssue>,
+}
+
+/// dBFS of a linear amplitude, floored at -120
+pub fn to_db(amplitude: f64) -> f64 {
+    if amplitude > 0.0 {
+        (20.0 * amplitude.log10()).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
+
+/// Speed of a `paths` key such as `"0.75x"`
+pub fn parse_speed(label: &str) -> Option<f64> {
+    let speed: f64 = label.strip_suffix('x')?.parse().ok()?;
+    (speed.is_finite() && speed > 0.0).then_some(speed)
+}
+
+/// Read format details and levels of the audio file at `path`
+pub fn probe(path: &Path) -> CommandResult<AudioInfo> {
+    let mut decoder = StreamingDecoder::open(path)?;
+    let params = decoder.codec_params();
+    let codec = opus::codecs()
+        .get_codec(params.codec)
+        .map_or("unknown", |descriptor| descriptor.short_name)
+        .to_string();
+    let channels = params.channels.map(|channels| channels.count());
+    let sample_rate = decoder.sample_rate();
+
+    let mut buf = vec![0.0f32; MEASURE_FRAMES * 2];
+    let mut frames = 0u64;
+    let mut peak = 0.0f64;
+    let mut sum_squares = 0.0f64;
+    loop {
+        let read = decoder.read(&mut buf);
+        for &sample in &buf[..read * 2] {
+            let sample = sample as f64;
+            peak = peak.max(sample.abs());
+            sum_squares += sample * sample;
+        }
+        frames += read as u64;
+        if read < MEASURE_FRAMES {
+            break;
+        }
+    }
+
+    Ok(AudioInfo {
+        codec,
+        sample_rate,
+        channels,
+        duration: frames as f64 / sample_rate as f64,
+        peak_db: to_db(peak),
+        rms_db: if frames > 0 {
+            to_db((sum_squares / (frames * 2) as f64).sqrt())
+        } else {
+            SILENCE_DB
+        },
+    })
+}
+
+/// Probe every stem file of `analysis` and report where they disagree with
+/// it
+pub fn check_stems(analysis: &SongAnalysis, song_dir: &Path) -> ProbeReport {
+    let mut stems = Vec::new();
+    let mut issues = Vec::new();
+    let mut report = |severity, path: String, message: String| {
+        issues.push(ValidationIssue {
+            severity,
+            path,
+            message,
+            fix: None,
+        });
+    };
+
+    let mut names: Vec<&String> = analysis.stems.keys().collect();
+    names.sort();
+    for name in names {
+        let stem = &analysis.stems[name];
+        let mut speeds: Vec<&String> = stem.paths.keys().collect();
+        speeds.sort();
+        for label in speeds {
+            let relative = &stem.paths[label];
+            let json_path = format!("$.stems.{}.paths[\"{}\"]", name, label);
+            let info = match sandbox::resolve_in_dir(song_dir, relative).and_then(|p| probe(&p)) {
+                Ok(info) => info,
+                Err(e) => {
+                    report(Severity::Error, json_path, format!("cannot probe: {}", e));
+                    stems.push(StemProbe {
+                        stem: name.clone(),
+                        speed: label.clone(),
+                        path: relative.clone(),
+                        info: None,
+                    });
+                    continue;
+                }
+            };
+
+            if analysis.sample_rate > 0 && info.sample_rate != analysis.sample_rate as u32 {
+                report(
+                    Severity::Warning,
+                    json_path.clone(),
+                    format!(
+                        "file is {} Hz but sampleRate is {}",
+                        info.sample_rate, analysis.sample_rate
+                    ),
+                );
+            }
+
+            let speed = parse_speed(label);
+            match speed {
+                None => report(
+                    Severity::Warning,
+                    json_path.clone(),
+                    format!("\"{}\" is not a speed like \"0.75x\"", label),
+                ),
+                Some(speed) if analysis.original_duration > 0.0 => {
+                    let duration = info.duration;
+                    let expected = analysis.original_duration / speed;
+                    if (duration - expected).abs() > DURATION_SLACK + expected * DURATION_TOLERANCE
+                    {
+                        report(
+                            Severity::Error,
+                            json_path.clone(),
+                            format!(
+                                "file lasts {:.2}s but a {} render should last {:.2}s \
+                                 (it plays like {:.2}x)",
+                                duration,
+                                label,
+                                expected,
+                                analysis.original_duration / duration
+                            ),
+                        );
+                    }
+                }
+                _ => {}
+            }
+
+            if speed == Some(1.0) && (info.peak_db - stem.peak_db).abs() > PEAK_TOLERANCE_DB {
+                report(
+                    Severity::Warning,
+                    format!("$.stems.{}.peakDb", name),
+                    format!(
+                        "peakDb is {:.1} but the {} file peaks at {:.1} dB",
+                        stem.peak_db, label, info.peak_db
+                    ),
+                );
+            }
+
+            stems.push(StemProbe {
+                stem: name.clone(),
+                speed: label.clone(),
+                path: relative.clone(),
+                info: Some(info),
+            });
+        }
+    }
+
+    ProbeReport { stems, issues }
+}
+
+/// Decode every stem of a song to measure its format and levels and check
+/// them against analysis.json
+#[tauri::command]
+pub fn probe_stems(song_dir: &str) -> CommandResult<ProbeReport> {
+    let song_dir = Path::new(song_dir);
+    let analysis = read_analysis(&song_dir.join("analysis.json"))?;
+    Ok(check_stems(&analysis, song_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::decoder::tests::write_test_wav;
+    use super::super::opus::tests::write_test_opus;
+    use super::*;
+    use crate::test_analysis;
+
+    #[test]
+    fn test_probes_and_flags_mismatches() {
+        let dir = std::env::temp_dir().join(format!("music-tutor-probe-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Two seconds at 1.0x; the "0.5x" render is only as long as 0.8x
+        write_test_wav(&dir.join("bass.wav"), 8000, 16_000, 16384);
+        write_test_wav(&dir.join("bass_0.5x.wav"), 8000, 20_000, 16384);
+
+        let info = probe(&dir.join("bass.wav")).unwrap();
+        assert_eq!(info.codec, "pcm_s16le");
+        assert_eq!(info.sample_rate, 8000);
+        assert_eq!(info.channels, Some(2));
+        assert_eq!(info.duration, 2.0);
+        // The right channel sits at half scale
+        assert!((info.peak_db - -6.02).abs() < 0.01);
+
+        let mut analysis = test_analysis();
+        analysis.original_duration = 2.0;
+        analysis.stems = serde_json::from_value(serde_json::json!({"bass": {
+            "name": "bass",
+            "paths": {"1.0x": "bass.wav", "0.5x": "bass_0.5x.wav", "fast": "bass.wav"},
+            "hasNotes": false,
+            "peakDb": 0.0
+        }}))
+        .unwrap();
+        analysis.notes = Some(Default::default());
+        analysis.drum_strikes = Some(Default::default());
+        let report = check_stems(&analysis, &dir);
+        assert_eq!(report.stems.len(), 3);
+
+        let messages: Vec<(&str, &str)> = report
+            .issues
+            .iter()
+            .map(|issue| (issue.path.as_str(), issue.message.as_str()))
+            .collect();
+        assert!(messages
+            .iter()
+            .any(|(path, m)| *path == "$.stems.bass.paths[\"0.5x\"]" && m.contains("0.80x")));
+        assert!(messages
+            .iter()
+            .any(|(path, _)| *path == "$.stems.bass.peakDb"));
+        assert!(messages
+            .iter()
+            .any(|(path, m)| *path == "$.stems.bass.paths[\"fast\"]" && m.contains("not a speed")));
+        assert!(messages.iter().any(|(_, m)| m.contains("8000 Hz")));
+        assert!(!messages
+            .iter()
+            .any(|(path, m)| *path == "$.stems.bass.paths[\"1.0x\"]" && m.contains("lasts")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_measures_opus_levels() {
+        let path =
+            std::env::temp_dir().join(format!("music-tutor-probe-{}.opus", std::process::id()));
+        write_test_opus(&path, 48_000, 0.5);
+
+        let info = probe(&path).unwrap();
+        assert_eq!(info.codec, "opus");
+        assert_eq!(info.sample_rate, 48_000);
+        assert_eq!(info.channels, Some(2));
+        assert_eq!(info.duration, 1.0);
+        // A half-scale sine on one channel: -6 dB peak, -12 dB RMS over both
+        assert!((info.peak_db - -6.02).abs() < 0.5, "{}", info.peak_db);
+        assert!((info.rms_db - -12.04).abs() < 0.5, "{}", info.rms_db);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
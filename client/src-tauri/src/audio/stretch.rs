@@ -0,0 +1,331 @@
+//! Real-time time-stretch and pitch shift for the mixed stereo stream.
+//!
+//! Tempo is changed with WSOLA: overlapping Hann-windowed frames are read
+//! from the source at one hop size and written at another, each frame
+//! nudged within a small search window to the offset whose waveform best
+//! continues the previous frame, which keeps pitch intact without the
+//! phasiness of a phase vocoder. Pitch shift stretches the tempo by the
+//! pitch ratio and then resamples by the same ratio, so the two can be set
+//! independently.
+
+use crate::error::{CommandError, CommandResult};
+
+pub const MIN_SPEED: f64 = 0.25;
+pub const MAX_SPEED: f64 = 2.0;
+/// Transposition range in semitones either way
+pub const MAX_SEMITONES: f64 = 24.0;
+
+/// Frames pulled from the source at a time
+const SOURCE_BLOCK: usize = 1024;
+
+/// Source of interleaved stereo frames; fills the whole buffer
+pub type Source<'a> = dyn FnMut(&mut [f32]) + 'a;
+
+/// Check speed and transposition, returning the pitch ratio
+pub fn validate(speed: f64, semitones: f64) -> CommandResult<f64> {
+    if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        return Err(CommandError::invalid(format!(
+            "Speed must be between {}x and {}x",
+            MIN_SPEED, MAX_SPEED
+        )));
+    }
+    if !semitones.is_finite() || semitones.abs() > MAX_SEMITONES {
+        return Err(CommandError::invalid(format!(
+            "Pitch shift must be within ±{} semitones",
+            MAX_SEMITONES
+        )));
+    }
+    Ok(2f64.powf(semitones / 12.0))
+}
+
+/// Tempo-only stretcher: consumes source frames at `rate` per output frame
+struct Wsola {
+    rate: f64,
+    frame_len: usize,
+    hop: usize,
+    search: usize,
+    window: Vec<f32>,
+    /// Buffered source frames (interleaved stereo); `input[0]` is source
+    /// frame `input_start`
+    input: Vec<f32>,
+    input_start: usize,
+    /// Where the next frame would be read with no adjustment
+    analysis_pos: f64,
+    /// Start of the previously chosen frame
+    prev_pos: Option<usize>,
+    /// Windowed second half of the previous frame, waiting to be overlapped
+    overlap: Vec<f32>,
+    output: Vec<f32>,
+    output_pos: usize,
+}
+
+impl Wsola {
+    fn new(sample_rate: u32) -> Self {
+        // ~46 ms frames at 44.1 kHz; long enough to hold a bass period
+        let frame_len = ((sample_rate as usize * 46 / 1000) & !1).max(64);
+        let hop = frame_len / 2;
+        let window = (0..frame_len)
+            .map(|i| {
+                let phase = std::f32::consts::TAU * i as f32 / frame_len as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        Self {
+            rate: 1.0,
+            frame_len,
+            hop,
+            search: frame_len / 4,
+            window,
+            input: Vec::new(),
+            input_start: 0,
+            analysis_pos: 0.0,
+            prev_pos: None,
+            overlap: vec![0.0; hop * 2],
+            output: Vec::new(),
+            output_pos: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.input.clear();
+        self.input_start = 0;
+        self.analysis_pos = 0.0;
+        self.prev_pos = None;
+        self.overlap.fill(0.0);
+        self.output.clear();
+        self.output_pos = 0;
+    }
+
+    fn input_end(&self) -> usize {
+        self.input_start + self.input.len() / 2
+    }
+
+    /// Make sure source frames up to `end` are buffered
+    fn fill_to(&mut self, end: usize, source: &mut Source) {
+        while self.input_end() < end {
+            let len = self.input.len();
+            self.input.resize(len + SOURCE_BLOCK * 2, 0.0);
+            source(&mut self.input[len..]);
+        }
+    }
+
+    /// Frame `frame` of the buffered input
+    fn at(&self, frame: usize) -> (f32, f32) {
+        let i = (frame - self.input_start) * 2;
+        (self.input[i], self.input[i + 1])
+    }
+
+    fn next(&mut self, source: &mut Source) -> [f32; 2] {
+        if self.output_pos >= self.output.len() {
+            self.synthesize_hop(source);
+        }
+        let frame = [
+            self.output[self.output_pos],
+            self.output[self.output_pos + 1],
+        ];
+        self.output_pos += 2;
+        frame
+    }
+
+    /// Choose the next frame, overlap-add it and queue one hop of output
+    fn synthesize_hop(&mut self, source: &mut Source) {
+        let nominal = self.analysis_pos.round() as usize;
+        let lowest = nominal.saturating_sub(self.search).max(self.input_start);
+        self.fill_to(nominal + self.search + self.frame_len, source);
+
+        let pos = match self.prev_pos {
+            None => nominal,
+            Some(prev) => {
+                // Compare candidates against the natural continuation of the
+                // previous frame; every 4th sample of the mono sum is enough
+                let natural = prev + self.hop;
+                let mut best = (f32::MIN, nominal);
+                for candidate in lowest..=nominal + self.search {
+                    let mut score = 0.0;
+                    for i in (0..self.hop).step_by(4) {
+                        let (a, b) = self.at(candidate + i);
+                        let (c, d) = self.at(natural + i);
+                        score += (a + b) * (c + d);
+                    }
+                    if score > best.0 {
+                        best = (score, candidate);
+                    }
+                }
+                best.1
+            }
+        };
+
+        self.output.clear();
+        self.output_pos = 0;
+        for i in 0..self.hop {
+            let (left, right) = self.at(pos + i);
+            let w = self.window[i];
+            self.output.push(self.overlap[i * 2] + left * w);
+            self.output.push(self.overlap[i * 2 + 1] + right * w);
+        }
+        for i in 0..self.hop {
+            let (left, right) = self.at(pos + self.hop + i);
+            let w = self.window[self.hop + i];
+            self.overlap[i * 2] = left * w;
+            self.overlap[i * 2 + 1] = right * w;
+        }
+
+        self.prev_pos = Some(pos);
+        self.analysis_pos += self.hop as f64 * self.rate;
+
+        // Drop input no future frame can reach
+        let keep_from = (pos + self.hop)
+            .min((self.analysis_pos as usize).saturating_sub(self.search))
+            .max(self.input_start);
+        let drop = keep_from - self.input_start;
+        self.input.drain(..drop * 2);
+        self.input_start = keep_from;
+    }
+}
+
+/// Time-stretch plus pitch shift
+pub struct Stretch {
+    pitch: f64,
+    wsola: Wsola,
+    /// Four consecutive stretched frames around the resampling position,
+    /// for cubic interpolation
+    history: [[f32; 2]; 4],
+    frac: f64,
+    primed: bool,
+}
+
+impl Stretch {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            pitch: 1.0,
+            wsola: Wsola::new(sample_rate),
+            history: [[0.0; 2]; 4],
+            frac: 0.0,
+            primed: false,
+        }
+    }
+
+    /// Change speed (0.25–2.0) and pitch ratio without interrupting the
+    /// stream
+    pub fn set(&mut self, speed: f64, pitch: f64) {
+        self.pitch = pitch;
+        // Stretch by pitch/speed, then resampling by `pitch` brings the
+        // duration back to 1/speed and raises the pitch by `pitch`
+        self.wsola.rate = speed / pitch;
+    }
+
+    /// Forget buffered audio, e.g. after a seek
+    pub fn reset(&mut self) {
+        self.wsola.reset();
+        self.history = [[0.0; 2]; 4];
+        self.frac = 0.0;
+        self.primed = false;
+    }
+
+    /// Fill `out` with processed interleaved stereo frames
+    pub fn process(&mut self, source: &mut Source, out: &mut [f32]) {
+        if !self.primed {
+            for _ in 0..3 {
+                self.advance(source);
+            }
+            self.primed = true;
+        }
+
+        for frame in out.chunks_exact_mut(2) {
+            while self.frac >= 1.0 {
+                self.advance(source);
+                self.frac -= 1.0;
+            }
+            let t = self.frac as f32;
+            let [p0, p1, p2, p3] = self.history;
+            for channel in 0..2 {
+                frame[channel] = catmull_rom(p0[channel], p1[channel], p2[channel], p3[channel], t);
+            }
+            self.frac += self.pitch;
+        }
+    }
+
+    fn advance(&mut self, source: &mut Source) {
+        self.history.rotate_left(1);
+        self.history[3] = self.wsola.next(source);
+    }
+}
+
+/// Cubic interpolation between `p1` and `p2`
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let a = -0.5 * p0 + 1.5 * p1 - 1.5 * p2 + 0.5 * p3;
+    let b = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+    let c = -0.5 * p0 + 0.5 * p2;
+    ((a * t + b) * t + c) * t + p1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 8000;
+
+    /// Render `frames` of a 200 Hz sine through `stretch`, returning the
+    /// output and how many source frames were consumed
+    fn run(stretch: &mut Stretch, frames: usize) -> (Vec<f32>, usize) {
+        let mut consumed = 0;
+        let mut source = |buf: &mut [f32]| {
+            for frame in buf.chunks_exact_mut(2) {
+                let t = consumed as f32 / RATE as f32;
+                let sample = (std::f32::consts::TAU * 200.0 * t).sin() * 0.5;
+                frame[0] = sample;
+                frame[1] = sample;
+                consumed += 1;
+            }
+        };
+        let mut out = vec![0.0; frames * 2];
+        stretch.process(&mut source, &mut out);
+        (out, consumed)
+    }
+
+    /// Frequency estimated from upward zero crossings of the left channel,
+    /// skipping the fade-in
+    fn frequency(out: &[f32]) -> f64 {
+        let left: Vec<f32> = out.chunks_exact(2).skip(1000).map(|f| f[0]).collect();
+        let crossings = left
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        crossings as f64 * RATE as f64 / left.len() as f64
+    }
+
+    #[test]
+    fn test_slows_down_without_changing_pitch() {
+        let mut stretch = Stretch::new(RATE);
+        stretch.set(0.5, validate(0.5, 0.0).unwrap());
+        let (out, consumed) = run(&mut stretch, 16_000);
+
+        assert!((frequency(&out) - 200.0).abs() < 5.0, "{}", frequency(&out));
+        // Half as much source for the same output, plus lookahead read in
+        // whole blocks
+        assert!((8000..10_000).contains(&consumed), "consumed {}", consumed);
+    }
+
+    #[test]
+    fn test_pitch_shift_keeps_tempo() {
+        let mut stretch = Stretch::new(RATE);
+        stretch.set(1.0, validate(1.0, 12.0).unwrap());
+        let (out, consumed) = run(&mut stretch, 16_000);
+
+        assert!((frequency(&out) - 400.0).abs() < 10.0);
+        assert!(
+            (16_000..18_000).contains(&consumed),
+            "consumed {}",
+            consumed
+        );
+    }
+
+    #[test]
+    fn test_rejects_out_of_range_settings() {
+        assert!(validate(0.1, 0.0).is_err());
+        assert!(validate(2.5, 0.0).is_err());
+        assert!(validate(1.0, 24.5).is_err());
+        assert!(validate(1.0, f64::NAN).is_err());
+        assert!((validate(1.0, -12.0).unwrap() - 0.5).abs() < 1e-12);
+    }
+}
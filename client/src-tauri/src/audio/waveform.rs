@@ -0,0 +1,381 @@
+//! Waveform peaks for drawing stems without decoding them in the webview.
+//!
+//! A stem is decoded once into min/max/RMS buckets of the mono mix at
+//! several resolutions, each level twice as coarse as the one before, and
+//! cached in a binary sidecar next to analysis.json
+//! (`waveform.<stem>.<speed>.peaks`, both percent-encoded). The sidecar
+//! records the source file's size and modification time, so a re-rendered
+//! stem is picked up on the next request.
+
+use super::decoder::StreamingDecoder;
+use crate::error::{CommandError, CommandResult};
+use crate::{read_analysis, sandbox};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const MAGIC: &[u8; 4] = b"MTPK";
+/// Bump when the sidecar layout or the bucketing changes
+const FORMAT_VERSION: u32 = 1;
+
+/// Frames per bucket at the finest level
+const BASE_BUCKET: u32 = 256;
+/// Coarser levels are added until one has no more buckets than this
+const MIN_BUCKETS: usize = 1024;
+
+/// Frames decoded at a time
+const READ_FRAMES: usize = 4096;
+
+/// Peaks at one resolution, as returned to the frontend
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WaveformPeaks {
+    pub sample_rate: u32,
+    /// Length of the stem in seconds
+    pub duration: f64,
+    /// Seconds covered by each bucket (the last one may be shorter)
+    pub bucket_duration: f64,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+    pub rms: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Level {
+    frames_per_bucket: u32,
+    min: Vec<f32>,
+    max: Vec<f32>,
+    rms: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Peaks {
+    sample_rate: u32,
+    frames: u64,
+    /// Finest first
+    levels: Vec<Level>,
+}
+
+impl Peaks {
+    /// The coarsest level with at least `resolution` buckets per second,
+    /// or the finest level when none is that detailed
+    fn at_resolution(&self, resolution: f64) -> WaveformPeaks {
+        let level = self
+            .levels
+            .iter()
+            .rev()
+            .find(|level| self.sample_rate as f64 / level.frames_per_bucket as f64 >= resolution)
+            .unwrap_or(&self.levels[0]);
+        WaveformPeaks {
+            sample_rate: self.sample_rate,
+            duration: self.frames as f64 / self.sample_rate as f64,
+            bucket_duration: level.frames_per_bucket as f64 / self.sample_rate as f64,
+            min: level.min.clone(),
+            max: level.max.clone(),
+            rms: level.rms.clone(),
+        }
+    }
+}
+
+/// Decode `path` and bucket it at every level
+fn compute(path: &Path) -> CommandResult<Peaks> {
+    let mut decoder = StreamingDecoder::open(path)?;
+    let sample_rate = decoder.sample_rate();
+
+    let mut min = Vec::new();
+    let mut max = Vec::new();
+    let mut sum_squares = Vec::new();
+    let mut counts = Vec::new();
+    let mut in_bucket = BASE_BUCKET;
+    let mut frames = 0u64;
+    let mut buf = vec![0.0f32; READ_FRAMES * 2];
+    loop {
+        let read = decoder.read(&mut buf);
+        for frame in buf[..read * 2].chunks_exact(2) {
+            if in_bucket == BASE_BUCKET {
+                min.push(f32::MAX);
+                max.push(f32::MIN);
+                sum_squares.push(0.0f64);
+                counts.push(0u32);
+                in_bucket = 0;
+            }
+            let sample = (frame[0] + frame[1]) * 0.5;
+            let i = min.len() - 1;
+            min[i] = min[i].min(sample);
+            max[i] = max[i].max(sample);
+            sum_squares[i] += sample as f64 * sample as f64;
+            counts[i] += 1;
+            in_bucket += 1;
+        }
+        frames += read as u64;
+        if read < READ_FRAMES {
+            break;
+        }
+    }
+
+    let mut levels = Vec::new();
+    let mut frames_per_bucket = BASE_BUCKET;
+    loop {
+        let rms = sum_squares
+            .iter()
+            .zip(&counts)
+            .map(|(sum, count)| (sum / *count as f64).sqrt() as f32)
+            .collect();
+        levels.push(Level {
+            frames_per_bucket,
+            min: min.clone(),
+            max: max.clone(),
+            rms,
+        });
+        if min.len() <= MIN_BUCKETS {
+            break;
+        }
+
+        // Merge neighbouring buckets into the next level
+        min = min
+            .chunks(2)
+            .map(|pair| pair.iter().copied().fold(f32::MAX, f32::min))
+            .collect();
+        max = max
+            .chunks(2)
+            .map(|pair| pair.iter().copied().fold(f32::MIN, f32::max))
+            .collect();
+        sum_squares = sum_squares
+            .chunks(2)
+            .map(|pair| pair.iter().sum())
+            .collect();
+        counts = counts.chunks(2).map(|pair| pair.iter().sum()).collect();
+        frames_per_bucket *= 2;
+    }
+
+    Ok(Peaks {
+        sample_rate,
+        frames,
+        levels,
+    })
+}
+
+/// Size and modification time of the source, stored with the peaks so a
+/// changed file invalidates them
+fn source_stamp(path: &Path) -> CommandResult<(u64, u64)> {
+    let metadata = fs::metadata(path).map_err(|e| CommandError::io(path, e))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_nanos() as u64)
+        .unwrap_or(0);
+    Ok((metadata.len(), modified))
+}
+
+fn encode(peaks: &Peaks, stamp: (u64, u64)) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&stamp.0.to_le_bytes());
+    bytes.extend_from_slice(&stamp.1.to_le_bytes());
+    bytes.extend_from_slice(&peaks.sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&peaks.frames.to_le_bytes());
+    bytes.extend_from_slice(&(peaks.levels.len() as u32).to_le_bytes());
+    for level in &peaks.levels {
+        bytes.extend_from_slice(&level.frames_per_bucket.to_le_bytes());
+        bytes.extend_from_slice(&(level.min.len() as u32).to_le_bytes());
+        for values in [&level.min, &level.max, &level.rms] {
+            for value in values.iter() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+    bytes
+}
+
+/// Little-endian reader over a sidecar; every read fails past the end
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, rest) = self.bytes.split_first_chunk::<N>()?;
+        self.bytes = rest;
+        Some(*head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    fn f32s(&mut self, count: usize) -> Option<Vec<f32>> {
+        (0..count)
+            .map(|_| self.take().map(f32::from_le_bytes))
+            .collect()
+    }
+}
+
+/// Parse a sidecar, or `None` if it is stale, from another format version
+/// or damaged
+fn decode(bytes: &[u8], stamp: (u64, u64)) -> Option<Peaks> {
+    let mut reader = Reader { bytes };
+    if &reader.take::<4>()? != MAGIC
+        || reader.u32()? != FORMAT_VERSION
+        || (reader.u64()?, reader.u64()?) != stamp
+    {
+        return None;
+    }
+    let sample_rate = reader.u32()?;
+    let frames = reader.u64()?;
+    let level_count = reader.u32()?;
+    let mut levels = Vec::new();
+    for _ in 0..level_count {
+        let frames_per_bucket = reader.u32()?;
+        let count = reader.u32()? as usize;
+        levels.push(Level {
+            frames_per_bucket,
+            min: reader.f32s(count)?,
+            max: reader.f32s(count)?,
+            rms: reader.f32s(count)?,
+        });
+    }
+    if levels.is_empty() || sample_rate == 0 || !reader.bytes.is_empty() {
+        return None;
+    }
+    Some(Peaks {
+        sample_rate,
+        frames,
+        levels,
+    })
+}
+
+/// Sidecar for one stem at one speed. Both are percent-encoded, dots
+/// included, so every (stem, speed) pair gets its own safe file name
+fn sidecar_path(song_dir: &Path, stem: &str, speed: &str) -> PathBuf {
+    let encode = |name: &str| -> String {
+        name.bytes()
+            .map(|b| match b {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => (b as char).to_string(),
+                _ => format!("%{:02X}", b),
+            })
+            .collect()
+    };
+    song_dir.join(format!("waveform.{}.{}.peaks", encode(stem), encode(speed)))
+}
+
+/// Peaks for `source` from its sidecar, computing and caching them first if
+/// the sidecar is missing or stale
+fn load_or_compute(source: &Path, sidecar: &Path) -> CommandResult<Peaks> {
+    let stamp = source_stamp(source)?;
+    if let Some(peaks) = fs::read(sidecar)
+        .ok()
+        .and_then(|bytes| decode(&bytes, stamp))
+    {
+        return Ok(peaks);
+    }
+
+    let peaks = compute(source)?;
+    let tmp = sidecar.with_extension("peaks.tmp");
+    fs::write(&tmp, encode(&peaks, stamp)).map_err(|e| CommandError::io(&tmp, e))?;
+    fs::rename(&tmp, sidecar).map_err(|e| CommandError::io(sidecar, e))?;
+    Ok(peaks)
+}
+
+/// Min/max/RMS peaks of a stem at `speed` (`"1.0x"` by default), at no
+/// fewer than `resolution` buckets per second where the cache has that much
+/// detail. The first request for a stem decodes it; later ones read the
+/// sidecar.
+#[tauri::command]
+pub fn get_waveform_peaks(
+    song_dir: &str,
+    stem: &str,
+    speed: Option<String>,
+    resolution: f64,
+) -> CommandResult<WaveformPeaks> {
+    if !(resolution.is_finite() && resolution > 0.0) {
+        return Err(CommandError::invalid(format!(
+            "Invalid resolution: {}",
+            resolution
+        )));
+    }
+    let song_dir = Path::new(song_dir);
+    let analysis = read_analysis(&song_dir.join("analysis.json"))?;
+    let speed = speed.unwrap_or_else(|| "1.0x".to_string());
+    let relative = analysis
+        .stems
+        .get(stem)
+        .and_then(|info| info.paths.get(&speed))
+        .ok_or_else(|| {
+            CommandError::invalid(format!("Stem {} has no {} rendering", stem, speed))
+        })?;
+    let source = sandbox::stem_path(song_dir, &analysis, relative)?;
+
+    let peaks = load_or_compute(&source, &sidecar_path(song_dir, stem, &speed))?;
+    Ok(peaks.at_resolution(resolution))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::decoder::tests::{counter_sample, write_test_wav};
+    use super::*;
+
+    #[test]
+    fn test_builds_levels_and_caches_them() {
+        let dir = std::env::temp_dir().join(format!("music-tutor-waveform-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("bass.wav");
+        let sidecar = sidecar_path(&dir, "bass/left", "1.0x");
+        assert_eq!(
+            sidecar.file_name().unwrap(),
+            "waveform.bass%2Fleft.1%2E0x.peaks"
+        );
+        // Names that only differ in characters that need escaping, or in
+        // where the stem ends and the speed begins, don't share a sidecar
+        assert_ne!(sidecar, sidecar_path(&dir, "bass_left", "1.0x"));
+        assert_ne!(
+            sidecar_path(&dir, "a.b", "c"),
+            sidecar_path(&dir, "a", "b.c")
+        );
+        write_test_wav(&source, 8000, 300_000, 0);
+
+        let peaks = load_or_compute(&source, &sidecar).unwrap();
+        assert_eq!(peaks.frames, 300_000);
+        let sizes: Vec<(u32, usize)> = peaks
+            .levels
+            .iter()
+            .map(|level| (level.frames_per_bucket, level.min.len()))
+            .collect();
+        assert_eq!(sizes, vec![(256, 1172), (512, 586)]);
+
+        // Left counts 0..999 against a silent right channel; the first
+        // bucket covers frames 0..256
+        let base = &peaks.levels[0];
+        assert_eq!(base.min[0], 0.0);
+        assert_eq!(base.max[0], counter_sample(255) * 0.5);
+        assert_eq!(peaks.levels[1].max[0], base.max[0].max(base.max[1]));
+
+        // Served from the sidecar while the source is unchanged
+        assert_eq!(
+            decode(&fs::read(&sidecar).unwrap(), source_stamp(&source).unwrap()),
+            Some(peaks.clone())
+        );
+        assert_eq!(load_or_compute(&source, &sidecar).unwrap(), peaks);
+
+        // A re-rendered stem invalidates it
+        write_test_wav(&source, 8000, 1000, 0);
+        let peaks = load_or_compute(&source, &sidecar).unwrap();
+        assert_eq!(peaks.frames, 1000);
+        assert_eq!(peaks.levels.len(), 1);
+
+        // Requests get the coarsest level with enough detail
+        let fine = load_or_compute(&source, &sidecar)
+            .unwrap()
+            .at_resolution(10.0);
+        assert_eq!(fine.bucket_duration, 256.0 / 8000.0);
+        assert_eq!(fine.min.len(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+//! Error type returned by every Tauri command.
+//!
+//! Errors serialize as `{ "kind": "...", ...fields }` so the frontend can
+//! tell failures apart and offer a matching recovery action.
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, thiserror::Error)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum CommandError {
+    /// A file or directory that doesn't exist
+    #[error("Not found: {path}")]
+    NotFound { path: String },
+
+    /// Malformed JSON
+    #[error("Failed to parse {path} at line {line}, column {column}: {message}")]
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    /// Valid JSON that doesn't match the analysis schema this app understands
+    #[error("{path} was written by an incompatible converter ({}): {message}", .converter_version.as_deref().unwrap_or("unknown version"))]
+    SchemaVersion {
+        path: String,
+        converter_version: Option<String>,
+        message: String,
+    },
+
+    /// An external program needed by the command isn't installed
+    #[error("{tool} is not installed or not on PATH")]
+    ToolMissing { tool: String },
+
+    /// The music-tutor pipeline exited while running `stage`
+    #[error("Pipeline stage {stage} failed: {message}")]
+    PipelineStageFailed { stage: String, message: String },
+
+    #[error("Permission denied: {path}")]
+    PermissionDenied { path: String },
+
+    /// A request that can't be carried out in the current state
+    #[error("{message}")]
+    InvalidRequest { message: String },
+
+    #[error("{message}")]
+    Io { message: String },
+
+    /// An audio file that couldn't be opened or decoded
+    #[error("Failed to decode {path}: {message}")]
+    Decode { path: String, message: String },
+
+    /// No usable audio output device, or the device stopped working
+    #[error("Audio output error: {message}")]
+    AudioDevice { message: String },
+
+    /// The song library index couldn't be read or updated
+    #[error("Library index error: {message}")]
+    Database { message: String },
+}
+
+pub type CommandResult<T> = Result<T, CommandError>;
+
+impl CommandError {
+    /// Classify an I/O error that occurred while accessing `path`
+    pub fn io(path: &Path, error: io::Error) -> Self {
+        let path = path.to_string_lossy().to_string();
+        match error.kind() {
+            io::ErrorKind::NotFound => CommandError::NotFound { path },
+            io::ErrorKind::PermissionDenied => CommandError::PermissionDenied { path },
+            _ => CommandError::Io {
+                message: format!("{}: {}", path, error),
+            },
+        }
+    }
+
+    /// Classify a failure to deserialize the JSON document at `path`
+    pub fn json(path: &Path, content: &str, error: serde_json::Error) -> Self {
+        use serde_json::error::Category;
+
+        let display_path = path.to_string_lossy().to_string();
+        match error.classify() {
+            Category::Syntax | Category::Eof => CommandError::Parse {
+                path: display_path,
+                line: error.line(),
+                column: error.column(),
+                message: error.to_string(),
+            },
+            Category::Data => CommandError::schema(path, content, error.to_string()),
+            Category::Io => CommandError::Io {
+                message: format!("{}: {}", display_path, error),
+            },
+        }
+    }
+
+    /// The document at `path` doesn't match a schema this app understands
+    pub fn schema(path: &Path, content: &str, message: impl Into<String>) -> Self {
+        CommandError::SchemaVersion {
+            path: path.to_string_lossy().to_string(),
+            converter_version: converter_version(content),
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid(message: impl Into<String>) -> Self {
+        CommandError::InvalidRequest {
+            message: message.into(),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for CommandError {
+    fn from(error: rusqlite::Error) -> Self {
+        CommandError::Database {
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Best-effort read of `converterVersion` from a document that failed to
+/// deserialize as a full `SongAnalysis`
+fn converter_version(content: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct VersionProbe {
+        converter_version: Option<String>,
+    }
+
+    serde_json::from_str::<VersionProbe>(content)
+        .ok()
+        .and_then(|probe| probe.converter_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SongAnalysis;
+
+    #[test]
+    fn test_json_errors_are_classified() {
+        let path = Path::new("song/analysis.json");
+
+        let content = "{\n  \"title\": ";
+        let err = serde_json::from_str::<SongAnalysis>(content).unwrap_err();
+        assert!(matches!(
+            CommandError::json(path, content, err),
+            CommandError::Parse { line: 2, .. }
+        ));
+
+        let content = r#"{"title": "Old", "converterVersion": "0.0.1"}"#;
+        let err = serde_json::from_str::<SongAnalysis>(content).unwrap_err();
+        match CommandError::json(path, content, err) {
+            CommandError::SchemaVersion {
+                converter_version, ..
+            } => assert_eq!(converter_version.as_deref(), Some("0.0.1")),
+            other => panic!("expected SchemaVersion, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_serializes_with_kind_tag() {
+        let err = CommandError::PipelineStageFailed {
+            stage: "separation".into(),
+            message: "Model not found".into(),
+        };
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json["kind"], "pipelineStageFailed");
+        assert_eq!(json["stage"], "separation");
+    }
+}
@@ -0,0 +1,642 @@
+//! SQLite index of song summaries, so listing the library doesn't have to
+//! deserialize every `analysis.json`.
+//!
+//! Rows are keyed by song path and remember the size and modification time
+//! of the `analysis.json` they were built from; a rescan only re-reads files
+//! whose size or mtime changed. Cached rows still have their stem files
+//! rechecked, since those can go missing without analysis.json changing.
+//! The index is a cache: if its schema changes
+//! it is simply rebuilt.
+
+use crate::error::CommandResult;
+use crate::library::{stem_files_status, SongStatus, SongSummary};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// Bump when the table layout changes to force a rebuild
+const INDEX_VERSION: i32 = 2;
+
+const SONG_COLUMNS: &str = "path, root, title, artist, album, duration, tempo_bpm, musical_key, \
+     time_sig_num, time_sig_den, stem_names, stem_files, has_lyrics, has_drum_strikes, \
+     source_file, processing_date, status, diagnostic";
+
+/// Search, filters, ordering and paging for `query_songs`.
+///
+/// Every field is optional; unset filters match all songs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SongQuery {
+    /// Words that must each appear in the title, artist, album or source file
+    pub text: Option<String>,
+    pub min_tempo: Option<f64>,
+    pub max_tempo: Option<f64>,
+    pub time_signature: Option<(i32, i32)>,
+    /// Duration range in seconds
+    pub min_duration: Option<f64>,
+    pub max_duration: Option<f64>,
+    pub has_lyrics: Option<bool>,
+    pub has_drum_strikes: Option<bool>,
+    /// Stem names that must all be present
+    pub stems: Vec<String>,
+    /// Applied in order; ties fall back to the song path
+    pub sort: Vec<SortKey>,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SortKey {
+    pub field: SortField,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SortField {
+    Title,
+    Artist,
+    Album,
+    Duration,
+    TempoBpm,
+    ProcessingDate,
+    Path,
+}
+
+impl SortField {
+    fn column(self) -> &'static str {
+        match self {
+            SortField::Title => "title",
+            SortField::Artist => "artist",
+            SortField::Album => "album",
+            SortField::Duration => "duration",
+            SortField::TempoBpm => "tempo_bpm",
+            SortField::ProcessingDate => "processing_date",
+            SortField::Path => "path",
+        }
+    }
+
+    fn is_text(self) -> bool {
+        matches!(
+            self,
+            SortField::Title | SortField::Artist | SortField::Album | SortField::Path
+        )
+    }
+}
+
+/// One page of `query_songs` results
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SongPage {
+    pub songs: Vec<SongSummary>,
+    /// Number of songs matching the query, ignoring paging
+    pub total: usize,
+}
+
+/// Size and modification time of an `analysis.json`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FileStamp {
+    size: i64,
+    mtime_ns: i64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let mtime_ns = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_nanos() as i64;
+        Some(Self {
+            size: metadata.len() as i64,
+            mtime_ns,
+        })
+    }
+}
+
+/// Managed state wrapping the index database
+pub struct SongIndex {
+    conn: Mutex<Connection>,
+}
+
+impl SongIndex {
+    /// Open (or create) the index database at `path`
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        Self::init(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> rusqlite::Result<Self> {
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version != INDEX_VERSION {
+            conn.execute_batch("DROP TABLE IF EXISTS songs")?;
+        }
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS songs (
+                path TEXT PRIMARY KEY,
+                root TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                mtime_ns INTEGER NOT NULL,
+                title TEXT,
+                artist TEXT,
+                album TEXT,
+                duration REAL NOT NULL,
+                tempo_bpm REAL,
+                musical_key TEXT,
+                time_sig_num INTEGER,
+                time_sig_den INTEGER,
+                stem_names TEXT NOT NULL,
+                stem_files TEXT NOT NULL,
+                has_lyrics INTEGER NOT NULL,
+                has_drum_strikes INTEGER NOT NULL,
+                source_file TEXT,
+                processing_date TEXT,
+                status TEXT NOT NULL,
+                diagnostic TEXT
+            );
+            CREATE INDEX IF NOT EXISTS songs_root ON songs (root);
+            PRAGMA user_version = {};",
+            INDEX_VERSION
+        ))?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Bring the index up to date with the songs found under `root` and
+    /// return their summaries, sorted by path.
+    ///
+    /// Songs whose `analysis.json` is unchanged are served from the index;
+    /// rows for songs no longer under `root` are removed.
+    pub fn refresh(&self, root: &Path, song_dirs: &[PathBuf]) -> CommandResult<Vec<SongSummary>> {
+        let root_key = root.to_string_lossy().to_string();
+        let mut conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let tx = conn.transaction()?;
+
+        let mut current = HashSet::new();
+        for song_dir in song_dirs {
+            let path = song_dir.to_string_lossy().to_string();
+            current.insert(path.clone());
+
+            let stamp = FileStamp::of(&song_dir.join("analysis.json"));
+            // Rows indexed under a different root are rebuilt so they move to this one
+            let indexed = tx
+                .query_row(
+                    "SELECT size, mtime_ns, stem_files, status, diagnostic FROM songs \
+                     WHERE path = ?1 AND root = ?2",
+                    params![path, root_key],
+                    |row| {
+                        let stamp = FileStamp {
+                            size: row.get(0)?,
+                            mtime_ns: row.get(1)?,
+                        };
+                        let stem_files: String = row.get(2)?;
+                        let status = SongStatus::parse(&row.get::<_, String>(3)?);
+                        let diagnostic: Option<String> = row.get(4)?;
+                        Ok((stamp, stem_files, status, diagnostic))
+                    },
+                )
+                .optional()?;
+
+            if let Some((indexed, stem_files, status, diagnostic)) = indexed {
+                if stamp == Some(indexed) {
+                    // Only rows whose analysis.json parsed have stem files to recheck
+                    if matches!(status, SongStatus::Ok | SongStatus::MissingStems) {
+                        let stem_files: Vec<String> =
+                            serde_json::from_str(&stem_files).unwrap_or_default();
+                        let current = stem_files_status(song_dir, &stem_files);
+                        if current != (status, diagnostic) {
+                            tx.execute(
+                                "UPDATE songs SET status = ?1, diagnostic = ?2 WHERE path = ?3",
+                                params![current.0.as_str(), current.1, path],
+                            )?;
+                        }
+                    }
+                    continue;
+                }
+            }
+            let stamp = stamp.unwrap_or(FileStamp {
+                size: 0,
+                mtime_ns: 0,
+            });
+            upsert(&tx, &SongSummary::load(song_dir, root), stamp)?;
+        }
+
+        let indexed_paths: Vec<String> = {
+            let mut stmt = tx.prepare("SELECT path FROM songs WHERE root = ?1")?;
+            let rows = stmt.query_map(params![root_key], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        for path in indexed_paths.iter().filter(|p| !current.contains(*p)) {
+            tx.execute("DELETE FROM songs WHERE path = ?1", params![path])?;
+        }
+
+        let songs = {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT {} FROM songs WHERE root = ?1 ORDER BY path",
+                SONG_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![root_key], summary_from_row)?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+
+        tx.commit()?;
+        Ok(songs)
+    }
+
+    /// Songs under `roots` matching `query`
+    pub fn query(&self, roots: &[String], query: &SongQuery) -> CommandResult<SongPage> {
+        if roots.is_empty() {
+            return Ok(SongPage {
+                songs: Vec::new(),
+                total: 0,
+            });
+        }
+
+        let mut conditions = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+
+        conditions.push(format!("root IN ({})", vec!["?"; roots.len()].join(", ")));
+        values.extend(roots.iter().map(|root| Value::Text(root.clone())));
+
+        if let Some(text) = &query.text {
+            for word in text.split_whitespace() {
+                conditions.push(
+                    "(title LIKE ? ESCAPE '\\' OR artist LIKE ? ESCAPE '\\' \
+                     OR album LIKE ? ESCAPE '\\' OR source_file LIKE ? ESCAPE '\\')"
+                        .to_string(),
+                );
+                let pattern = format!("%{}%", escape_like(word));
+                values.extend(std::iter::repeat_n(Value::Text(pattern), 4));
+            }
+        }
+
+        let mut range = |column: &str, op: &str, bound: Option<f64>| {
+            if let Some(bound) = bound {
+                conditions.push(format!("{} {} ?", column, op));
+                values.push(Value::Real(bound));
+            }
+        };
+        range("tempo_bpm", ">=", query.min_tempo);
+        range("tempo_bpm", "<=", query.max_tempo);
+        range("duration", ">=", query.min_duration);
+        range("duration", "<=", query.max_duration);
+
+        if let Some((num, den)) = query.time_signature {
+            conditions.push("time_sig_num = ? AND time_sig_den = ?".to_string());
+            values.extend([Value::Integer(num.into()), Value::Integer(den.into())]);
+        }
+        if let Some(has_lyrics) = query.has_lyrics {
+            conditions.push("has_lyrics = ?".to_string());
+            values.push(Value::Integer(has_lyrics.into()));
+        }
+        if let Some(has_drum_strikes) = query.has_drum_strikes {
+            conditions.push("has_drum_strikes = ?".to_string());
+            values.push(Value::Integer(has_drum_strikes.into()));
+        }
+        for stem in &query.stems {
+            conditions.push(
+                "EXISTS (SELECT 1 FROM json_each(songs.stem_names) WHERE value = ?)".to_string(),
+            );
+            values.push(Value::Text(stem.clone()));
+        }
+
+        let filter = conditions.join(" AND ");
+
+        let mut order: Vec<String> = Vec::new();
+        for key in &query.sort {
+            let column = key.field.column();
+            let direction = if key.descending { "DESC" } else { "ASC" };
+            // Songs missing the value sort last either way
+            order.push(format!("{} IS NULL", column));
+            if key.field.is_text() {
+                order.push(format!("{} COLLATE NOCASE {}", column, direction));
+            } else {
+                order.push(format!("{} {}", column, direction));
+            }
+        }
+        order.push("path".to_string());
+
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM songs WHERE {}", filter),
+            params_from_iter(values.iter()),
+            |row| row.get(0),
+        )?;
+
+        let limit = query.limit.map_or(-1, |limit| limit as i64);
+        values.extend([Value::Integer(limit), Value::Integer(query.offset as i64)]);
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM songs WHERE {} ORDER BY {} LIMIT ? OFFSET ?",
+            SONG_COLUMNS,
+            filter,
+            order.join(", ")
+        ))?;
+        let songs = stmt
+            .query_map(params_from_iter(values.iter()), summary_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(SongPage {
+            songs,
+            total: total as usize,
+        })
+    }
+
+    /// Re-read one song's `analysis.json` into the index
+    pub fn update_song(&self, root: &Path, song_dir: &Path) -> CommandResult<SongSummary> {
+        let summary = SongSummary::load(song_dir, root);
+        let stamp = FileStamp::of(&song_dir.join("analysis.json")).unwrap_or(FileStamp {
+            size: 0,
+            mtime_ns: 0,
+        });
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        upsert(&conn, &summary, stamp)?;
+        Ok(summary)
+    }
+
+    /// Drop every indexed song at or below `path`, returning their paths
+    pub fn remove_under(&self, path: &Path) -> CommandResult<Vec<String>> {
+        let mut conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let tx = conn.transaction()?;
+
+        let removed: Vec<String> = {
+            let mut stmt = tx.prepare("SELECT path FROM songs")?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            rows.filter_map(Result::ok)
+                .filter(|song| Path::new(song).starts_with(path))
+                .collect()
+        };
+        for song in &removed {
+            tx.execute("DELETE FROM songs WHERE path = ?1", params![song])?;
+        }
+
+        tx.commit()?;
+        Ok(removed)
+    }
+}
+
+/// Escape `LIKE` wildcards so `word` matches literally
+fn escape_like(word: &str) -> String {
+    word.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn upsert(conn: &Connection, song: &SongSummary, stamp: FileStamp) -> rusqlite::Result<()> {
+    let stem_names = serde_json::to_string(&song.stem_names).unwrap_or_else(|_| "[]".into());
+    let stem_files = serde_json::to_string(&song.stem_files).unwrap_or_else(|_| "[]".into());
+    conn.execute(
+        "INSERT OR REPLACE INTO songs (path, root, size, mtime_ns, title, artist, album, \
+         duration, tempo_bpm, musical_key, time_sig_num, time_sig_den, stem_names, stem_files, \
+         has_lyrics, has_drum_strikes, source_file, processing_date, status, diagnostic) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, \
+         ?18, ?19, ?20)",
+        params![
+            song.path,
+            song.root,
+            stamp.size,
+            stamp.mtime_ns,
+            song.title,
+            song.artist,
+            song.album,
+            song.duration,
+            song.tempo_bpm,
+            song.key,
+            song.time_signature.map(|(num, _)| num),
+            song.time_signature.map(|(_, den)| den),
+            stem_names,
+            stem_files,
+            song.has_lyrics,
+            song.has_drum_strikes,
+            song.source_file,
+            song.processing_date,
+            song.status.as_str(),
+            song.diagnostic,
+        ],
+    )?;
+    Ok(())
+}
+
+fn summary_from_row(row: &Row) -> rusqlite::Result<SongSummary> {
+    let stem_names: Vec<String> =
+        serde_json::from_str(&row.get::<_, String>("stem_names")?).unwrap_or_default();
+    let stem_files: Vec<String> =
+        serde_json::from_str(&row.get::<_, String>("stem_files")?).unwrap_or_default();
+    let time_signature = match (
+        row.get::<_, Option<i32>>("time_sig_num")?,
+        row.get::<_, Option<i32>>("time_sig_den")?,
+    ) {
+        (Some(num), Some(den)) => Some((num, den)),
+        _ => None,
+    };
+
+    Ok(SongSummary {
+        path: row.get("path")?,
+        root: row.get("root")?,
+        title: row.get("title")?,
+        artist: row.get("artist")?,
+        album: row.get("album")?,
+        duration: row.get("duration")?,
+        tempo_bpm: row.get("tempo_bpm")?,
+        key: row.get("musical_key")?,
+        time_signature,
+        stem_count: stem_names.len(),
+        stem_names,
+        stem_files,
+        has_lyrics: row.get("has_lyrics")?,
+        has_drum_strikes: row.get("has_drum_strikes")?,
+        source_file: row.get("source_file")?,
+        processing_date: row.get("processing_date")?,
+        status: SongStatus::parse(&row.get::<_, String>("status")?),
+        diagnostic: row.get("diagnostic")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANALYSIS: &str = r#"{
+        "title": "Indexed", "artist": "Band", "album": "Record",
+        "originalDuration": 42.0, "sampleRate": 44100,
+        "tempoBpm": 120.0, "timeSignature": [3, 4],
+        "stems": {}, "beats": [], "sourceFile": "indexed.mp3",
+        "processingDate": "2025-01-01", "converterVersion": "0.1.0"
+    }"#;
+
+    #[test]
+    fn test_refresh_is_incremental() {
+        let root = std::env::temp_dir().join(format!("music-tutor-index-{}", std::process::id()));
+        let song = root.join("song");
+        fs::create_dir_all(&song).unwrap();
+        fs::write(song.join("analysis.json"), ANALYSIS).unwrap();
+
+        let index = SongIndex::open_in_memory().unwrap();
+        let songs = index.refresh(&root, std::slice::from_ref(&song)).unwrap();
+        assert_eq!(songs.len(), 1);
+        assert_eq!(songs[0].title.as_deref(), Some("Indexed"));
+        assert_eq!(songs[0].time_signature, Some((3, 4)));
+        assert_eq!(songs[0], SongSummary::load(&song, &root));
+
+        // Unchanged files are served from the index: tamper with the row to prove it
+        index
+            .conn
+            .lock()
+            .unwrap()
+            .execute("UPDATE songs SET title = 'Cached'", [])
+            .unwrap();
+        let songs = index.refresh(&root, std::slice::from_ref(&song)).unwrap();
+        assert_eq!(songs[0].title.as_deref(), Some("Cached"));
+
+        // A changed file is re-read
+        fs::write(
+            song.join("analysis.json"),
+            ANALYSIS.replace("Indexed", "Renamed"),
+        )
+        .unwrap();
+        let songs = index.refresh(&root, std::slice::from_ref(&song)).unwrap();
+        assert_eq!(songs[0].title.as_deref(), Some("Renamed"));
+
+        // Songs that disappeared are dropped
+        assert!(index.refresh(&root, &[]).unwrap().is_empty());
+
+        index.update_song(&root, &song).unwrap();
+        assert_eq!(
+            index.remove_under(&root).unwrap(),
+            vec![song.to_string_lossy()]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_refresh_rechecks_stem_files() {
+        let root = std::env::temp_dir().join(format!("music-tutor-stems-{}", std::process::id()));
+        let song = root.join("song");
+        fs::create_dir_all(&song).unwrap();
+        fs::write(
+            song.join("analysis.json"),
+            ANALYSIS.replace(
+                r#""stems": {}"#,
+                r#""stems": {"bass": {"name": "bass", "paths": {"1.0": "bass.wav"},
+                    "hasNotes": false, "peakDb": -3.0}}"#,
+            ),
+        )
+        .unwrap();
+        fs::write(song.join("bass.wav"), b"").unwrap();
+
+        let index = SongIndex::open_in_memory().unwrap();
+        let songs = index.refresh(&root, std::slice::from_ref(&song)).unwrap();
+        assert_eq!(songs[0].status, SongStatus::Ok);
+
+        // analysis.json is untouched, but the stem is gone
+        fs::remove_file(song.join("bass.wav")).unwrap();
+        let songs = index.refresh(&root, std::slice::from_ref(&song)).unwrap();
+        assert_eq!(songs[0].status, SongStatus::MissingStems);
+        assert_eq!(songs[0], SongSummary::load(&song, &root));
+
+        fs::write(song.join("bass.wav"), b"").unwrap();
+        let songs = index.refresh(&root, std::slice::from_ref(&song)).unwrap();
+        assert_eq!(songs[0].status, SongStatus::Ok);
+        assert_eq!(songs[0].diagnostic, None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    fn song(path: &str, title: &str, tempo: f64, stems: &[&str]) -> SongSummary {
+        SongSummary {
+            path: path.to_string(),
+            root: "lib".to_string(),
+            title: Some(title.to_string()),
+            duration: 180.0,
+            tempo_bpm: Some(tempo),
+            time_signature: Some((4, 4)),
+            stem_count: stems.len(),
+            stem_names: stems.iter().map(|s| s.to_string()).collect(),
+            ..SongSummary::default()
+        }
+    }
+
+    #[test]
+    fn test_query_filters_sorts_and_pages() {
+        let index = SongIndex::open_in_memory().unwrap();
+        {
+            let conn = index.conn.lock().unwrap();
+            let stamp = FileStamp {
+                size: 0,
+                mtime_ns: 0,
+            };
+            let mut waltz = song("lib/a", "Slow Waltz", 90.0, &["vocals", "bass"]);
+            waltz.time_signature = Some((3, 4));
+            waltz.has_lyrics = true;
+            upsert(&conn, &waltz, stamp).unwrap();
+            upsert(&conn, &song("lib/b", "Fast 100%", 170.0, &["drums"]), stamp).unwrap();
+            upsert(
+                &conn,
+                &song("lib/c", "Mid Groove", 120.0, &["drums", "bass"]),
+                stamp,
+            )
+            .unwrap();
+            let mut elsewhere = song("other/d", "Groove Elsewhere", 120.0, &[]);
+            elsewhere.root = "other".to_string();
+            upsert(&conn, &elsewhere, stamp).unwrap();
+        }
+        let roots = vec!["lib".to_string()];
+        let titles = |page: SongPage| -> Vec<String> {
+            page.songs.into_iter().filter_map(|s| s.title).collect()
+        };
+
+        let query = |json: &str| serde_json::from_str::<SongQuery>(json).unwrap();
+        let all = index.query(&roots, &query("{}")).unwrap();
+        assert_eq!(all.total, 3);
+
+        let page = index
+            .query(&roots, &query(r#"{"text": "groove"}"#))
+            .unwrap();
+        assert_eq!(titles(page), vec!["Mid Groove"]);
+        // LIKE wildcards in the search text match literally
+        let page = index.query(&roots, &query(r#"{"text": "100%"}"#)).unwrap();
+        assert_eq!(titles(page), vec!["Fast 100%"]);
+
+        let page = index
+            .query(
+                &roots,
+                &query(r#"{"minTempo": 100, "stems": ["drums", "bass"]}"#),
+            )
+            .unwrap();
+        assert_eq!(titles(page), vec!["Mid Groove"]);
+        let page = index
+            .query(
+                &roots,
+                &query(r#"{"timeSignature": [3, 4], "hasLyrics": true}"#),
+            )
+            .unwrap();
+        assert_eq!(titles(page), vec!["Slow Waltz"]);
+
+        let page = index
+            .query(
+                &roots,
+                &query(r#"{"sort": [{"field": "tempoBpm", "descending": true}], "offset": 1, "limit": 1}"#),
+            )
+            .unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(titles(page), vec!["Mid Groove"]);
+    }
+}
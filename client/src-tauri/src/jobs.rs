@@ -0,0 +1,521 @@
+//! Queue of songs waiting to be processed by the music-tutor pipeline.
+//!
+//! The queue lives in managed Tauri state, owns the child processes it
+//! starts and is saved to `jobs.json` in the app data directory so queued
+//! work survives an app restart.
+
+use crate::error::{CommandError, CommandResult};
+use crate::lyrics;
+use crate::processing::{self, ProgressEvent, ProgressParser, ProgressUpdate};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Event emitted whenever a job changes status
+pub const JOB_UPDATED_EVENT: &str = "job-updated";
+
+/// Number of songs processed at once unless configured otherwise.
+/// Separation saturates the CPU/GPU, so one at a time is the safe default.
+const DEFAULT_MAX_CONCURRENT: usize = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "camelCase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    /// Stopping; becomes `Cancelled` once its process has exited, and
+    /// can't be retried before then
+    Cancelling,
+    Completed,
+    Failed {
+        error: CommandError,
+    },
+    Cancelled,
+}
+
+impl JobStatus {
+    fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Completed | JobStatus::Failed { .. } | JobStatus::Cancelled
+        )
+    }
+}
+
+/// A song queued for processing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub id: String,
+    /// Audio file to convert, or `None` to re-analyze the stems already in `output_dir`
+    pub audio_file: Option<String>,
+    pub output_dir: String,
+    pub separate_drums: bool,
+    pub status: JobStatus,
+    /// Stage currently running, if any
+    pub stage: Option<String>,
+    pub percent: f64,
+    pub warnings: Vec<String>,
+}
+
+/// Serializable queue state, independent of the running processes
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobQueue {
+    pub max_concurrent: usize,
+    pub jobs: Vec<Job>,
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        Self {
+            max_concurrent: DEFAULT_MAX_CONCURRENT,
+            jobs: Vec::new(),
+        }
+    }
+}
+
+fn next_job_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    format!("job-{}-{}", millis, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+impl JobQueue {
+    /// Jobs interrupted by an app exit are queued again from the start,
+    /// unless they were being cancelled
+    fn requeue_interrupted(&mut self) {
+        for job in &mut self.jobs {
+            match job.status {
+                JobStatus::Running => {
+                    job.status = JobStatus::Queued;
+                    job.percent = 0.0;
+                }
+                JobStatus::Cancelling => job.status = JobStatus::Cancelled,
+                _ => continue,
+            }
+            job.stage = None;
+        }
+    }
+
+    pub fn enqueue(&mut self, audio_file: &str, output_dir: &str, separate_drums: bool) -> Job {
+        self.push(Some(audio_file.to_string()), output_dir, separate_drums)
+    }
+
+    /// Queue a re-run of the analysis stages on an already processed song
+    pub fn enqueue_reanalysis(&mut self, output_dir: &str) -> Job {
+        self.push(None, output_dir, false)
+    }
+
+    fn push(&mut self, audio_file: Option<String>, output_dir: &str, separate_drums: bool) -> Job {
+        let job = Job {
+            id: next_job_id(),
+            audio_file,
+            output_dir: output_dir.to_string(),
+            separate_drums,
+            status: JobStatus::Queued,
+            stage: None,
+            percent: 0.0,
+            warnings: Vec::new(),
+        };
+        self.jobs.push(job.clone());
+        job
+    }
+
+    pub fn get_mut(&mut self, job_id: &str) -> CommandResult<&mut Job> {
+        self.jobs
+            .iter_mut()
+            .find(|job| job.id == job_id)
+            .ok_or_else(|| CommandError::invalid(format!("No job {}", job_id)))
+    }
+
+    /// Mark queued jobs as running, up to the concurrency limit, and return them.
+    /// A job still being cancelled holds its slot until its process exits
+    pub fn start_runnable(&mut self) -> Vec<Job> {
+        let running = self
+            .jobs
+            .iter()
+            .filter(|job| matches!(job.status, JobStatus::Running | JobStatus::Cancelling))
+            .count();
+        let available = self.max_concurrent.max(1).saturating_sub(running);
+
+        self.jobs
+            .iter_mut()
+            .filter(|job| job.status == JobStatus::Queued)
+            .take(available)
+            .map(|job| {
+                job.status = JobStatus::Running;
+                job.clone()
+            })
+            .collect()
+    }
+
+    fn record_progress(&mut self, job_id: &str, percent: f64, update: &ProgressUpdate) {
+        let Ok(job) = self.get_mut(job_id) else {
+            return;
+        };
+        job.percent = percent;
+        match update {
+            ProgressUpdate::StageStarted { stage } => job.stage = Some(stage.clone()),
+            ProgressUpdate::Warning { message } => job.warnings.push(message.clone()),
+            _ => {}
+        }
+    }
+
+    /// Put a failed or cancelled job back in the queue
+    pub fn retry(&mut self, job_id: &str) -> CommandResult<Job> {
+        let job = self.get_mut(job_id)?;
+        if !matches!(job.status, JobStatus::Failed { .. } | JobStatus::Cancelled) {
+            return Err(CommandError::invalid(format!(
+                "Job {} can only be retried after it fails or is cancelled",
+                job_id
+            )));
+        }
+        job.status = JobStatus::Queued;
+        job.stage = None;
+        job.percent = 0.0;
+        job.warnings.clear();
+        Ok(job.clone())
+    }
+}
+
+/// Managed state owning the queue and the processes it spawned
+pub struct JobManager {
+    queue: Mutex<JobQueue>,
+    /// Process ids of running jobs, by job id
+    processes: Mutex<HashMap<String, u32>>,
+    store_path: PathBuf,
+}
+
+impl JobManager {
+    /// Load the queue saved at `store_path`, or start empty
+    pub fn load(store_path: PathBuf) -> Self {
+        let mut queue: JobQueue = fs::read_to_string(&store_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        queue.requeue_interrupted();
+
+        Self {
+            queue: Mutex::new(queue),
+            processes: Mutex::new(HashMap::new()),
+            store_path,
+        }
+    }
+
+    fn save(&self, queue: &JobQueue) {
+        if let Some(parent) = self.store_path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(content) = serde_json::to_string_pretty(queue) {
+            let _ = fs::write(&self.store_path, content);
+        }
+    }
+
+    /// Apply `change` to the queue, persist it and return the result
+    fn update<T>(&self, change: impl FnOnce(&mut JobQueue) -> T) -> T {
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        let result = change(&mut queue);
+        self.save(&queue);
+        result
+    }
+
+    fn is_cancelling(&self, job_id: &str) -> bool {
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        queue
+            .get_mut(job_id)
+            .is_ok_and(|job| job.status == JobStatus::Cancelling)
+    }
+
+    pub fn jobs(&self) -> Vec<Job> {
+        self.queue
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .jobs
+            .clone()
+    }
+}
+
+fn emit_job(app: &AppHandle, job: &Job) {
+    let _ = app.emit(JOB_UPDATED_EVENT, job);
+}
+
+/// Start as many queued jobs as the concurrency limit allows
+pub fn schedule(app: &AppHandle) {
+    let manager = app.state::<JobManager>();
+    for job in manager.update(JobQueue::start_runnable) {
+        emit_job(app, &job);
+        let app = app.clone();
+        thread::spawn(move || run_job(app, job));
+    }
+}
+
+fn run_job(app: AppHandle, job: Job) {
+    let manager = app.state::<JobManager>();
+
+    let (spawned, stages) = match &job.audio_file {
+        Some(audio_file) => (
+            processing::spawn_convert(audio_file, &job.output_dir, job.separate_drums),
+            processing::CONVERT_STAGES,
+        ),
+        None => (
+            processing::spawn_reanalyze(&job.output_dir),
+            processing::REANALYZE_STAGES,
+        ),
+    };
+
+    let outcome = match spawned {
+        Ok(mut child) => {
+            manager
+                .processes
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(job.id.clone(), child.id());
+            // Cancelled between being scheduled and its process starting
+            if manager.is_cancelling(&job.id) {
+                let _ = processing::kill_process_tree(child.id());
+            }
+
+            let mut parser = ProgressParser::new(stages);
+            let output = processing::watch_progress(&mut child, &mut parser, |parser, update| {
+                let percent = parser.percent();
+                // Log lines are only forwarded to the UI, not persisted
+                if !matches!(update, ProgressUpdate::Log { .. }) {
+                    manager.update(|queue| queue.record_progress(&job.id, percent, &update));
+                }
+                let _ = app.emit(
+                    processing::PROGRESS_EVENT,
+                    ProgressEvent {
+                        job_id: job.id.clone(),
+                        percent,
+                        update,
+                    },
+                );
+            });
+
+            manager
+                .processes
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(&job.id);
+
+            match output {
+                Ok(output) if output.status.success() => Ok(()),
+                Ok(output) => Err(parser.failure(&output)),
+                Err(e) => Err(CommandError::Io {
+                    message: format!("Failed to read process output: {}", e),
+                }),
+            }
+        }
+        Err(e) => Err(processing::spawn_error(e)),
+    };
+
+    let finished = manager.update(|queue| {
+        let current = queue.get_mut(&job.id).ok()?;
+        // A job being cancelled ends cancelled, whatever its process exited with
+        current.status = match (&current.status, outcome) {
+            (JobStatus::Cancelling, _) => JobStatus::Cancelled,
+            (_, Ok(())) => JobStatus::Completed,
+            (_, Err(error)) => JobStatus::Failed { error },
+        };
+        if current.status == JobStatus::Completed {
+            current.percent = 100.0;
+            // The lyrics undo steps were for the analysis just replaced.
+            // They are also ignored once the analysis changes, so a file
+            // that can't be removed does no harm
+            let _ = lyrics::edit::clear_history(Path::new(&current.output_dir));
+        }
+        current.stage = None;
+        Some(current.clone())
+    });
+    if let Some(job) = finished {
+        emit_job(&app, &job);
+    }
+
+    schedule(&app);
+}
+
+/// Add a song to the processing queue
+#[tauri::command]
+pub fn enqueue_song(
+    app: AppHandle,
+    audio_file: &str,
+    output_dir: &str,
+    separate_drums: bool,
+) -> CommandResult<Job> {
+    let manager = app.state::<JobManager>();
+    let job = manager.update(|queue| queue.enqueue(audio_file, output_dir, separate_drums));
+    emit_job(&app, &job);
+    schedule(&app);
+    Ok(job)
+}
+
+/// Queue a re-analysis of a processed song, keeping its stems
+#[tauri::command]
+pub fn reanalyze_song(app: AppHandle, song_dir: &str) -> CommandResult<Job> {
+    let manager = app.state::<JobManager>();
+    let job = manager.update(|queue| queue.enqueue_reanalysis(song_dir));
+    emit_job(&app, &job);
+    schedule(&app);
+    Ok(job)
+}
+
+/// Cancel a queued or running job, killing its process tree.
+///
+/// A queued job is cancelled at once. A running one is marked cancelling
+/// before the kill, so its worker doesn't record the dying process as a
+/// failure, and becomes cancelled when the worker sees it exit; if the
+/// kill fails the job is put back as it was.
+#[tauri::command]
+pub fn cancel_job(app: AppHandle, job_id: &str) -> CommandResult<Job> {
+    let manager = app.state::<JobManager>();
+    let (previous, job) = manager.update(|queue| {
+        let job = queue.get_mut(job_id)?;
+        if job.status.is_finished() {
+            return Err(CommandError::invalid(format!(
+                "Job {} has already finished",
+                job_id
+            )));
+        }
+        let previous = job.clone();
+        job.status = match job.status {
+            JobStatus::Queued => JobStatus::Cancelled,
+            _ => JobStatus::Cancelling,
+        };
+        job.stage = None;
+        Ok((previous, job.clone()))
+    })?;
+
+    let pid = manager
+        .processes
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(job_id)
+        .copied();
+    if let Some(pid) = pid {
+        if let Err(e) = processing::kill_process_tree(pid) {
+            // Unless the worker has finished with the job meanwhile, it is
+            // still running
+            let restored = manager.update(|queue| {
+                let current = queue.get_mut(job_id).ok()?;
+                if current.status != JobStatus::Cancelling {
+                    return None;
+                }
+                current.status = previous.status;
+                current.stage = previous.stage;
+                Some(current.clone())
+            });
+            if let Some(job) = restored {
+                emit_job(&app, &job);
+            }
+            return Err(CommandError::Io {
+                message: format!("Failed to stop job {}: {}", job_id, e),
+            });
+        }
+    }
+
+    emit_job(&app, &job);
+    Ok(job)
+}
+
+/// All jobs, in the order they were queued
+#[tauri::command]
+pub fn list_jobs(app: AppHandle) -> Vec<Job> {
+    app.state::<JobManager>().jobs()
+}
+
+/// Queue a failed or cancelled job again
+#[tauri::command]
+pub fn retry_job(app: AppHandle, job_id: &str) -> CommandResult<Job> {
+    let manager = app.state::<JobManager>();
+    let job = manager.update(|queue| queue.retry(job_id))?;
+    emit_job(&app, &job);
+    schedule(&app);
+    Ok(job)
+}
+
+/// Change how many songs are processed at once
+#[tauri::command]
+pub fn set_max_concurrent_jobs(app: AppHandle, limit: usize) -> CommandResult<()> {
+    if limit == 0 {
+        return Err(CommandError::invalid(
+            "Concurrency limit must be at least 1",
+        ));
+    }
+    app.state::<JobManager>()
+        .update(|queue| queue.max_concurrent = limit);
+    schedule(&app);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_runnable_respects_concurrency_limit() {
+        let mut queue = JobQueue {
+            max_concurrent: 2,
+            jobs: Vec::new(),
+        };
+        for name in ["a", "b", "c"] {
+            queue.enqueue(&format!("{}.mp3", name), &format!("out/{}", name), false);
+        }
+
+        let started = queue.start_runnable();
+        assert_eq!(started.len(), 2);
+        assert_eq!(started[0].audio_file.as_deref(), Some("a.mp3"));
+        assert!(queue.start_runnable().is_empty());
+
+        queue.jobs[0].status = JobStatus::Completed;
+        let started = queue.start_runnable();
+        assert_eq!(started.len(), 1);
+        assert_eq!(started[0].audio_file.as_deref(), Some("c.mp3"));
+
+        // A job being cancelled keeps its slot, and can't be retried, until
+        // its process has exited
+        let c = started[0].id.clone();
+        queue.get_mut(&c).unwrap().status = JobStatus::Cancelling;
+        queue.enqueue("d.mp3", "out/d", false);
+        assert!(queue.start_runnable().is_empty());
+        assert!(queue.retry(&c).is_err());
+        queue.get_mut(&c).unwrap().status = JobStatus::Cancelled;
+        assert_eq!(queue.start_runnable().len(), 1);
+        assert!(queue.retry(&c).is_ok());
+    }
+
+    #[test]
+    fn test_retry_and_restart_recovery() {
+        let mut queue = JobQueue::default();
+        let job = queue.enqueue("a.mp3", "out/a", true);
+        assert!(
+            queue.retry(&job.id).is_err(),
+            "queued jobs cannot be retried"
+        );
+
+        queue.start_runnable();
+        queue.get_mut(&job.id).unwrap().percent = 50.0;
+
+        // Simulate an app restart while the job was running
+        let saved = serde_json::to_string(&queue).unwrap();
+        let mut restored: JobQueue = serde_json::from_str(&saved).unwrap();
+        restored.requeue_interrupted();
+        assert_eq!(restored.jobs[0].status, JobStatus::Queued);
+        assert_eq!(restored.jobs[0].percent, 0.0);
+
+        restored.jobs[0].status = JobStatus::Failed {
+            error: CommandError::invalid("boom"),
+        };
+        let retried = restored.retry(&job.id).unwrap();
+        assert_eq!(retried.status, JobStatus::Queued);
+    }
+}
@@ -1,7 +1,25 @@
+mod audio;
+mod error;
+mod index;
+mod jobs;
+mod library;
+mod lyrics;
+mod midi;
+mod migration;
+mod notation;
+mod processing;
+mod sandbox;
+mod timeline;
+mod validation;
+mod watcher;
+
+use error::{CommandError, CommandResult};
+
 use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
 use std::fs;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
 
 /// Stem information from analysis.json
 #[derive(Debug, Serialize, Deserialize)]
@@ -51,7 +69,7 @@ pub struct DrumStrike {
 }
 
 /// Lyric word with timing
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LyricWord {
     pub text: String,
@@ -61,7 +79,7 @@ pub struct LyricWord {
 }
 
 /// Lyric line with words
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LyricLine {
     pub text: String,
@@ -71,7 +89,7 @@ pub struct LyricLine {
 }
 
 /// Lyrics data
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LyricsData {
     pub source: String,
@@ -82,12 +100,18 @@ pub struct LyricsData {
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SongAnalysis {
+    /// Version of the analysis.json layout; older files are migrated on load
+    #[serde(default)]
+    pub schema_version: u64,
     pub title: Option<String>,
     pub artist: Option<String>,
     pub album: Option<String>,
     pub original_duration: f64,
     pub sample_rate: i32,
     pub tempo_bpm: Option<f64>,
+    /// Musical key (e.g. "A minor"), when the converter detected one
+    #[serde(default)]
+    pub key: Option<String>,
     pub time_signature: Option<(i32, i32)>,
     pub stems: std::collections::HashMap<String, StemInfo>,
     pub beats: Vec<BeatEvent>,
@@ -102,110 +126,227 @@ pub struct SongAnalysis {
     pub converter_version: String,
 }
 
-/// Summary info for song browser
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct SongSummary {
-    pub path: String,
-    pub title: Option<String>,
-    pub artist: Option<String>,
-    pub duration: f64,
-    pub stem_count: usize,
+/// Read and deserialize `analysis.json`, upgrading older schemas
+pub(crate) fn read_analysis(analysis_path: &Path) -> CommandResult<SongAnalysis> {
+    read_analysis_versioned(analysis_path).map(|(analysis, _)| analysis)
 }
 
-/// List songs in a directory that contain analysis.json
-#[tauri::command]
-fn list_songs(dir: &str) -> Result<Vec<SongSummary>, String> {
-    let path = Path::new(dir);
-    if !path.exists() {
-        return Ok(vec![]);
-    }
+/// Like [`read_analysis`], also returning the schema version the file was
+/// written with
+pub(crate) fn read_analysis_versioned(analysis_path: &Path) -> CommandResult<(SongAnalysis, u64)> {
+    let content =
+        fs::read_to_string(analysis_path).map_err(|e| CommandError::io(analysis_path, e))?;
+    let mut doc: serde_json::Value = serde_json::from_str(&content)
+        .map_err(|e| CommandError::json(analysis_path, &content, e))?;
+    let from_version = migration::migrate(&mut doc)
+        .map_err(|message| CommandError::schema(analysis_path, &content, message))?;
+    let analysis =
+        serde_json::from_value(doc).map_err(|e| CommandError::json(analysis_path, &content, e))?;
+    Ok((analysis, from_version))
+}
 
-    let mut songs = Vec::new();
+/// Replace `analysis.json` with `analysis`, via a temporary file so a
+/// failed write never leaves a truncated document
+pub(crate) fn write_analysis(analysis_path: &Path, analysis: &SongAnalysis) -> CommandResult<()> {
+    let content = serde_json::to_string_pretty(analysis).map_err(|e| CommandError::Io {
+        message: e.to_string(),
+    })?;
+    let temp_path = sibling_path(analysis_path, "tmp");
+    fs::write(&temp_path, content).map_err(|e| CommandError::io(&temp_path, e))?;
+    fs::rename(&temp_path, analysis_path).map_err(|e| CommandError::io(analysis_path, e))
+}
 
-    let entries = fs::read_dir(path).map_err(|e| e.to_string())?;
-    for entry in entries {
-        let entry = entry.map_err(|e| e.to_string())?;
-        let song_path = entry.path();
-        let analysis_path = song_path.join("analysis.json");
+/// Copy `analysis.json` to `analysis.json.<label>.bak` before it is
+/// rewritten, returning the backup path
+pub(crate) fn backup_analysis(analysis_path: &Path, label: &str) -> CommandResult<PathBuf> {
+    let backup_path = sibling_path(analysis_path, &format!("{}.bak", label));
+    fs::copy(analysis_path, &backup_path).map_err(|e| CommandError::io(&backup_path, e))?;
+    Ok(backup_path)
+}
 
-        if analysis_path.exists() {
-            if let Ok(content) = fs::read_to_string(&analysis_path) {
-                if let Ok(analysis) = serde_json::from_str::<SongAnalysis>(&content) {
-                    songs.push(SongSummary {
-                        path: song_path.to_string_lossy().to_string(),
-                        title: analysis.title,
-                        artist: analysis.artist,
-                        duration: analysis.original_duration,
-                        stem_count: analysis.stems.len(),
-                    });
-                }
-            }
-        }
-    }
+/// `path` with `.<suffix>` appended to its file name
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
 
-    Ok(songs)
+/// Result of `load_analysis`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadedAnalysis {
+    pub analysis: SongAnalysis,
+    /// What the validation pass found; empty unless `fix` was requested.
+    /// Issues with a `fix` mean the returned data differs from the file
+    pub issues: Vec<validation::ValidationIssue>,
 }
 
-/// Load full analysis.json from a song directory
+/// Load full analysis.json from a song directory.
+///
+/// Files written with an older schema are upgraded in memory; with
+/// `write_back` the upgraded document is also saved, keeping the original
+/// as `analysis.json.v<old version>.bak`.
+///
+/// With `fix`, invalid events are sorted, clamped or dropped in the
+/// returned analysis (the file is left alone) and the issues are returned
+/// alongside; `validate_analysis` writes the repairs back.
 #[tauri::command]
-fn load_analysis(song_dir: &str) -> Result<SongAnalysis, String> {
+fn load_analysis(
+    song_dir: &str,
+    write_back: Option<bool>,
+    fix: Option<bool>,
+) -> CommandResult<LoadedAnalysis> {
     let analysis_path = Path::new(song_dir).join("analysis.json");
-    let content = fs::read_to_string(&analysis_path).map_err(|e| e.to_string())?;
-    serde_json::from_str(&content).map_err(|e| e.to_string())
+    let (mut analysis, from_version) = read_analysis_versioned(&analysis_path)?;
+    if write_back.unwrap_or(false) && from_version < migration::CURRENT_SCHEMA_VERSION {
+        backup_analysis(&analysis_path, &format!("v{}", from_version))?;
+        write_analysis(&analysis_path, &analysis)?;
+    }
+    let issues = if fix.unwrap_or(false) {
+        validation::validate(&mut analysis, Path::new(song_dir))
+    } else {
+        Vec::new()
+    };
+    Ok(LoadedAnalysis { analysis, issues })
 }
 
-/// Get the absolute path to a stem file
+/// Get the absolute path to a stem file.
+///
+/// Only files listed in the song's `StemInfo.paths` that resolve to a
+/// location inside `song_dir` are returned.
 #[tauri::command]
-fn get_stem_path(song_dir: &str, relative_path: &str) -> Result<String, String> {
-    let stem_path = Path::new(song_dir).join(relative_path);
-    if stem_path.exists() {
-        Ok(stem_path.to_string_lossy().to_string())
-    } else {
-        Err(format!("Stem file not found: {}", stem_path.display()))
-    }
+fn get_stem_path(song_dir: &str, relative_path: &str) -> CommandResult<String> {
+    let song_dir = Path::new(song_dir);
+    let analysis = read_analysis(&song_dir.join("analysis.json"))?;
+    let stem_path = sandbox::stem_path(song_dir, &analysis, relative_path)?;
+    Ok(stem_path.to_string_lossy().to_string())
 }
 
-/// Process an audio file through the music-tutor pipeline
+/// Process an audio file through the music-tutor pipeline.
+///
+/// Runs in the background and emits `processing-progress` events tagged with
+/// `job_id` as each stage starts and finishes.
 #[tauri::command]
-fn process_song(audio_file: &str, output_dir: &str, separate_drums: bool) -> Result<String, String> {
-    // Get the project root - go up from output_dir (e.g., ../output/song -> ..)
-    let output_path = Path::new(output_dir);
-    let project_root = output_path
-        .parent() // ../output
-        .and_then(|p| p.parent()) // ..
-        .unwrap_or(Path::new("."));
+async fn process_song(
+    app: AppHandle,
+    job_id: String,
+    audio_file: String,
+    output_dir: String,
+    separate_drums: bool,
+) -> CommandResult<String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        processing::run_convert(&app, &job_id, &audio_file, &output_dir, separate_drums)
+    })
+    .await
+    .map_err(|e| CommandError::Io {
+        message: e.to_string(),
+    })?
+}
 
-    let mut args = vec![
-        "run",
-        "music-tutor",
-        "convert",
-        audio_file,
-        "-o",
-        output_dir,
-    ];
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .plugin(tauri_plugin_fs::init())
+        .plugin(tauri_plugin_dialog::init())
+        .setup(|app| {
+            let store_path = app.path().app_data_dir()?.join("jobs.json");
+            app.manage(jobs::JobManager::load(store_path));
+            let config_path = app.path().app_config_dir()?.join("library.json");
+            app.manage(library::Library::load(config_path));
+            let index_path = app.path().app_data_dir()?.join("library.db");
+            app.manage(index::SongIndex::open(&index_path)?);
+            app.manage(watcher::LibraryWatcher::default());
+            app.manage(audio::PlaybackEngine::default());
+            sandbox::allow_library_roots(app.handle());
+            watcher::restart(app.handle());
+            jobs::schedule(app.handle());
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            library::list_songs,
+            library::query_songs,
+            library::get_library_config,
+            library::set_library_config,
+            library::add_library_root,
+            library::remove_library_root,
+            load_analysis,
+            validation::validate_analysis,
+            audio::load_playback,
+            audio::get_playback_state,
+            audio::start_playback,
+            audio::pause_playback,
+            audio::seek_playback,
+            audio::set_playback_loop,
+            audio::set_stem_gain,
+            audio::set_stem_muted,
+            audio::set_stem_solo,
+            audio::set_playback_speed,
+            audio::set_pitch_shift,
+            audio::probe::probe_stems,
+            audio::waveform::get_waveform_peaks,
+            audio::export::export_mix,
+            midi::export_midi,
+            midi::import_midi,
+            notation::musicxml::export_musicxml,
+            notation::tab::compute_tab,
+            notation::tab::export_tab,
+            notation::drums::quantize_drums,
+            notation::drums::export_drums,
+            lyrics::export::export_lyrics,
+            lyrics::edit::edit_lyrics,
+            lyrics::edit::undo_lyrics_edit,
+            lyrics::import::import_lyrics,
+            timeline::query_events,
+            get_stem_path,
+            process_song,
+            jobs::enqueue_song,
+            jobs::cancel_job,
+            jobs::list_jobs,
+            jobs::retry_job,
+            jobs::reanalyze_song,
+            jobs::set_max_concurrent_jobs
+        ])
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
 
-    if separate_drums {
-        args.push("--drum-sep");
+/// A minimal analysis for tests: ten seconds at 44.1 kHz with no stems,
+/// beats or events. Tests set the fields they care about
+#[cfg(test)]
+pub(crate) fn test_analysis() -> SongAnalysis {
+    SongAnalysis {
+        schema_version: migration::CURRENT_SCHEMA_VERSION,
+        title: None,
+        artist: None,
+        album: None,
+        original_duration: 10.0,
+        sample_rate: 44100,
+        tempo_bpm: None,
+        key: None,
+        time_signature: None,
+        stems: std::collections::HashMap::new(),
+        beats: Vec::new(),
+        notes: None,
+        lyrics: None,
+        drum_strikes: None,
+        source_file: "song.mp3".to_string(),
+        processing_date: String::new(),
+        converter_version: String::new(),
     }
+}
 
-    let output = Command::new("uv")
-        .args(&args)
-        .current_dir(project_root)
-        .output()
-        .map_err(|e| format!("Failed to start process: {}", e))?;
-
-    if output.status.success() {
-        Ok(output_dir.to_string())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        Err(format!(
-            "Processing failed:\n{}\n{}",
-            stdout.trim(),
-            stderr.trim()
-        ))
-    }
+/// `count` beats `interval` seconds apart from time 0, with a downbeat
+/// every `per_bar` beats
+#[cfg(test)]
+pub(crate) fn test_beats(count: usize, interval: f64, per_bar: usize) -> Vec<BeatEvent> {
+    (0..count)
+        .map(|i| BeatEvent {
+            time: i as f64 * interval,
+            beat_type: if i % per_bar == 0 { "downbeat" } else { "beat" }.to_string(),
+            beat_in_measure: Some((i % per_bar) as i32 + 1),
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -246,10 +387,12 @@ mod tests {
     #[test]
     fn test_real_file_deserialization() {
         // Test with an actual analysis file
-        let test_path = std::path::Path::new("../../output/murdocks_revenge_of_course_you_die/analysis.json");
+        let test_path =
+            std::path::Path::new("../../output/murdocks_revenge_of_course_you_die/analysis.json");
         if test_path.exists() {
             let content = std::fs::read_to_string(test_path).expect("Should read file");
-            let result: SongAnalysis = serde_json::from_str(&content).expect("Should parse real file");
+            let result: SongAnalysis =
+                serde_json::from_str(&content).expect("Should parse real file");
 
             println!("Title: {:?}", result.title);
             println!("drum_strikes is_some: {}", result.drum_strikes.is_some());
@@ -269,18 +412,3 @@ mod tests {
         }
     }
 }
-
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    tauri::Builder::default()
-        .plugin(tauri_plugin_fs::init())
-        .plugin(tauri_plugin_dialog::init())
-        .invoke_handler(tauri::generate_handler![
-            list_songs,
-            load_analysis,
-            get_stem_path,
-            process_song
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
-}
@@ -0,0 +1,512 @@
+//! Song library: the configured library roots and the scan that finds
+//! processed songs beneath them.
+//!
+//! Songs can be nested at any depth (e.g. `artist/album/song/analysis.json`);
+//! a directory containing `analysis.json` is a song and is not descended into.
+
+use crate::error::{CommandError, CommandResult};
+use crate::index::{SongIndex, SongPage, SongQuery};
+use crate::read_analysis;
+use crate::{sandbox, watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::AppHandle;
+use tauri::Emitter;
+use tauri::Manager;
+
+/// Emitted with a `CommandError` when keeping the library up to date fails
+/// outside any command, such as in the watcher
+pub const LIBRARY_ERROR_EVENT: &str = "library-error";
+
+/// Which directories make up the library and how deep to look in them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LibraryConfig {
+    pub roots: Vec<String>,
+    /// How many directory levels below a root to search for songs
+    pub max_depth: usize,
+    /// File name patterns (`*` and `?` wildcards) of directories to skip
+    pub ignore: Vec<String>,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            roots: vec!["../output".to_string()], // Relative to client/ in development
+            max_depth: 4,
+            ignore: vec![
+                ".*".to_string(),
+                "node_modules".to_string(),
+                "stems".to_string(),
+            ],
+        }
+    }
+}
+
+impl LibraryConfig {
+    pub(crate) fn is_ignored(&self, name: &str) -> bool {
+        self.ignore
+            .iter()
+            .any(|pattern| wildcard_match(pattern, name))
+    }
+}
+
+/// Match `name` against a pattern where `*` matches any run of characters
+/// and `?` matches a single character
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` seen, and the name position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, tried)) = backtrack {
+            p = star + 1;
+            n = tried + 1;
+            backtrack = Some((star, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Finds song directories under one or more roots
+pub struct Scanner<'a> {
+    config: &'a LibraryConfig,
+    /// Canonical paths of directories already scanned, so symlink loops and
+    /// overlapping roots are only visited once
+    visited: HashSet<PathBuf>,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new(config: &'a LibraryConfig) -> Self {
+        Self {
+            config,
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Song directories under `root`, sorted by path
+    pub fn scan(&mut self, root: &Path) -> Vec<PathBuf> {
+        let mut songs = Vec::new();
+        self.walk(root, 0, &mut songs);
+        songs.sort();
+        songs
+    }
+
+    fn walk(&mut self, dir: &Path, depth: usize, songs: &mut Vec<PathBuf>) {
+        let Ok(canonical) = dir.canonicalize() else {
+            return;
+        };
+        if !self.visited.insert(canonical) {
+            return;
+        }
+
+        if dir.join("analysis.json").is_file() {
+            songs.push(dir.to_path_buf());
+            return;
+        }
+        if depth >= self.config.max_depth {
+            return;
+        }
+
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if self.config.is_ignored(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let path = entry.path();
+            // is_dir follows symlinks; loops are caught by `visited`
+            if path.is_dir() {
+                self.walk(&path, depth + 1, songs);
+            }
+        }
+    }
+}
+
+/// Whether a song in the browser can be opened, and if not, why
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SongStatus {
+    #[default]
+    Ok,
+    /// analysis.json couldn't be read or isn't valid JSON
+    Unreadable,
+    /// analysis.json was written by a converter with a different schema
+    IncompatibleSchema,
+    /// One or more stem files listed in analysis.json are missing
+    MissingStems,
+}
+
+impl SongStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SongStatus::Ok => "ok",
+            SongStatus::Unreadable => "unreadable",
+            SongStatus::IncompatibleSchema => "incompatibleSchema",
+            SongStatus::MissingStems => "missingStems",
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "unreadable" => SongStatus::Unreadable,
+            "incompatibleSchema" => SongStatus::IncompatibleSchema,
+            "missingStems" => SongStatus::MissingStems,
+            _ => SongStatus::Ok,
+        }
+    }
+}
+
+/// Summary info for song browser
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SongSummary {
+    pub path: String,
+    /// Library root the song was found under
+    pub root: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: f64,
+    pub tempo_bpm: Option<f64>,
+    pub key: Option<String>,
+    pub time_signature: Option<(i32, i32)>,
+    pub stem_count: usize,
+    /// Stem names, sorted
+    pub stem_names: Vec<String>,
+    /// Stem file paths relative to the song, sorted; kept so the index can
+    /// recheck them without re-reading analysis.json
+    #[serde(skip)]
+    pub stem_files: Vec<String>,
+    pub has_lyrics: bool,
+    pub has_drum_strikes: bool,
+    pub source_file: Option<String>,
+    pub processing_date: Option<String>,
+    pub status: SongStatus,
+    /// What is wrong with the song when `status` isn't `ok`
+    pub diagnostic: Option<String>,
+}
+
+impl SongSummary {
+    /// Summarize the song in `song_path`, reporting rather than skipping
+    /// broken analysis files
+    pub fn load(song_path: &Path, root: &Path) -> Self {
+        let path = song_path.to_string_lossy().to_string();
+        let root = root.to_string_lossy().to_string();
+        let analysis = match read_analysis(&song_path.join("analysis.json")) {
+            Ok(analysis) => analysis,
+            Err(error) => {
+                let status = match error {
+                    CommandError::SchemaVersion { .. } => SongStatus::IncompatibleSchema,
+                    _ => SongStatus::Unreadable,
+                };
+                return SongSummary {
+                    path,
+                    root,
+                    status,
+                    diagnostic: Some(error.to_string()),
+                    ..Default::default()
+                };
+            }
+        };
+
+        let mut stem_files: Vec<String> = analysis
+            .stems
+            .values()
+            .flat_map(|stem| stem.paths.values().cloned())
+            .collect();
+        stem_files.sort();
+        let (status, diagnostic) = stem_files_status(song_path, &stem_files);
+
+        let mut stem_names: Vec<String> = analysis.stems.keys().cloned().collect();
+        stem_names.sort();
+
+        SongSummary {
+            path,
+            root,
+            title: analysis.title,
+            artist: analysis.artist,
+            album: analysis.album,
+            duration: analysis.original_duration,
+            tempo_bpm: analysis.tempo_bpm,
+            key: analysis.key,
+            time_signature: analysis.time_signature,
+            stem_count: stem_names.len(),
+            stem_names,
+            stem_files,
+            has_lyrics: analysis
+                .lyrics
+                .is_some_and(|lyrics| !lyrics.lines.is_empty()),
+            has_drum_strikes: analysis
+                .drum_strikes
+                .is_some_and(|strikes| strikes.values().any(|s| !s.is_empty())),
+            source_file: Some(analysis.source_file),
+            processing_date: Some(analysis.processing_date),
+            status,
+            diagnostic,
+        }
+    }
+}
+
+/// `MissingStems` with a diagnostic if any of `stem_files` (relative to
+/// `song_path`) don't exist, otherwise `Ok`
+pub fn stem_files_status(song_path: &Path, stem_files: &[String]) -> (SongStatus, Option<String>) {
+    let missing: Vec<&str> = stem_files
+        .iter()
+        .filter(|relative| !song_path.join(relative).exists())
+        .map(String::as_str)
+        .collect();
+    if missing.is_empty() {
+        (SongStatus::Ok, None)
+    } else {
+        (
+            SongStatus::MissingStems,
+            Some(format!("Missing stem files: {}", missing.join(", "))),
+        )
+    }
+}
+
+/// Managed state holding the library configuration, saved to `library.json`
+pub struct Library {
+    config: Mutex<LibraryConfig>,
+    store_path: PathBuf,
+}
+
+impl Library {
+    /// Load the configuration saved at `store_path`, or use the defaults
+    pub fn load(store_path: PathBuf) -> Self {
+        let config = fs::read_to_string(&store_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        Self {
+            config: Mutex::new(config),
+            store_path,
+        }
+    }
+
+    pub fn config(&self) -> LibraryConfig {
+        self.config
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Apply `change` to the configuration and persist it
+    fn update(&self, change: impl FnOnce(&mut LibraryConfig)) -> CommandResult<LibraryConfig> {
+        let mut config = self.config.lock().unwrap_or_else(|e| e.into_inner());
+        change(&mut config);
+
+        if let Some(parent) = self.store_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| CommandError::io(parent, e))?;
+        }
+        let content = serde_json::to_string_pretty(&*config).map_err(|e| CommandError::Io {
+            message: e.to_string(),
+        })?;
+        fs::write(&self.store_path, content).map_err(|e| CommandError::io(&self.store_path, e))?;
+        Ok(config.clone())
+    }
+}
+
+/// Tell the frontend about a library failure no command can return
+pub fn report_error(app: &AppHandle, error: CommandError) {
+    let _ = app.emit(LIBRARY_ERROR_EVENT, error);
+}
+
+/// List songs under every library root (or just `dir` when given),
+/// including ones whose analysis can't be loaded
+#[tauri::command]
+pub fn list_songs(app: AppHandle, dir: Option<&str>) -> CommandResult<Vec<SongSummary>> {
+    let config = app.state::<Library>().config();
+    let roots = match dir {
+        Some(dir) => vec![dir.to_string()],
+        None => config.roots.clone(),
+    };
+
+    refresh(&app, &config, &roots)
+}
+
+/// Search, filter, sort and page through the songs in every library root.
+///
+/// Answers from the index alone; `list_songs` rescans the roots and the
+/// watcher keeps the index current in between.
+#[tauri::command]
+pub fn query_songs(app: AppHandle, query: SongQuery) -> CommandResult<SongPage> {
+    let config = app.state::<Library>().config();
+    app.state::<SongIndex>().query(&config.roots, &query)
+}
+
+/// Rescan `roots` and bring the index up to date, returning their songs
+fn refresh(
+    app: &AppHandle,
+    config: &LibraryConfig,
+    roots: &[String],
+) -> CommandResult<Vec<SongSummary>> {
+    let index = app.state::<SongIndex>();
+    let mut scanner = Scanner::new(config);
+    let mut songs = Vec::new();
+    for root in roots {
+        let root = Path::new(root);
+        let song_dirs = scanner.scan(root);
+        songs.extend(index.refresh(root, &song_dirs)?);
+    }
+
+    Ok(songs)
+}
+
+#[tauri::command]
+pub fn get_library_config(app: AppHandle) -> LibraryConfig {
+    app.state::<Library>().config()
+}
+
+#[tauri::command]
+pub fn set_library_config(app: AppHandle, config: LibraryConfig) -> CommandResult<LibraryConfig> {
+    let config = app.state::<Library>().update(|current| *current = config)?;
+    sandbox::allow_library_roots(&app);
+    watcher::restart(&app);
+    Ok(config)
+}
+
+/// Add a directory to the library roots
+#[tauri::command]
+pub fn add_library_root(app: AppHandle, path: &str) -> CommandResult<LibraryConfig> {
+    if !Path::new(path).is_dir() {
+        return Err(CommandError::NotFound {
+            path: path.to_string(),
+        });
+    }
+    let config = app.state::<Library>().update(|config| {
+        if !config.roots.iter().any(|root| root == path) {
+            config.roots.push(path.to_string());
+        }
+    })?;
+    sandbox::allow_library_roots(&app);
+    watcher::restart(&app);
+    Ok(config)
+}
+
+#[tauri::command]
+pub fn remove_library_root(app: AppHandle, path: &str) -> CommandResult<LibraryConfig> {
+    let config = app
+        .state::<Library>()
+        .update(|config| config.roots.retain(|root| root != path))?;
+    sandbox::allow_library_roots(&app);
+    watcher::restart(&app);
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("music-tutor-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn make_song(dir: &Path) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("analysis.json"), "{}").unwrap();
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match(".*", ".git"));
+        assert!(!wildcard_match(".*", "git"));
+        assert!(wildcard_match("*.bak", "song.bak"));
+        assert!(wildcard_match("take?", "take2"));
+        assert!(wildcard_match("a*b*c", "axxbyybc"));
+        assert!(!wildcard_match("a*b*c", "axxbyyb"));
+    }
+
+    #[test]
+    fn test_scan_nested_with_depth_and_ignore() {
+        let root = temp_dir("scan");
+        make_song(&root.join("artist/album/song"));
+        make_song(&root.join("single"));
+        make_song(&root.join(".trash/deleted"));
+        make_song(&root.join("a/b/c/d/too_deep"));
+        // Stems next to an analysis.json are never scanned
+        make_song(&root.join("single/stems/nested"));
+
+        let config = LibraryConfig::default();
+        let songs = Scanner::new(&config).scan(&root);
+        assert_eq!(
+            songs,
+            vec![root.join("artist/album/song"), root.join("single")]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_survives_symlink_loop() {
+        let root = temp_dir("symlink");
+        make_song(&root.join("artist/song"));
+        std::os::unix::fs::symlink(&root, root.join("artist/loop")).unwrap();
+
+        let config = LibraryConfig::default();
+        let songs = Scanner::new(&config).scan(&root);
+        assert_eq!(songs, vec![root.join("artist/song")]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_song_summary_reports_broken_songs() {
+        let dir = std::env::temp_dir().join(format!("music-tutor-summary-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(dir.join("analysis.json"), "{ not json").unwrap();
+        let summary = SongSummary::load(&dir, &dir);
+        assert_eq!(summary.status, SongStatus::Unreadable);
+        assert!(summary.diagnostic.is_some());
+
+        fs::write(dir.join("analysis.json"), r#"{"title": "Old"}"#).unwrap();
+        assert_eq!(
+            SongSummary::load(&dir, &dir).status,
+            SongStatus::IncompatibleSchema
+        );
+
+        fs::write(
+            dir.join("analysis.json"),
+            r#"{
+                "title": "Test", "artist": null, "album": null,
+                "originalDuration": 10.0, "sampleRate": 44100,
+                "tempoBpm": null, "timeSignature": null,
+                "stems": {"bass": {"name": "bass", "paths": {"1.0x": "stems/bass_1.0x.flac"},
+                                   "hasNotes": false, "peakDb": 0.0}},
+                "beats": [], "sourceFile": "test.mp3",
+                "processingDate": "2025-01-01", "converterVersion": "0.1.0"
+            }"#,
+        )
+        .unwrap();
+        let summary = SongSummary::load(&dir, &dir);
+        assert_eq!(summary.status, SongStatus::MissingStems);
+        assert_eq!(summary.title.as_deref(), Some("Test"));
+
+        fs::create_dir_all(dir.join("stems")).unwrap();
+        fs::write(dir.join("stems/bass_1.0x.flac"), b"").unwrap();
+        assert_eq!(SongSummary::load(&dir, &dir).status, SongStatus::Ok);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
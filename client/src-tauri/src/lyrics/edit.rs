@@ -0,0 +1,595 @@
+//! Hand corrections to aligned lyrics.
+//!
+//! Each [`LyricsEdit`] fixes one line's words or timing and saves the
+//! lyrics back to `analysis.json` with `source` set to `"edited"`. The
+//! lyrics as they were before every edit are kept, newest last, in
+//! `lyrics_history.json` next to it, so [`undo_lyrics_edit`] can step back
+//! through them even after the app restarts. The history belongs to one
+//! analysis: it is dropped once the song is re-analyzed.
+
+use super::spread_words;
+use crate::error::{CommandError, CommandResult};
+use crate::{read_analysis, write_analysis, LyricLine, LyricWord, LyricsData, SongAnalysis};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const HISTORY_FILE: &str = "lyrics_history.json";
+
+/// Undo steps kept; older ones are forgotten
+const HISTORY_LIMIT: usize = 100;
+
+/// How far a word start may move when snapping, unless the edit says
+const DEFAULT_SNAP_DISTANCE: f64 = 0.15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SnapTarget {
+    Beats,
+    /// Note starts detected in the vocals stem
+    Onsets,
+}
+
+/// One correction; `line` and `word` are indexes into `LyricsData.lines`
+/// and `LyricLine.words`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum LyricsEdit {
+    /// Replace a line's text. Words keep their timing when the number of
+    /// words is unchanged and are spread across the line otherwise
+    SetLineText { line: usize, text: String },
+    SetWordText {
+        line: usize,
+        word: usize,
+        text: String,
+    },
+    /// Split a word before character `at`, at `time` or in proportion to
+    /// the characters on each side
+    SplitWord {
+        line: usize,
+        word: usize,
+        at: usize,
+        time: Option<f64>,
+    },
+    /// Join a word with the one after it
+    MergeWords { line: usize, word: usize },
+    /// Move a line and its words by `offset` seconds
+    ShiftLine { line: usize, offset: f64 },
+    /// Fit a line and its words into `start`..`end`, keeping their
+    /// relative timing
+    StretchLine { line: usize, start: f64, end: f64 },
+    /// Move word starts onto the nearest beat or onset within
+    /// `max_distance` seconds, in one line or all of them
+    SnapWords {
+        line: Option<usize>,
+        target: SnapTarget,
+        max_distance: Option<f64>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LyricsEditResult {
+    pub lyrics: Option<LyricsData>,
+    /// Edits that can still be undone
+    pub undo_steps: usize,
+}
+
+fn line_mut(lyrics: &mut LyricsData, line: usize) -> CommandResult<&mut LyricLine> {
+    let count = lyrics.lines.len();
+    lyrics
+        .lines
+        .get_mut(line)
+        .ok_or_else(|| CommandError::invalid(format!("line {} of {} doesn't exist", line, count)))
+}
+
+fn check_word(line: &LyricLine, word: usize) -> CommandResult<()> {
+    if word < line.words.len() {
+        Ok(())
+    } else {
+        Err(CommandError::invalid(format!(
+            "word {} of {} in \"{}\" doesn't exist",
+            word,
+            line.words.len(),
+            line.text
+        )))
+    }
+}
+
+fn check_time(seconds: f64, what: &str) -> CommandResult<()> {
+    if seconds.is_finite() && seconds >= 0.0 {
+        Ok(())
+    } else {
+        Err(CommandError::invalid(format!(
+            "{} would be at {} seconds",
+            what, seconds
+        )))
+    }
+}
+
+/// Line text rebuilt from its words after they change
+fn join_words(line: &mut LyricLine) {
+    line.text = line
+        .words
+        .iter()
+        .map(|word| word.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+}
+
+/// Grow a line to cover words that moved outside it
+fn cover_words(line: &mut LyricLine) {
+    if let (Some(first), Some(last)) = (line.words.first(), line.words.last()) {
+        line.start = line.start.min(first.start);
+        line.end = line.end.max(last.end);
+    }
+}
+
+/// Move word starts in `line` to the nearest of `targets` (sorted) within
+/// `max_distance`, keeping the words in order
+fn snap_line(line: &mut LyricLine, targets: &[f64], max_distance: f64) {
+    let mut floor = f64::NEG_INFINITY;
+    for i in 0..line.words.len() {
+        let start = line.words[i].start;
+        let after = targets.partition_point(|time| *time < start);
+        let nearest = [after.checked_sub(1), Some(after)]
+            .into_iter()
+            .flatten()
+            .filter_map(|j| targets.get(j).copied())
+            .filter(|time| *time >= floor && (time - start).abs() <= max_distance)
+            .min_by(|a, b| (a - start).abs().total_cmp(&(b - start).abs()));
+        if let Some(time) = nearest {
+            let word = &mut line.words[i];
+            if time >= word.end {
+                word.end = time + (word.end - word.start);
+            }
+            word.start = time;
+            if i > 0 && line.words[i - 1].end > time {
+                line.words[i - 1].end = time;
+            }
+        }
+        floor = line.words[i].start;
+    }
+    cover_words(line);
+}
+
+/// Times word starts can snap to, sorted
+fn snap_targets(analysis: &SongAnalysis, target: SnapTarget) -> CommandResult<Vec<f64>> {
+    let mut times: Vec<f64> = match target {
+        SnapTarget::Beats => analysis.beats.iter().map(|beat| beat.time).collect(),
+        SnapTarget::Onsets => analysis
+            .notes
+            .as_ref()
+            .and_then(|notes| notes.get("vocals"))
+            .map(|notes| notes.iter().map(|note| note.start).collect())
+            .unwrap_or_default(),
+    };
+    times.retain(|time| time.is_finite());
+    if times.is_empty() {
+        return Err(CommandError::invalid(match target {
+            SnapTarget::Beats => "the song has no detected beats",
+            SnapTarget::Onsets => "the song has no notes detected in the vocals",
+        }));
+    }
+    times.sort_by(f64::total_cmp);
+    Ok(times)
+}
+
+/// Apply one edit to the song's lyrics and mark them as edited
+pub fn apply(analysis: &mut SongAnalysis, edit: &LyricsEdit) -> CommandResult<()> {
+    let targets = match edit {
+        LyricsEdit::SnapWords { target, .. } => snap_targets(analysis, *target)?,
+        _ => Vec::new(),
+    };
+    let lyrics = analysis
+        .lyrics
+        .as_mut()
+        .ok_or_else(|| CommandError::invalid("the song has no lyrics"))?;
+
+    match edit {
+        LyricsEdit::SetLineText { line, text } => {
+            let line = line_mut(lyrics, *line)?;
+            let tokens: Vec<&str> = text.split_whitespace().collect();
+            if tokens.len() == line.words.len() {
+                for (word, token) in line.words.iter_mut().zip(tokens) {
+                    if word.text != token {
+                        word.text = token.to_string();
+                        word.confidence = 1.0;
+                    }
+                }
+            } else {
+                line.words = spread_words(text, line.start, line.end);
+            }
+            line.text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+        LyricsEdit::SetWordText { line, word, text } => {
+            let line = line_mut(lyrics, *line)?;
+            check_word(line, *word)?;
+            let text = text.trim();
+            if text.is_empty() || text.contains(char::is_whitespace) {
+                return Err(CommandError::invalid(
+                    "a word needs text without spaces; split it to make two words",
+                ));
+            }
+            line.words[*word].text = text.to_string();
+            line.words[*word].confidence = 1.0;
+            join_words(line);
+        }
+        LyricsEdit::SplitWord {
+            line,
+            word,
+            at,
+            time,
+        } => {
+            let line = line_mut(lyrics, *line)?;
+            check_word(line, *word)?;
+            let original = &line.words[*word];
+            let chars: Vec<char> = original.text.chars().collect();
+            let left: String = chars[..(*at).min(chars.len())].iter().collect();
+            let right: String = chars[(*at).min(chars.len())..].iter().collect();
+            let (left, right) = (left.trim(), right.trim());
+            if left.is_empty() || right.is_empty() {
+                return Err(CommandError::invalid(format!(
+                    "splitting \"{}\" at {} leaves an empty word",
+                    original.text, at
+                )));
+            }
+            let split = match time {
+                Some(time) if *time >= original.start && *time <= original.end => *time,
+                Some(time) => {
+                    return Err(CommandError::invalid(format!(
+                        "{} is outside \"{}\" ({}–{})",
+                        time, original.text, original.start, original.end
+                    )))
+                }
+                None => {
+                    original.start
+                        + (original.end - original.start) * *at as f64 / chars.len() as f64
+                }
+            };
+            let second = LyricWord {
+                text: right.to_string(),
+                start: split,
+                end: original.end,
+                confidence: original.confidence,
+            };
+            let first = &mut line.words[*word];
+            first.text = left.to_string();
+            first.end = split;
+            line.words.insert(*word + 1, second);
+            join_words(line);
+        }
+        LyricsEdit::MergeWords { line, word } => {
+            let line = line_mut(lyrics, *line)?;
+            check_word(line, *word + 1)?;
+            let next = line.words.remove(*word + 1);
+            let merged = &mut line.words[*word];
+            merged.text.push_str(&next.text);
+            merged.end = merged.end.max(next.end);
+            merged.confidence = merged.confidence.min(next.confidence);
+            join_words(line);
+        }
+        LyricsEdit::ShiftLine { line, offset } => {
+            let line = line_mut(lyrics, *line)?;
+            check_time(line.start + offset, "the line")?;
+            line.start += offset;
+            line.end += offset;
+            for word in &mut line.words {
+                word.start += offset;
+                word.end += offset;
+            }
+        }
+        LyricsEdit::StretchLine { line, start, end } => {
+            let line = line_mut(lyrics, *line)?;
+            check_time(*start, "the line")?;
+            if !end.is_finite() || end < start {
+                return Err(CommandError::invalid(format!(
+                    "a line can't end at {} before it starts at {}",
+                    end, start
+                )));
+            }
+            if line.end > line.start {
+                let scale = (end - start) / (line.end - line.start);
+                let at = |time: f64| start + (time - line.start) * scale;
+                for word in &mut line.words {
+                    word.start = at(word.start);
+                    word.end = at(word.end);
+                }
+            } else {
+                let text = line
+                    .words
+                    .iter()
+                    .map(|word| word.text.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                for (word, spread) in line.words.iter_mut().zip(spread_words(&text, *start, *end)) {
+                    word.start = spread.start;
+                    word.end = spread.end;
+                }
+            }
+            line.start = *start;
+            line.end = *end;
+        }
+        LyricsEdit::SnapWords {
+            line, max_distance, ..
+        } => {
+            let max_distance = max_distance.unwrap_or(DEFAULT_SNAP_DISTANCE);
+            match line {
+                Some(line) => snap_line(line_mut(lyrics, *line)?, &targets, max_distance),
+                None => {
+                    for line in &mut lyrics.lines {
+                        snap_line(line, &targets, max_distance);
+                    }
+                }
+            }
+        }
+    }
+
+    // Timing edits can move a line past its neighbours
+    lyrics.lines.sort_by(|a, b| a.start.total_cmp(&b.start));
+    lyrics.source = "edited".to_string();
+    Ok(())
+}
+
+/// Contents of `lyrics_history.json`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct History {
+    /// `processingDate` of the analysis the edits were made to
+    processing_date: String,
+    /// Lyrics before each saved edit, oldest first
+    steps: Vec<Option<LyricsData>>,
+}
+
+/// Undo steps for `analysis`; a history left from an earlier analysis of
+/// the song is ignored
+fn read_history(path: &Path, analysis: &SongAnalysis) -> CommandResult<Vec<Option<LyricsData>>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(path).map_err(|e| CommandError::io(path, e))?;
+    let history: History =
+        serde_json::from_str(&content).map_err(|e| CommandError::json(path, &content, e))?;
+    if history.processing_date != analysis.processing_date {
+        return Ok(Vec::new());
+    }
+    Ok(history.steps)
+}
+
+fn write_history(
+    path: &Path,
+    analysis: &SongAnalysis,
+    steps: Vec<Option<LyricsData>>,
+) -> CommandResult<()> {
+    let history = History {
+        processing_date: analysis.processing_date.clone(),
+        steps,
+    };
+    let content = serde_json::to_string(&history).map_err(|e| CommandError::Io {
+        message: e.to_string(),
+    })?;
+    std::fs::write(path, content).map_err(|e| CommandError::io(path, e))
+}
+
+/// Forget a song's undo steps, for when its analysis is rewritten
+pub fn clear_history(song_dir: &Path) -> CommandResult<()> {
+    let path = song_dir.join(HISTORY_FILE);
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(CommandError::io(&path, e)),
+        _ => Ok(()),
+    }
+}
+
+/// Save `analysis`, remembering `previous` lyrics as the newest undo step;
+/// returns the number of steps
+pub(super) fn save(
+    song_dir: &Path,
+    analysis: &SongAnalysis,
+    previous: Option<LyricsData>,
+) -> CommandResult<usize> {
+    let history_path = song_dir.join(HISTORY_FILE);
+    let mut history = read_history(&history_path, analysis)?;
+    history.push(previous);
+    if history.len() > HISTORY_LIMIT {
+        history.drain(..history.len() - HISTORY_LIMIT);
+    }
+    let undo_steps = history.len();
+    // History first: if the analysis write fails, undo just restores the
+    // lyrics that are still there
+    write_history(&history_path, analysis, history)?;
+    write_analysis(&song_dir.join("analysis.json"), analysis)?;
+    Ok(undo_steps)
+}
+
+/// Apply a correction to a song's lyrics and save it, keeping the previous
+/// lyrics for undo
+#[tauri::command]
+pub fn edit_lyrics(song_dir: &str, edit: LyricsEdit) -> CommandResult<LyricsEditResult> {
+    let song_dir = Path::new(song_dir);
+    let mut analysis = read_analysis(&song_dir.join("analysis.json"))?;
+    let previous = analysis.lyrics.clone();
+    apply(&mut analysis, &edit)?;
+    let undo_steps = save(song_dir, &analysis, previous)?;
+    Ok(LyricsEditResult {
+        lyrics: analysis.lyrics,
+        undo_steps,
+    })
+}
+
+/// Restore the lyrics as they were before the most recent edit
+#[tauri::command]
+pub fn undo_lyrics_edit(song_dir: &str) -> CommandResult<LyricsEditResult> {
+    let song_dir = Path::new(song_dir);
+    let analysis_path = song_dir.join("analysis.json");
+    let history_path = song_dir.join(HISTORY_FILE);
+    let mut analysis = read_analysis(&analysis_path)?;
+    let mut history = read_history(&history_path, &analysis)?;
+    let previous = history
+        .pop()
+        .ok_or_else(|| CommandError::invalid("there is no lyrics edit to undo"))?;
+
+    analysis.lyrics = previous;
+    let undo_steps = history.len();
+    write_analysis(&analysis_path, &analysis)?;
+    write_history(&history_path, &analysis, history)?;
+    Ok(LyricsEditResult {
+        lyrics: analysis.lyrics,
+        undo_steps,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_analysis;
+
+    fn analysis() -> SongAnalysis {
+        let mut song = test_analysis();
+        song.beats = serde_json::from_value(serde_json::json!([
+            {"time": 1.0, "type": "downbeat", "beatInMeasure": 1},
+            {"time": 2.0, "type": "beat", "beatInMeasure": 2},
+            {"time": 3.0, "type": "beat", "beatInMeasure": 3}
+        ]))
+        .unwrap();
+        song.notes = serde_json::from_value(serde_json::json!({"vocals": [
+            {"start": 1.05, "end": 1.5, "pitch": 60, "velocity": 0.5},
+            {"start": 1.45, "end": 2.0, "pitch": 62, "velocity": 0.5}
+        ]}))
+        .unwrap();
+        song.lyrics =
+            serde_json::from_value(serde_json::json!({"source": "transcribed", "lines": [
+                {"text": "hello wold", "start": 1.0, "end": 2.0, "words": [
+                    {"text": "hello", "start": 1.0, "end": 1.5, "confidence": 0.9},
+                    {"text": "wold", "start": 1.5, "end": 2.0, "confidence": 0.4}
+                ]},
+                {"text": "some thing", "start": 4.0, "end": 5.0, "words": [
+                    {"text": "some", "start": 4.0, "end": 4.5, "confidence": 0.8},
+                    {"text": "thing", "start": 4.5, "end": 5.0, "confidence": 0.6}
+                ]}
+            ]}))
+            .unwrap();
+        song
+    }
+
+    fn words(analysis: &SongAnalysis, line: usize) -> Vec<(String, f64, f64)> {
+        analysis.lyrics.as_ref().unwrap().lines[line]
+            .words
+            .iter()
+            .map(|word| (word.text.clone(), word.start, word.end))
+            .collect()
+    }
+
+    fn edit(analysis: &mut SongAnalysis, edit: serde_json::Value) {
+        apply(analysis, &serde_json::from_value(edit).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_text_edits() {
+        let mut song = analysis();
+        edit(
+            &mut song,
+            serde_json::json!({"op": "setWordText", "line": 0, "word": 1, "text": "world"}),
+        );
+        let lyrics = song.lyrics.as_ref().unwrap();
+        assert_eq!(lyrics.source, "edited");
+        assert_eq!(lyrics.lines[0].text, "hello world");
+        assert_eq!(lyrics.lines[0].words[1].confidence, 1.0);
+
+        edit(
+            &mut song,
+            serde_json::json!({"op": "mergeWords", "line": 1, "word": 0}),
+        );
+        assert_eq!(words(&song, 1), vec![("something".to_string(), 4.0, 5.0)]);
+        edit(
+            &mut song,
+            serde_json::json!({"op": "splitWord", "line": 1, "word": 0, "at": 4}),
+        );
+        assert_eq!(
+            words(&song, 1),
+            vec![
+                ("some".to_string(), 4.0, 4.444444444444445),
+                ("thing".to_string(), 4.444444444444445, 5.0)
+            ]
+        );
+
+        // A different number of words is spread across the line
+        edit(
+            &mut song,
+            serde_json::json!({"op": "setLineText", "line": 0, "text": "hi  there all"}),
+        );
+        assert_eq!(song.lyrics.as_ref().unwrap().lines[0].text, "hi there all");
+        assert_eq!(words(&song, 0)[1], ("there".to_string(), 1.2, 1.7));
+
+        let bad = serde_json::json!({"op": "mergeWords", "line": 0, "word": 2});
+        assert!(apply(&mut song, &serde_json::from_value(bad).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_timing_edits() {
+        let mut song = analysis();
+        edit(
+            &mut song,
+            serde_json::json!({"op": "snapWords", "line": 0, "target": "onsets"}),
+        );
+        assert_eq!(
+            words(&song, 0),
+            vec![
+                ("hello".to_string(), 1.05, 1.45),
+                ("wold".to_string(), 1.45, 2.0)
+            ]
+        );
+
+        // Moving the first line after the second reorders them
+        edit(
+            &mut song,
+            serde_json::json!({"op": "shiftLine", "line": 0, "offset": 5.0}),
+        );
+        assert_eq!(words(&song, 1)[0], ("hello".to_string(), 6.05, 6.45));
+        edit(
+            &mut song,
+            serde_json::json!({"op": "stretchLine", "line": 0, "start": 4.0, "end": 6.0}),
+        );
+        assert_eq!(
+            words(&song, 0),
+            vec![
+                ("some".to_string(), 4.0, 5.0),
+                ("thing".to_string(), 5.0, 6.0)
+            ]
+        );
+
+        let early = serde_json::json!({"op": "shiftLine", "line": 0, "offset": -5.0});
+        assert!(apply(&mut song, &serde_json::from_value(early).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_history_follows_the_analysis() {
+        let dir =
+            std::env::temp_dir().join(format!("music-tutor-lyrics-edit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let analysis_path = dir.join("analysis.json");
+        let mut song = analysis();
+        song.processing_date = "2024-01-01".to_string();
+        write_analysis(&analysis_path, &song).unwrap();
+        let song_dir = dir.to_str().unwrap();
+
+        let fix = serde_json::json!({"op": "setWordText", "line": 0, "word": 1, "text": "world"});
+        let result = edit_lyrics(song_dir, serde_json::from_value(fix.clone()).unwrap()).unwrap();
+        assert_eq!(result.undo_steps, 1);
+        let result = undo_lyrics_edit(song_dir).unwrap();
+        assert_eq!(result.undo_steps, 0);
+        assert_eq!(result.lyrics.unwrap().lines[0].words[1].text, "wold");
+
+        // Re-analyzed behind the history's back: its steps no longer apply
+        edit_lyrics(song_dir, serde_json::from_value(fix.clone()).unwrap()).unwrap();
+        song.processing_date = "2024-02-01".to_string();
+        write_analysis(&analysis_path, &song).unwrap();
+        assert!(undo_lyrics_edit(song_dir).is_err());
+
+        // Clearing forgets them outright
+        edit_lyrics(song_dir, serde_json::from_value(fix).unwrap()).unwrap();
+        clear_history(&dir).unwrap();
+        assert!(undo_lyrics_edit(song_dir).is_err());
+        clear_history(&dir).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
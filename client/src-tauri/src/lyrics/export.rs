@@ -0,0 +1,320 @@
+//! Lyrics export as LRC, SRT or WebVTT.
+//!
+//! Every line becomes an LRC line or a subtitle cue. Word timing is kept
+//! where the format has room for it: enhanced LRC (A2) `<mm:ss.xx>` tags
+//! before each word, and WebVTT cue timestamps that karaoke-style players
+//! highlight word by word. SRT has none, so it carries lines only. Times
+//! can be scaled to one of the slowed-down or sped-up renderings.
+
+use crate::audio::probe::parse_speed;
+use crate::error::{CommandError, CommandResult};
+use crate::{read_analysis, LyricLine, SongAnalysis};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::path::Path;
+
+/// How long a cue stays up when its line has no length, unless the next
+/// line starts sooner
+const MIN_CUE_SECONDS: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LyricsFormat {
+    Lrc,
+    Srt,
+    Vtt,
+}
+
+impl LyricsFormat {
+    fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "lrc" => Some(LyricsFormat::Lrc),
+            "srt" => Some(LyricsFormat::Srt),
+            "vtt" => Some(LyricsFormat::Vtt),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LyricsExport {
+    pub output_path: String,
+    /// Taken from the output extension when missing
+    pub format: Option<LyricsFormat>,
+    /// Rendering the times should match (`"1.0x"` by default)
+    pub speed: Option<String>,
+    /// Include per-word timing where the format allows (default true)
+    pub word_timing: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LyricsExportResult {
+    pub path: String,
+    pub lines: usize,
+    /// Words written with their own timestamp
+    pub timed_words: usize,
+}
+
+/// `mm:ss.xx`, as LRC writes times
+fn lrc_time(seconds: f64) -> String {
+    let centis = (seconds.max(0.0) * 100.0).round() as u64;
+    format!(
+        "{:02}:{:02}.{:02}",
+        centis / 6000,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
+
+/// `hh:mm:ss<separator>mmm`, as SRT (`,`) and WebVTT (`.`) write times
+fn cue_time(seconds: f64, separator: char) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        millis % 1000
+    )
+}
+
+/// A line's text on one line, as every format needs
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Lines worth writing, with cue ends that give empty spans some length
+fn cues(lines: &[LyricLine]) -> Vec<(&LyricLine, f64)> {
+    let lines: Vec<&LyricLine> = lines
+        .iter()
+        .filter(|line| !line.text.trim().is_empty() || !line.words.is_empty())
+        .collect();
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let end = if line.end > line.start {
+                line.end
+            } else {
+                let next = lines.get(i + 1).map_or(f64::INFINITY, |next| next.start);
+                (line.start + MIN_CUE_SECONDS).min(next).max(line.start)
+            };
+            (*line, end)
+        })
+        .collect()
+}
+
+/// Text of a line, from its words when it has them
+fn line_text(line: &LyricLine) -> String {
+    if line.text.trim().is_empty() {
+        one_line(
+            &line
+                .words
+                .iter()
+                .map(|word| word.text.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    } else {
+        one_line(&line.text)
+    }
+}
+
+/// Render lyrics in `format`, with times divided by `speed`; returns the
+/// text and the number of lines and timed words
+pub fn render(
+    analysis: &SongAnalysis,
+    format: LyricsFormat,
+    speed: f64,
+    word_timing: bool,
+) -> CommandResult<(String, usize, usize)> {
+    let lyrics = analysis
+        .lyrics
+        .as_ref()
+        .filter(|lyrics| !lyrics.lines.is_empty())
+        .ok_or_else(|| CommandError::invalid("the song has no lyrics"))?;
+    let at = |seconds: f64| seconds / speed;
+    let cues = cues(&lyrics.lines);
+    let mut timed_words = 0;
+    let mut out = String::new();
+
+    match format {
+        LyricsFormat::Lrc => {
+            for (tag, value) in [
+                ("ti", &analysis.title),
+                ("ar", &analysis.artist),
+                ("al", &analysis.album),
+            ] {
+                if let Some(value) = value {
+                    let _ = writeln!(out, "[{}:{}]", tag, one_line(value));
+                }
+            }
+            let length = at(analysis.original_duration).round() as u64;
+            let _ = writeln!(out, "[length:{:02}:{:02}]", length / 60, length % 60);
+            for (line, end) in &cues {
+                let _ = write!(out, "[{}]", lrc_time(at(line.start)));
+                if word_timing && !line.words.is_empty() {
+                    for (i, word) in line.words.iter().enumerate() {
+                        if i > 0 {
+                            out.push(' ');
+                        }
+                        let _ = write!(
+                            out,
+                            "<{}>{}",
+                            lrc_time(at(word.start)),
+                            one_line(&word.text)
+                        );
+                        timed_words += 1;
+                    }
+                    // The closing tag marks when the last word ends
+                    let last = line.words.last().map_or(*end, |word| word.end);
+                    let _ = write!(out, " <{}>", lrc_time(at(last)));
+                } else {
+                    out.push_str(&line_text(line));
+                }
+                out.push('\n');
+            }
+        }
+        LyricsFormat::Srt => {
+            for (i, (line, end)) in cues.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "{}\n{} --> {}\n{}\n",
+                    i + 1,
+                    cue_time(at(line.start), ','),
+                    cue_time(at(*end), ','),
+                    line_text(line)
+                );
+            }
+        }
+        LyricsFormat::Vtt => {
+            out.push_str("WEBVTT\n\n");
+            for (i, (line, end)) in cues.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "{}\n{} --> {}",
+                    i + 1,
+                    cue_time(at(line.start), '.'),
+                    cue_time(at(*end), '.')
+                );
+                if word_timing && !line.words.is_empty() {
+                    for (j, word) in line.words.iter().enumerate() {
+                        if j > 0 {
+                            out.push(' ');
+                        }
+                        // The first word shows with the cue; later ones get
+                        // a timestamp to light up at
+                        if j > 0 && word.start > line.start {
+                            let _ = write!(out, "<{}>", cue_time(at(word.start), '.'));
+                            timed_words += 1;
+                        }
+                        out.push_str(&escape_vtt(&one_line(&word.text)));
+                    }
+                    out.push_str("\n\n");
+                } else {
+                    let _ = writeln!(out, "{}\n", escape_vtt(&line_text(line)));
+                }
+            }
+        }
+    }
+    Ok((out, cues.len(), timed_words))
+}
+
+/// WebVTT cue text treats `<` and `&` as markup
+fn escape_vtt(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Write a song's lyrics as LRC (with enhanced word tags), SRT or WebVTT,
+/// optionally timed for one of the speed renderings
+#[tauri::command]
+pub fn export_lyrics(song_dir: &str, export: LyricsExport) -> CommandResult<LyricsExportResult> {
+    let analysis = read_analysis(&Path::new(song_dir).join("analysis.json"))?;
+    let path = Path::new(&export.output_path);
+    let format = export
+        .format
+        .or_else(|| LyricsFormat::from_extension(path))
+        .ok_or_else(|| {
+            CommandError::invalid("choose a format or an .lrc, .srt or .vtt file name")
+        })?;
+    let speed = match &export.speed {
+        Some(label) => parse_speed(label).ok_or_else(|| {
+            CommandError::invalid(format!("\"{}\" is not a speed like \"0.75x\"", label))
+        })?,
+        None => 1.0,
+    };
+
+    let (text, lines, timed_words) =
+        render(&analysis, format, speed, export.word_timing.unwrap_or(true))?;
+    std::fs::write(path, text).map_err(|e| CommandError::io(path, e))?;
+    Ok(LyricsExportResult {
+        path: export.output_path,
+        lines,
+        timed_words,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_analysis;
+
+    fn analysis() -> SongAnalysis {
+        let mut song = test_analysis();
+        song.title = Some("Song".to_string());
+        song.artist = Some("Band".to_string());
+        song.original_duration = 125.0;
+        song.lyrics =
+            serde_json::from_value(serde_json::json!({"source": "transcribed", "lines": [
+                {"text": "hello  world", "start": 1.0, "end": 2.5, "words": [
+                    {"text": "hello", "start": 1.0, "end": 1.5, "confidence": 0.9},
+                    {"text": "world", "start": 1.75, "end": 2.5, "confidence": 0.9}
+                ]},
+                {"text": "", "start": 3.0, "end": 3.0, "words": []},
+                {"text": "rock & roll", "start": 61.0, "end": 61.0, "words": []}
+            ]}))
+            .unwrap();
+        song
+    }
+
+    #[test]
+    fn test_time_formats() {
+        assert_eq!(lrc_time(61.234), "01:01.23");
+        assert_eq!(cue_time(3723.4567, ','), "01:02:03,457");
+    }
+
+    #[test]
+    fn test_renders_each_format_at_speed() {
+        let (lrc, lines, words) = render(&analysis(), LyricsFormat::Lrc, 0.5, true).unwrap();
+        assert_eq!((lines, words), (2, 2));
+        assert_eq!(
+            lrc,
+            "[ti:Song]\n[ar:Band]\n[length:04:10]\n\
+             [00:02.00]<00:02.00>hello <00:03.50>world <00:05.00>\n\
+             [02:02.00]rock & roll\n"
+        );
+
+        let (srt, _, words) = render(&analysis(), LyricsFormat::Srt, 1.0, true).unwrap();
+        assert_eq!(words, 0);
+        assert_eq!(
+            srt,
+            "1\n00:00:01,000 --> 00:00:02,500\nhello world\n\n\
+             2\n00:01:01,000 --> 00:01:03,000\nrock & roll\n\n"
+        );
+
+        let (vtt, _, words) = render(&analysis(), LyricsFormat::Vtt, 1.0, true).unwrap();
+        assert_eq!(words, 1);
+        assert!(vtt.starts_with("WEBVTT\n\n1\n00:00:01.000 --> 00:00:02.500\n"));
+        assert!(vtt.contains("hello <00:00:01.750>world\n"));
+        assert!(vtt.contains("rock &amp; roll"));
+
+        let (lrc, _, words) = render(&analysis(), LyricsFormat::Lrc, 1.0, false).unwrap();
+        assert_eq!(words, 0);
+        assert!(lrc.contains("[00:01.00]hello world\n"));
+    }
+}
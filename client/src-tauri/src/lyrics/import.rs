@@ -0,0 +1,378 @@
+//! Lyrics import from LRC or plain text files.
+//!
+//! LRC lines carry `[mm:ss.xx]` start times, several for a repeated line,
+//! and enhanced (A2) `<mm:ss.xx>` tags for the words that follow them.
+//! Words without a tag share the time up to the next tag or line, in
+//! proportion to their length. Plain text has no timing at all, so its
+//! lines are spread the same way across the part of the song where vocals
+//! were detected.
+//!
+//! The imported lyrics replace any in `analysis.json` and the old ones
+//! become an undo step, as after an edit.
+
+use super::edit;
+use super::spread_words;
+use crate::error::{CommandError, CommandResult};
+use crate::{read_analysis, LyricLine, LyricWord, LyricsData, SongAnalysis};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Longest an untimed word is held when its line would otherwise run on
+/// until the next one, e.g. across an instrumental break
+const MAX_WORD_SECONDS: f64 = 1.0;
+
+/// Time of a word tag (none before the first) and the text after it
+type Segment = (Option<f64>, String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LyricsFileFormat {
+    Lrc,
+    Txt,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LyricsImport {
+    pub input_path: String,
+    /// LRC when the file has timestamped lines, plain text otherwise
+    pub format: Option<LyricsFileFormat>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LyricsImportResult {
+    pub format: LyricsFileFormat,
+    pub lines: usize,
+    /// Words whose start came from the file rather than being spread
+    pub timed_words: usize,
+    /// Lyrics edits, including this import, that can be undone
+    pub undo_steps: usize,
+}
+
+/// Seconds of an LRC time such as `01:02.50`, `01:02:50` or `01:02`
+fn lrc_time(tag: &str) -> Option<f64> {
+    let (minutes, seconds) = tag.trim().split_once(':')?;
+    let minutes: u32 = minutes.parse().ok()?;
+    let seconds = seconds.replacen(':', ".", 1);
+    if !seconds.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let seconds: f64 = seconds.parse().ok()?;
+    Some(minutes as f64 * 60.0 + seconds)
+}
+
+/// Text of an LRC line split at its word tags
+fn segments(text: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut time = None;
+    let mut current = String::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('<') {
+        let tag = rest[open + 1..]
+            .find('>')
+            .and_then(|close| Some((lrc_time(&rest[open + 1..open + 1 + close])?, close)));
+        match tag {
+            Some((tag_time, close)) => {
+                current.push_str(&rest[..open]);
+                segments.push((time, std::mem::take(&mut current)));
+                time = Some(tag_time);
+                rest = &rest[open + close + 2..];
+            }
+            None => {
+                current.push_str(&rest[..=open]);
+                rest = &rest[open + 1..];
+            }
+        }
+    }
+    current.push_str(rest);
+    segments.push((time, current));
+    segments
+}
+
+/// Words of a line starting at `start` and ending by `end`; returns them
+/// with the number that start on a tag
+fn timed_words(segments: &[Segment], start: f64, end: f64) -> (Vec<LyricWord>, usize) {
+    let mut words: Vec<LyricWord> = Vec::new();
+    let mut timed = 0;
+    let mut after_space = true;
+    for (i, (time, text)) in segments.iter().enumerate() {
+        let segment_start = time.unwrap_or(start);
+        let segment_end = segments[i + 1..]
+            .iter()
+            .find_map(|(time, _)| *time)
+            .unwrap_or(end)
+            .max(segment_start);
+        let mut spread = spread_words(text, segment_start, segment_end);
+        // A tag inside a word times a syllable, not a new word
+        if !after_space && !text.starts_with(char::is_whitespace) && !spread.is_empty() {
+            if let Some(last) = words.last_mut() {
+                let syllable = spread.remove(0);
+                last.text.push_str(&syllable.text);
+                last.end = syllable.end;
+            }
+        } else if time.is_some() && !spread.is_empty() {
+            timed += 1;
+        }
+        words.extend(spread);
+        if !text.is_empty() {
+            after_space = text.ends_with(char::is_whitespace);
+        }
+    }
+    (words, timed)
+}
+
+/// Lines of an LRC file, sorted, with the number of tagged words
+pub fn parse_lrc(content: &str) -> (Vec<LyricLine>, usize) {
+    let mut offset = 0.0;
+    // Start time and word segments of every line, including empty ones
+    // that only end the line before
+    let mut entries: Vec<(f64, Vec<Segment>)> = Vec::new();
+    for raw in content.lines() {
+        let mut rest = raw.trim();
+        let mut times = Vec::new();
+        while let Some((tag, after)) = rest.strip_prefix('[').and_then(|tag| tag.split_once(']')) {
+            if let Some(time) = lrc_time(tag) {
+                times.push(time);
+            } else if let Some((key, value)) = tag.split_once(':') {
+                // A positive offset (in ms) shows the lyrics sooner
+                if key.trim().eq_ignore_ascii_case("offset") {
+                    offset = value.trim().parse::<f64>().unwrap_or(0.0) / 1000.0;
+                }
+            }
+            rest = after.trim_start();
+        }
+        let Some(first) = times.first().copied() else {
+            continue;
+        };
+        let segments = segments(rest);
+        for time in &times {
+            // A repeated line's word tags follow its first time
+            let shifted = segments
+                .iter()
+                .map(|(tag, text)| (tag.map(|tag| tag - first + time), text.clone()))
+                .collect();
+            entries.push((*time, shifted));
+        }
+    }
+    for (start, segments) in &mut entries {
+        *start = (*start - offset).max(0.0);
+        for (tag, _) in segments.iter_mut() {
+            *tag = tag.map(|tag| (tag - offset).max(0.0));
+        }
+    }
+    entries.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut lines = Vec::new();
+    let mut timed = 0;
+    for (i, (start, segments)) in entries.iter().enumerate() {
+        let count: usize = segments
+            .iter()
+            .map(|(_, text)| text.split_whitespace().count())
+            .sum();
+        if count == 0 {
+            continue;
+        }
+        let end = match segments.last() {
+            // A closing tag after the last word says when it ends
+            Some((Some(tag), text)) if text.trim().is_empty() => tag.max(*start),
+            _ => {
+                let next = entries.get(i + 1).map_or(f64::INFINITY, |entry| entry.0);
+                let last_tag = segments
+                    .iter()
+                    .filter_map(|(tag, _)| *tag)
+                    .fold(*start, f64::max);
+                next.min(start + MAX_WORD_SECONDS * count as f64)
+                    .max(last_tag)
+            }
+        };
+        let (words, tagged) = timed_words(segments, *start, end);
+        timed += tagged;
+        let text: String = segments.iter().map(|(_, text)| text.as_str()).collect();
+        lines.push(LyricLine {
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+            start: *start,
+            end,
+            words,
+        });
+    }
+    (lines, timed)
+}
+
+/// Plain text lines spread across `start`..`end` in proportion to their
+/// length
+pub fn spread_lines(content: &str, start: f64, end: f64) -> Vec<LyricLine> {
+    let texts: Vec<String> = content
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect();
+    let total: usize = texts.iter().map(|text| text.chars().count()).sum();
+    let at = |chars: usize| start + (end - start).max(0.0) * chars as f64 / total as f64;
+    let mut offset = 0;
+    texts
+        .into_iter()
+        .map(|text| {
+            let line_start = at(offset);
+            offset += text.chars().count();
+            let line_end = at(offset);
+            LyricLine {
+                words: spread_words(&text, line_start, line_end),
+                text,
+                start: line_start,
+                end: line_end,
+            }
+        })
+        .collect()
+}
+
+/// Where plain text lyrics go: from the first to the last detected vocal
+/// note, or the whole song
+fn vocal_span(analysis: &SongAnalysis) -> (f64, f64) {
+    let vocals = analysis
+        .notes
+        .as_ref()
+        .and_then(|notes| notes.get("vocals"))
+        .filter(|notes| !notes.is_empty());
+    match vocals {
+        Some(notes) => (
+            notes
+                .iter()
+                .map(|note| note.start)
+                .fold(f64::INFINITY, f64::min),
+            notes
+                .iter()
+                .map(|note| note.end)
+                .fold(f64::NEG_INFINITY, f64::max),
+        ),
+        None => (0.0, analysis.original_duration),
+    }
+}
+
+/// Lyrics parsed from `content`, with the format used and the number of
+/// tagged words
+pub fn parse(
+    analysis: &SongAnalysis,
+    content: &str,
+    format: Option<LyricsFileFormat>,
+) -> (LyricsData, LyricsFileFormat, usize) {
+    let content = content.trim_start_matches('\u{feff}');
+    let (lines, timed) = match format {
+        Some(LyricsFileFormat::Txt) => (Vec::new(), 0),
+        _ => parse_lrc(content),
+    };
+    let (format, lines, timed) = if format == Some(LyricsFileFormat::Lrc) || !lines.is_empty() {
+        (LyricsFileFormat::Lrc, lines, timed)
+    } else {
+        let (start, end) = vocal_span(analysis);
+        (LyricsFileFormat::Txt, spread_lines(content, start, end), 0)
+    };
+    let source = match format {
+        LyricsFileFormat::Lrc => "lrc",
+        LyricsFileFormat::Txt => "txt",
+    };
+    (
+        LyricsData {
+            source: source.to_string(),
+            lines,
+        },
+        format,
+        timed,
+    )
+}
+
+/// Replace a song's lyrics with an LRC or plain text file, keeping the old
+/// lyrics as an undo step. Stems and other analysis are left as they are
+#[tauri::command]
+pub fn import_lyrics(song_dir: &str, import: LyricsImport) -> CommandResult<LyricsImportResult> {
+    let song_dir = Path::new(song_dir);
+    let mut analysis = read_analysis(&song_dir.join("analysis.json"))?;
+    let input_path = Path::new(&import.input_path);
+    let bytes = std::fs::read(input_path).map_err(|e| CommandError::io(input_path, e))?;
+    let (lyrics, format, timed_words) =
+        parse(&analysis, &String::from_utf8_lossy(&bytes), import.format);
+    if lyrics.lines.is_empty() {
+        return Err(CommandError::invalid(format!(
+            "no lyrics found in {}",
+            input_path.display()
+        )));
+    }
+
+    let lines = lyrics.lines.len();
+    let previous = analysis.lyrics.replace(lyrics);
+    let undo_steps = edit::save(song_dir, &analysis, previous)?;
+    Ok(LyricsImportResult {
+        format,
+        lines,
+        timed_words,
+        undo_steps,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_analysis;
+
+    fn words(line: &LyricLine) -> Vec<(&str, f64, f64)> {
+        line.words
+            .iter()
+            .map(|word| (word.text.as_str(), word.start, word.end))
+            .collect()
+    }
+
+    #[test]
+    fn test_parses_lrc_lines_and_word_tags() {
+        let content = "[ar:Band]\n[offset:+500]\n\
+             [00:10.50]<00:10.50>Hel<00:11.00>lo <00:11.50>big world <00:13.50>\n\
+             [00:20.00][00:40.00]la la\n\
+             [00:30.00]\n\
+             not a lyric line\n";
+        let (lines, timed) = parse_lrc(content);
+        assert_eq!(timed, 2);
+        assert_eq!(lines.len(), 3);
+
+        assert_eq!(lines[0].text, "Hello big world");
+        assert_eq!((lines[0].start, lines[0].end), (10.0, 13.0));
+        assert_eq!(
+            words(&lines[0]),
+            vec![
+                ("Hello", 10.0, 11.0),
+                ("big", 11.0, 11.75),
+                ("world", 11.75, 13.0)
+            ]
+        );
+
+        // Untimed words share at most a second each before the next line
+        assert_eq!((lines[1].start, lines[1].end), (19.5, 21.5));
+        assert_eq!(
+            words(&lines[1]),
+            vec![("la", 19.5, 20.5), ("la", 20.5, 21.5)]
+        );
+        assert_eq!(lines[2].start, 39.5);
+    }
+
+    #[test]
+    fn test_spreads_plain_text_over_the_vocals() {
+        let mut analysis = test_analysis();
+        analysis.original_duration = 60.0;
+        analysis.notes = serde_json::from_value(serde_json::json!({"vocals": [
+            {"start": 10.0, "end": 11.0, "pitch": 60, "velocity": 0.5},
+            {"start": 15.0, "end": 20.0, "pitch": 62, "velocity": 0.5}
+        ]}))
+        .unwrap();
+        let (lyrics, format, _) = parse(&analysis, "ab cd\n\n  ef   gh  \n", None);
+        assert_eq!(format, LyricsFileFormat::Txt);
+        assert_eq!(lyrics.source, "txt");
+        let lines: Vec<_> = lyrics
+            .lines
+            .iter()
+            .map(|line| (line.text.as_str(), line.start, line.end))
+            .collect();
+        assert_eq!(lines, vec![("ab cd", 10.0, 15.0), ("ef gh", 15.0, 20.0)]);
+        assert_eq!(
+            words(&lyrics.lines[0]),
+            vec![("ab", 10.0, 12.5), ("cd", 12.5, 15.0)]
+        );
+    }
+}
@@ -0,0 +1,35 @@
+//! Working with a song's aligned lyrics outside the lyrics view.
+//!
+//! [`export`] writes `LyricsData` as LRC, SRT or WebVTT for karaoke players
+//! and video editors. [`import`] replaces them with an LRC or plain text
+//! file, and [`edit`] applies hand corrections to the words and their
+//! timing; both can be undone.
+
+pub mod edit;
+pub mod export;
+pub mod import;
+
+use crate::LyricWord;
+
+/// Words of `text` spread across `start`..`end`, each given time in
+/// proportion to its length
+fn spread_words(text: &str, start: f64, end: f64) -> Vec<LyricWord> {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let total: usize = tokens.iter().map(|token| token.chars().count()).sum();
+    let span = (end - start).max(0.0);
+    let mut offset = 0;
+    tokens
+        .into_iter()
+        .map(|token| {
+            let at = |chars: usize| start + span * chars as f64 / total as f64;
+            let word_start = at(offset);
+            offset += token.chars().count();
+            LyricWord {
+                text: token.to_string(),
+                start: word_start,
+                end: at(offset),
+                confidence: 1.0,
+            }
+        })
+        .collect()
+}
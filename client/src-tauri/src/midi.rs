@@ -0,0 +1,1085 @@
+//! Standard MIDI File export and import of notes and drum strikes.
+//!
+//! Exported ticks follow the song's detected beats rather than a fixed
+//! tempo: every beat in `beats` sits on a whole beat of the tick grid and a
+//! tempo event keeps the spacing in seconds, so the file lines up with the
+//! recording in any DAW. Songs without beats fall back to `tempoBpm` (or
+//! 120 bpm).
+//!
+//! Imported files are placed either by their own tempo map or on that same
+//! beat grid, so a file exported, corrected in a DAW and imported again
+//! lands where it started.
+
+use crate::error::{CommandError, CommandResult};
+use crate::{
+    backup_analysis, read_analysis, write_analysis, DrumStrike, Note, PitchBendPoint, SongAnalysis,
+};
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::{
+    Format, Header, MetaMessage, MidiMessage, PitchBend, Smf, Timing, TrackEvent, TrackEventKind,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Ticks per quarter note
+pub const PPQ: u16 = 480;
+
+/// MIDI channel 10, reserved for drums in General MIDI
+const DRUM_CHANNEL: u8 = 9;
+
+/// Drum strikes are written as short notes of this many ticks
+const DRUM_NOTE_TICKS: u64 = PPQ as u64 / 8;
+
+/// Bend range used unless a stem bends further (GM default, semitones)
+const DEFAULT_BEND_RANGE: f64 = 2.0;
+const MAX_BEND_RANGE: f64 = 24.0;
+
+/// General MIDI percussion keys for the drum names DrumSep produces, plus
+/// common spellings
+const GM_DRUMS: &[(&str, u8)] = &[
+    ("kick", 36),
+    ("bass_drum", 36),
+    ("snare", 38),
+    ("toms", 47),
+    ("tom", 47),
+    ("hh", 42),
+    ("hihat", 42),
+    ("hi-hat", 42),
+    ("hi_hat", 42),
+    ("open_hh", 46),
+    ("ride", 51),
+    ("crash", 49),
+    ("cymbals", 49),
+];
+
+/// General MIDI program (0-based) for a stem name
+fn gm_program(stem: &str) -> u8 {
+    match stem.to_lowercase().as_str() {
+        "bass" => 33,   // Electric Bass (finger)
+        "guitar" => 27, // Electric Guitar (clean)
+        "vocals" => 53, // Voice Oohs
+        _ => 0,         // Acoustic Grand Piano
+    }
+}
+
+/// Drum name for a General MIDI percussion key, using the names DrumSep
+/// produces
+pub fn gm_drum_name(key: u8) -> Option<&'static str> {
+    match key {
+        35 | 36 => Some("kick"),
+        37..=40 => Some("snare"),
+        41 | 43 | 45 | 47 | 48 | 50 => Some("toms"),
+        42 | 44 | 46 => Some("hh"),
+        51 | 53 | 59 => Some("ride"),
+        49 | 52 | 55 | 57 => Some("crash"),
+        _ => None,
+    }
+}
+
+pub fn gm_drum(name: &str) -> Option<u8> {
+    let name = name.to_lowercase();
+    GM_DRUMS
+        .iter()
+        .find(|(drum, _)| *drum == name)
+        .map(|(_, key)| *key)
+}
+
+/// Piecewise-linear mapping between seconds and ticks through the song's
+/// beats
+pub struct TempoMap {
+    /// (seconds, ticks), strictly increasing in both
+    points: Vec<(f64, f64)>,
+    numerator: u8,
+    /// log2 of the time signature denominator, as MIDI stores it
+    denominator_power: u8,
+    /// Ticks of pickup before the first full bar
+    pickup_ticks: u64,
+}
+
+impl TempoMap {
+    pub fn from_analysis(analysis: &SongAnalysis) -> Self {
+        let (numerator, denominator) = match analysis.time_signature {
+            Some((n, d)) if (1..=32).contains(&n) && [1, 2, 4, 8, 16, 32].contains(&d) => {
+                (n as u8, d as u8)
+            }
+            _ => (4, 4),
+        };
+        // A beat is one denominator note
+        let beat_ticks = PPQ as f64 * 4.0 / denominator as f64;
+
+        let mut beats: Vec<(f64, Option<i32>)> = Vec::new();
+        for beat in &analysis.beats {
+            if beat.time.is_finite()
+                && beat.time >= 0.0
+                && beats.last().is_none_or(|(last, _)| beat.time > *last)
+            {
+                beats.push((beat.time, beat.beat_in_measure));
+            }
+        }
+
+        let mut points = Vec::new();
+        let mut pickup_ticks = 0;
+        if beats.len() >= 2 {
+            let first = beats[0].0;
+            // Whole beats of lead-in before the first detected beat
+            let lead_in = (first / (beats[1].0 - first)).round() as usize;
+            if lead_in > 0 {
+                points.push((0.0, 0.0));
+            }
+            for (i, (time, _)) in beats.iter().enumerate() {
+                // Less than half a beat of lead-in stretches the first beat
+                // back to the start instead
+                let time = if i == 0 && lead_in == 0 { 0.0 } else { *time };
+                points.push((time, (lead_in + i) as f64 * beat_ticks));
+            }
+
+            // Beats before the first downbeat form a pickup bar
+            let downbeat = beats
+                .iter()
+                .position(|(_, in_measure)| *in_measure == Some(1))
+                .unwrap_or(0);
+            let pickup = (lead_in + downbeat) % numerator as usize;
+            pickup_ticks = (pickup as f64 * beat_ticks) as u64;
+        } else {
+            let bpm = analysis
+                .tempo_bpm
+                .filter(|bpm| bpm.is_finite() && *bpm > 0.0)
+                .unwrap_or(120.0);
+            points.push((0.0, 0.0));
+            points.push((60.0 / bpm, beat_ticks));
+        }
+
+        Self {
+            points,
+            numerator,
+            denominator_power: denominator.trailing_zeros() as u8,
+            pickup_ticks,
+        }
+    }
+
+    pub fn ticks(&self, seconds: f64) -> u64 {
+        let after = self.points.partition_point(|point| point.0 <= seconds);
+        let i = after.clamp(1, self.points.len() - 1) - 1;
+        let (t0, k0) = self.points[i];
+        let (t1, k1) = self.points[i + 1];
+        (k0 + (seconds - t0) * (k1 - k0) / (t1 - t0))
+            .round()
+            .max(0.0) as u64
+    }
+
+    /// Inverse of [`TempoMap::ticks`], for fractional ticks
+    pub fn seconds(&self, ticks: f64) -> f64 {
+        let after = self.points.partition_point(|point| point.1 <= ticks);
+        let i = after.clamp(1, self.points.len() - 1) - 1;
+        let (t0, k0) = self.points[i];
+        let (t1, k1) = self.points[i + 1];
+        t0 + (ticks - k0) * (t1 - t0) / (k1 - k0)
+    }
+
+    /// Tempo changes as (tick, microseconds per quarter note)
+    fn tempo_changes(&self) -> Vec<(u64, u32)> {
+        let mut changes: Vec<(u64, u32)> = Vec::new();
+        for pair in self.points.windows(2) {
+            let ((t0, k0), (t1, k1)) = (pair[0], pair[1]);
+            let micros = ((t1 - t0) / (k1 - k0) * PPQ as f64 * 1e6).round();
+            let micros = micros.clamp(1.0, u24::max_value().as_int() as f64) as u32;
+            if changes.last().is_none_or(|(_, last)| *last != micros) {
+                changes.push((k0.round() as u64, micros));
+            }
+        }
+        changes
+    }
+}
+
+/// Events for one track, sorted and delta-encoded by [`Track::finish`]
+struct Track<'a> {
+    /// (tick, order within the tick, event)
+    events: Vec<(u64, u8, TrackEventKind<'a>)>,
+}
+
+/// Within a tick: setup first, then note-offs, bends, and note-ons last
+const ORDER_SETUP: u8 = 0;
+const ORDER_NOTE_OFF: u8 = 1;
+const ORDER_BEND: u8 = 2;
+const ORDER_NOTE_ON: u8 = 3;
+
+impl<'a> Track<'a> {
+    fn new(name: &'a str) -> Self {
+        Self {
+            events: vec![(
+                0,
+                ORDER_SETUP,
+                TrackEventKind::Meta(MetaMessage::TrackName(name.as_bytes())),
+            )],
+        }
+    }
+
+    fn meta(&mut self, tick: u64, message: MetaMessage<'a>) {
+        self.events
+            .push((tick, ORDER_SETUP, TrackEventKind::Meta(message)));
+    }
+
+    fn midi(&mut self, tick: u64, order: u8, channel: u8, message: MidiMessage) {
+        self.events.push((
+            tick,
+            order,
+            TrackEventKind::Midi {
+                channel: u4::new(channel),
+                message,
+            },
+        ));
+    }
+
+    fn controller(&mut self, channel: u8, controller: u8, value: u8) {
+        self.midi(
+            0,
+            ORDER_SETUP,
+            channel,
+            MidiMessage::Controller {
+                controller: u7::new(controller),
+                value: u7::new(value),
+            },
+        );
+    }
+
+    fn note(&mut self, channel: u8, key: u8, velocity: u8, start: u64, end: u64) {
+        let key = u7::new(key);
+        self.midi(
+            start,
+            ORDER_NOTE_ON,
+            channel,
+            MidiMessage::NoteOn {
+                key,
+                vel: u7::new(velocity),
+            },
+        );
+        self.midi(
+            end.max(start + 1),
+            ORDER_NOTE_OFF,
+            channel,
+            MidiMessage::NoteOff {
+                key,
+                vel: u7::new(0),
+            },
+        );
+    }
+
+    fn finish(mut self) -> Vec<TrackEvent<'a>> {
+        // Stable, so events keep their insertion order within a slot
+        self.events.sort_by_key(|(tick, order, _)| (*tick, *order));
+        let mut last = 0;
+        let mut events: Vec<TrackEvent> = self
+            .events
+            .into_iter()
+            .map(|(tick, _, kind)| {
+                let delta = u28::new((tick - last) as u32);
+                last = tick;
+                TrackEvent { delta, kind }
+            })
+            .collect();
+        events.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        events
+    }
+}
+
+fn midi_velocity(velocity: f64) -> u8 {
+    (velocity * 127.0).round().clamp(1.0, 127.0) as u8
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MidiExportResult {
+    pub path: String,
+    /// Tracks written, the tempo track included
+    pub tracks: usize,
+    pub notes: usize,
+    pub drum_strikes: usize,
+    /// Drum names with no General MIDI equivalent, left out
+    pub skipped_drums: Vec<String>,
+}
+
+/// Build the MIDI file for `analysis` and write it to `path`
+pub fn export(analysis: &SongAnalysis, path: &Path) -> CommandResult<MidiExportResult> {
+    let tempo = TempoMap::from_analysis(analysis);
+    let title = analysis.title.clone().unwrap_or_default();
+    let mut tracks = Vec::new();
+    let mut note_count = 0;
+    let mut strike_count = 0;
+    let mut skipped_drums = Vec::new();
+
+    let mut conductor = Track::new(&title);
+    let signature =
+        |numerator: u8| MetaMessage::TimeSignature(numerator, tempo.denominator_power, 24, 8);
+    if tempo.pickup_ticks > 0 {
+        let pickup_beats = tempo.pickup_ticks * (1 << tempo.denominator_power) / (PPQ as u64 * 4);
+        conductor.meta(0, signature(pickup_beats as u8));
+        conductor.meta(tempo.pickup_ticks, signature(tempo.numerator));
+    } else {
+        conductor.meta(0, signature(tempo.numerator));
+    }
+    for (tick, micros) in tempo.tempo_changes() {
+        conductor.meta(tick, MetaMessage::Tempo(u24::new(micros)));
+    }
+    tracks.push(conductor.finish());
+
+    let empty = Default::default();
+    let notes = analysis.notes.as_ref().unwrap_or(&empty);
+    let mut stems: Vec<&String> = notes.keys().collect();
+    stems.sort();
+    let melodic_channels = (0..16u8).filter(|channel| *channel != DRUM_CHANNEL).cycle();
+    for (stem, channel) in stems.into_iter().zip(melodic_channels) {
+        let mut track = Track::new(stem);
+        track.midi(
+            0,
+            ORDER_SETUP,
+            channel,
+            MidiMessage::ProgramChange {
+                program: u7::new(gm_program(stem)),
+            },
+        );
+
+        let widest_bend = notes[stem]
+            .iter()
+            .flat_map(|note| note.pitch_bend.iter().flatten())
+            .map(|point| point.cents.abs() / 100.0)
+            .fold(0.0, f64::max);
+        let bend_range = widest_bend.ceil().clamp(DEFAULT_BEND_RANGE, MAX_BEND_RANGE);
+        if widest_bend > 0.0 {
+            // RPN 0: pitch bend sensitivity, then the null RPN
+            track.controller(channel, 101, 0);
+            track.controller(channel, 100, 0);
+            track.controller(channel, 6, bend_range as u8);
+            track.controller(channel, 38, 0);
+            track.controller(channel, 101, 127);
+            track.controller(channel, 100, 127);
+        }
+
+        for note in &notes[stem] {
+            let start = tempo.ticks(note.start);
+            // Unvalidated documents can hold notes that end before they start
+            let end = tempo.ticks(note.end).max(start);
+            let key = note.pitch.clamp(0, 127) as u8;
+            track.note(channel, key, midi_velocity(note.velocity), start, end);
+            note_count += 1;
+
+            let Some(bend) = note.pitch_bend.as_ref().filter(|bend| !bend.is_empty()) else {
+                continue;
+            };
+            for point in bend {
+                let tick = tempo.ticks(note.start + point.time).clamp(start, end);
+                let value = point.cents / (bend_range * 100.0);
+                track.midi(
+                    tick,
+                    ORDER_BEND,
+                    channel,
+                    MidiMessage::PitchBend {
+                        bend: PitchBend::from_f64(value),
+                    },
+                );
+            }
+            // Bends apply to the whole channel; recenter for the next note
+            track.midi(
+                end.max(start + 1),
+                ORDER_NOTE_OFF,
+                channel,
+                MidiMessage::PitchBend {
+                    bend: PitchBend::from_f64(0.0),
+                },
+            );
+        }
+        tracks.push(track.finish());
+    }
+
+    let empty = Default::default();
+    let drums = analysis.drum_strikes.as_ref().unwrap_or(&empty);
+    let mut drum_names: Vec<&String> = drums.keys().collect();
+    drum_names.sort();
+    let mut drum_track = Track::new("Drums");
+    for name in drum_names {
+        let Some(key) = gm_drum(name) else {
+            skipped_drums.push(name.clone());
+            continue;
+        };
+        for strike in &drums[name] {
+            let start = tempo.ticks(strike.time);
+            let velocity = midi_velocity(strike.velocity);
+            drum_track.note(DRUM_CHANNEL, key, velocity, start, start + DRUM_NOTE_TICKS);
+            strike_count += 1;
+        }
+    }
+    if strike_count > 0 {
+        tracks.push(drum_track.finish());
+    }
+
+    let track_count = tracks.len();
+    let mut smf = Smf::new(Header::new(
+        Format::Parallel,
+        Timing::Metrical(u15::new(PPQ)),
+    ));
+    smf.tracks = tracks;
+    smf.save(path).map_err(|e| CommandError::io(path, e))?;
+
+    Ok(MidiExportResult {
+        path: path.to_string_lossy().to_string(),
+        tracks: track_count,
+        notes: note_count,
+        drum_strikes: strike_count,
+        skipped_drums,
+    })
+}
+
+/// Write a song's notes and drum strikes to a Standard MIDI File: a tempo
+/// track following the detected beats, one track per stem and General MIDI
+/// drums on channel 10
+#[tauri::command]
+pub fn export_midi(song_dir: &str, output_path: &str) -> CommandResult<MidiExportResult> {
+    let analysis = read_analysis(&Path::new(song_dir).join("analysis.json"))?;
+    export(&analysis, Path::new(output_path))
+}
+
+/// Stem name that imports into `drumStrikes` instead of `notes`
+pub const DRUMS_STEM: &str = "drums";
+
+/// How imported ticks are turned into song time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MidiTiming {
+    /// The file's own tempo events
+    #[default]
+    File,
+    /// The song's beat grid, as `export_midi` writes it
+    Beats,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackMapping {
+    /// Index of the track in the file
+    pub track: usize,
+    /// Stem whose notes the track replaces, or `"drums"` for drum strikes
+    pub stem: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MidiImport {
+    pub input_path: String,
+    #[serde(default)]
+    pub timing: MidiTiming,
+    /// Tracks to import; when empty, tracks are matched to stems by name
+    /// and tracks played only on channel 10 become drums
+    #[serde(default)]
+    pub tracks: Vec<TrackMapping>,
+    /// Report the mapping without changing analysis.json
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MidiTrackInfo {
+    pub index: usize,
+    pub name: Option<String>,
+    pub notes: usize,
+    /// Stem the track was imported into, if any
+    pub stem: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MidiImportResult {
+    pub tracks: Vec<MidiTrackInfo>,
+    pub notes: usize,
+    pub drum_strikes: usize,
+    /// Drum notes on keys with no drum name, left out
+    pub skipped_drum_notes: usize,
+    /// Copy of the previous analysis.json; `None` on a dry run
+    pub backup: Option<String>,
+}
+
+/// A note as read from a track, still in ticks
+struct MidiNote {
+    channel: u8,
+    key: u8,
+    velocity: u8,
+    start: u64,
+    end: u64,
+    /// (tick, semitones) of the channel's bend from `start` to `end`
+    bend: Vec<(u64, f64)>,
+}
+
+struct MidiTrack {
+    name: Option<String>,
+    notes: Vec<MidiNote>,
+}
+
+impl MidiTrack {
+    fn is_drums(&self) -> bool {
+        !self.notes.is_empty() && self.notes.iter().all(|note| note.channel == DRUM_CHANNEL)
+    }
+}
+
+/// Notes of one track, with pitch bends attached to the notes they sound
+/// under
+fn read_track(events: &[TrackEvent]) -> MidiTrack {
+    let mut name = None;
+    let mut notes = Vec::new();
+    // Sounding notes by (channel, key): (start, velocity), oldest first
+    let mut sounding: HashMap<(u8, u8), Vec<(u64, u8)>> = HashMap::new();
+    let mut bends: [Vec<(u64, f64)>; 16] = Default::default();
+    let mut bend_range = [DEFAULT_BEND_RANGE; 16];
+    let mut rpn = [(127u8, 127u8); 16];
+
+    let mut tick = 0u64;
+    for event in events {
+        tick += event.delta.as_int() as u64;
+        let (channel, message) = match event.kind {
+            TrackEventKind::Meta(MetaMessage::TrackName(bytes)) if name.is_none() => {
+                let text = String::from_utf8_lossy(bytes).trim().to_string();
+                name = Some(text).filter(|text| !text.is_empty());
+                continue;
+            }
+            TrackEventKind::Midi { channel, message } => (channel.as_int(), message),
+            _ => continue,
+        };
+        let ch = channel as usize;
+        match message {
+            MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                sounding
+                    .entry((channel, key.as_int()))
+                    .or_default()
+                    .push((tick, vel.as_int()));
+            }
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                let Some(started) = sounding.get_mut(&(channel, key.as_int())) else {
+                    continue;
+                };
+                if started.is_empty() {
+                    continue;
+                }
+                let (start, velocity) = started.remove(0);
+                notes.push(MidiNote {
+                    channel,
+                    key: key.as_int(),
+                    velocity,
+                    start,
+                    end: tick,
+                    bend: Vec::new(),
+                });
+            }
+            MidiMessage::PitchBend { bend } => {
+                bends[ch].push((tick, bend.as_f64() * bend_range[ch]));
+            }
+            MidiMessage::Controller { controller, value } => {
+                let value = value.as_int();
+                match controller.as_int() {
+                    101 => rpn[ch].0 = value,
+                    100 => rpn[ch].1 = value,
+                    6 if rpn[ch] == (0, 0) => bend_range[ch] = value as f64,
+                    38 if rpn[ch] == (0, 0) => {
+                        bend_range[ch] = bend_range[ch].trunc() + value as f64 / 100.0
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Notes still sounding at the end of the track end with it
+    for ((channel, key), started) in sounding {
+        for (start, velocity) in started {
+            notes.push(MidiNote {
+                channel,
+                key,
+                velocity,
+                start,
+                end: tick,
+                bend: Vec::new(),
+            });
+        }
+    }
+    notes.sort_by_key(|note| (note.start, note.key));
+
+    for note in &mut notes {
+        let changes = &bends[note.channel as usize];
+        let at_start = changes.partition_point(|(tick, _)| *tick <= note.start);
+        let initial = at_start.checked_sub(1).map_or(0.0, |i| changes[i].1);
+        // A bend carried over from before the note applies from its start
+        if initial != 0.0 {
+            note.bend.push((note.start, initial));
+        }
+        note.bend.extend(
+            changes[at_start..]
+                .iter()
+                .take_while(|(tick, _)| *tick < note.end),
+        );
+        if note.bend.iter().all(|(_, semitones)| *semitones == 0.0) {
+            note.bend.clear();
+        }
+    }
+
+    MidiTrack { name, notes }
+}
+
+/// Seconds of a tick under the file's own tempo events
+struct FileClock {
+    /// (tick, seconds at that tick, seconds per tick from there on)
+    tempos: Vec<(u64, f64, f64)>,
+}
+
+impl FileClock {
+    fn new(smf: &Smf) -> Self {
+        let ppq = match smf.header.timing {
+            Timing::Metrical(ppq) => ppq.as_int().max(1) as f64,
+            Timing::Timecode(fps, subframes) => {
+                let rate = 1.0 / (fps.as_f32() as f64 * subframes.max(1) as f64);
+                return Self {
+                    tempos: vec![(0, 0.0, rate)],
+                };
+            }
+        };
+
+        let mut changes: Vec<(u64, u32)> = Vec::new();
+        for track in &smf.tracks {
+            let mut tick = 0u64;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                if let TrackEventKind::Meta(MetaMessage::Tempo(micros)) = event.kind {
+                    changes.push((tick, micros.as_int().max(1)));
+                }
+            }
+        }
+        changes.sort_by_key(|(tick, _)| *tick);
+
+        // 120 bpm until the first tempo event
+        let mut tempos = vec![(0, 0.0, 0.5 / ppq)];
+        for (tick, micros) in changes {
+            let (last_tick, last_seconds, last_rate) = *tempos.last().unwrap();
+            let seconds = last_seconds + (tick - last_tick) as f64 * last_rate;
+            if tick == last_tick {
+                tempos.pop();
+            }
+            tempos.push((tick, seconds, micros as f64 / 1e6 / ppq));
+        }
+        Self { tempos }
+    }
+
+    fn seconds(&self, tick: u64) -> f64 {
+        let i = self.tempos.partition_point(|(start, _, _)| *start <= tick) - 1;
+        let (start, seconds, rate) = self.tempos[i];
+        seconds + (tick - start) as f64 * rate
+    }
+}
+
+/// Stem for a track when no mapping is given: drums for channel-10 tracks,
+/// otherwise the song's stem named in the track name
+fn suggest_stem(track: &MidiTrack, stems: &[String]) -> Option<String> {
+    if track.is_drums() {
+        return Some(DRUMS_STEM.to_string());
+    }
+    let name = track.name.as_deref()?.to_lowercase();
+    stems
+        .iter()
+        .filter(|stem| stem.as_str() != DRUMS_STEM && name.contains(&stem.to_lowercase()))
+        .max_by_key(|stem| stem.len())
+        .cloned()
+}
+
+/// Replace notes and drum strikes of `analysis` with the mapped tracks of
+/// the MIDI file in `bytes`
+pub fn import(
+    analysis: &mut SongAnalysis,
+    bytes: &[u8],
+    options: &MidiImport,
+) -> CommandResult<MidiImportResult> {
+    let smf = Smf::parse(bytes).map_err(|e| {
+        CommandError::invalid(format!("{} is not a MIDI file: {}", options.input_path, e))
+    })?;
+    let tracks: Vec<MidiTrack> = smf.tracks.iter().map(|track| read_track(track)).collect();
+
+    let seconds: Box<dyn Fn(u64) -> f64> = match options.timing {
+        MidiTiming::File => {
+            let clock = FileClock::new(&smf);
+            Box::new(move |tick| clock.seconds(tick))
+        }
+        MidiTiming::Beats => {
+            let Timing::Metrical(ppq) = smf.header.timing else {
+                return Err(CommandError::invalid(
+                    "a file with SMPTE timing has no beats to align",
+                ));
+            };
+            let scale = PPQ as f64 / ppq.as_int().max(1) as f64;
+            let map = TempoMap::from_analysis(analysis);
+            Box::new(move |tick| map.seconds(tick as f64 * scale).max(0.0))
+        }
+    };
+
+    let mut stems: Vec<Option<String>> = vec![None; tracks.len()];
+    if options.tracks.is_empty() {
+        let mut names: Vec<String> = analysis.stems.keys().cloned().collect();
+        if let Some(notes) = &analysis.notes {
+            names.extend(
+                notes
+                    .keys()
+                    .filter(|stem| !analysis.stems.contains_key(*stem))
+                    .cloned(),
+            );
+        }
+        for (stem, track) in stems.iter_mut().zip(&tracks) {
+            *stem = suggest_stem(track, &names);
+        }
+    } else {
+        for mapping in &options.tracks {
+            if mapping.track >= tracks.len() {
+                return Err(CommandError::invalid(format!(
+                    "track {} does not exist; the file has {}",
+                    mapping.track,
+                    tracks.len()
+                )));
+            }
+            stems[mapping.track] = Some(mapping.stem.clone());
+        }
+    }
+
+    let mut notes: HashMap<String, Vec<Note>> = HashMap::new();
+    let mut drum_strikes: Option<HashMap<String, Vec<DrumStrike>>> = None;
+    let mut skipped_drum_notes = 0;
+    for (track, stem) in tracks.iter().zip(&stems) {
+        let Some(stem) = stem else {
+            continue;
+        };
+        if stem == DRUMS_STEM {
+            let drums = drum_strikes.get_or_insert_with(HashMap::new);
+            for note in &track.notes {
+                let Some(drum) = gm_drum_name(note.key) else {
+                    skipped_drum_notes += 1;
+                    continue;
+                };
+                drums.entry(drum.to_string()).or_default().push(DrumStrike {
+                    time: seconds(note.start),
+                    velocity: note.velocity as f64 / 127.0,
+                });
+            }
+            continue;
+        }
+
+        let stem_notes = notes.entry(stem.clone()).or_default();
+        for note in &track.notes {
+            let start = seconds(note.start);
+            let bend: Vec<PitchBendPoint> = note
+                .bend
+                .iter()
+                .map(|(tick, semitones)| PitchBendPoint {
+                    time: seconds(*tick) - start,
+                    cents: semitones * 100.0,
+                })
+                .collect();
+            stem_notes.push(Note {
+                start,
+                end: seconds(note.end),
+                pitch: note.key as i32,
+                velocity: note.velocity as f64 / 127.0,
+                pitch_bend: (!bend.is_empty()).then_some(bend),
+            });
+        }
+    }
+
+    let note_count = notes.values().map(Vec::len).sum();
+    let strike_count = drum_strikes
+        .iter()
+        .flat_map(HashMap::values)
+        .map(Vec::len)
+        .sum();
+    for (stem, mut stem_notes) in notes {
+        stem_notes.sort_by(|a, b| a.start.total_cmp(&b.start));
+        if let Some(info) = analysis.stems.get_mut(&stem) {
+            info.has_notes = true;
+        }
+        analysis
+            .notes
+            .get_or_insert_with(HashMap::new)
+            .insert(stem, stem_notes);
+    }
+    if let Some(mut drums) = drum_strikes {
+        for strikes in drums.values_mut() {
+            strikes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        }
+        analysis.drum_strikes = Some(drums);
+    }
+
+    Ok(MidiImportResult {
+        tracks: tracks
+            .iter()
+            .zip(stems)
+            .enumerate()
+            .map(|(index, (track, stem))| MidiTrackInfo {
+                index,
+                name: track.name.clone(),
+                notes: track.notes.len(),
+                stem,
+            })
+            .collect(),
+        notes: note_count,
+        drum_strikes: strike_count,
+        skipped_drum_notes,
+        backup: None,
+    })
+}
+
+/// Replace a song's notes and drum strikes with tracks of a MIDI file,
+/// keeping the previous analysis.json as `analysis.json.premidi.bak`.
+///
+/// Each mapped stem's notes are replaced as a whole; a drums track replaces
+/// all drum strikes.
+#[tauri::command]
+pub fn import_midi(song_dir: &str, import: MidiImport) -> CommandResult<MidiImportResult> {
+    let analysis_path = Path::new(song_dir).join("analysis.json");
+    let mut analysis = read_analysis(&analysis_path)?;
+    let input_path = Path::new(&import.input_path);
+    let bytes = std::fs::read(input_path).map_err(|e| CommandError::io(input_path, e))?;
+    let mut result = self::import(&mut analysis, &bytes, &import)?;
+
+    let mapped = result.tracks.iter().any(|track| track.stem.is_some());
+    if !import.dry_run && mapped {
+        let backup_path = backup_analysis(&analysis_path, "premidi")?;
+        write_analysis(&analysis_path, &analysis)?;
+        result.backup = Some(backup_path.to_string_lossy().to_string());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_analysis;
+
+    fn analysis() -> SongAnalysis {
+        let mut song = test_analysis();
+        song.title = Some("Test".to_string());
+        song.original_duration = 3.0;
+        song.time_signature = Some((4, 4));
+        song.beats = serde_json::from_value(serde_json::json!([
+            {"time": 0.5, "type": "downbeat", "beatInMeasure": 1},
+            {"time": 1.0, "type": "beat", "beatInMeasure": 2},
+            {"time": 1.5, "type": "beat", "beatInMeasure": 3},
+            {"time": 2.25, "type": "beat", "beatInMeasure": 4}
+        ]))
+        .unwrap();
+        song.notes = serde_json::from_value(serde_json::json!({"bass": [
+            {"start": 1.0, "end": 1.5, "pitch": 40, "velocity": 0.5,
+             "pitchBend": [{"time": 0.25, "cents": 300.0}]}
+        ]}))
+        .unwrap();
+        song.drum_strikes = serde_json::from_value(serde_json::json!({
+            "kick": [{"time": 0.5, "velocity": 1.0}],
+            "cowbell": [{"time": 1.0, "velocity": 1.0}]
+        }))
+        .unwrap();
+        song
+    }
+
+    #[test]
+    fn test_tempo_map_follows_beats() {
+        let map = TempoMap::from_analysis(&analysis());
+        // One beat of lead-in, then a beat per detected beat
+        assert_eq!(map.ticks(0.0), 0);
+        assert_eq!(map.ticks(0.5), 480);
+        assert_eq!(map.ticks(1.25), 1200);
+        assert_eq!(map.ticks(2.25), 1920);
+        // Past the last beat the last tempo carries on
+        assert_eq!(map.ticks(3.0), 2400);
+        assert_eq!(map.pickup_ticks, 480);
+        assert_eq!(map.tempo_changes(), vec![(0, 500_000), (1440, 750_000)]);
+    }
+
+    #[test]
+    fn test_short_lead_in_stretches_first_beat() {
+        let mut song = analysis();
+        song.beats = crate::test_beats(4, 0.5, 4);
+        for beat in &mut song.beats {
+            beat.time += 0.02;
+        }
+        let map = TempoMap::from_analysis(&song);
+        assert_eq!(map.ticks(0.0), 0);
+        assert_eq!(map.ticks(0.52), 480);
+        assert_eq!(map.pickup_ticks, 0);
+        assert_eq!(map.tempo_changes(), vec![(0, 520_000), (480, 500_000)]);
+    }
+
+    #[test]
+    fn test_writes_tracks_per_stem_and_drums() {
+        let path = std::env::temp_dir().join(format!("music-tutor-{}.mid", std::process::id()));
+        let result = export(&analysis(), &path).unwrap();
+        assert_eq!(result.tracks, 3);
+        assert_eq!(result.notes, 1);
+        assert_eq!(result.drum_strikes, 1);
+        assert_eq!(result.skipped_drums, vec!["cowbell".to_string()]);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!(smf.tracks.len(), 3);
+
+        // Absolute ticks of the channel messages in a track
+        let messages = |track: &[TrackEvent<'_>]| {
+            let mut tick = 0;
+            let mut out = Vec::new();
+            for event in track {
+                tick += event.delta.as_int();
+                if let TrackEventKind::Midi { channel, message } = event.kind {
+                    out.push((tick, channel.as_int(), message));
+                }
+            }
+            out
+        };
+
+        let bass = messages(&smf.tracks[1]);
+        assert!(bass.contains(&(
+            960,
+            0,
+            MidiMessage::NoteOn {
+                key: u7::new(40),
+                vel: u7::new(64)
+            }
+        )));
+        // 300 cents with the range widened to 3 semitones is a full bend
+        assert!(bass.iter().any(|(tick, _, message)| *tick == 1200
+            && matches!(message, MidiMessage::PitchBend { bend } if bend.as_int() == 0x1FFF)));
+        assert!(bass.contains(&(
+            0,
+            0,
+            MidiMessage::Controller {
+                controller: u7::new(6),
+                value: u7::new(3)
+            }
+        )));
+
+        let drums = messages(&smf.tracks[2]);
+        assert_eq!(
+            drums[0],
+            (
+                480,
+                9,
+                MidiMessage::NoteOn {
+                    key: u7::new(36),
+                    vel: u7::new(127)
+                }
+            )
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_inverted_note_with_bend_exports() {
+        let mut song = analysis();
+        song.notes = serde_json::from_value(serde_json::json!({"bass": [
+            {"start": 1.5, "end": 1.0, "pitch": 40, "velocity": 0.5,
+             "pitchBend": [{"time": 0.25, "cents": 100.0}]}
+        ]}))
+        .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("music-tutor-inverted-{}.mid", std::process::id()));
+        let result = export(&song, &path).unwrap();
+        assert_eq!(result.notes, 1);
+
+        // The bend is held at the note's start, which is also its end
+        let bytes = std::fs::read(&path).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        let mut tick = 0;
+        let mut bends = Vec::new();
+        for event in &smf.tracks[1] {
+            tick += event.delta.as_int();
+            if let TrackEventKind::Midi {
+                message: MidiMessage::PitchBend { bend },
+                ..
+            } = event.kind
+            {
+                bends.push((tick, bend.as_f64() > 0.0));
+            }
+        }
+        assert_eq!(bends, vec![(1440, true), (1441, false)]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_import_round_trips_export() {
+        let path = std::env::temp_dir().join(format!("music-tutor-{}-rt.mid", std::process::id()));
+        export(&analysis(), &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        for timing in [MidiTiming::File, MidiTiming::Beats] {
+            let mut song = analysis();
+            // The stem stays known by name, with its notes gone
+            song.notes.as_mut().unwrap().get_mut("bass").unwrap().clear();
+            song.drum_strikes = None;
+            let options = MidiImport {
+                input_path: "song.mid".to_string(),
+                timing,
+                tracks: Vec::new(),
+                dry_run: false,
+            };
+            let result = import(&mut song, &bytes, &options).unwrap();
+            let stems: Vec<Option<&str>> = result
+                .tracks
+                .iter()
+                .map(|track| track.stem.as_deref())
+                .collect();
+            assert_eq!(stems, vec![None, Some("bass"), Some("drums")]);
+            assert_eq!((result.notes, result.drum_strikes), (1, 1));
+
+            let note = &song.notes.as_ref().unwrap()["bass"][0];
+            assert!((note.start - 1.0).abs() < 1e-3, "{:?}", timing);
+            assert!((note.end - 1.5).abs() < 1e-3);
+            assert_eq!(note.pitch, 40);
+            assert!((note.velocity - 64.0 / 127.0).abs() < 1e-9);
+            let bend = note.pitch_bend.as_ref().unwrap();
+            assert_eq!(bend.len(), 1);
+            assert!((bend[0].time - 0.25).abs() < 1e-3);
+            assert!((bend[0].cents - 300.0).abs() < 0.1);
+
+            let kick = &song.drum_strikes.as_ref().unwrap()["kick"][0];
+            assert!((kick.time - 0.5).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_import_applies_explicit_mapping() {
+        let path = std::env::temp_dir().join(format!("music-tutor-{}-map.mid", std::process::id()));
+        export(&analysis(), &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut song = analysis();
+        let options = MidiImport {
+            input_path: "song.mid".to_string(),
+            timing: MidiTiming::File,
+            tracks: vec![TrackMapping {
+                track: 1,
+                stem: "guitar".to_string(),
+            }],
+            dry_run: true,
+        };
+        import(&mut song, &bytes, &options).unwrap();
+        let notes = song.notes.as_ref().unwrap();
+        // Stems not mapped are left alone
+        assert_eq!(notes["bass"].len(), 1);
+        assert_eq!(notes["guitar"].len(), 1);
+        assert_eq!(song.drum_strikes.as_ref().unwrap().len(), 2);
+
+        let options = MidiImport {
+            tracks: vec![TrackMapping {
+                track: 9,
+                stem: "bass".to_string(),
+            }],
+            ..options
+        };
+        assert!(import(&mut song, &bytes, &options).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Upgrades `analysis.json` documents written by older converters to the
+//! current schema before they are deserialized.
+//!
+//! Documents carry a `schemaVersion`; files from before versioning have none
+//! and are treated as version 0. Each entry in [`MIGRATIONS`] upgrades a
+//! document by one version, so a file of any age is brought up to date by
+//! running the steps after its version in order.
+
+use serde_json::{Map, Value};
+
+/// Schema version written by the current converter
+pub const CURRENT_SCHEMA_VERSION: u64 = 1;
+
+/// `MIGRATIONS[n]` upgrades a version `n` document to version `n + 1`
+const MIGRATIONS: [fn(&mut Map<String, Value>); CURRENT_SCHEMA_VERSION as usize] =
+    [migrate_v0_to_v1];
+
+/// Stem names used by early converters, and what they are called now
+const RENAMED_STEMS: &[(&str, &str)] = &[
+    ("vocal", "vocals"),
+    ("voice", "vocals"),
+    ("drum", "drums"),
+    ("accompaniment", "other"),
+    ("instrumental", "other"),
+];
+
+/// Schema version of `doc`; documents without one predate versioning
+pub fn schema_version(doc: &Value) -> u64 {
+    doc.get("schemaVersion")
+        .and_then(Value::as_u64)
+        .unwrap_or(0)
+}
+
+/// Upgrade `doc` in place to [`CURRENT_SCHEMA_VERSION`], returning the
+/// version it started at.
+///
+/// Fails if the document isn't a JSON object or was written by a newer
+/// converter than this app understands.
+pub fn migrate(doc: &mut Value) -> Result<u64, String> {
+    let from = schema_version(doc);
+    if from > CURRENT_SCHEMA_VERSION {
+        return Err(format!(
+            "schema version {} is newer than the supported version {}",
+            from, CURRENT_SCHEMA_VERSION
+        ));
+    }
+    let Some(object) = doc.as_object_mut() else {
+        return Err("expected a JSON object".to_string());
+    };
+
+    for step in &MIGRATIONS[from as usize..] {
+        step(object);
+    }
+    object.insert("schemaVersion".into(), CURRENT_SCHEMA_VERSION.into());
+    Ok(from)
+}
+
+/// Pre-versioning documents: fill in fields added since the first
+/// converter, normalize `timeSignature` and rename legacy stems
+fn migrate_v0_to_v1(doc: &mut Map<String, Value>) {
+    for key in ["stems", "notes", "drumStrikes"] {
+        if doc.get(key).is_none_or(Value::is_null) {
+            doc.insert(key.into(), Value::Object(Map::new()));
+        }
+    }
+    if doc.get("beats").is_none_or(Value::is_null) {
+        doc.insert("beats".into(), Value::Array(Vec::new()));
+    }
+    for key in ["sourceFile", "processingDate", "converterVersion"] {
+        doc.entry(key)
+            .or_insert_with(|| Value::String(String::new()));
+    }
+
+    if let Some(time_signature) = doc.get_mut("timeSignature") {
+        *time_signature = normalize_time_signature(time_signature);
+    }
+
+    for key in ["stems", "notes"] {
+        if let Some(Value::Object(by_stem)) = doc.get_mut(key) {
+            rename_stems(by_stem);
+        }
+    }
+    if let Some(Value::Object(stems)) = doc.get_mut("stems") {
+        for (name, stem) in stems.iter_mut() {
+            if let Some(stem) = stem.as_object_mut() {
+                stem.insert("name".into(), Value::String(name.clone()));
+            }
+        }
+    }
+}
+
+/// Early converters wrote the time signature as `"3/4"` or as
+/// `{"numerator": 3, "denominator": 4}`; the current form is `[3, 4]`.
+/// Anything unrecognizable is dropped rather than failing the load.
+fn normalize_time_signature(value: &Value) -> Value {
+    let pair = match value {
+        Value::Array(items) if items.len() == 2 => items[0].as_i64().zip(items[1].as_i64()),
+        Value::String(text) => text.split_once('/').and_then(|(num, den)| {
+            num.trim()
+                .parse::<i64>()
+                .ok()
+                .zip(den.trim().parse::<i64>().ok())
+        }),
+        Value::Object(fields) => fields
+            .get("numerator")
+            .and_then(Value::as_i64)
+            .zip(fields.get("denominator").and_then(Value::as_i64)),
+        _ => None,
+    };
+    match pair {
+        Some((num, den)) if num > 0 && den > 0 => Value::from(vec![num, den]),
+        _ => Value::Null,
+    }
+}
+
+/// Rename legacy stem keys, keeping the current name if both are present
+fn rename_stems(by_stem: &mut Map<String, Value>) {
+    for (old, new) in RENAMED_STEMS {
+        if let Some(value) = by_stem.remove(*old) {
+            by_stem.entry(*new).or_insert(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SongAnalysis;
+    use serde_json::json;
+
+    #[test]
+    fn test_migrates_unversioned_document() {
+        let mut doc = json!({
+            "title": "Old", "artist": null, "album": null,
+            "originalDuration": 10.0, "sampleRate": 44100,
+            "timeSignature": "3/4",
+            "stems": {
+                "accompaniment": {
+                    "name": "accompaniment", "paths": {"1.0x": "stems/accompaniment.flac"},
+                    "hasNotes": true, "peakDb": -1.0
+                }
+            },
+            "beats": [],
+            "notes": {"accompaniment": []},
+            "processingDate": "2024-01-01"
+        });
+
+        assert_eq!(migrate(&mut doc), Ok(0));
+        let analysis: SongAnalysis = serde_json::from_value(doc).unwrap();
+        assert_eq!(analysis.schema_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(analysis.time_signature, Some((3, 4)));
+        assert_eq!(analysis.stems["other"].name, "other");
+        assert!(analysis.notes.unwrap().contains_key("other"));
+        assert!(analysis.drum_strikes.unwrap().is_empty());
+        assert_eq!(analysis.converter_version, "");
+    }
+
+    #[test]
+    fn test_current_document_is_unchanged() {
+        let mut doc = json!({"schemaVersion": CURRENT_SCHEMA_VERSION, "timeSignature": "4/4"});
+        assert_eq!(migrate(&mut doc), Ok(CURRENT_SCHEMA_VERSION));
+        assert_eq!(doc["timeSignature"], "4/4");
+
+        let mut doc = json!({"schemaVersion": CURRENT_SCHEMA_VERSION + 1});
+        assert!(migrate(&mut doc).is_err());
+    }
+}
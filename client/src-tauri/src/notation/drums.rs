@@ -0,0 +1,719 @@
+//! Drum strikes quantized into bars for drum tab and percussion notation.
+//!
+//! Strikes are snapped to 8ths, 16ths or 8th-note triplets of the song's
+//! beats. Swing is detected from where off-beat strikes fall inside the
+//! beat: when they cluster late (around 2/3 of the beat) the grid is warped
+//! so they snap to the straight off-beat, and the swing is marked on the
+//! score instead of writing every off-beat as a triplet. Without a grid in
+//! the request the one that fits the strikes best is chosen.
+
+use super::musicxml::{self, voice, Layout, Member};
+use super::{BeatGrid, KeySignature};
+use crate::error::{CommandError, CommandResult};
+use crate::midi::{gm_drum, gm_drum_name};
+use crate::{read_analysis, SongAnalysis};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::path::Path;
+
+/// Bars per line of drum tab
+const BARS_PER_LINE: usize = 4;
+
+/// Off-beat strikes are those this far into a beat
+const OFF_BEAT: (f64, f64) = (0.35, 0.8);
+
+/// Position in the beat of a swung off-beat, from a light shuffle to a
+/// hard one
+const SWING_RANGE: (f64, f64) = (0.56, 0.72);
+
+/// Share of off-beat strikes that must sit near the same position to count
+/// as swing, and how near
+const SWING_AGREEMENT: f64 = 0.6;
+const SWING_SPREAD: f64 = 0.06;
+
+/// Fewest off-beat strikes needed to judge swing
+const MIN_OFF_BEATS: usize = 8;
+
+/// Average snapping error, in beats, below which 8ths are chosen over 16ths
+const EIGHTHS_ERROR: f64 = 0.04;
+
+/// Velocity at which a strike is written accented
+const ACCENT: f64 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DrumGrid {
+    Eighths,
+    Sixteenths,
+    /// 8th-note triplets
+    Triplets,
+}
+
+impl DrumGrid {
+    pub fn subdivision(self) -> u32 {
+        match self {
+            DrumGrid::Eighths => 2,
+            DrumGrid::Sixteenths => 4,
+            DrumGrid::Triplets => 3,
+        }
+    }
+
+    /// Counting syllables of one beat's steps, beat number first
+    fn counts(self) -> &'static str {
+        match self {
+            DrumGrid::Eighths => "+",
+            DrumGrid::Sixteenths => "e+a",
+            DrumGrid::Triplets => "&a",
+        }
+    }
+}
+
+/// How a drum is labelled in tab and placed on the percussion staff
+struct DrumStyle {
+    name: &'static str,
+    label: &'static str,
+    /// Display step and octave on the staff
+    step: char,
+    octave: u8,
+    cymbal: bool,
+    /// Played with the feet, so written in the lower voice
+    foot: bool,
+}
+
+/// Drums DrumSep produces, top to bottom as tab lists them
+const STYLES: [DrumStyle; 6] = [
+    DrumStyle {
+        name: "crash",
+        label: "CC",
+        step: 'A',
+        octave: 5,
+        cymbal: true,
+        foot: false,
+    },
+    DrumStyle {
+        name: "ride",
+        label: "RD",
+        step: 'F',
+        octave: 5,
+        cymbal: true,
+        foot: false,
+    },
+    DrumStyle {
+        name: "hh",
+        label: "HH",
+        step: 'G',
+        octave: 5,
+        cymbal: true,
+        foot: false,
+    },
+    DrumStyle {
+        name: "snare",
+        label: "SD",
+        step: 'C',
+        octave: 5,
+        cymbal: false,
+        foot: false,
+    },
+    DrumStyle {
+        name: "toms",
+        label: "T ",
+        step: 'A',
+        octave: 4,
+        cymbal: false,
+        foot: false,
+    },
+    DrumStyle {
+        name: "kick",
+        label: "BD",
+        step: 'F',
+        octave: 4,
+        cymbal: false,
+        foot: true,
+    },
+];
+
+/// Style for a drum name, accepting the spellings `export_midi` knows
+fn style_of(name: &str) -> Option<&'static DrumStyle> {
+    let canonical = gm_drum(name).and_then(gm_drum_name)?;
+    STYLES.iter().find(|style| style.name == canonical)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DrumHit {
+    pub drum: String,
+    /// Grid step within the bar
+    pub step: u32,
+    pub velocity: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DrumMeasure {
+    /// 1-based, as written
+    pub number: usize,
+    /// Seconds at which the bar starts
+    pub start: f64,
+    /// Patterns are numbered as they first appear; bars with the same hits,
+    /// ignoring velocity, share one
+    pub pattern: usize,
+    pub hits: Vec<DrumHit>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DrumTranscription {
+    pub grid: DrumGrid,
+    pub steps_per_bar: u32,
+    /// Where swung off-beats fall in the beat (0.5 is straight, 0.67 a
+    /// triplet shuffle); `None` when the groove is straight
+    pub swing: Option<f64>,
+    /// Drums in the order tab lists them
+    pub drums: Vec<String>,
+    pub measures: Vec<DrumMeasure>,
+    /// Number of distinct patterns among the bars
+    pub patterns: usize,
+}
+
+/// Position of a strike inside its beat, 0 to 1
+fn phase(beat: f64) -> f64 {
+    beat - beat.floor()
+}
+
+/// Detect swing from where off-beat strikes fall in the beat
+fn detect_swing(beats: &[f64]) -> Option<f64> {
+    let mut off_beats: Vec<f64> = beats
+        .iter()
+        .map(|beat| phase(*beat))
+        .filter(|phase| (OFF_BEAT.0..OFF_BEAT.1).contains(phase))
+        .collect();
+    if off_beats.len() < MIN_OFF_BEATS {
+        return None;
+    }
+    off_beats.sort_by(f64::total_cmp);
+    let median = off_beats[off_beats.len() / 2];
+    let agreeing = off_beats
+        .iter()
+        .filter(|phase| (**phase - median).abs() <= SWING_SPREAD)
+        .count();
+    let swung = (SWING_RANGE.0..=SWING_RANGE.1).contains(&median)
+        && agreeing as f64 >= off_beats.len() as f64 * SWING_AGREEMENT;
+    swung.then_some(median)
+}
+
+/// Move a swung off-beat at `swing` to the middle of the beat, stretching
+/// the rest of the beat around it
+fn straighten(beat: f64, swing: f64) -> f64 {
+    let p = phase(beat);
+    let straight = if p < swing {
+        p * 0.5 / swing
+    } else {
+        0.5 + (p - swing) * 0.5 / (1.0 - swing)
+    };
+    beat.floor() + straight
+}
+
+/// Average distance, in beats, from each strike to its nearest step
+fn snapping_error(beats: &[f64], subdivision: u32) -> f64 {
+    if beats.is_empty() {
+        return 0.0;
+    }
+    let sub = subdivision as f64;
+    let total: f64 = beats
+        .iter()
+        .map(|beat| (beat * sub - (beat * sub).round()).abs() / sub)
+        .sum();
+    total / beats.len() as f64
+}
+
+/// Quantize a song's drum strikes into bars; `grid` picks the subdivision,
+/// or the best fit when `None`
+pub fn transcribe(
+    analysis: &SongAnalysis,
+    grid: Option<DrumGrid>,
+) -> CommandResult<DrumTranscription> {
+    let strikes = analysis
+        .drum_strikes
+        .as_ref()
+        .filter(|strikes| strikes.values().any(|s| !s.is_empty()))
+        .ok_or_else(|| CommandError::invalid("the song has no drum strikes"))?;
+    let beat_grid = BeatGrid::from_analysis(analysis);
+
+    let mut drums: Vec<&String> = strikes
+        .keys()
+        .filter(|name| !strikes[*name].is_empty())
+        .collect();
+    drums.sort_by_key(|name| {
+        let position =
+            style_of(name).and_then(|style| STYLES.iter().position(|s| s.name == style.name));
+        (position.unwrap_or(STYLES.len()), name.to_string())
+    });
+
+    let all_beats: Vec<f64> = drums
+        .iter()
+        .flat_map(|name| &strikes[*name])
+        .map(|strike| beat_grid.beat_at(strike.time))
+        .collect();
+    let swing = match grid {
+        Some(DrumGrid::Triplets) => None,
+        _ => detect_swing(&all_beats),
+    };
+    let straight: Vec<f64> = match swing {
+        Some(swing) => all_beats
+            .iter()
+            .map(|beat| straighten(*beat, swing))
+            .collect(),
+        None => all_beats.clone(),
+    };
+    let grid = grid.unwrap_or_else(|| {
+        let eighths = snapping_error(&straight, 2);
+        if eighths <= EIGHTHS_ERROR {
+            DrumGrid::Eighths
+        } else if swing.is_none()
+            && snapping_error(&straight, 3) < snapping_error(&straight, 4) * 0.6
+        {
+            DrumGrid::Triplets
+        } else {
+            DrumGrid::Sixteenths
+        }
+    });
+    let subdivision = grid.subdivision();
+
+    // (drum, step, velocity) in order of drums
+    let mut hits: Vec<(usize, i64, f64)> = Vec::new();
+    let mut beats = straight.iter();
+    for (d, name) in drums.iter().enumerate() {
+        for strike in &strikes[*name] {
+            let beat = beats.next().copied().unwrap_or_default();
+            let step = (beat * subdivision as f64).round() as i64;
+            // Two strikes of one drum on a step are one hit
+            if hits
+                .last()
+                .is_some_and(|(drum, last, _)| *drum == d && *last == step)
+            {
+                continue;
+            }
+            hits.push((d, step, strike.velocity));
+        }
+    }
+
+    let first = hits.iter().map(|(_, step, _)| *step).min().unwrap_or(0);
+    let last = hits.iter().map(|(_, step, _)| *step + 1).max().unwrap_or(1);
+    let layout = Layout::new(&beat_grid, subdivision, first, last);
+
+    let mut measures: Vec<DrumMeasure> = Vec::new();
+    let mut seen: Vec<Vec<(usize, u32)>> = Vec::new();
+    for bar in 0..layout.bars {
+        let (start, end) = layout.bar_range(bar);
+        let mut in_bar: Vec<&(usize, i64, f64)> = hits
+            .iter()
+            .filter(|(_, step, _)| (start..end).contains(step))
+            .collect();
+        in_bar.sort_by_key(|(drum, step, _)| (*step, *drum));
+        let shape: Vec<(usize, u32)> = in_bar
+            .iter()
+            .map(|(drum, step, _)| (*drum, (step - start) as u32))
+            .collect();
+        let pattern = seen
+            .iter()
+            .position(|known| *known == shape)
+            .unwrap_or_else(|| {
+                seen.push(shape);
+                seen.len() - 1
+            });
+        measures.push(DrumMeasure {
+            number: bar + 1,
+            start: beat_grid.seconds_at(start as f64 / subdivision as f64),
+            pattern,
+            hits: in_bar
+                .iter()
+                .map(|(drum, step, velocity)| DrumHit {
+                    drum: drums[*drum].clone(),
+                    step: (step - start) as u32,
+                    velocity: *velocity,
+                })
+                .collect(),
+        });
+    }
+
+    Ok(DrumTranscription {
+        grid,
+        steps_per_bar: beat_grid.numerator * subdivision,
+        swing,
+        drums: drums.into_iter().cloned().collect(),
+        measures,
+        patterns: seen.len(),
+    })
+}
+
+/// Drum tab: one line per drum, one column per grid step, `x` for
+/// cymbals and `o` for drums, upper case when accented
+pub fn tab_text(analysis: &SongAnalysis, transcription: &DrumTranscription) -> String {
+    let grid = BeatGrid::from_analysis(analysis);
+    let labels: Vec<String> = transcription
+        .drums
+        .iter()
+        .map(|name| match style_of(name) {
+            Some(style) => style.label.to_string(),
+            None => name.chars().take(2).collect::<String>().to_uppercase(),
+        })
+        .collect();
+    let width = labels
+        .iter()
+        .map(|label| label.chars().count())
+        .max()
+        .unwrap_or(2);
+
+    let mut count = String::new();
+    for beat in 1..=grid.numerator {
+        let _ = write!(count, "{}{}", beat % 10, transcription.grid.counts());
+    }
+
+    let mut out = String::new();
+    if let Some(title) = &analysis.title {
+        let _ = writeln!(out, "{}\n", title);
+    }
+    if let Some(swing) = transcription.swing {
+        let _ = writeln!(out, "Swing ({:.0}% off-beats)\n", swing * 100.0);
+    }
+    for line_bars in transcription.measures.chunks(BARS_PER_LINE) {
+        let _ = write!(out, "{:width$} |", "", width = width);
+        for _ in line_bars {
+            let _ = write!(out, "{}|", count);
+        }
+        out.push('\n');
+        for (d, name) in transcription.drums.iter().enumerate() {
+            let cymbal = style_of(name).is_some_and(|style| style.cymbal);
+            let _ = write!(out, "{:width$} |", labels[d], width = width);
+            for measure in line_bars {
+                let mut cells = vec!['-'; transcription.steps_per_bar as usize];
+                for hit in measure.hits.iter().filter(|hit| hit.drum == *name) {
+                    let mark = match (cymbal, hit.velocity >= ACCENT) {
+                        (true, false) => 'x',
+                        (true, true) => 'X',
+                        (false, false) => 'o',
+                        (false, true) => 'O',
+                    };
+                    cells[hit.step as usize] = mark;
+                }
+                out.extend(cells);
+                out.push('|');
+            }
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// A drum on the percussion staff
+struct Unpitched {
+    instrument: usize,
+    step: char,
+    octave: u8,
+    cymbal: bool,
+    foot: bool,
+}
+
+impl Member for Unpitched {
+    fn sound(&self) -> String {
+        format!(
+            "<unpitched><display-step>{}</display-step>\
+             <display-octave>{}</display-octave></unpitched>",
+            self.step, self.octave
+        )
+    }
+
+    fn instrument(&self) -> String {
+        format!("<instrument id=\"P1-I{}\"/>", self.instrument + 1)
+    }
+
+    fn appearance(&self) -> String {
+        let stem = if self.foot { "down" } else { "up" };
+        let notehead = if self.cymbal {
+            "<notehead>x</notehead>"
+        } else {
+            ""
+        };
+        format!("<stem>{}</stem>{}", stem, notehead)
+    }
+}
+
+/// MusicXML percussion part: hands in voice 1 with stems up, kick in
+/// voice 2 with stems down
+pub fn percussion_xml(analysis: &SongAnalysis, transcription: &DrumTranscription) -> String {
+    let grid = BeatGrid::from_analysis(analysis);
+    let subdivision = transcription.grid.subdivision();
+    let steps_per_bar = transcription.steps_per_bar as i64;
+
+    let mut instruments = String::new();
+    let mut midi = String::new();
+    let mut hands = Vec::new();
+    let mut feet = Vec::new();
+    for (d, name) in transcription.drums.iter().enumerate() {
+        let _ = write!(
+            instruments,
+            "<score-instrument id=\"P1-I{}\"><instrument-name>{}</instrument-name>\
+             </score-instrument>",
+            d + 1,
+            musicxml::escape(name)
+        );
+        if let Some(key) = gm_drum(name) {
+            let _ = write!(
+                midi,
+                "<midi-instrument id=\"P1-I{}\"><midi-channel>10</midi-channel>\
+                 <midi-unpitched>{}</midi-unpitched></midi-instrument>",
+                d + 1,
+                key as u32 + 1
+            );
+        }
+    }
+    instruments.push_str(&midi);
+
+    // Bars are written from the transcription's first bar
+    let first = transcription
+        .measures
+        .first()
+        .map_or(0, |measure| Layout::step(&grid, subdivision, measure.start));
+    let bars = transcription.measures.len().max(1) as i64;
+    let layout = Layout::new(&grid, subdivision, first, first + bars * steps_per_bar);
+
+    for (bar, measure) in transcription.measures.iter().enumerate() {
+        for hit in &measure.hits {
+            let d = transcription
+                .drums
+                .iter()
+                .position(|name| *name == hit.drum)
+                .unwrap_or(0);
+            let style = style_of(&hit.drum);
+            let member = Unpitched {
+                instrument: d,
+                step: style.map_or('E', |style| style.step),
+                octave: style.map_or(5, |style| style.octave),
+                cymbal: style.is_none_or(|style| style.cymbal),
+                foot: style.is_some_and(|style| style.foot),
+            };
+            // Each hit lasts until the next in its voice, at most a beat
+            // and no further than the last bar
+            let start = first + bar as i64 * steps_per_bar + hit.step as i64;
+            let end = (start + subdivision as i64).min(first + bars * steps_per_bar);
+            let event = (start, end, member);
+            if event.2.foot {
+                feet.push(event);
+            } else {
+                hands.push(event);
+            }
+        }
+    }
+    let hands = voice(hands);
+    let feet = voice(feet);
+
+    let mut out = musicxml::score_header(analysis, "Drums", &instruments);
+    let clef = "<clef><sign>percussion</sign></clef>";
+    let no_key = KeySignature {
+        fifths: 0,
+        minor: false,
+    };
+    for bar in 0..transcription.measures.len() {
+        let _ = write!(out, "<measure number=\"{}\">", bar + 1);
+        if bar == 0 {
+            out.push_str(&layout.attributes(&grid, no_key, clef));
+            out.push_str(&musicxml::tempo_direction(analysis, &grid));
+            if let Some(swing) = transcription.swing {
+                let _ = write!(
+                    out,
+                    "<direction placement=\"above\"><direction-type><words>Swing</words>\
+                     </direction-type><sound><swing><first>{}</first><second>{}</second>\
+                     <swing-type>eighth</swing-type></swing></sound></direction>",
+                    (swing * 100.0).round(),
+                    ((1.0 - swing) * 100.0).round()
+                );
+            }
+        }
+        layout.write_voice(&mut out, bar, &hands, 1);
+        if !feet.is_empty() {
+            let _ = write!(
+                out,
+                "<backup><duration>{}</duration></backup>",
+                layout.bar_divisions()
+            );
+            layout.write_voice(&mut out, bar, &feet, 2);
+        }
+        out.push_str("</measure>");
+    }
+    out.push_str("</part></score-partwise>\n");
+    out
+}
+
+/// Quantize a song's drum strikes into bars of 8ths, 16ths or triplets
+/// (best fit when `grid` is omitted), detecting swing
+#[tauri::command]
+pub fn quantize_drums(song_dir: &str, grid: Option<DrumGrid>) -> CommandResult<DrumTranscription> {
+    let analysis = read_analysis(&Path::new(song_dir).join("analysis.json"))?;
+    transcribe(&analysis, grid)
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DrumFormat {
+    /// Drum tab text
+    Text,
+    /// MusicXML percussion staff
+    MusicXml,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DrumExportResult {
+    pub path: String,
+    pub grid: DrumGrid,
+    pub swing: Option<f64>,
+    pub measures: usize,
+    pub patterns: usize,
+}
+
+/// Write a song's quantized drum strikes as drum tab text or MusicXML
+#[tauri::command]
+pub fn export_drums(
+    song_dir: &str,
+    grid: Option<DrumGrid>,
+    format: DrumFormat,
+    output_path: &str,
+) -> CommandResult<DrumExportResult> {
+    let analysis = read_analysis(&Path::new(song_dir).join("analysis.json"))?;
+    let transcription = transcribe(&analysis, grid)?;
+    let text = match format {
+        DrumFormat::Text => tab_text(&analysis, &transcription),
+        DrumFormat::MusicXml => percussion_xml(&analysis, &transcription),
+    };
+    let path = Path::new(output_path);
+    std::fs::write(path, text).map_err(|e| CommandError::io(path, e))?;
+    Ok(DrumExportResult {
+        path: output_path.to_string(),
+        grid: transcription.grid,
+        swing: transcription.swing,
+        measures: transcription.measures.len(),
+        patterns: transcription.patterns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_analysis, test_beats};
+
+    /// Two bars of 4/4 at 120 bpm with the given strikes
+    fn analysis(strikes: serde_json::Value) -> SongAnalysis {
+        let mut song = test_analysis();
+        song.original_duration = 4.0;
+        song.tempo_bpm = Some(120.0);
+        song.time_signature = Some((4, 4));
+        song.beats = test_beats(8, 0.5, 4);
+        song.drum_strikes = serde_json::from_value(strikes).unwrap();
+        song
+    }
+
+    fn strikes(times: impl Iterator<Item = f64>) -> serde_json::Value {
+        times
+            .map(|time| serde_json::json!({"time": time, "velocity": 0.5}))
+            .collect()
+    }
+
+    /// Kick on 1 and 3, snare on 2 and 4, hi-hat on each beat and at
+    /// `off_beat` of the way through it
+    fn groove(off_beat: f64) -> SongAnalysis {
+        analysis(serde_json::json!({
+            "kick": strikes((0..4).map(|i| i as f64 * 1.0)),
+            "snare": strikes((0..4).map(|i| i as f64 * 1.0 + 0.5)),
+            "hh": strikes((0..16).map(|i| (i / 2) as f64 * 0.5 + (i % 2) as f64 * off_beat * 0.5))
+        }))
+    }
+
+    #[test]
+    fn test_detects_swing_and_patterns() {
+        let straight = transcribe(&groove(0.5), None).unwrap();
+        assert_eq!(straight.grid, DrumGrid::Eighths);
+        assert_eq!(straight.swing, None);
+
+        let swung = transcribe(&groove(0.66), None).unwrap();
+        assert_eq!(swung.grid, DrumGrid::Eighths);
+        assert!((swung.swing.unwrap() - 0.66).abs() < 1e-9);
+        assert_eq!(swung.drums, vec!["hh", "snare", "kick"]);
+        assert_eq!(swung.measures.len(), 2);
+        assert_eq!(swung.patterns, 1);
+        assert_eq!(swung.measures[1].pattern, 0);
+        assert_eq!(swung.measures[1].start, 2.0);
+        let hats: Vec<u32> = swung.measures[0]
+            .hits
+            .iter()
+            .filter(|hit| hit.drum == "hh")
+            .map(|hit| hit.step)
+            .collect();
+        assert_eq!(hats, (0..8).collect::<Vec<_>>());
+
+        // Asked for triplets, the swung off-beat lands on the third
+        let triplets = transcribe(&groove(0.66), Some(DrumGrid::Triplets)).unwrap();
+        assert_eq!(triplets.swing, None);
+        assert_eq!(triplets.steps_per_bar, 12);
+        assert!(triplets.measures[0]
+            .hits
+            .iter()
+            .any(|hit| hit.drum == "hh" && hit.step == 2));
+
+        let sixteenths = analysis(serde_json::json!({
+            "hh": strikes((0..32).map(|i| i as f64 * 0.125))
+        }));
+        assert_eq!(
+            transcribe(&sixteenths, None).unwrap().grid,
+            DrumGrid::Sixteenths
+        );
+    }
+
+    #[test]
+    fn test_writes_tab_and_percussion() {
+        let song = groove(0.5);
+        let transcription = transcribe(&song, None).unwrap();
+        let text = tab_text(&song, &transcription);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "   |1+2+3+4+|1+2+3+4+|");
+        assert_eq!(lines[1], "HH |xxxxxxxx|xxxxxxxx|");
+        assert_eq!(lines[2], "SD |--o---o-|--o---o-|");
+        assert_eq!(lines[3], "BD |o---o---|o---o---|");
+
+        let xml = percussion_xml(&song, &transcription);
+        assert_eq!(xml.matches("<measure ").count(), 2);
+        assert!(xml.contains("<sign>percussion</sign>"));
+        assert!(xml.contains("<midi-unpitched>37</midi-unpitched>"));
+        assert!(xml.contains("<backup><duration>8</duration></backup>"));
+        assert!(xml.contains(
+            "<unpitched><display-step>F</display-step><display-octave>4</display-octave></unpitched>\
+             <duration>2</duration><instrument id=\"P1-I3\"/><voice>2</voice><type>quarter</type>\
+             <stem>down</stem>"
+        ));
+
+        let swung = groove(0.66);
+        let xml = percussion_xml(&swung, &transcribe(&swung, None).unwrap());
+        assert!(xml.contains("<swing><first>66</first><second>34</second>"));
+
+        let xml = percussion_xml(
+            &swung,
+            &transcribe(&swung, Some(DrumGrid::Triplets)).unwrap(),
+        );
+        assert!(xml.contains("<actual-notes>3</actual-notes>"));
+        // The swung hi-hat is in triplets; a kick lasting a whole beat and
+        // the rest after it are plain quarters
+        assert!(xml.contains(
+            "<duration>1</duration><instrument id=\"P1-I1\"/><voice>1</voice><type>eighth</type>\
+             <time-modification>"
+        ));
+        assert!(xml.contains(
+            "<duration>3</duration><instrument id=\"P1-I3\"/><voice>2</voice><type>quarter</type>\
+             <stem>down</stem>"
+        ));
+        assert!(xml.contains(
+            "<note><rest/><duration>3</duration><voice>2</voice><type>quarter</type></note>"
+        ));
+        // Nothing is tied past the last bar
+        assert!(!xml.contains("<tie "));
+    }
+}
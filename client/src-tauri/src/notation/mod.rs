@@ -0,0 +1,261 @@
+//! Printable notation from the detected events.
+//!
+//! [`BeatGrid`] places times on the song's detected beats and bars, and
+//! [`musicxml`] quantizes a stem's notes to that grid and writes them as
+//! MusicXML for notation programs such as MuseScore. [`tab`] fingers notes
+//! on guitar or bass strings for tablature, and [`drums`] quantizes drum
+//! strikes into per-bar patterns for drum tab and percussion notation.
+
+pub mod drums;
+pub mod musicxml;
+pub mod tab;
+
+use crate::SongAnalysis;
+
+/// Beat positions of a song, with bars counted from its first downbeat.
+///
+/// Beats are numbered from the first detected beat; times before it or
+/// after the last one continue at the nearest beat interval.
+pub struct BeatGrid {
+    /// Seconds of each beat, strictly increasing, at least two
+    times: Vec<f64>,
+    /// Beats per bar
+    pub numerator: u32,
+    /// Note value of a beat (4 for quarter notes)
+    pub denominator: u32,
+    /// Index in `times` of the first downbeat
+    downbeat: usize,
+}
+
+impl BeatGrid {
+    pub fn from_analysis(analysis: &SongAnalysis) -> Self {
+        let (numerator, denominator) = match analysis.time_signature {
+            Some((n, d)) if (1..=32).contains(&n) && [1, 2, 4, 8, 16].contains(&d) => {
+                (n as u32, d as u32)
+            }
+            _ => (4, 4),
+        };
+
+        let mut times: Vec<f64> = Vec::new();
+        let mut downbeat = None;
+        for beat in &analysis.beats {
+            if !beat.time.is_finite() || times.last().is_some_and(|last| beat.time <= *last) {
+                continue;
+            }
+            if downbeat.is_none()
+                && (beat.beat_in_measure == Some(1) || beat.beat_type == "downbeat")
+            {
+                downbeat = Some(times.len());
+            }
+            times.push(beat.time);
+        }
+
+        if times.len() < 2 {
+            let bpm = analysis
+                .tempo_bpm
+                .filter(|bpm| bpm.is_finite() && *bpm > 0.0)
+                .unwrap_or(120.0);
+            times = vec![0.0, 60.0 / bpm];
+            downbeat = Some(0);
+        }
+
+        Self {
+            times,
+            numerator,
+            denominator,
+            downbeat: downbeat.unwrap_or(0),
+        }
+    }
+
+    /// Fractional beat number of a time
+    pub fn beat_at(&self, seconds: f64) -> f64 {
+        let after = self.times.partition_point(|time| *time <= seconds);
+        let i = after.clamp(1, self.times.len() - 1) - 1;
+        let (t0, t1) = (self.times[i], self.times[i + 1]);
+        i as f64 + (seconds - t0) / (t1 - t0)
+    }
+
+    /// Time of a fractional beat number; inverse of [`BeatGrid::beat_at`]
+    pub fn seconds_at(&self, beat: f64) -> f64 {
+        let i = (beat.floor().max(0.0) as usize).min(self.times.len() - 2);
+        let (t0, t1) = (self.times[i], self.times[i + 1]);
+        t0 + (beat - i as f64) * (t1 - t0)
+    }
+
+    /// Beat number on which bar `bar` starts; bar 0 starts on the first
+    /// downbeat and earlier bars are negative
+    pub fn bar_start(&self, bar: i64) -> i64 {
+        self.downbeat as i64 + bar * self.numerator as i64
+    }
+
+    /// Bar containing a (fractional) beat number
+    pub fn bar_of(&self, beat: f64) -> i64 {
+        ((beat - self.downbeat as f64) / self.numerator as f64).floor() as i64
+    }
+}
+
+/// Key signature as a number of sharps (positive) or flats (negative)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeySignature {
+    pub fifths: i32,
+    pub minor: bool,
+}
+
+impl KeySignature {
+    /// Parse a key name such as `"A minor"`, `"F# major"` or `"Bbm"`;
+    /// unknown names give C major
+    pub fn parse(name: Option<&str>) -> Self {
+        let c_major = Self {
+            fifths: 0,
+            minor: false,
+        };
+        let Some(name) = name.map(str::trim).filter(|name| !name.is_empty()) else {
+            return c_major;
+        };
+        let mut chars = name.chars();
+        let Some(letter) = chars.next().map(|c| c.to_ascii_uppercase()) else {
+            return c_major;
+        };
+        // Fifths of the natural major keys, by letter
+        let Some(natural) = "FCGDAEB".find(letter) else {
+            return c_major;
+        };
+        let rest = chars.as_str();
+        let (accidental, mode) = if let Some(mode) = rest.strip_prefix(['#', '♯']) {
+            (7, mode)
+        } else if let Some(mode) = rest.strip_prefix(['b', '♭']) {
+            (-7, mode)
+        } else {
+            (0, rest)
+        };
+        let mode = mode.trim().to_lowercase();
+        let minor = mode.starts_with("min") || mode == "m";
+        let fifths = natural as i32 - 1 + accidental - if minor { 3 } else { 0 };
+        if !(-7..=7).contains(&fifths) {
+            return c_major;
+        }
+        Self { fifths, minor }
+    }
+
+    /// Step, alteration and octave for a MIDI pitch, spelled with sharps in
+    /// sharp keys and flats in flat keys
+    pub fn spell(&self, pitch: i32) -> (char, i32, i32) {
+        const SHARPS: [(char, i32); 12] = [
+            ('C', 0),
+            ('C', 1),
+            ('D', 0),
+            ('D', 1),
+            ('E', 0),
+            ('F', 0),
+            ('F', 1),
+            ('G', 0),
+            ('G', 1),
+            ('A', 0),
+            ('A', 1),
+            ('B', 0),
+        ];
+        const FLATS: [(char, i32); 12] = [
+            ('C', 0),
+            ('D', -1),
+            ('D', 0),
+            ('E', -1),
+            ('E', 0),
+            ('F', 0),
+            ('G', -1),
+            ('G', 0),
+            ('A', -1),
+            ('A', 0),
+            ('B', -1),
+            ('B', 0),
+        ];
+        let names = if self.fifths < 0 { &FLATS } else { &SHARPS };
+        let (step, alter) = names[pitch.rem_euclid(12) as usize];
+        (step, alter, pitch.div_euclid(12) - 1)
+    }
+}
+
+/// Note values a duration is written as: (type, dots, length in divisions)
+/// from longest to shortest, tied together when there is more than one.
+///
+/// `divisions` is the number of divisions in a quarter note.
+pub fn note_values(length: u32, divisions: u32) -> Vec<(&'static str, u8, u32)> {
+    // Length of each type in 128ths of a quarter note
+    const TYPES: [(&str, u32); 8] = [
+        ("whole", 512),
+        ("half", 256),
+        ("quarter", 128),
+        ("eighth", 64),
+        ("16th", 32),
+        ("32nd", 16),
+        ("64th", 8),
+        ("128th", 4),
+    ];
+    let mut values = Vec::new();
+    let mut remaining = length;
+    while remaining > 0 {
+        let fit = TYPES.iter().flat_map(|(name, units)| {
+            [(*name, 1, units * 3 / 2), (*name, 0, *units)]
+                .into_iter()
+                .filter(|(_, _, units)| (units * divisions).is_multiple_of(128))
+                .map(|(name, dots, units)| (name, dots, units * divisions / 128))
+        });
+        let Some(value) = fit
+            .filter(|(_, _, length)| *length > 0 && *length <= remaining)
+            .max_by_key(|(_, _, length)| *length)
+        else {
+            break;
+        };
+        values.push(value);
+        remaining -= value.2;
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_analysis;
+
+    #[test]
+    fn test_beat_grid_counts_bars_from_downbeat() {
+        let mut analysis = test_analysis();
+        analysis.original_duration = 4.0;
+        analysis.time_signature = Some((3, 4));
+        analysis.beats = serde_json::from_value(serde_json::json!([
+            {"time": 1.0, "type": "beat", "beatInMeasure": 3},
+            {"time": 1.5, "type": "downbeat", "beatInMeasure": 1},
+            {"time": 2.0, "type": "beat", "beatInMeasure": 2},
+            {"time": 3.0, "type": "beat", "beatInMeasure": 3}
+        ]))
+        .unwrap();
+        let grid = BeatGrid::from_analysis(&analysis);
+        assert_eq!(grid.beat_at(0.5), -1.0);
+        assert_eq!(grid.beat_at(1.75), 1.5);
+        assert_eq!(grid.beat_at(4.0), 4.0);
+        assert_eq!(grid.seconds_at(2.5), 2.5);
+        assert_eq!(grid.seconds_at(-1.0), 0.5);
+        assert_eq!(grid.bar_start(0), 1);
+        assert_eq!(grid.bar_of(0.0), -1);
+        assert_eq!(grid.bar_of(3.9), 0);
+        assert_eq!(grid.bar_of(4.0), 1);
+    }
+
+    #[test]
+    fn test_key_spelling_and_note_values() {
+        let a_minor = KeySignature::parse(Some("A minor"));
+        assert_eq!(a_minor.fifths, 0);
+        assert!(a_minor.minor);
+        assert_eq!(KeySignature::parse(Some("F# major")).fifths, 6);
+        assert_eq!(KeySignature::parse(Some("Bb major")).fifths, -2);
+        assert_eq!(KeySignature::parse(Some("Ebm")).fifths, -6);
+        assert_eq!(KeySignature::parse(Some("mystery")).fifths, 0);
+
+        assert_eq!(a_minor.spell(61), ('C', 1, 4));
+        assert_eq!(KeySignature::parse(Some("F major")).spell(70), ('B', -1, 4));
+
+        // Four divisions per quarter: 16ths are one division
+        assert_eq!(note_values(6, 4), vec![("quarter", 1, 6)]);
+        assert_eq!(note_values(5, 4), vec![("quarter", 0, 4), ("16th", 0, 1)]);
+        assert_eq!(note_values(16, 4), vec![("whole", 0, 16)]);
+    }
+}
@@ -0,0 +1,561 @@
+//! MusicXML export of a stem's notes.
+//!
+//! Notes are snapped to a subdivision of the song's beats, grouped into
+//! chords where they start together and cut short where the next chord
+//! starts, so each stem becomes a single voice. Bars follow the detected
+//! downbeats; durations that cross a barline or need more than one note
+//! value are written as tied notes and gaps are filled with rests.
+
+use super::{note_values, BeatGrid, KeySignature};
+use crate::error::{CommandError, CommandResult};
+use crate::{read_analysis, SongAnalysis};
+use serde::Serialize;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Grid steps per beat allowed for quantizing
+pub const SUBDIVISIONS: [u32; 4] = [1, 2, 4, 8];
+
+/// 16ths in 4/4
+pub const DEFAULT_SUBDIVISION: u32 = 4;
+
+/// Something that sounds in a chord: one pitch, or one drum
+pub trait Member {
+    /// `<pitch>` or `<unpitched>` element
+    fn sound(&self) -> String;
+
+    /// Elements between `<duration>`/`<tie>` and `<voice>`, such as
+    /// `<instrument>`
+    fn instrument(&self) -> String {
+        String::new()
+    }
+
+    /// Elements after `<type>`/`<dot>`, such as `<stem>` or `<notehead>`
+    fn appearance(&self) -> String {
+        String::new()
+    }
+
+    /// Content of `<notations>` besides ties, such as `<technical>`
+    fn notations(&self) -> String {
+        String::new()
+    }
+}
+
+/// Members sounding together from grid step `start` until `end`
+pub struct Chord<T> {
+    pub start: i64,
+    pub end: i64,
+    pub members: Vec<T>,
+}
+
+/// Group events into chords by start step, each lasting to the latest end
+/// among its members but no further than the next chord's start
+pub fn voice<T>(mut events: Vec<(i64, i64, T)>) -> Vec<Chord<T>> {
+    events.sort_by_key(|(start, _, _)| *start);
+    let mut chords: Vec<Chord<T>> = Vec::new();
+    for (start, end, member) in events {
+        match chords.last_mut() {
+            Some(chord) if chord.start == start => {
+                chord.end = chord.end.max(end);
+                chord.members.push(member);
+            }
+            _ => chords.push(Chord {
+                start,
+                end: end.max(start + 1),
+                members: vec![member],
+            }),
+        }
+    }
+    for i in 1..chords.len() {
+        let next = chords[i].start;
+        let chord = &mut chords[i - 1];
+        chord.end = chord.end.min(next);
+    }
+    chords
+}
+
+/// Bars and divisions of a part quantized to a grid
+pub struct Layout {
+    /// MusicXML divisions per quarter note
+    pub divisions: u32,
+    /// Divisions per grid step
+    step_divisions: u32,
+    steps_per_bar: i64,
+    /// Grid step on which the first written bar starts
+    first_step: i64,
+    pub bars: usize,
+    /// Grid steps per beat
+    subdivision: i64,
+    /// Steps are triplets, written three in the time of two
+    triplets: bool,
+}
+
+const TRIPLET: &str = "<time-modification><actual-notes>3</actual-notes>\
+                       <normal-notes>2</normal-notes></time-modification>";
+
+impl Layout {
+    /// Bars of `grid` covering steps `first..last`, at least one
+    pub fn new(grid: &BeatGrid, subdivision: u32, first: i64, last: i64) -> Self {
+        // A beat of 1/denominator is 4/denominator quarters
+        let divisions = subdivision * grid.denominator.max(4) / 4;
+        let step_divisions = divisions * 4 / (grid.denominator * subdivision);
+        let steps_per_bar = grid.numerator as i64 * subdivision as i64;
+        let bar_step = |bar: i64| grid.bar_start(bar) * subdivision as i64;
+        let first_bar = grid.bar_of(first as f64 / subdivision as f64);
+        let last_bar = grid
+            .bar_of((last - 1).max(first) as f64 / subdivision as f64)
+            .max(first_bar);
+        Self {
+            divisions,
+            step_divisions,
+            steps_per_bar,
+            first_step: bar_step(first_bar),
+            bars: (last_bar - first_bar + 1) as usize,
+            subdivision: subdivision as i64,
+            triplets: subdivision.is_multiple_of(3),
+        }
+    }
+
+    /// Note values for grid steps `start..end`, as (type, dots, divisions,
+    /// triplet). With triplet steps, whole beats are written as plain
+    /// values and only the steps before the first beat boundary or after
+    /// the last are triplets, whose type is the written value, 3/2 of the
+    /// real length
+    fn values(&self, start: i64, end: i64) -> Vec<(&'static str, u8, u32, bool)> {
+        let plain = |steps: i64| {
+            note_values(steps as u32 * self.step_divisions, self.divisions)
+                .into_iter()
+                .map(|(name, dots, length)| (name, dots, length, false))
+        };
+        if !self.triplets {
+            return plain(end - start).collect();
+        }
+        // Written lengths in halves of a division, so 3/2 stays whole
+        let triplet = |steps: i64| {
+            note_values(steps as u32 * self.step_divisions * 3, self.divisions * 2)
+                .into_iter()
+                .map(|(name, dots, written)| (name, dots, written / 3, true))
+        };
+        let first_beat = (start + self.subdivision - 1).div_euclid(self.subdivision);
+        let lead_end = (first_beat * self.subdivision).min(end);
+        let tail_start = (end.div_euclid(self.subdivision) * self.subdivision).max(lead_end);
+        triplet(lead_end - start)
+            .chain(plain(tail_start - lead_end))
+            .chain(triplet(end - tail_start))
+            .collect()
+    }
+
+    /// Divisions in one bar, for `<backup>` between voices
+    pub fn bar_divisions(&self) -> u32 {
+        self.steps_per_bar as u32 * self.step_divisions
+    }
+
+    /// Grid step of a time
+    pub fn step(grid: &BeatGrid, subdivision: u32, seconds: f64) -> i64 {
+        (grid.beat_at(seconds) * subdivision as f64).round() as i64
+    }
+
+    /// Grid steps `start..end` of written bar `bar`
+    pub fn bar_range(&self, bar: usize) -> (i64, i64) {
+        let start = self.first_step + bar as i64 * self.steps_per_bar;
+        (start, start + self.steps_per_bar)
+    }
+
+    /// `<attributes>` for the first bar, with `clef` holding `<clef>` and
+    /// any `<transpose>` element
+    pub fn attributes(&self, grid: &BeatGrid, key: KeySignature, clef: &str) -> String {
+        format!(
+            "<attributes><divisions>{}</divisions>\
+             <key><fifths>{}</fifths><mode>{}</mode></key>\
+             <time><beats>{}</beats><beat-type>{}</beat-type></time>{}</attributes>",
+            self.divisions,
+            key.fifths,
+            if key.minor { "minor" } else { "major" },
+            grid.numerator,
+            grid.denominator,
+            clef
+        )
+    }
+
+    /// Write bar `bar` of one voice: its chords, tied where they continue
+    /// from or into another bar, and rests between them
+    pub fn write_voice<T: Member>(
+        &self,
+        out: &mut String,
+        bar: usize,
+        chords: &[Chord<T>],
+        voice: u32,
+    ) {
+        let (bar_start, bar_end) = self.bar_range(bar);
+        let in_bar: Vec<&Chord<T>> = chords
+            .iter()
+            .filter(|chord| chord.start < bar_end && chord.end > bar_start)
+            .collect();
+        if in_bar.is_empty() {
+            let _ = write!(
+                out,
+                "<note><rest measure=\"yes\"/><duration>{}</duration><voice>{}</voice></note>",
+                self.bar_divisions(),
+                voice
+            );
+            return;
+        }
+
+        let mut position = bar_start;
+        for chord in in_bar {
+            let start = chord.start.max(bar_start);
+            let end = chord.end.min(bar_end);
+            if start > position {
+                self.write_rest(out, position, start, voice);
+            }
+            let values = self.values(start, end);
+            for (i, (name, dots, duration, triplet)) in values.iter().enumerate() {
+                let tie_stop = i > 0 || start > chord.start;
+                let tie_start = i + 1 < values.len() || end < chord.end;
+                for (m, member) in chord.members.iter().enumerate() {
+                    out.push_str("<note>");
+                    if m > 0 {
+                        out.push_str("<chord/>");
+                    }
+                    out.push_str(&member.sound());
+                    let _ = write!(out, "<duration>{}</duration>", duration);
+                    if tie_stop {
+                        out.push_str("<tie type=\"stop\"/>");
+                    }
+                    if tie_start {
+                        out.push_str("<tie type=\"start\"/>");
+                    }
+                    out.push_str(&member.instrument());
+                    let _ = write!(out, "<voice>{}</voice><type>{}</type>", voice, name);
+                    for _ in 0..*dots {
+                        out.push_str("<dot/>");
+                    }
+                    if *triplet {
+                        out.push_str(TRIPLET);
+                    }
+                    out.push_str(&member.appearance());
+                    let notations = member.notations();
+                    if tie_stop || tie_start || !notations.is_empty() {
+                        out.push_str("<notations>");
+                        if tie_stop {
+                            out.push_str("<tied type=\"stop\"/>");
+                        }
+                        if tie_start {
+                            out.push_str("<tied type=\"start\"/>");
+                        }
+                        out.push_str(&notations);
+                        out.push_str("</notations>");
+                    }
+                    out.push_str("</note>");
+                }
+            }
+            position = end;
+        }
+        if bar_end > position {
+            self.write_rest(out, position, bar_end, voice);
+        }
+    }
+
+    /// Rests filling grid steps `start..end`
+    fn write_rest(&self, out: &mut String, start: i64, end: i64, voice: u32) {
+        for (name, dots, duration, triplet) in self.values(start, end) {
+            let _ = write!(
+                out,
+                "<note><rest/><duration>{}</duration><voice>{}</voice><type>{}</type>",
+                duration, voice, name
+            );
+            for _ in 0..dots {
+                out.push_str("<dot/>");
+            }
+            if triplet {
+                out.push_str(TRIPLET);
+            }
+            out.push_str("</note>");
+        }
+    }
+}
+
+/// Escape text for XML content and attribute values
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Start of a single-part score up to the opening `<part>` tag;
+/// `instruments` holds any `<score-instrument>` elements of the part
+pub fn score_header(analysis: &SongAnalysis, part_name: &str, instruments: &str) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n\
+         <!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \
+         \"http://www.musicxml.org/dtds/partwise.dtd\">\n\
+         <score-partwise version=\"4.0\">",
+    );
+    if let Some(title) = &analysis.title {
+        let _ = write!(
+            out,
+            "<work><work-title>{}</work-title></work>",
+            escape(title)
+        );
+    }
+    out.push_str("<identification>");
+    if let Some(artist) = &analysis.artist {
+        let _ = write!(
+            out,
+            "<creator type=\"composer\">{}</creator>",
+            escape(artist)
+        );
+    }
+    out.push_str("<encoding><software>Music Tutor</software></encoding></identification>");
+    let _ = write!(
+        out,
+        "<part-list><score-part id=\"P1\"><part-name>{}</part-name>{}</score-part></part-list>\
+         <part id=\"P1\">",
+        escape(part_name),
+        instruments
+    );
+    out
+}
+
+/// `<direction>` marking the tempo, in beats of the time signature
+pub fn tempo_direction(analysis: &SongAnalysis, grid: &BeatGrid) -> String {
+    let Some(bpm) = analysis
+        .tempo_bpm
+        .filter(|bpm| bpm.is_finite() && *bpm > 0.0)
+    else {
+        return String::new();
+    };
+    let beat_unit = match grid.denominator {
+        1 => "whole",
+        2 => "half",
+        8 => "eighth",
+        16 => "16th",
+        _ => "quarter",
+    };
+    format!(
+        "<direction placement=\"above\"><direction-type><metronome>\
+         <beat-unit>{}</beat-unit><per-minute>{}</per-minute></metronome></direction-type>\
+         <sound tempo=\"{:.2}\"/></direction>",
+        beat_unit,
+        bpm.round(),
+        bpm * 4.0 / grid.denominator as f64
+    )
+}
+
+/// One pitch, as written
+pub struct Pitch {
+    step: char,
+    alter: i32,
+    octave: i32,
+}
+
+impl Pitch {
+    /// Spell a MIDI pitch in `key`
+    pub fn new(key: KeySignature, pitch: i32) -> Self {
+        let (step, alter, octave) = key.spell(pitch);
+        Self {
+            step,
+            alter,
+            octave,
+        }
+    }
+}
+
+impl Member for Pitch {
+    fn sound(&self) -> String {
+        let alter = if self.alter != 0 {
+            format!("<alter>{}</alter>", self.alter)
+        } else {
+            String::new()
+        };
+        format!(
+            "<pitch><step>{}</step>{}<octave>{}</octave></pitch>",
+            self.step, alter, self.octave
+        )
+    }
+}
+
+/// Clef for a stem, and how many semitones its notes are written above
+/// where they sound: bass and guitar read an octave up, as is usual
+fn clef_for(stem: &str, pitches: &[i32]) -> (String, i32) {
+    const TREBLE: &str = "<clef><sign>G</sign><line>2</line></clef>";
+    const BASS: &str = "<clef><sign>F</sign><line>4</line></clef>";
+    const OCTAVE_DOWN: &str =
+        "<transpose><diatonic>-7</diatonic><chromatic>-12</chromatic><octave-change>-1</octave-change></transpose>";
+    let stem = stem.to_lowercase();
+    if stem.contains("bass") {
+        return (format!("{}{}", BASS, OCTAVE_DOWN), 12);
+    }
+    if stem.contains("guitar") {
+        return (format!("{}{}", TREBLE, OCTAVE_DOWN), 12);
+    }
+    let mut sorted = pitches.to_vec();
+    sorted.sort();
+    match sorted.get(sorted.len() / 2) {
+        Some(median) if *median < 60 => (BASS.to_string(), 0),
+        _ => (TREBLE.to_string(), 0),
+    }
+}
+
+/// Render a stem's notes as a MusicXML document, returning it with the
+/// number of bars and notes written
+pub fn render(
+    analysis: &SongAnalysis,
+    stem: &str,
+    subdivision: u32,
+) -> CommandResult<(String, usize, usize)> {
+    if !SUBDIVISIONS.contains(&subdivision) {
+        return Err(CommandError::invalid(format!(
+            "subdivision must be one of {:?}",
+            SUBDIVISIONS
+        )));
+    }
+    let notes = analysis
+        .notes
+        .as_ref()
+        .and_then(|notes| notes.get(stem))
+        .filter(|notes| !notes.is_empty())
+        .ok_or_else(|| CommandError::invalid(format!("stem {} has no notes", stem)))?;
+
+    let grid = BeatGrid::from_analysis(analysis);
+    let key = KeySignature::parse(analysis.key.as_deref());
+    let pitches: Vec<i32> = notes.iter().map(|note| note.pitch).collect();
+    let (clef, written_offset) = clef_for(stem, &pitches);
+
+    let events: Vec<(i64, i64, Pitch)> = notes
+        .iter()
+        .map(|note| {
+            (
+                Layout::step(&grid, subdivision, note.start),
+                Layout::step(&grid, subdivision, note.end),
+                Pitch::new(key, note.pitch + written_offset),
+            )
+        })
+        .collect();
+    let chords = voice(events);
+    let first = chords.first().map_or(0, |chord| chord.start);
+    let last = chords.last().map_or(0, |chord| chord.end);
+    let layout = Layout::new(&grid, subdivision, first, last);
+
+    let mut out = score_header(analysis, stem, "");
+    for bar in 0..layout.bars {
+        let _ = write!(out, "<measure number=\"{}\">", bar + 1);
+        if bar == 0 {
+            out.push_str(&layout.attributes(&grid, key, &clef));
+            out.push_str(&tempo_direction(analysis, &grid));
+        }
+        layout.write_voice(&mut out, bar, &chords, 1);
+        out.push_str("</measure>");
+    }
+    out.push_str("</part></score-partwise>\n");
+    Ok((out, layout.bars, notes.len()))
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MusicXmlResult {
+    pub path: String,
+    pub measures: usize,
+    pub notes: usize,
+}
+
+/// Write a stem's notes as MusicXML sheet music, quantized to
+/// `subdivision` steps per beat (default 4)
+#[tauri::command]
+pub fn export_musicxml(
+    song_dir: &str,
+    stem: &str,
+    output_path: &str,
+    subdivision: Option<u32>,
+) -> CommandResult<MusicXmlResult> {
+    let analysis = read_analysis(&Path::new(song_dir).join("analysis.json"))?;
+    let (xml, measures, notes) =
+        render(&analysis, stem, subdivision.unwrap_or(DEFAULT_SUBDIVISION))?;
+    let path = Path::new(output_path);
+    std::fs::write(path, xml).map_err(|e| CommandError::io(path, e))?;
+    Ok(MusicXmlResult {
+        path: output_path.to_string(),
+        measures,
+        notes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_analysis, test_beats};
+
+    fn analysis() -> SongAnalysis {
+        let mut song = test_analysis();
+        song.title = Some("Rock & Roll".to_string());
+        song.original_duration = 4.0;
+        song.tempo_bpm = Some(120.0);
+        song.key = Some("A minor".to_string());
+        song.time_signature = Some((4, 4));
+        song.beats = test_beats(8, 0.5, 4);
+        song.notes = serde_json::from_value(serde_json::json!({"bass": [
+            // A quarter on beat 1, a slightly early 16th before beat 3
+            {"start": 0.02, "end": 0.5, "pitch": 40, "velocity": 0.8},
+            {"start": 0.87, "end": 1.0, "pitch": 42, "velocity": 0.8},
+            // Held across the barline, with a fifth on top
+            {"start": 1.5, "end": 2.5, "pitch": 45, "velocity": 0.8},
+            {"start": 1.5, "end": 2.5, "pitch": 52, "velocity": 0.8}
+        ]}))
+        .unwrap();
+        song
+    }
+
+    #[test]
+    fn test_voice_cuts_chords_at_next_start() {
+        let chords = voice(vec![(4, 12, 'b'), (0, 8, 'a'), (0, 2, 'c')]);
+        let spans: Vec<(i64, i64, usize)> = chords
+            .iter()
+            .map(|chord| (chord.start, chord.end, chord.members.len()))
+            .collect();
+        assert_eq!(spans, vec![(0, 4, 2), (4, 12, 1)]);
+    }
+
+    #[test]
+    fn test_renders_bars_ties_and_rests() {
+        let (xml, measures, notes) = render(&analysis(), "bass", 4).unwrap();
+        assert_eq!((measures, notes), (2, 4));
+        assert!(xml.contains("<work-title>Rock &amp; Roll</work-title>"));
+        assert!(xml.contains("<divisions>4</divisions>"));
+        assert!(xml.contains("<fifths>0</fifths><mode>minor</mode>"));
+        assert!(xml.contains("<sign>F</sign>"));
+        assert!(xml.contains("<per-minute>120</per-minute>"));
+
+        let first_bar = &xml[xml.find("<measure number=\"1\">").unwrap()
+            ..xml.find("<measure number=\"2\">").unwrap()];
+        // Written an octave up: E3 quarter, eighth rest, dotted eighth...
+        assert!(first_bar
+            .contains("<pitch><step>E</step><octave>3</octave></pitch><duration>4</duration>"));
+        assert!(first_bar.contains(
+            "<note><rest/><duration>3</duration><voice>1</voice><type>eighth</type><dot/></note>"
+        ));
+        assert!(first_bar.contains(
+            "<pitch><step>F</step><alter>1</alter><octave>3</octave></pitch><duration>1</duration>"
+        ));
+        // Beats 3 and 4 hold the chord, tied into the next bar
+        assert_eq!(first_bar.matches("<tie type=\"start\"/>").count(), 2);
+        assert_eq!(first_bar.matches("<chord/>").count(), 1);
+
+        let second_bar = &xml[xml.find("<measure number=\"2\">").unwrap()..];
+        assert_eq!(second_bar.matches("<tied type=\"stop\"/>").count(), 2);
+        assert!(second_bar.contains(
+            "<note><rest/><duration>12</duration><voice>1</voice><type>half</type><dot/></note>"
+        ));
+
+        assert!(render(&analysis(), "vocals", 4).is_err());
+        assert!(render(&analysis(), "bass", 3).is_err());
+    }
+}
@@ -0,0 +1,648 @@
+//! Guitar and bass tablature from a stem's notes.
+//!
+//! Notes starting together are fingered as one chord. Each chord gets a
+//! list of candidate fingerings (one string per note, frets within reach),
+//! and a shortest-path pass over the song picks the sequence that keeps the
+//! fretting hand's position moving least, with small penalties for wide
+//! stretches and high frets. The result is returned to the UI and can be
+//! written as ASCII tab or as MusicXML with a TAB staff, which Guitar Pro
+//! and MuseScore import.
+
+use super::musicxml::{self, voice, Layout, Member, Pitch, DEFAULT_SUBDIVISION};
+use super::{BeatGrid, KeySignature};
+use crate::error::{CommandError, CommandResult};
+use crate::{read_analysis, Note, SongAnalysis};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::path::Path;
+
+/// Highest fret considered unless the request says otherwise
+pub const DEFAULT_MAX_FRET: u8 = 24;
+
+/// Notes starting within this many seconds are played as one chord
+const CHORD_WINDOW: f64 = 0.03;
+
+/// Frets the hand covers without stretching
+const COMFORTABLE_SPAN: u8 = 4;
+
+/// Fingerings kept per chord for the path search, cheapest first
+const MAX_CANDIDATES: usize = 48;
+
+/// Bars per line of ASCII tab
+const BARS_PER_LINE: usize = 4;
+
+/// Cost of leaving a note out of a chord that can't be fingered whole
+const DROPPED_NOTE_COST: f64 = 100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TuningPreset {
+    /// E A D G B E
+    Standard,
+    /// D A D G B E
+    DropD,
+    /// E A D G
+    Bass,
+    /// B E A D G
+    FiveStringBass,
+}
+
+/// Open-string pitches, either a preset or MIDI pitches from the lowest
+/// string to the highest
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Tuning {
+    Preset(TuningPreset),
+    Custom(Vec<i32>),
+}
+
+impl Tuning {
+    /// MIDI pitch of each open string, lowest first
+    pub fn strings(&self) -> CommandResult<Vec<i32>> {
+        let strings = match self {
+            Tuning::Preset(TuningPreset::Standard) => vec![40, 45, 50, 55, 59, 64],
+            Tuning::Preset(TuningPreset::DropD) => vec![38, 45, 50, 55, 59, 64],
+            Tuning::Preset(TuningPreset::Bass) => vec![28, 33, 38, 43],
+            Tuning::Preset(TuningPreset::FiveStringBass) => vec![23, 28, 33, 38, 43],
+            Tuning::Custom(strings) => strings.clone(),
+        };
+        if strings.is_empty()
+            || strings.len() > 12
+            || strings.iter().any(|pitch| !(0..=127).contains(pitch))
+        {
+            return Err(CommandError::invalid(
+                "a tuning needs 1 to 12 strings with MIDI pitches 0-127",
+            ));
+        }
+        Ok(strings)
+    }
+
+    /// The tuning used when none is given: 4-string bass for bass stems,
+    /// standard guitar otherwise
+    pub fn for_stem(stem: &str) -> Self {
+        if stem.to_lowercase().contains("bass") {
+            Tuning::Preset(TuningPreset::Bass)
+        } else {
+            Tuning::Preset(TuningPreset::Standard)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TabNote {
+    pub start: f64,
+    pub end: f64,
+    pub pitch: i32,
+    /// 1 for the highest string, as tab counts them
+    pub string: usize,
+    pub fret: u8,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tablature {
+    /// Open-string pitches, lowest first
+    pub tuning: Vec<i32>,
+    pub notes: Vec<TabNote>,
+    /// Notes out of the instrument's range, or beyond its strings in a
+    /// chord, left out
+    pub unplayable: usize,
+}
+
+/// One way to finger a chord: a (string, fret) for each of its notes, or
+/// `None` for notes left out
+#[derive(Debug, Clone)]
+struct Fingering {
+    positions: Vec<Option<(usize, u8)>>,
+    cost: f64,
+}
+
+impl Fingering {
+    /// Lowest fretted fret, where the index finger sits; `None` when only
+    /// open strings are played
+    fn hand(&self) -> Option<u8> {
+        self.positions
+            .iter()
+            .flatten()
+            .map(|(_, fret)| *fret)
+            .filter(|fret| *fret > 0)
+            .min()
+    }
+}
+
+/// What a fingering costs on its own, before hand movement. Placing more
+/// notes never makes it cheaper, so partial fingerings can be pruned
+fn fingering_cost(positions: &[Option<(usize, u8)>]) -> f64 {
+    let frets: Vec<u8> = positions
+        .iter()
+        .flatten()
+        .map(|(_, fret)| *fret)
+        .filter(|fret| *fret > 0)
+        .collect();
+    let span = match (frets.iter().min(), frets.iter().max()) {
+        (Some(low), Some(high)) => high - low,
+        _ => 0,
+    };
+    let dropped = positions.iter().filter(|p| p.is_none()).count();
+    let mut cost = dropped as f64 * DROPPED_NOTE_COST + span as f64 * 0.5;
+    if span > COMFORTABLE_SPAN {
+        cost += (span - COMFORTABLE_SPAN) as f64 * 10.0;
+    }
+    // Prefer lower positions, which ring longer and read easier
+    cost + frets.iter().map(|fret| *fret as f64).sum::<f64>() * 0.05
+}
+
+/// The cheapest fingerings of `pitches` on `strings`, cheapest first.
+///
+/// Notes are placed one at a time, keeping only the `MAX_CANDIDATES`
+/// cheapest partial fingerings after each, so large chords on many
+/// strings stay linear in the chord size instead of trying every
+/// permutation.
+fn fingerings(pitches: &[i32], strings: &[i32], max_fret: u8) -> Vec<Fingering> {
+    let mut beam = vec![Fingering {
+        positions: Vec::new(),
+        cost: 0.0,
+    }];
+    for (i, &pitch) in pitches.iter().enumerate() {
+        let remaining = pitches.len() - i;
+        let mut next = Vec::new();
+        for partial in &beam {
+            let used: Vec<usize> = partial.positions.iter().flatten().map(|p| p.0).collect();
+            let free = strings.len() - used.len();
+            let mut placed = false;
+            for (string, open) in strings.iter().enumerate() {
+                let fret = pitch - open;
+                if used.contains(&string) || !(0..=max_fret as i32).contains(&fret) {
+                    continue;
+                }
+                placed = true;
+                let mut positions = partial.positions.clone();
+                positions.push(Some((string, fret as u8)));
+                next.push(Fingering {
+                    cost: fingering_cost(&positions),
+                    positions,
+                });
+            }
+            // Leave the note out only if it can't be placed or there are
+            // more notes left than strings
+            if !placed || remaining > free {
+                let mut positions = partial.positions.clone();
+                positions.push(None);
+                next.push(Fingering {
+                    cost: fingering_cost(&positions),
+                    positions,
+                });
+            }
+        }
+        next.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+        next.truncate(MAX_CANDIDATES);
+        beam = next;
+    }
+    beam
+}
+
+/// Assign a string and fret to each note, minimizing hand movement
+pub fn solve(notes: &[Note], strings: &[i32], max_fret: u8) -> Tablature {
+    let mut order: Vec<&Note> = notes.iter().collect();
+    order.sort_by(|a, b| a.start.total_cmp(&b.start).then(a.pitch.cmp(&b.pitch)));
+
+    let mut chords: Vec<Vec<&Note>> = Vec::new();
+    for note in order {
+        match chords.last_mut() {
+            Some(chord) if note.start - chord[0].start <= CHORD_WINDOW => chord.push(note),
+            _ => chords.push(vec![note]),
+        }
+    }
+
+    // Cheapest path through one fingering per chord: (cost, previous)
+    let options: Vec<Vec<Fingering>> = chords
+        .iter()
+        .map(|chord| {
+            let pitches: Vec<i32> = chord.iter().map(|note| note.pitch).collect();
+            fingerings(&pitches, strings, max_fret)
+        })
+        .collect();
+    let mut paths: Vec<Vec<(f64, usize)>> = Vec::with_capacity(options.len());
+    for (i, candidates) in options.iter().enumerate() {
+        let row = candidates
+            .iter()
+            .map(|candidate| {
+                let Some(previous) = i.checked_sub(1) else {
+                    return (candidate.cost, 0);
+                };
+                options[previous]
+                    .iter()
+                    .zip(&paths[previous])
+                    .enumerate()
+                    .map(|(p, (before, (total, _)))| {
+                        let movement = match (before.hand(), candidate.hand()) {
+                            (Some(from), Some(to)) => from.abs_diff(to) as f64,
+                            _ => 0.0,
+                        };
+                        (total + movement + candidate.cost, p)
+                    })
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+                    .unwrap_or((candidate.cost, 0))
+            })
+            .collect();
+        paths.push(row);
+    }
+
+    let mut chosen = vec![0; options.len()];
+    if let Some(last) = paths.last() {
+        let mut index = last
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1 .0.total_cmp(&b.1 .0))
+            .map_or(0, |(index, _)| index);
+        for i in (0..options.len()).rev() {
+            chosen[i] = index;
+            index = paths[i][index].1;
+        }
+    }
+
+    let mut tab_notes = Vec::new();
+    let mut unplayable = 0;
+    for ((chord, candidates), choice) in chords.iter().zip(&options).zip(chosen) {
+        let Some(fingering) = candidates.get(choice) else {
+            unplayable += chord.len();
+            continue;
+        };
+        for (note, position) in chord.iter().zip(&fingering.positions) {
+            match position {
+                Some((string, fret)) => tab_notes.push(TabNote {
+                    start: note.start,
+                    end: note.end,
+                    pitch: note.pitch,
+                    string: strings.len() - string,
+                    fret: *fret,
+                }),
+                None => unplayable += 1,
+            }
+        }
+    }
+
+    Tablature {
+        tuning: strings.to_vec(),
+        notes: tab_notes,
+        unplayable,
+    }
+}
+
+/// Letter names of the strings, highest first; where two strings share a
+/// name the higher one is written in lower case, as in `e B G D A E`
+fn string_names(strings: &[i32]) -> Vec<String> {
+    const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+    let names: Vec<&str> = strings
+        .iter()
+        .map(|pitch| NAMES[pitch.rem_euclid(12) as usize])
+        .collect();
+    let mut labels: Vec<String> = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            if names[..i].contains(name) {
+                name.to_lowercase()
+            } else {
+                name.to_string()
+            }
+        })
+        .collect();
+    labels.reverse();
+    let width = labels.iter().map(String::len).max().unwrap_or(1);
+    labels
+        .into_iter()
+        .map(|label| format!("{:<width$}", label, width = width))
+        .collect()
+}
+
+/// ASCII tab of `tab` in bars of the song's beat grid, one column per
+/// 16th; notes show where they start
+pub fn ascii(analysis: &SongAnalysis, tab: &Tablature) -> (String, usize) {
+    let grid = BeatGrid::from_analysis(analysis);
+    let subdivision = DEFAULT_SUBDIVISION;
+    let steps: Vec<i64> = tab
+        .notes
+        .iter()
+        .map(|note| Layout::step(&grid, subdivision, note.start))
+        .collect();
+    let first = steps.iter().copied().min().unwrap_or(0);
+    let last = steps.iter().copied().max().map_or(1, |step| step + 1);
+    let layout = Layout::new(&grid, subdivision, first, last);
+    let names = string_names(&tab.tuning);
+
+    let mut out = String::new();
+    if let Some(title) = &analysis.title {
+        let _ = writeln!(out, "{}\n", title);
+    }
+    let mut bar = 0;
+    while bar < layout.bars {
+        let line_bars = bar..(bar + BARS_PER_LINE).min(layout.bars);
+        let mut lines: Vec<String> = names.iter().map(|name| format!("{}|", name)).collect();
+        for bar in line_bars.clone() {
+            let (start, end) = layout.bar_range(bar);
+            for step in start..end {
+                let frets: Vec<Option<String>> = (1..=tab.tuning.len())
+                    .map(|string| {
+                        tab.notes
+                            .iter()
+                            .zip(&steps)
+                            .find(|(note, at)| **at == step && note.string == string)
+                            .map(|(note, _)| note.fret.to_string())
+                    })
+                    .collect();
+                let width = frets.iter().flatten().map(String::len).max().unwrap_or(1);
+                for (line, fret) in lines.iter_mut().zip(&frets) {
+                    match fret {
+                        Some(fret) => {
+                            let _ = write!(line, "{:-<width$}-", fret, width = width);
+                        }
+                        None => line.push_str(&"-".repeat(width + 1)),
+                    }
+                }
+            }
+            for line in &mut lines {
+                line.push('|');
+            }
+        }
+        for line in lines {
+            let _ = writeln!(out, "{}", line);
+        }
+        out.push('\n');
+        bar = line_bars.end;
+    }
+    (out, layout.bars)
+}
+
+/// A fretted note on a TAB staff
+struct Fretted {
+    pitch: Pitch,
+    string: usize,
+    fret: u8,
+}
+
+impl Member for Fretted {
+    fn sound(&self) -> String {
+        self.pitch.sound()
+    }
+
+    fn notations(&self) -> String {
+        format!(
+            "<technical><string>{}</string><fret>{}</fret></technical>",
+            self.string, self.fret
+        )
+    }
+}
+
+/// MusicXML with a TAB staff carrying each note's string and fret
+pub fn musicxml(analysis: &SongAnalysis, stem: &str, tab: &Tablature) -> (String, usize) {
+    let grid = BeatGrid::from_analysis(analysis);
+    let subdivision = DEFAULT_SUBDIVISION;
+    let key = KeySignature::parse(analysis.key.as_deref());
+    let events: Vec<(i64, i64, Fretted)> = tab
+        .notes
+        .iter()
+        .map(|note| {
+            (
+                Layout::step(&grid, subdivision, note.start),
+                Layout::step(&grid, subdivision, note.end),
+                Fretted {
+                    pitch: Pitch::new(key, note.pitch),
+                    string: note.string,
+                    fret: note.fret,
+                },
+            )
+        })
+        .collect();
+    let chords = voice(events);
+    let first = chords.first().map_or(0, |chord| chord.start);
+    let last = chords.last().map_or(0, |chord| chord.end);
+    let layout = Layout::new(&grid, subdivision, first, last);
+
+    let mut staff = format!(
+        "<clef><sign>TAB</sign><line>5</line></clef>\
+         <staff-details><staff-lines>{}</staff-lines>",
+        tab.tuning.len()
+    );
+    for (line, open) in tab.tuning.iter().enumerate() {
+        let (step, alter, octave) = key.spell(*open);
+        let alter = if alter != 0 {
+            format!("<tuning-alter>{}</tuning-alter>", alter)
+        } else {
+            String::new()
+        };
+        let _ = write!(
+            staff,
+            "<staff-tuning line=\"{}\"><tuning-step>{}</tuning-step>{}\
+             <tuning-octave>{}</tuning-octave></staff-tuning>",
+            line + 1,
+            step,
+            alter,
+            octave
+        );
+    }
+    staff.push_str("</staff-details>");
+
+    let mut out = musicxml::score_header(analysis, stem, "");
+    for bar in 0..layout.bars {
+        let _ = write!(out, "<measure number=\"{}\">", bar + 1);
+        if bar == 0 {
+            out.push_str(&layout.attributes(&grid, key, &staff));
+            out.push_str(&musicxml::tempo_direction(analysis, &grid));
+        }
+        layout.write_voice(&mut out, bar, &chords, 1);
+        out.push_str("</measure>");
+    }
+    out.push_str("</part></score-partwise>\n");
+    (out, layout.bars)
+}
+
+fn tablature(
+    analysis: &SongAnalysis,
+    stem: &str,
+    tuning: Option<Tuning>,
+    max_fret: Option<u8>,
+) -> CommandResult<Tablature> {
+    let notes = analysis
+        .notes
+        .as_ref()
+        .and_then(|notes| notes.get(stem))
+        .ok_or_else(|| CommandError::invalid(format!("stem {} has no notes", stem)))?;
+    let strings = tuning.unwrap_or_else(|| Tuning::for_stem(stem)).strings()?;
+    Ok(solve(notes, &strings, max_fret.unwrap_or(DEFAULT_MAX_FRET)))
+}
+
+/// String and fret for each note of a stem, for a preset or custom
+/// tuning (4-string bass for bass stems and standard guitar otherwise)
+#[tauri::command]
+pub fn compute_tab(
+    song_dir: &str,
+    stem: &str,
+    tuning: Option<Tuning>,
+    max_fret: Option<u8>,
+) -> CommandResult<Tablature> {
+    let analysis = read_analysis(&Path::new(song_dir).join("analysis.json"))?;
+    tablature(&analysis, stem, tuning, max_fret)
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TabFormat {
+    Ascii,
+    /// MusicXML with a TAB staff, for Guitar Pro, MuseScore and the like
+    MusicXml,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TabExportResult {
+    pub path: String,
+    pub measures: usize,
+    pub notes: usize,
+    pub unplayable: usize,
+}
+
+/// Write a stem's tablature as ASCII tab or MusicXML
+#[tauri::command]
+pub fn export_tab(
+    song_dir: &str,
+    stem: &str,
+    tuning: Option<Tuning>,
+    max_fret: Option<u8>,
+    format: TabFormat,
+    output_path: &str,
+) -> CommandResult<TabExportResult> {
+    let analysis = read_analysis(&Path::new(song_dir).join("analysis.json"))?;
+    let tab = tablature(&analysis, stem, tuning, max_fret)?;
+    let (text, measures) = match format {
+        TabFormat::Ascii => ascii(&analysis, &tab),
+        TabFormat::MusicXml => musicxml(&analysis, stem, &tab),
+    };
+    let path = Path::new(output_path);
+    std::fs::write(path, text).map_err(|e| CommandError::io(path, e))?;
+    Ok(TabExportResult {
+        path: output_path.to_string(),
+        measures,
+        notes: tab.notes.len(),
+        unplayable: tab.unplayable,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_analysis, test_beats};
+
+    fn note(start: f64, pitch: i32) -> Note {
+        Note {
+            start,
+            end: start + 0.25,
+            pitch,
+            velocity: 0.8,
+            pitch_bend: None,
+        }
+    }
+
+    fn analysis(notes: Vec<Note>) -> SongAnalysis {
+        let mut song = test_analysis();
+        song.title = Some("Riff".to_string());
+        song.original_duration = 4.0;
+        song.tempo_bpm = Some(120.0);
+        song.time_signature = Some((4, 4));
+        song.beats = test_beats(2, 0.5, 4);
+        song.notes = Some([("guitar".to_string(), notes)].into_iter().collect());
+        song
+    }
+
+    #[test]
+    fn test_tunings() {
+        let parse = |json| serde_json::from_str::<Tuning>(json).unwrap().strings();
+        assert_eq!(parse("\"dropD\"").unwrap(), vec![38, 45, 50, 55, 59, 64]);
+        assert_eq!(parse("\"fiveStringBass\"").unwrap()[0], 23);
+        assert_eq!(parse("[36, 43, 50]").unwrap(), vec![36, 43, 50]);
+        assert!(parse("[]").is_err());
+        assert_eq!(
+            string_names(&[40, 45, 50, 55, 59, 64]),
+            vec!["e", "B", "G", "D", "A", "E"]
+        );
+    }
+
+    #[test]
+    fn test_solver_stays_in_position() {
+        let strings = Tuning::Preset(TuningPreset::Standard).strings().unwrap();
+        // An A minor pentatonic run is played in one position near the nut
+        // rather than walking up a single string
+        let run = [57, 60, 62, 64, 67, 69]
+            .iter()
+            .enumerate()
+            .map(|(i, pitch)| note(i as f64 * 0.25, *pitch))
+            .collect::<Vec<_>>();
+        let tab = solve(&run, &strings, 24);
+        let frets: Vec<(usize, u8)> = tab.notes.iter().map(|n| (n.string, n.fret)).collect();
+        assert_eq!(frets, vec![(3, 2), (2, 1), (2, 3), (1, 0), (1, 3), (1, 5)]);
+
+        // A chord takes one string per note; a note below the low E can't
+        // be played
+        let chord = vec![note(0.0, 40), note(0.0, 47), note(0.0, 52), note(1.0, 30)];
+        let tab = solve(&chord, &strings, 24);
+        assert_eq!(tab.unplayable, 1);
+        let mut used: Vec<usize> = tab.notes.iter().map(|n| n.string).collect();
+        used.sort();
+        used.dedup();
+        assert_eq!(used.len(), 3);
+    }
+
+    #[test]
+    fn test_large_chord_on_many_strings() {
+        // Twelve strings a fourth apart and a sixteen-note cluster that
+        // fits on most of them: trying every assignment would never finish
+        let strings: Vec<i32> = (0..12).map(|i| 28 + i * 5).collect();
+        let cluster: Vec<Note> = (0..16).map(|i| note(0.0, 40 + i * 3)).collect();
+        let tab = solve(&cluster, &strings, 24);
+
+        // Every string is used once and the rest are left out
+        assert_eq!(tab.notes.len(), 12);
+        assert_eq!(tab.unplayable, 4);
+        let mut used: Vec<usize> = tab.notes.iter().map(|n| n.string).collect();
+        used.sort();
+        used.dedup();
+        assert_eq!(used.len(), 12);
+        for played in &tab.notes {
+            assert_eq!(
+                strings[12 - played.string] + played.fret as i32,
+                played.pitch
+            );
+        }
+    }
+
+    #[test]
+    fn test_ascii_and_musicxml() {
+        let song = analysis(vec![
+            note(0.0, 40),
+            note(0.5, 45),
+            note(0.5, 52),
+            note(2.0, 50),
+        ]);
+        let tab = tablature(&song, "guitar", None, None).unwrap();
+        let (text, bars) = ascii(&song, &tab);
+        assert_eq!(bars, 2);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "Riff");
+        // The low E string: open E on beat 1, then nothing but the chord's
+        // root on beat 2
+        assert!(lines[7].starts_with("E|0-------"), "{}", text);
+        assert_eq!(lines[7].matches('|').count(), 3);
+
+        let (xml, bars) = musicxml(&song, "guitar", &tab);
+        assert_eq!(bars, 2);
+        assert!(xml.contains("<sign>TAB</sign>"));
+        assert!(xml.contains("<staff-lines>6</staff-lines>"));
+        assert!(xml.contains(
+            "<staff-tuning line=\"1\"><tuning-step>E</tuning-step><tuning-octave>2</tuning-octave>"
+        ));
+        assert!(xml.contains("<technical><string>6</string><fret>0</fret></technical>"));
+    }
+}
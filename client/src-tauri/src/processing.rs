@@ -0,0 +1,431 @@
+//! Running the music-tutor CLI and turning its console output into progress events.
+
+use crate::error::{CommandError, CommandResult};
+use serde::Serialize;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use tauri::{AppHandle, Emitter};
+
+/// Event emitted for every progress update of a processing job
+pub const PROGRESS_EVENT: &str = "processing-progress";
+
+/// Stages of the full conversion pipeline, in the order the CLI runs them
+pub const CONVERT_STAGES: &[&str] = &[
+    "ingest",
+    "separation",
+    "beat_detection",
+    "pitch_detection",
+    "strike_detection",
+    "lyrics_alignment",
+    "time_stretch",
+    "finalize",
+];
+
+/// Stages run by `convert --reanalyze`, which reuses the existing stems
+pub const REANALYZE_STAGES: &[&str] = &[
+    "beat_detection",
+    "pitch_detection",
+    "strike_detection",
+    "lyrics_alignment",
+    "finalize",
+];
+
+/// A single progress update parsed from the CLI output
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ProgressUpdate {
+    StageStarted { stage: String },
+    StageFinished { stage: String, seconds: f64 },
+    StageFailed { stage: String, message: String },
+    Warning { message: String },
+    Log { line: String },
+}
+
+/// Payload of a `processing-progress` event
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressEvent {
+    pub job_id: String,
+    /// Overall completion, 0-100, based on finished stages
+    pub percent: f64,
+    #[serde(flatten)]
+    pub update: ProgressUpdate,
+}
+
+/// Tracks pipeline position while reading the CLI output line by line.
+///
+/// The CLI prints `  <stage> (<seconds>s)` when a stage finishes and
+/// `  <stage> failed: <message>` when it fails, followed by a `Warnings:`
+/// block at the end of a successful run.
+#[derive(Debug)]
+pub struct ProgressParser {
+    stages: &'static [&'static str],
+    completed: usize,
+    in_warnings: bool,
+    /// Stage and message from a `failed:` line
+    failed: Option<(String, String)>,
+}
+
+impl ProgressParser {
+    pub fn new(stages: &'static [&'static str]) -> Self {
+        Self {
+            stages,
+            completed: 0,
+            in_warnings: false,
+            failed: None,
+        }
+    }
+
+    /// Update announcing the first stage, emitted when the process starts
+    pub fn start(&self) -> Option<ProgressUpdate> {
+        self.stages
+            .first()
+            .map(|stage| ProgressUpdate::StageStarted {
+                stage: stage.to_string(),
+            })
+    }
+
+    pub fn percent(&self) -> f64 {
+        if self.stages.is_empty() {
+            return 0.0;
+        }
+        self.completed as f64 / self.stages.len() as f64 * 100.0
+    }
+
+    /// Stage that is running (or was running when the process exited)
+    pub fn current_stage(&self) -> Option<&'static str> {
+        self.stages.get(self.completed).copied()
+    }
+
+    /// Error for a run that exited unsuccessfully
+    pub fn failure(&self, output: &PipelineOutput) -> CommandError {
+        match &self.failed {
+            Some((stage, message)) => CommandError::PipelineStageFailed {
+                stage: stage.clone(),
+                message: message.clone(),
+            },
+            None => CommandError::PipelineStageFailed {
+                stage: self.current_stage().unwrap_or("unknown").to_string(),
+                message: format!("{}\n{}", output.stdout.trim(), output.stderr.trim())
+                    .trim()
+                    .to_string(),
+            },
+        }
+    }
+
+    /// Parse one line of CLI output into zero or more updates
+    pub fn parse_line(&mut self, line: &str) -> Vec<ProgressUpdate> {
+        let line = line.trim_end();
+        if line.trim().is_empty() {
+            return vec![];
+        }
+
+        if line == "Warnings:" {
+            self.in_warnings = true;
+            return vec![];
+        }
+        if self.in_warnings {
+            if let Some(message) = line.trim_start().strip_prefix("- ") {
+                return vec![ProgressUpdate::Warning {
+                    message: message.to_string(),
+                }];
+            }
+            self.in_warnings = false;
+        }
+
+        if let Some(updates) = line
+            .strip_prefix("  ")
+            .and_then(|rest| self.parse_stage_line(rest))
+        {
+            return updates;
+        }
+
+        vec![ProgressUpdate::Log {
+            line: line.to_string(),
+        }]
+    }
+
+    fn parse_stage_line(&mut self, rest: &str) -> Option<Vec<ProgressUpdate>> {
+        let (name, tail) = rest.split_once(' ')?;
+        let index = self.stages.iter().position(|s| *s == name)?;
+
+        if let Some(message) = tail.strip_prefix("failed: ") {
+            self.failed = Some((name.to_string(), message.to_string()));
+            return Some(vec![ProgressUpdate::StageFailed {
+                stage: name.to_string(),
+                message: message.to_string(),
+            }]);
+        }
+
+        let seconds = tail
+            .strip_prefix('(')?
+            .strip_suffix("s)")?
+            .parse::<f64>()
+            .ok()?;
+
+        self.completed = self.completed.max(index + 1);
+        let mut updates = vec![ProgressUpdate::StageFinished {
+            stage: name.to_string(),
+            seconds,
+        }];
+        if let Some(next) = self.stages.get(index + 1) {
+            updates.push(ProgressUpdate::StageStarted {
+                stage: next.to_string(),
+            });
+        }
+        Some(updates)
+    }
+}
+
+/// Everything the CLI printed, plus how it exited
+#[derive(Debug)]
+pub struct PipelineOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Project root for running `uv`, derived from the song output directory
+/// (e.g. `../output/song` -> `..`)
+fn project_root(output_dir: &str) -> &Path {
+    Path::new(output_dir)
+        .parent() // ../output
+        .and_then(|p| p.parent()) // ..
+        .unwrap_or(Path::new("."))
+}
+
+/// Error for a pipeline process that couldn't be started
+pub fn spawn_error(error: std::io::Error) -> CommandError {
+    if error.kind() == std::io::ErrorKind::NotFound {
+        CommandError::ToolMissing {
+            tool: "uv".to_string(),
+        }
+    } else {
+        CommandError::Io {
+            message: format!("Failed to start process: {}", error),
+        }
+    }
+}
+
+/// Spawn `uv run music-tutor convert` with piped, unbuffered output
+pub fn spawn_convert(
+    audio_file: &str,
+    output_dir: &str,
+    separate_drums: bool,
+) -> std::io::Result<Child> {
+    let mut args = vec!["convert", audio_file, "-o", output_dir];
+
+    if separate_drums {
+        args.push("--drum-sep");
+    }
+
+    spawn_cli(&args, output_dir)
+}
+
+/// Spawn `uv run music-tutor convert --reanalyze` for an existing song
+pub fn spawn_reanalyze(output_dir: &str) -> std::io::Result<Child> {
+    spawn_cli(&["convert", "-o", output_dir, "--reanalyze"], output_dir)
+}
+
+fn spawn_cli(args: &[&str], output_dir: &str) -> std::io::Result<Child> {
+    let mut command = Command::new("uv");
+    command
+        .args(["run", "music-tutor"])
+        .args(args)
+        .current_dir(project_root(output_dir))
+        // Flush every line as it is printed, and keep rich from colouring
+        // or wrapping the stage lines we parse
+        .env("PYTHONUNBUFFERED", "1")
+        .env("NO_COLOR", "1")
+        .env("COLUMNS", "1000")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    // Run in a new process group so cancelling can stop uv and the Python
+    // process it starts together
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+    command.spawn()
+}
+
+/// Kill a pipeline process started by [`spawn_convert`] along with its children
+pub fn kill_process_tree(pid: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    let status = Command::new("kill")
+        .args(["-TERM", "--", &format!("-{}", pid)])
+        .status()?;
+    #[cfg(windows)]
+    let status = Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "kill exited with {}",
+            status
+        )))
+    }
+}
+
+fn forward_lines<R: Read + Send + 'static>(
+    reader: R,
+    is_stderr: bool,
+    tx: mpsc::Sender<(bool, String)>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else { break };
+            if tx.send((is_stderr, line)).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Read the child's stdout and stderr line by line until it exits,
+/// passing every parsed update to `on_update`.
+pub fn watch_progress<F>(
+    child: &mut Child,
+    parser: &mut ProgressParser,
+    mut on_update: F,
+) -> std::io::Result<PipelineOutput>
+where
+    F: FnMut(&ProgressParser, ProgressUpdate),
+{
+    if let Some(update) = parser.start() {
+        on_update(parser, update);
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut readers = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(forward_lines(stdout, false, tx.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(forward_lines(stderr, true, tx.clone()));
+    }
+    drop(tx);
+
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    for (is_stderr, line) in rx {
+        let buffer = if is_stderr { &mut stderr } else { &mut stdout };
+        buffer.push_str(&line);
+        buffer.push('\n');
+
+        for update in parser.parse_line(&line) {
+            on_update(parser, update);
+        }
+    }
+
+    for reader in readers {
+        let _ = reader.join();
+    }
+
+    Ok(PipelineOutput {
+        status: child.wait()?,
+        stdout,
+        stderr,
+    })
+}
+
+/// Run the conversion pipeline for one song, emitting a `processing-progress`
+/// event for every update. Returns the output directory on success.
+pub fn run_convert(
+    app: &AppHandle,
+    job_id: &str,
+    audio_file: &str,
+    output_dir: &str,
+    separate_drums: bool,
+) -> CommandResult<String> {
+    let mut child = spawn_convert(audio_file, output_dir, separate_drums).map_err(spawn_error)?;
+
+    let mut parser = ProgressParser::new(CONVERT_STAGES);
+    let output = watch_progress(&mut child, &mut parser, |parser, update| {
+        let _ = app.emit(
+            PROGRESS_EVENT,
+            ProgressEvent {
+                job_id: job_id.to_string(),
+                percent: parser.percent(),
+                update,
+            },
+        );
+    })
+    .map_err(|e| CommandError::Io {
+        message: format!("Failed to read process output: {}", e),
+    })?;
+
+    if output.status.success() {
+        Ok(output_dir.to_string())
+    } else {
+        Err(parser.failure(&output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_lines_advance_progress() {
+        let mut parser = ProgressParser::new(CONVERT_STAGES);
+        assert_eq!(parser.percent(), 0.0);
+
+        let updates = parser.parse_line("  ingest (1.5s)");
+        assert_eq!(
+            updates,
+            vec![
+                ProgressUpdate::StageFinished {
+                    stage: "ingest".into(),
+                    seconds: 1.5
+                },
+                ProgressUpdate::StageStarted {
+                    stage: "separation".into()
+                },
+            ]
+        );
+        assert_eq!(parser.percent(), 12.5);
+
+        let updates = parser.parse_line("  separation failed: Model not found");
+        assert_eq!(
+            updates,
+            vec![ProgressUpdate::StageFailed {
+                stage: "separation".into(),
+                message: "Model not found".into()
+            }]
+        );
+        assert_eq!(parser.percent(), 12.5);
+    }
+
+    #[test]
+    fn test_warnings_block_and_logs() {
+        let mut parser = ProgressParser::new(CONVERT_STAGES);
+        assert_eq!(
+            parser.parse_line("Processing: song.mp3"),
+            vec![ProgressUpdate::Log {
+                line: "Processing: song.mp3".into()
+            }]
+        );
+        assert!(parser.parse_line("Warnings:").is_empty());
+        assert_eq!(
+            parser.parse_line("  - No lyrics found"),
+            vec![ProgressUpdate::Warning {
+                message: "No lyrics found".into()
+            }]
+        );
+        // An unknown indented name is not a stage
+        assert_eq!(
+            parser.parse_line("  vocals (2.0s)"),
+            vec![ProgressUpdate::Log {
+                line: "  vocals (2.0s)".into()
+            }]
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Limits which files the frontend can reach.
+//!
+//! Stem files are only served if the song's analysis.json lists them and
+//! they resolve to a location inside the song directory. The webview's
+//! asset protocol starts with an empty scope (see `tauri.conf.json`) and is
+//! widened here to cover just the library roots.
+
+use crate::error::{CommandError, CommandResult};
+use crate::library::{self, Library};
+use crate::SongAnalysis;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
+
+/// Resolve `relative` against `dir`, following symlinks and `..`, and
+/// refuse anything that ends up outside `dir`
+pub fn resolve_in_dir(dir: &Path, relative: &str) -> CommandResult<PathBuf> {
+    let base = dir.canonicalize().map_err(|e| CommandError::io(dir, e))?;
+    let joined = dir.join(relative);
+    let resolved = joined
+        .canonicalize()
+        .map_err(|e| CommandError::io(&joined, e))?;
+    if !resolved.starts_with(&base) {
+        return Err(CommandError::PermissionDenied {
+            path: joined.to_string_lossy().to_string(),
+        });
+    }
+    Ok(resolved)
+}
+
+/// Absolute path of a stem file of the song in `song_dir`.
+///
+/// `relative` must be one of the paths in the analysis' `StemInfo.paths`.
+pub fn stem_path(
+    song_dir: &Path,
+    analysis: &SongAnalysis,
+    relative: &str,
+) -> CommandResult<PathBuf> {
+    let listed = analysis
+        .stems
+        .values()
+        .any(|stem| stem.paths.values().any(|path| path == relative));
+    if !listed {
+        return Err(CommandError::PermissionDenied {
+            path: song_dir.join(relative).to_string_lossy().to_string(),
+        });
+    }
+    resolve_in_dir(song_dir, relative)
+}
+
+/// Let the asset protocol serve files under every configured library root.
+///
+/// Tauri scopes can only grow, so roots removed from the library stay
+/// readable until the app restarts.
+pub fn allow_library_roots(app: &AppHandle) {
+    let scope = app.asset_protocol_scope();
+    for root in app.state::<Library>().config().roots {
+        let Ok(root) = Path::new(&root).canonicalize() else {
+            continue;
+        };
+        if let Err(e) = scope.allow_directory(&root, true) {
+            library::report_error(
+                app,
+                CommandError::Io {
+                    message: format!("Failed to allow {} in asset scope: {}", root.display(), e),
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_stem_paths_stay_inside_song() {
+        let root = std::env::temp_dir().join(format!("music-tutor-sandbox-{}", std::process::id()));
+        let song = root.join("song");
+        fs::create_dir_all(song.join("stems")).unwrap();
+        fs::write(song.join("stems/bass.flac"), b"").unwrap();
+        fs::write(root.join("secret.txt"), b"").unwrap();
+
+        let analysis: SongAnalysis = serde_json::from_value(serde_json::json!({
+            "title": null, "artist": null, "album": null,
+            "originalDuration": 1.0, "sampleRate": 44100, "timeSignature": null,
+            "stems": {"bass": {"name": "bass", "hasNotes": false, "peakDb": 0.0, "paths": {
+                "1.0x": "stems/bass.flac",
+                "0.5x": "../secret.txt"
+            }}},
+            "beats": [], "sourceFile": "", "processingDate": "", "converterVersion": ""
+        }))
+        .unwrap();
+
+        let resolved = stem_path(&song, &analysis, "stems/bass.flac").unwrap();
+        assert_eq!(
+            resolved,
+            song.join("stems/bass.flac").canonicalize().unwrap()
+        );
+
+        // Listed but escaping the song directory
+        assert!(matches!(
+            stem_path(&song, &analysis, "../secret.txt"),
+            Err(CommandError::PermissionDenied { .. })
+        ));
+        // Inside the song directory but not a listed stem
+        assert!(matches!(
+            stem_path(&song, &analysis, "stems/../stems/bass.flac"),
+            Err(CommandError::PermissionDenied { .. })
+        ));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("secret.txt"), song.join("stems/link.flac"))
+                .unwrap();
+            assert!(matches!(
+                resolve_in_dir(&song, "stems/link.flac"),
+                Err(CommandError::PermissionDenied { .. })
+            ));
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
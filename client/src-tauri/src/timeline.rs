@@ -0,0 +1,369 @@
+//! Speed-aware view of a song's events.
+//!
+//! Everything in analysis.json is timed at 1.0x. A [`Timeline`] turns those
+//! original times into playback times at one speed and back, places them
+//! at bar:beat:tick positions on the detected beats, and gathers the beats,
+//! notes, drum strikes and lyric words inside a window, so views don't each
+//! scale times themselves.
+
+use crate::error::{CommandError, CommandResult};
+use crate::midi::PPQ;
+use crate::notation::BeatGrid;
+use crate::{read_analysis, SongAnalysis};
+use serde::Serialize;
+use std::path::Path;
+
+/// Musical position of a time. Bar 1 starts on the first downbeat; a pickup
+/// is bar 0 and anything earlier is negative
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Position {
+    pub bar: i64,
+    /// From 1
+    pub beat: u32,
+    /// [`PPQ`] ticks to a beat, as in exported MIDI files
+    pub tick: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum EventKind {
+    Beat {
+        downbeat: bool,
+    },
+    Note {
+        stem: String,
+        pitch: i32,
+        velocity: f64,
+    },
+    Strike {
+        drum: String,
+        velocity: f64,
+    },
+    Word {
+        line: usize,
+        word: usize,
+        text: String,
+    },
+}
+
+/// An event with its times at 1.0x and at the timeline's speed. Beats and
+/// strikes end where they start
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineEvent {
+    pub start: f64,
+    pub end: f64,
+    pub playback_start: f64,
+    pub playback_end: f64,
+    /// Position of `start`
+    pub position: Position,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+pub struct Timeline<'a> {
+    analysis: &'a SongAnalysis,
+    grid: BeatGrid,
+    /// Beat numbers of the detected downbeats, in order
+    downbeats: Vec<i64>,
+    speed: f64,
+}
+
+impl<'a> Timeline<'a> {
+    pub fn new(analysis: &'a SongAnalysis, speed: f64) -> CommandResult<Self> {
+        if !speed.is_finite() || speed <= 0.0 {
+            return Err(CommandError::invalid(format!(
+                "{} is not a playback speed",
+                speed
+            )));
+        }
+        let grid = BeatGrid::from_analysis(analysis);
+        let mut downbeats: Vec<i64> = analysis
+            .beats
+            .iter()
+            .filter(|beat| beat.beat_in_measure == Some(1) || beat.beat_type == "downbeat")
+            .filter(|beat| beat.time.is_finite())
+            .map(|beat| grid.beat_at(beat.time).round() as i64)
+            .collect();
+        downbeats.sort();
+        downbeats.dedup();
+        Ok(Self {
+            analysis,
+            grid,
+            downbeats,
+            speed,
+        })
+    }
+
+    /// Seconds into playback at this speed of an original time
+    pub fn playback_time(&self, original: f64) -> f64 {
+        original / self.speed
+    }
+
+    /// Original time of a playback time at this speed
+    pub fn original_time(&self, playback: f64) -> f64 {
+        playback * self.speed
+    }
+
+    /// Bar, beat and tick of an original time, to the nearest tick.
+    ///
+    /// Bars run from one detected downbeat to the next, however many beats
+    /// apart; the time signature only carries the count on before the
+    /// first downbeat and after the last
+    pub fn position(&self, original: f64) -> Position {
+        let ticks = (self.grid.beat_at(original) * PPQ as f64).round() as i64;
+        let beat = ticks.div_euclid(PPQ as i64);
+        let (bar, start) = self.bar(beat);
+        Position {
+            bar: bar + 1,
+            beat: (beat - start) as u32 + 1,
+            tick: ticks.rem_euclid(PPQ as i64) as u32,
+        }
+    }
+
+    /// Bar containing a beat number, counted from 0 at the first downbeat,
+    /// and the beat it starts on
+    fn bar(&self, beat: i64) -> (i64, i64) {
+        let numerator = self.grid.numerator as i64;
+        let (Some(&first), Some(&last)) = (self.downbeats.first(), self.downbeats.last()) else {
+            let bar = self.grid.bar_of(beat as f64);
+            return (bar, self.grid.bar_start(bar));
+        };
+        if beat < first {
+            let bar = (beat - first).div_euclid(numerator);
+            return (bar, first + bar * numerator);
+        }
+        if beat >= last {
+            let extra = (beat - last) / numerator;
+            let bar = self.downbeats.len() as i64 - 1 + extra;
+            return (bar, last + extra * numerator);
+        }
+        let bar = self.downbeats.partition_point(|downbeat| *downbeat <= beat) - 1;
+        (bar as i64, self.downbeats[bar])
+    }
+
+    fn event(&self, start: f64, end: f64, kind: EventKind) -> TimelineEvent {
+        TimelineEvent {
+            start,
+            end,
+            playback_start: self.playback_time(start),
+            playback_end: self.playback_time(end),
+            position: self.position(start),
+            kind,
+        }
+    }
+
+    /// Events sounding in the original time window `start`..`end`, by
+    /// start time. Instant events count from `start` up to, not including,
+    /// `end`
+    pub fn events(&self, start: f64, end: f64) -> Vec<TimelineEvent> {
+        let inside = |from: f64, to: f64| from < end && (to > start || from >= start);
+        let mut events = Vec::new();
+
+        for beat in &self.analysis.beats {
+            if inside(beat.time, beat.time) {
+                let downbeat = beat.beat_in_measure == Some(1) || beat.beat_type == "downbeat";
+                events.push(self.event(beat.time, beat.time, EventKind::Beat { downbeat }));
+            }
+        }
+
+        if let Some(notes) = &self.analysis.notes {
+            let mut stems: Vec<&String> = notes.keys().collect();
+            stems.sort();
+            for stem in stems {
+                for note in notes[stem]
+                    .iter()
+                    .filter(|note| inside(note.start, note.end))
+                {
+                    let kind = EventKind::Note {
+                        stem: stem.clone(),
+                        pitch: note.pitch,
+                        velocity: note.velocity,
+                    };
+                    events.push(self.event(note.start, note.end, kind));
+                }
+            }
+        }
+
+        if let Some(strikes) = &self.analysis.drum_strikes {
+            let mut drums: Vec<&String> = strikes.keys().collect();
+            drums.sort();
+            for drum in drums {
+                for strike in strikes[drum]
+                    .iter()
+                    .filter(|strike| inside(strike.time, strike.time))
+                {
+                    let kind = EventKind::Strike {
+                        drum: drum.clone(),
+                        velocity: strike.velocity,
+                    };
+                    events.push(self.event(strike.time, strike.time, kind));
+                }
+            }
+        }
+
+        if let Some(lyrics) = &self.analysis.lyrics {
+            for (i, line) in lyrics.lines.iter().enumerate() {
+                if !inside(line.start, line.end) {
+                    continue;
+                }
+                for (j, word) in line.words.iter().enumerate() {
+                    if inside(word.start, word.end) {
+                        let kind = EventKind::Word {
+                            line: i,
+                            word: j,
+                            text: word.text.clone(),
+                        };
+                        events.push(self.event(word.start, word.end, kind));
+                    }
+                }
+            }
+        }
+
+        events.sort_by(|a, b| a.start.total_cmp(&b.start));
+        events
+    }
+}
+
+/// Beats, notes, drum strikes and lyric words between `start` and `end`
+/// seconds of playback at `speed`, with their original and playback times
+/// and bar:beat:tick positions
+#[tauri::command]
+pub fn query_events(
+    song_dir: &str,
+    speed: f64,
+    start: f64,
+    end: f64,
+) -> CommandResult<Vec<TimelineEvent>> {
+    let analysis = read_analysis(&Path::new(song_dir).join("analysis.json"))?;
+    let timeline = Timeline::new(&analysis, speed)?;
+    Ok(timeline.events(timeline.original_time(start), timeline.original_time(end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_analysis;
+
+    fn analysis() -> SongAnalysis {
+        let mut song = test_analysis();
+        song.time_signature = Some((3, 4));
+        song.beats = serde_json::from_value(serde_json::json!([
+            {"time": 0.5, "type": "beat", "beatInMeasure": 3},
+            {"time": 1.0, "type": "downbeat", "beatInMeasure": 1},
+            {"time": 1.5, "type": "beat", "beatInMeasure": 2},
+            {"time": 2.0, "type": "beat", "beatInMeasure": 3},
+            {"time": 2.5, "type": "downbeat", "beatInMeasure": 1}
+        ]))
+        .unwrap();
+        song.notes = serde_json::from_value(serde_json::json!({"bass": [
+            {"start": 0.75, "end": 1.25, "pitch": 40, "velocity": 0.5},
+            {"start": 2.0, "end": 3.0, "pitch": 43, "velocity": 0.5}
+        ]}))
+        .unwrap();
+        song.drum_strikes =
+            serde_json::from_value(serde_json::json!({"kick": [{"time": 1.0, "velocity": 0.9}]}))
+                .unwrap();
+        song.lyrics = serde_json::from_value(serde_json::json!({"source": "lrc", "lines": [
+            {"text": "hey you", "start": 1.5, "end": 2.2, "words": [
+                {"text": "hey", "start": 1.5, "end": 1.8, "confidence": 1.0},
+                {"text": "you", "start": 1.9, "end": 2.2, "confidence": 1.0}
+            ]}
+        ]}))
+        .unwrap();
+        song
+    }
+
+    #[test]
+    fn test_times_and_positions() {
+        let song = analysis();
+        let timeline = Timeline::new(&song, 0.5).unwrap();
+        assert_eq!(timeline.playback_time(1.5), 3.0);
+        assert_eq!(timeline.original_time(3.0), 1.5);
+        assert!(Timeline::new(&song, 0.0).is_err());
+
+        let at = |seconds| {
+            let p = timeline.position(seconds);
+            (p.bar, p.beat, p.tick)
+        };
+        assert_eq!(at(0.5), (0, 3, 0));
+        assert_eq!(at(1.0), (1, 1, 0));
+        assert_eq!(at(1.75), (1, 2, 240));
+        assert_eq!(at(2.5), (2, 1, 0));
+        // Past the last beat the interval carries on
+        assert_eq!(at(4.0), (3, 1, 0));
+        assert_eq!(at(0.0), (0, 2, 0));
+    }
+
+    #[test]
+    fn test_bars_follow_detected_downbeats() {
+        // 4/4, but the second bar is cut to two beats
+        let beats: Vec<serde_json::Value> = (0..11)
+            .map(|i| {
+                let downbeat = [0, 4, 6].contains(&i);
+                serde_json::json!({
+                    "time": i as f64 * 0.5,
+                    "type": if downbeat { "downbeat" } else { "beat" },
+                    "beatInMeasure": null
+                })
+            })
+            .collect();
+        let mut song = analysis();
+        song.time_signature = Some((4, 4));
+        song.beats = serde_json::from_value(serde_json::Value::Array(beats)).unwrap();
+        let timeline = Timeline::new(&song, 1.0).unwrap();
+
+        let at = |seconds| {
+            let p = timeline.position(seconds);
+            (p.bar, p.beat)
+        };
+        assert_eq!(at(2.5), (2, 2));
+        assert_eq!(at(3.0), (3, 1));
+        assert_eq!(at(4.5), (3, 4));
+        // After the last downbeat the time signature takes over
+        assert_eq!(at(5.0), (4, 1));
+        assert_eq!(at(7.5), (5, 2));
+    }
+
+    #[test]
+    fn test_events_in_a_playback_window() {
+        let song = analysis();
+        let timeline = Timeline::new(&song, 0.5).unwrap();
+        // 2.0–4.0 s of playback is 1.0–2.0 s of the song
+        let events = timeline.events(timeline.original_time(2.0), timeline.original_time(4.0));
+        let summary: Vec<(f64, f64, &str)> = events
+            .iter()
+            .map(|event| {
+                let name = match &event.kind {
+                    EventKind::Beat { downbeat: true } => "downbeat",
+                    EventKind::Beat { downbeat: false } => "beat",
+                    EventKind::Note { .. } => "note",
+                    EventKind::Strike { .. } => "strike",
+                    EventKind::Word { text, .. } => text.as_str(),
+                };
+                (event.start, event.playback_start, name)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (0.75, 1.5, "note"),
+                (1.0, 2.0, "downbeat"),
+                (1.0, 2.0, "strike"),
+                (1.5, 3.0, "beat"),
+                (1.5, 3.0, "hey"),
+                (1.9, 3.8, "you"),
+            ]
+        );
+
+        let json = serde_json::to_value(&events[2]).unwrap();
+        assert_eq!(json["type"], "strike");
+        assert_eq!(json["drum"], "kick");
+        assert_eq!(json["position"]["bar"], 1);
+    }
+}
@@ -0,0 +1,366 @@
+//! Semantic checks on a deserialized `SongAnalysis`.
+//!
+//! A document can match the schema and still be unusable: beats out of
+//! order, notes that end before they start, pitches outside the MIDI range,
+//! lyric words outside their line, stems whose files are gone. [`validate`]
+//! reports each problem with the JSON path of the offending value and
+//! repairs what it can in place (sorting, clamping or dropping invalid
+//! events), so callers can either surface the issues or hand clean data to
+//! the visualizations. Missing stem files are only reported.
+
+use crate::error::CommandResult;
+use crate::{backup_analysis, read_analysis, write_analysis, SongAnalysis};
+use serde::Serialize;
+use std::path::Path;
+
+/// Lyric words may overhang their line by this much (seconds) before it's
+/// reported; aligners routinely round line bounds
+const LYRIC_TOLERANCE: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+    /// Suspicious but usable as-is
+    Warning,
+    /// Would break or mislead the visualizations
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    /// JSON path of the offending value in analysis.json, e.g.
+    /// `$.notes.bass[3].end`
+    pub path: String,
+    pub message: String,
+    /// What the auto-fix does about it, if anything
+    pub fix: Option<String>,
+}
+
+/// Result of `validate_analysis`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+    /// Where the original was saved when fixes were written back
+    pub backup: Option<String>,
+}
+
+struct Validator {
+    issues: Vec<ValidationIssue>,
+}
+
+impl Validator {
+    fn report(&mut self, severity: Severity, path: String, message: String, fix: Option<&str>) {
+        self.issues.push(ValidationIssue {
+            severity,
+            path,
+            message,
+            fix: fix.map(str::to_string),
+        });
+    }
+
+    fn error(&mut self, path: String, message: String, fix: &str) {
+        self.report(Severity::Error, path, message, Some(fix));
+    }
+
+    fn warning(&mut self, path: String, message: String, fix: &str) {
+        self.report(Severity::Warning, path, message, Some(fix));
+    }
+
+    /// Sort `items` by `key`, reporting if they weren't already in order
+    fn sort_by_time<T>(&mut self, path: &str, items: &mut [T], key: impl Fn(&T) -> f64) {
+        if items.windows(2).any(|pair| key(&pair[0]) > key(&pair[1])) {
+            self.warning(
+                path.to_string(),
+                "events are not in chronological order".to_string(),
+                "sorted",
+            );
+            items.sort_by(|a, b| key(a).total_cmp(&key(b)));
+        }
+    }
+
+    /// Clamp a velocity into 0–1, reporting if it was outside
+    fn clamp_velocity(&mut self, path: String, value: &mut f64) {
+        if !(0.0..=1.0).contains(value) {
+            self.warning(
+                path,
+                format!("velocity {} is outside 0–1", value),
+                "clamped",
+            );
+            *value = value.clamp(0.0, 1.0);
+        }
+    }
+}
+
+/// Drop items for which `keep` returns false, passing each item's original
+/// index so issues refer to positions in the file
+fn retain_indexed<T>(items: &mut Vec<T>, mut keep: impl FnMut(usize, &mut T) -> bool) {
+    let mut index = 0;
+    items.retain_mut(|item| {
+        let kept = keep(index, item);
+        index += 1;
+        kept
+    });
+}
+
+fn is_time(value: f64) -> bool {
+    value.is_finite() && value >= 0.0
+}
+
+/// Check `analysis` and repair it in place, returning everything that was
+/// wrong. Stem paths are resolved against `song_dir`.
+pub fn validate(analysis: &mut SongAnalysis, song_dir: &Path) -> Vec<ValidationIssue> {
+    let mut v = Validator { issues: Vec::new() };
+
+    if !(analysis.original_duration.is_finite() && analysis.original_duration > 0.0) {
+        v.report(
+            Severity::Error,
+            "$.originalDuration".into(),
+            format!("duration {} is not positive", analysis.original_duration),
+            None,
+        );
+    }
+    if let Some(tempo) = analysis.tempo_bpm {
+        if !(tempo.is_finite() && tempo > 0.0) {
+            v.warning(
+                "$.tempoBpm".into(),
+                format!("tempo {} is not positive", tempo),
+                "removed",
+            );
+            analysis.tempo_bpm = None;
+        }
+    }
+
+    retain_indexed(&mut analysis.beats, |i, beat| {
+        if !is_time(beat.time) {
+            v.error(
+                format!("$.beats[{}].time", i),
+                format!("invalid time {}", beat.time),
+                "dropped",
+            );
+            return false;
+        }
+        true
+    });
+    v.sort_by_time("$.beats", &mut analysis.beats, |beat| beat.time);
+
+    if let Some(notes) = analysis.notes.as_mut() {
+        let mut stems: Vec<_> = notes.keys().cloned().collect();
+        stems.sort();
+        for stem in stems {
+            let Some(stem_notes) = notes.get_mut(&stem) else {
+                continue;
+            };
+            let base = format!("$.notes.{}", stem);
+            retain_indexed(stem_notes, |i, note| {
+                let path = format!("{}[{}]", base, i);
+                if !is_time(note.start) || !note.end.is_finite() || note.end < note.start {
+                    v.error(
+                        format!("{}.end", path),
+                        format!("note spans {} to {}", note.start, note.end),
+                        "dropped",
+                    );
+                    return false;
+                }
+                if !(0..=127).contains(&note.pitch) {
+                    v.error(
+                        format!("{}.pitch", path),
+                        format!("pitch {} is outside the MIDI range 0–127", note.pitch),
+                        "clamped",
+                    );
+                    note.pitch = note.pitch.clamp(0, 127);
+                }
+                v.clamp_velocity(format!("{}.velocity", path), &mut note.velocity);
+                true
+            });
+            v.sort_by_time(&base, stem_notes, |note| note.start);
+        }
+    }
+
+    if let Some(drum_strikes) = analysis.drum_strikes.as_mut() {
+        let mut drums: Vec<_> = drum_strikes.keys().cloned().collect();
+        drums.sort();
+        for drum in drums {
+            let Some(strikes) = drum_strikes.get_mut(&drum) else {
+                continue;
+            };
+            let base = format!("$.drumStrikes.{}", drum);
+            retain_indexed(strikes, |i, strike| {
+                let path = format!("{}[{}]", base, i);
+                if !is_time(strike.time) {
+                    v.error(
+                        format!("{}.time", path),
+                        format!("invalid time {}", strike.time),
+                        "dropped",
+                    );
+                    return false;
+                }
+                v.clamp_velocity(format!("{}.velocity", path), &mut strike.velocity);
+                true
+            });
+            v.sort_by_time(&base, strikes, |strike| strike.time);
+        }
+    }
+
+    if let Some(lyrics) = analysis.lyrics.as_mut() {
+        retain_indexed(&mut lyrics.lines, |i, line| {
+            let path = format!("$.lyrics.lines[{}]", i);
+            if !is_time(line.start) || !line.end.is_finite() || line.end < line.start {
+                v.error(
+                    path,
+                    format!("line spans {} to {}", line.start, line.end),
+                    "dropped",
+                );
+                return false;
+            }
+            let (line_start, line_end) = (line.start, line.end);
+            retain_indexed(&mut line.words, |j, word| {
+                let path = format!("{}.words[{}]", path, j);
+                if !is_time(word.start) || !word.end.is_finite() || word.end < word.start {
+                    v.error(
+                        path,
+                        format!(
+                            "word \"{}\" spans {} to {}",
+                            word.text, word.start, word.end
+                        ),
+                        "dropped",
+                    );
+                    return false;
+                }
+                if word.start < line_start - LYRIC_TOLERANCE
+                    || word.end > line_end + LYRIC_TOLERANCE
+                {
+                    v.warning(
+                        path,
+                        format!(
+                            "word \"{}\" ({}–{}) falls outside its line ({}–{})",
+                            word.text, word.start, word.end, line_start, line_end
+                        ),
+                        "clamped to the line",
+                    );
+                    word.start = word.start.clamp(line_start, line_end);
+                    word.end = word.end.clamp(word.start, line_end);
+                }
+                true
+            });
+            v.sort_by_time(&format!("{}.words", path), &mut line.words, |word| {
+                word.start
+            });
+            true
+        });
+        v.sort_by_time("$.lyrics.lines", &mut lyrics.lines, |line| line.start);
+    }
+
+    let mut stems: Vec<_> = analysis.stems.keys().cloned().collect();
+    stems.sort();
+    for stem in stems {
+        let Some(info) = analysis.stems.get_mut(&stem) else {
+            continue;
+        };
+        let mut speeds: Vec<_> = info.paths.keys().cloned().collect();
+        speeds.sort();
+        for speed in speeds {
+            let relative = &info.paths[&speed];
+            // Reported only: the file may be on a drive that isn't mounted,
+            // and dropping the path would lose it for good
+            if !song_dir.join(relative).is_file() {
+                v.report(
+                    Severity::Error,
+                    format!("$.stems.{}.paths[\"{}\"]", stem, speed),
+                    format!("stem file {} is missing", relative),
+                    None,
+                );
+            }
+        }
+    }
+
+    v.issues
+}
+
+/// Check a song's analysis.json for semantic problems.
+///
+/// With `fix`, the repaired document is written back after saving the
+/// original as `analysis.json.unfixed.bak`.
+#[tauri::command]
+pub fn validate_analysis(song_dir: &str, fix: Option<bool>) -> CommandResult<ValidationReport> {
+    let song_dir = Path::new(song_dir);
+    let analysis_path = song_dir.join("analysis.json");
+    let mut analysis = read_analysis(&analysis_path)?;
+    let issues = validate(&mut analysis, song_dir);
+
+    let mut backup = None;
+    if fix.unwrap_or(false) && issues.iter().any(|issue| issue.fix.is_some()) {
+        let backup_path = backup_analysis(&analysis_path, "unfixed")?;
+        write_analysis(&analysis_path, &analysis)?;
+        backup = Some(backup_path.to_string_lossy().to_string());
+    }
+
+    Ok(ValidationReport { issues, backup })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_and_repairs_problems() {
+        let dir = std::env::temp_dir().join(format!("music-tutor-validate-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("stems")).unwrap();
+        std::fs::write(dir.join("stems/bass_1.0x.flac"), b"").unwrap();
+
+        let mut analysis: SongAnalysis = serde_json::from_str(
+            r#"{
+                "title": null, "artist": null, "album": null,
+                "originalDuration": 10.0, "sampleRate": 44100,
+                "timeSignature": [4, 4],
+                "stems": {"bass": {"name": "bass", "hasNotes": true, "peakDb": 0.0,
+                    "paths": {"1.0x": "stems/bass_1.0x.flac", "0.5x": "stems/bass_0.5x.flac"}}},
+                "beats": [
+                    {"time": 2.0, "type": "beat", "beatInMeasure": 2},
+                    {"time": 1.0, "type": "downbeat", "beatInMeasure": 1}
+                ],
+                "notes": {"bass": [
+                    {"start": 1.0, "end": 0.5, "pitch": 40, "velocity": 0.5},
+                    {"start": 2.0, "end": 2.5, "pitch": 130, "velocity": 1.5}
+                ]},
+                "lyrics": {"source": "lrc", "lines": [{"text": "hi there", "start": 1.0, "end": 2.0,
+                    "words": [
+                        {"text": "hi", "start": 1.0, "end": 1.5, "confidence": 1.0},
+                        {"text": "there", "start": 1.5, "end": 3.0, "confidence": 1.0}
+                    ]}]},
+                "sourceFile": "x.mp3", "processingDate": "", "converterVersion": ""
+            }"#,
+        )
+        .unwrap();
+
+        let issues = validate(&mut analysis, &dir);
+        let paths: Vec<&str> = issues.iter().map(|issue| issue.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "$.beats",
+                "$.notes.bass[0].end",
+                "$.notes.bass[1].pitch",
+                "$.notes.bass[1].velocity",
+                "$.lyrics.lines[0].words[1]",
+                "$.stems.bass.paths[\"0.5x\"]",
+            ]
+        );
+        assert_eq!(issues[1].severity, Severity::Error);
+
+        assert_eq!(analysis.beats[0].time, 1.0);
+        let notes = &analysis.notes.as_ref().unwrap()["bass"];
+        assert_eq!(notes.len(), 1);
+        assert_eq!((notes[0].pitch, notes[0].velocity), (127, 1.0));
+        assert_eq!(analysis.lyrics.as_ref().unwrap().lines[0].words[1].end, 2.0);
+        // The missing stem is kept and reported again; nothing else is left
+        assert_eq!(analysis.stems["bass"].paths.len(), 2);
+        let issues = validate(&mut analysis, &dir);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].fix, None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+//! Watches the library roots and tells the frontend when songs appear,
+//! change or disappear, so the browser doesn't need a manual rescan.
+//!
+//! Filesystem events are debounced, mapped to the song directory they
+//! belong to (using the same depth and ignore rules as the scanner), applied
+//! to the index and then emitted as a single `library-changed` event.
+//! Failures to watch or to update the index are emitted as
+//! `library-error` events.
+
+use crate::error::CommandError;
+use crate::index::SongIndex;
+use crate::library::{self, Library, LibraryConfig, SongSummary};
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+
+pub const LIBRARY_CHANGED_EVENT: &str = "library-changed";
+
+/// Quiet period before a burst of filesystem events is reported; the
+/// pipeline writes stems and analysis.json in quick succession
+const DEBOUNCE: Duration = Duration::from_millis(1500);
+
+/// Payload of the `library-changed` event
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryChange {
+    /// Songs that were added or whose files changed, freshly summarized
+    pub changed: Vec<SongSummary>,
+    /// Paths of songs that are no longer in the library
+    pub removed: Vec<String>,
+}
+
+/// What a filesystem event means for the library
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum SongEvent {
+    /// Something inside this song directory changed
+    Changed(PathBuf),
+    /// Everything at or below this path is gone
+    Removed(PathBuf),
+}
+
+/// A configured root, plus its canonical form for matching event paths
+#[derive(Clone)]
+struct WatchedRoot {
+    root: PathBuf,
+    canonical: PathBuf,
+}
+
+impl WatchedRoot {
+    /// `path` expressed relative to the root as configured, which is how
+    /// songs are keyed in the index
+    fn locate(&self, path: &Path) -> Option<PathBuf> {
+        if let Ok(rel) = path.strip_prefix(&self.canonical) {
+            return Some(self.root.join(rel));
+        }
+        path.strip_prefix(&self.root)
+            .ok()
+            .map(|rel| self.root.join(rel))
+    }
+}
+
+/// Map a changed `path` under `root` to the song it affects.
+///
+/// The song is the shallowest directory holding an `analysis.json`, as in
+/// the scanner; paths in ignored directories or beyond `max_depth` are
+/// not part of the library. Within a song only the directory itself, its
+/// `analysis.json` and its stem files matter, so the app's own sidecar
+/// writes (waveform peaks, lyrics history, backups) don't count as changes.
+fn classify(root: &Path, path: &Path, config: &LibraryConfig) -> Option<SongEvent> {
+    let rel = path.strip_prefix(root).ok()?;
+
+    let mut dir = root.to_path_buf();
+    for (depth, component) in std::iter::once(None)
+        .chain(rel.components().map(Some))
+        .enumerate()
+    {
+        if let Some(component) = component {
+            if config.is_ignored(&component.as_os_str().to_string_lossy()) {
+                return None;
+            }
+            dir.push(component);
+        }
+        if depth > config.max_depth {
+            break;
+        }
+        if dir.join("analysis.json").is_file() {
+            return affects_song(&dir, path).then_some(SongEvent::Changed(dir));
+        }
+    }
+
+    if path.exists() {
+        return None;
+    }
+    // A deleted analysis.json takes its song with it
+    if path.file_name().is_some_and(|name| name == "analysis.json") {
+        return path
+            .parent()
+            .map(|dir| SongEvent::Removed(dir.to_path_buf()));
+    }
+    Some(SongEvent::Removed(path.to_path_buf()))
+}
+
+/// Whether a change at `path` inside the song in `dir` can change its summary
+fn affects_song(dir: &Path, path: &Path) -> bool {
+    let analysis_path = dir.join("analysis.json");
+    if path == dir || path == analysis_path {
+        return true;
+    }
+    // An unreadable analysis.json doesn't depend on the stems
+    crate::read_analysis(&analysis_path).is_ok_and(|analysis| {
+        analysis
+            .stems
+            .values()
+            .flat_map(|stem| stem.paths.values())
+            .any(|relative| dir.join(relative) == path)
+    })
+}
+
+/// Managed state owning the active watcher; replacing it stops the old one
+#[derive(Default)]
+pub struct LibraryWatcher {
+    debouncer: Mutex<Option<Debouncer<RecommendedWatcher>>>,
+}
+
+/// (Re)start watching the configured library roots
+pub fn restart(app: &AppHandle) {
+    let config = app.state::<Library>().config();
+    let roots: Vec<WatchedRoot> = config
+        .roots
+        .iter()
+        .filter_map(|root| {
+            let root = PathBuf::from(root);
+            let canonical = root.canonicalize().ok()?;
+            Some(WatchedRoot { root, canonical })
+        })
+        .collect();
+
+    let handler_app = app.clone();
+    let handler_roots = roots.clone();
+    let debouncer = new_debouncer(DEBOUNCE, move |result: DebounceEventResult| {
+        let events = match result {
+            Ok(events) => events,
+            Err(e) => {
+                library::report_error(&handler_app, watch_error("Library watcher failed", e));
+                return;
+            }
+        };
+        let paths: Vec<PathBuf> = events.into_iter().map(|event| event.path).collect();
+        apply(&handler_app, &handler_roots, &paths);
+    });
+
+    let mut debouncer = match debouncer {
+        Ok(debouncer) => debouncer,
+        Err(e) => {
+            library::report_error(app, watch_error("Failed to start library watcher", e));
+            return;
+        }
+    };
+    for root in &roots {
+        if let Err(e) = debouncer
+            .watcher()
+            .watch(&root.canonical, RecursiveMode::Recursive)
+        {
+            let context = format!("Failed to watch {}", root.root.display());
+            library::report_error(app, watch_error(&context, e));
+        }
+    }
+
+    let state = app.state::<LibraryWatcher>();
+    *state.debouncer.lock().unwrap_or_else(|e| e.into_inner()) = Some(debouncer);
+}
+
+fn watch_error(context: &str, error: notify_debouncer_mini::notify::Error) -> CommandError {
+    CommandError::Io {
+        message: format!("{}: {}", context, error),
+    }
+}
+
+/// Update the index for a batch of changed paths and notify the frontend
+fn apply(app: &AppHandle, roots: &[WatchedRoot], paths: &[PathBuf]) {
+    let config = app.state::<Library>().config();
+
+    let mut events = BTreeSet::new();
+    for path in paths {
+        let Some((root, located)) = roots
+            .iter()
+            .find_map(|root| root.locate(path).map(|located| (root, located)))
+        else {
+            continue;
+        };
+        if let Some(event) = classify(&root.root, &located, &config) {
+            events.insert((root.root.clone(), event));
+        }
+    }
+    if events.is_empty() {
+        return;
+    }
+
+    let index = app.state::<SongIndex>();
+    let mut change = LibraryChange::default();
+    for (root, event) in events {
+        let result = match event {
+            SongEvent::Changed(song_dir) => index
+                .update_song(&root, &song_dir)
+                .map(|summary| change.changed.push(summary)),
+            SongEvent::Removed(path) => index
+                .remove_under(&path)
+                .map(|removed| change.removed.extend(removed)),
+        };
+        if let Err(e) = result {
+            library::report_error(app, e);
+        }
+    }
+
+    if !change.changed.is_empty() || !change.removed.is_empty() {
+        let _ = app.emit(LIBRARY_CHANGED_EVENT, &change);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_classify_maps_paths_to_songs() {
+        let root = std::env::temp_dir().join(format!("music-tutor-watch-{}", std::process::id()));
+        let song = root.join("artist").join("song");
+        fs::create_dir_all(song.join("stems")).unwrap();
+        let mut analysis = crate::test_analysis();
+        analysis.stems.insert(
+            "bass".to_string(),
+            crate::StemInfo {
+                name: "bass".to_string(),
+                paths: [("1.0x".to_string(), "stems/bass.wav".to_string())].into(),
+                has_notes: false,
+                peak_db: 0.0,
+            },
+        );
+        fs::write(
+            song.join("analysis.json"),
+            serde_json::to_string(&analysis).unwrap(),
+        )
+        .unwrap();
+        let config = LibraryConfig::default();
+
+        // The song's analysis.json and stem files belong to it
+        assert_eq!(
+            classify(&root, &song.join("analysis.json"), &config),
+            Some(SongEvent::Changed(song.clone()))
+        );
+        assert_eq!(
+            classify(&root, &song.join("stems").join("bass.wav"), &config),
+            Some(SongEvent::Changed(song.clone()))
+        );
+        // The app's own writes next to them don't
+        for own in [
+            "waveform.bass.peaks",
+            "lyrics_history.json",
+            "analysis.json.tmp",
+            "analysis.json.bak",
+        ] {
+            assert_eq!(classify(&root, &song.join(own), &config), None);
+        }
+        // Songs in ignored directories aren't in the library
+        let hidden = root.join(".trash").join("song");
+        fs::create_dir_all(&hidden).unwrap();
+        fs::write(hidden.join("analysis.json"), "{}").unwrap();
+        assert_eq!(
+            classify(&root, &hidden.join("analysis.json"), &config),
+            None
+        );
+        // Deleting a song's analysis.json or a whole folder removes songs
+        assert_eq!(
+            classify(&root, &root.join("gone").join("analysis.json"), &config),
+            Some(SongEvent::Removed(root.join("gone")))
+        );
+        assert_eq!(
+            classify(&root, &root.join("other"), &config),
+            Some(SongEvent::Removed(root.join("other")))
+        );
+        // Songs deeper than max_depth aren't in the library
+        let shallow = LibraryConfig {
+            max_depth: 1,
+            ..LibraryConfig::default()
+        };
+        assert_eq!(classify(&root, &song.join("analysis.json"), &shallow), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -2,12 +2,12 @@ import { useState, useEffect } from "react";
 import { useLocation, useNavigate } from "react-router-dom";
 import { Play, Pause, Square, ArrowLeft, ChevronDown, ChevronRight, BarChart3 } from "lucide-react";
 import { invoke } from "@tauri-apps/api/core";
-import { type SongAnalysis, PLAYBACK_SPEEDS, getStemColor } from "../types/analysis";
+import { type LoadedAnalysis, type SongAnalysis, PLAYBACK_SPEEDS, getStemColor } from "../types/analysis";
 import { useAudioEngine } from "../hooks/useAudioEngine";
 import { useBeatSync } from "../hooks/useBeatSync";
 import { useVisualization } from "../hooks/useVisualization";
 import { VisualizationPanel } from "./visualization/VisualizationPanel";
-import { formatTime, cn, scaleTime } from "../lib/utils";
+import { describeError, formatTime, cn, scaleTime } from "../lib/utils";
 
 // Drum component stems that should be grouped
 const DRUM_COMPONENTS = new Set(["drumKick", "drumSnare", "drumHh", "drumRide", "drumCrash", "drumToms"]);
@@ -198,10 +198,17 @@ export function PlayerPage() {
 
     async function loadAnalysis() {
       try {
-        const data = await invoke<SongAnalysis>("load_analysis", { songDir: songPath });
+        const { analysis: data, issues } = await invoke<LoadedAnalysis>("load_analysis", {
+          songDir: songPath,
+          fix: true,
+        });
+        const repaired = issues.filter((issue) => issue.fix !== null);
+        if (repaired.length > 0) {
+          console.warn("analysis.json was repaired for display:", repaired);
+        }
         setAnalysis(data);
       } catch (err) {
-        setError(String(err));
+        setError(describeError(err));
       }
     }
 
@@ -1,32 +1,117 @@
 import { useState, useEffect } from "react";
 import { useNavigate } from "react-router-dom";
-import { FolderOpen, Music, Clock, Layers, Plus, Loader2 } from "lucide-react";
+import {
+  FolderOpen,
+  Music,
+  Clock,
+  Layers,
+  Plus,
+  X,
+  RotateCcw,
+  AlertTriangle,
+} from "lucide-react";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import { open } from "@tauri-apps/plugin-dialog";
-import { type SongSummary } from "../types/analysis";
-import { formatTime, cn } from "../lib/utils";
+import {
+  type CommandError,
+  type Job,
+  type LibraryChange,
+  type LibraryConfig,
+  type ProcessingProgress,
+  type SongSummary,
+} from "../types/analysis";
+import { describeError, formatTime, cn } from "../lib/utils";
 
 export function SongBrowser() {
   const [songs, setSongs] = useState<SongSummary[]>([]);
   const [outputDir, setOutputDir] = useState<string>("");
   const [isLoading, setIsLoading] = useState(false);
-  const [isProcessing, setIsProcessing] = useState(false);
+  const [jobs, setJobs] = useState<Job[]>([]);
   const [processingError, setProcessingError] = useState<string | null>(null);
   const [separateDrums, setSeparateDrums] = useState(false);
   const navigate = useNavigate();
 
-  // Try to load songs from default output directory on mount
+  // Load songs from every library root on mount; new songs are written to
+  // the first root
   useEffect(() => {
-    const defaultDir = "../output"; // Relative to client/
-    loadSongs(defaultDir);
+    invoke<LibraryConfig>("get_library_config")
+      .then((config) => setOutputDir(config.roots[0] ?? ""))
+      .catch(console.error);
+    loadSongs();
+
+    // The backend watches the library roots and pushes changes as they happen
+    const unlistenLibrary = listen<LibraryChange>(
+      "library-changed",
+      (event) => {
+        const { changed, removed } = event.payload;
+        const replaced = new Set([
+          ...removed,
+          ...changed.map((song) => song.path),
+        ]);
+        setSongs((current) =>
+          [...current.filter((song) => !replaced.has(song.path)), ...changed]
+            .sort((a, b) => a.path.localeCompare(b.path))
+        );
+      }
+    );
+
+    // Watcher and index failures happen outside any command
+    const unlistenError = listen<CommandError>("library-error", (event) => {
+      console.error("Library update failed:", describeError(event.payload));
+    });
+
+    return () => {
+      unlistenLibrary.then((unlisten) => unlisten());
+      unlistenError.then((unlisten) => unlisten());
+    };
+  }, []);
+
+  // Track the processing queue
+  useEffect(() => {
+    invoke<Job[]>("list_jobs").then(setJobs).catch(console.error);
+
+    const unlistenJob = listen<Job>("job-updated", (event) => {
+      const job = event.payload;
+      setJobs((current) =>
+        current.some((j) => j.id === job.id)
+          ? current.map((j) => (j.id === job.id ? job : j))
+          : [...current, job]
+      );
+      if (job.status.state === "completed") {
+        loadSongs();
+      }
+    });
+    const unlistenProgress = listen<ProcessingProgress>(
+      "processing-progress",
+      (event) => {
+        const progress = event.payload;
+        setJobs((current) =>
+          current.map((j) =>
+            j.id === progress.jobId
+              ? {
+                  ...j,
+                  percent: progress.percent,
+                  stage:
+                    progress.kind === "stageStarted" ? progress.stage : j.stage,
+                }
+              : j
+          )
+        );
+      }
+    );
+
+    return () => {
+      unlistenJob.then((unlisten) => unlisten());
+      unlistenProgress.then((unlisten) => unlisten());
+    };
   }, []);
 
-  async function loadSongs(dir: string) {
+  async function loadSongs() {
     setIsLoading(true);
     try {
-      const songList = await invoke<SongSummary[]>("list_songs", { dir });
+      const songList = await invoke<SongSummary[]>("list_songs");
       setSongs(songList);
-      setOutputDir(dir);
     } catch (error) {
       console.error("Failed to load songs:", error);
     } finally {
@@ -38,23 +123,39 @@ export function SongBrowser() {
     const selected = await open({
       directory: true,
       multiple: false,
-      title: "Select output folder containing processed songs",
+      title: "Select a folder of processed songs to add to the library",
     });
 
     if (selected) {
-      loadSongs(selected);
+      try {
+        await invoke<LibraryConfig>("add_library_root", { path: selected });
+        setOutputDir(selected);
+        loadSongs();
+      } catch (error) {
+        setProcessingError(describeError(error));
+      }
     }
   }
 
   function handleSelectSong(song: SongSummary) {
+    if (song.status !== "ok") return;
     navigate("/player", { state: { songPath: song.path } });
   }
 
+  async function handleReanalyzeSong(song: SongSummary) {
+    setProcessingError(null);
+    try {
+      await invoke<Job>("reanalyze_song", { songDir: song.path });
+    } catch (error) {
+      setProcessingError(describeError(error));
+    }
+  }
+
   async function handleProcessNewSong() {
     const selected = await open({
       directory: false,
-      multiple: false,
-      title: "Select an audio file to process",
+      multiple: true,
+      title: "Select audio files to process",
       filters: [
         {
           name: "Audio Files",
@@ -63,33 +164,50 @@ export function SongBrowser() {
       ],
     });
 
-    if (!selected) return;
+    if (!selected || selected.length === 0) return;
 
-    setIsProcessing(true);
     setProcessingError(null);
 
     try {
-      // Derive output path from filename
-      const fileName = selected.split(/[/\\]/).pop() || "song";
-      const songName = fileName.replace(/\.[^.]+$/, "");
-      const songOutputDir = `${outputDir || "../output"}/${songName}`;
-
-      await invoke("process_song", {
-        audioFile: selected,
-        outputDir: songOutputDir,
-        separateDrums,
-      });
-
-      // Refresh song list
-      await loadSongs(outputDir || "../output");
+      for (const audioFile of selected) {
+        // Derive output path from filename
+        const fileName = audioFile.split(/[/\\]/).pop() || "song";
+        const songName = fileName.replace(/\.[^.]+$/, "");
+        const songOutputDir = `${outputDir || "../output"}/${songName}`;
+
+        await invoke<Job>("enqueue_song", {
+          audioFile,
+          outputDir: songOutputDir,
+          separateDrums,
+        });
+      }
     } catch (error) {
-      console.error("Failed to process song:", error);
-      setProcessingError(String(error));
-    } finally {
-      setIsProcessing(false);
+      console.error("Failed to queue song:", error);
+      setProcessingError(describeError(error));
     }
   }
 
+  async function handleCancelJob(job: Job) {
+    try {
+      await invoke<Job>("cancel_job", { jobId: job.id });
+    } catch (error) {
+      setProcessingError(describeError(error));
+    }
+  }
+
+  async function handleRetryJob(job: Job) {
+    try {
+      await invoke<Job>("retry_job", { jobId: job.id });
+    } catch (error) {
+      setProcessingError(describeError(error));
+    }
+  }
+
+  const activeJobs = jobs.filter(
+    (job) =>
+      job.status.state !== "completed" && job.status.state !== "cancelled"
+  );
+
   return (
     <div className="min-h-screen bg-zinc-900 flex items-center justify-center p-8">
       <div className="w-full max-w-3xl">
@@ -122,7 +240,6 @@ export function SongBrowser() {
 
           <button
             onClick={handleProcessNewSong}
-            disabled={isProcessing}
             className={cn(
               "flex-1 py-6 px-8 rounded-xl",
               "border-4 border-amber-600 bg-amber-600/20",
@@ -136,17 +253,8 @@ export function SongBrowser() {
                 "inset 0 4px 8px rgba(0,0,0,0.3), 0 8px 16px rgba(0,0,0,0.5)",
             }}
           >
-            {isProcessing ? (
-              <>
-                <Loader2 className="w-8 h-8 animate-spin" />
-                Processing...
-              </>
-            ) : (
-              <>
-                <Plus className="w-8 h-8" />
-                Process New Song
-              </>
-            )}
+            <Plus className="w-8 h-8" />
+            Process New Songs
           </button>
         </div>
 
@@ -163,6 +271,58 @@ export function SongBrowser() {
           </span>
         </label>
 
+        {/* Processing Queue */}
+        {activeJobs.length > 0 && (
+          <div className="mb-8 space-y-3">
+            {activeJobs.map((job) => (
+              <div
+                key={job.id}
+                className="p-4 rounded-xl border-4 border-zinc-700 bg-zinc-800/50"
+              >
+                <div className="flex items-center gap-3 mb-2">
+                  <span className="flex-1 truncate text-zinc-300">
+                    {(job.audioFile ?? job.outputDir).split(/[/\\]/).pop()}
+                  </span>
+                  <span className="text-sm text-zinc-400 uppercase tracking-wider">
+                    {job.status.state === "running"
+                      ? `${job.stage?.replace(/_/g, " ") ?? "starting"} ${Math.round(job.percent)}%`
+                      : job.status.state}
+                  </span>
+                  {job.status.state === "failed" ? (
+                    <button
+                      onClick={() => handleRetryJob(job)}
+                      title="Retry"
+                      className="text-zinc-400 hover:text-amber-400"
+                    >
+                      <RotateCcw className="w-5 h-5" />
+                    </button>
+                  ) : (
+                    <button
+                      onClick={() => handleCancelJob(job)}
+                      title="Cancel"
+                      className="text-zinc-400 hover:text-red-400"
+                    >
+                      <X className="w-5 h-5" />
+                    </button>
+                  )}
+                </div>
+                {job.status.state === "failed" ? (
+                  <p className="text-sm text-red-400 whitespace-pre-wrap line-clamp-3">
+                    {describeError(job.status.error)}
+                  </p>
+                ) : (
+                  <div className="h-3 rounded-full bg-zinc-800 border-2 border-zinc-700 overflow-hidden">
+                    <div
+                      className="h-full bg-amber-500 transition-all"
+                      style={{ width: `${job.percent}%` }}
+                    />
+                  </div>
+                )}
+              </div>
+            ))}
+          </div>
+        )}
+
         {/* Processing Error */}
         {processingError && (
           <div className="mb-8 p-4 rounded-xl border-4 border-red-600 bg-red-900/20 text-red-400">
@@ -195,45 +355,74 @@ export function SongBrowser() {
             </div>
           ) : (
             <div className="space-y-3">
-              {songs.map((song) => (
-                <button
-                  key={song.path}
-                  onClick={() => handleSelectSong(song)}
-                  className={cn(
-                    "w-full p-4 rounded-xl border-4 border-zinc-700 bg-zinc-900",
-                    "flex items-center gap-4 text-left",
-                    "hover:border-amber-500 active:translate-y-1 transition-all"
-                  )}
-                  style={{
-                    boxShadow:
-                      "0 4px 0 0 rgb(63, 63, 70), 0 6px 12px rgba(0,0,0,0.5)",
-                  }}
-                >
-                  <div className="p-3 rounded-lg bg-amber-500/20 border-2 border-amber-500/30">
-                    <Music className="w-6 h-6 text-amber-400" />
-                  </div>
+              {songs.map((song) =>
+                song.status !== "ok" ? (
+                  <div
+                    key={song.path}
+                    className="w-full p-4 rounded-xl border-4 border-red-900 bg-zinc-900 flex items-center gap-4"
+                  >
+                    <div className="p-3 rounded-lg bg-red-500/20 border-2 border-red-500/30">
+                      <AlertTriangle className="w-6 h-6 text-red-400" />
+                    </div>
 
-                  <div className="flex-1 min-w-0">
-                    <h3 className="text-xl text-zinc-200 truncate">
-                      {song.title || "Unknown Title"}
-                    </h3>
-                    <p className="text-zinc-500 truncate">
-                      {song.artist || "Unknown Artist"}
-                    </p>
+                    <div className="flex-1 min-w-0">
+                      <h3 className="text-xl text-zinc-300 truncate">
+                        {song.title || song.path.split(/[/\\]/).pop()}
+                      </h3>
+                      <p className="text-sm text-red-400 line-clamp-2">
+                        {song.diagnostic}
+                      </p>
+                    </div>
+
+                    {song.status === "incompatibleSchema" && (
+                      <button
+                        onClick={() => handleReanalyzeSong(song)}
+                        className="px-4 py-2 rounded-lg border-2 border-amber-600 text-amber-400 uppercase tracking-wider hover:bg-amber-600/20"
+                      >
+                        Re-analyze
+                      </button>
+                    )}
                   </div>
+                ) : (
+                  <button
+                    key={song.path}
+                    onClick={() => handleSelectSong(song)}
+                    className={cn(
+                      "w-full p-4 rounded-xl border-4 border-zinc-700 bg-zinc-900",
+                      "flex items-center gap-4 text-left",
+                      "hover:border-amber-500 active:translate-y-1 transition-all"
+                    )}
+                    style={{
+                      boxShadow:
+                        "0 4px 0 0 rgb(63, 63, 70), 0 6px 12px rgba(0,0,0,0.5)",
+                    }}
+                  >
+                    <div className="p-3 rounded-lg bg-amber-500/20 border-2 border-amber-500/30">
+                      <Music className="w-6 h-6 text-amber-400" />
+                    </div>
 
-                  <div className="flex items-center gap-4 text-zinc-400">
-                    <div className="flex items-center gap-1">
-                      <Clock className="w-4 h-4" />
-                      <span>{formatTime(song.duration)}</span>
+                    <div className="flex-1 min-w-0">
+                      <h3 className="text-xl text-zinc-200 truncate">
+                        {song.title || "Unknown Title"}
+                      </h3>
+                      <p className="text-zinc-500 truncate">
+                        {song.artist || "Unknown Artist"}
+                      </p>
                     </div>
-                    <div className="flex items-center gap-1">
-                      <Layers className="w-4 h-4" />
-                      <span>{song.stemCount}</span>
+
+                    <div className="flex items-center gap-4 text-zinc-400">
+                      <div className="flex items-center gap-1">
+                        <Clock className="w-4 h-4" />
+                        <span>{formatTime(song.duration)}</span>
+                      </div>
+                      <div className="flex items-center gap-1">
+                        <Layers className="w-4 h-4" />
+                        <span>{song.stemCount}</span>
+                      </div>
                     </div>
-                  </div>
-                </button>
-              ))}
+                  </button>
+                )
+              )}
             </div>
           )}
         </div>
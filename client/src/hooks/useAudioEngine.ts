@@ -1,15 +1,18 @@
 import { useState, useRef, useCallback, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
-import { type SongAnalysis, type PlaybackSpeed } from "../types/analysis";
-import { convertFileSrc } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import {
+  type CommandError,
+  type SongAnalysis,
+  type PlaybackSpeed,
+  type PlaybackPosition,
+  type PlaybackState,
+} from "../types/analysis";
 
 interface StemState {
   name: string;
   volume: number;
   isMuted: boolean;
-  buffer: AudioBuffer | null;
-  source: AudioBufferSourceNode | null;
-  gainNode: GainNode | null;
 }
 
 interface AudioEngineState {
@@ -18,480 +21,267 @@ interface AudioEngineState {
   currentTime: number;
   duration: number;
   speed: PlaybackSpeed;
+  stretch: number;  // Real-time speed (0.25-2.0), pitch preserved
+  pitchShift: number;  // Semitones
   stems: Record<string, StemState>;
   soloedStems: Set<string>;  // Multiple stems can be soloed (Pro Tools style)
   loopStart: number | null;
   loopEnd: number | null;
 }
 
+/**
+ * Drives the native playback engine in the Tauri backend. Stems are decoded
+ * and mixed in Rust; this hook mirrors the engine state for the UI and
+ * follows `playback-position` events for the playhead and
+ * `playback-error` events for failures outside a command.
+ */
 export function useAudioEngine(analysis: SongAnalysis | null, songDir: string) {
-  const audioContextRef = useRef<AudioContext | null>(null);
-  const startTimeRef = useRef<number>(0);
-  const pauseTimeRef = useRef<number>(0);
-  const rafIdRef = useRef<number | null>(null);
-  // Use a ref for playing state to avoid race conditions with async state updates
-  const isPlayingRef = useRef<boolean>(false);
-
   const [state, setState] = useState<AudioEngineState>({
     isLoading: false,
     isPlaying: false,
     currentTime: 0,
     duration: 0,
     speed: "1.0x",
+    stretch: 1,
+    pitchShift: 0,
     stems: {},
     soloedStems: new Set<string>(),
     loopStart: null,
     loopEnd: null,
   });
 
-  // Initialize audio context and load stems when analysis changes
+  // Refs for values read inside callbacks that shouldn't re-create them
+  const stateRef = useRef(state);
   useEffect(() => {
-    if (!analysis || !songDir) return;
-
-    const initAudio = async () => {
-      setState((prev) => ({ ...prev, isLoading: true }));
-
-      // Create audio context if needed
-      if (!audioContextRef.current) {
-        audioContextRef.current = new AudioContext();
-      }
-      const ctx = audioContextRef.current;
-
-      // Initialize stem states
-      const stemStates: Record<string, StemState> = {};
-      for (const [name] of Object.entries(analysis.stems)) {
-        const gainNode = ctx.createGain();
-        gainNode.gain.value = 1.0; // Full volume by default
-        gainNode.connect(ctx.destination);
-
-        stemStates[name] = {
-          name,
-          volume: 100,
-          isMuted: false,
-          buffer: null,
-          source: null,
-          gainNode,
-        };
-      }
-
-      // Calculate duration based on current speed
-      const speed = parseFloat(state.speed.replace("x", ""));
-      const duration = analysis.originalDuration / speed;
-
-      setState((prev) => ({
-        ...prev,
-        stems: stemStates,
-        duration,
-        isLoading: false,
-      }));
-
-      // Load the default speed stems
-      await loadStemsForSpeed(state.speed, stemStates);
-    };
-
-    initAudio();
-
-    // Cleanup on unmount - stop all audio
-    return () => {
-      if (rafIdRef.current) {
-        cancelAnimationFrame(rafIdRef.current);
-      }
-      // Stop all playing sources
-      Object.values(sourceNodesRef.current).forEach((source) => {
-        try {
-          source.stop();
-        } catch {
-          // Ignore if already stopped
-        }
-      });
-      sourceNodesRef.current = {};
-      // Close the audio context
-      if (audioContextRef.current) {
-        audioContextRef.current.close();
-        audioContextRef.current = null;
-      }
-    };
-  }, [analysis, songDir]);
-
-  // Load audio buffers for a specific speed
-  const loadStemsForSpeed = useCallback(
-    async (speed: PlaybackSpeed, currentStems?: Record<string, StemState>) => {
-      if (!analysis || !songDir || !audioContextRef.current) return;
-
-      const ctx = audioContextRef.current;
-      const stems = currentStems || state.stems;
-
-      setState((prev) => ({ ...prev, isLoading: true }));
-
-      for (const [name, info] of Object.entries(analysis.stems)) {
-        const relativePath = info.paths[speed];
-        if (!relativePath) continue;
-
-        try {
-          // Get absolute path from Tauri
-          const absolutePath = await invoke<string>("get_stem_path", {
-            songDir,
-            relativePath,
-          });
-
-          // Convert to asset URL for Tauri
-          const assetUrl = convertFileSrc(absolutePath);
-
-          // Fetch and decode audio
-          const response = await fetch(assetUrl);
-          if (!response.ok) {
-            throw new Error(`HTTP ${response.status}: ${response.statusText}`);
-          }
-          const arrayBuffer = await response.arrayBuffer();
-          const audioBuffer = await ctx.decodeAudioData(arrayBuffer);
-
-          if (stems[name]) {
-            stems[name].buffer = audioBuffer;
-          }
-        } catch (error) {
-          console.error(`Failed to load stem ${name}:`, error);
-        }
-      }
-
-      // Update duration for new speed
-      const speedValue = parseFloat(speed.replace("x", ""));
-      const duration = analysis.originalDuration / speedValue;
-
-      setState((prev) => ({
-        ...prev,
-        stems: { ...stems },
-        duration,
-        isLoading: false,
-      }));
-    },
-    [analysis, songDir, state.stems]
-  );
-
-  // Store loop points in refs to avoid stale closure issues
-  const loopStartRef = useRef<number | null>(null);
-  const loopEndRef = useRef<number | null>(null);
-  const durationRef = useRef<number>(0);
-  const stemsRef = useRef<Record<string, StemState>>({});
+    stateRef.current = state;
+  }, [state]);
+
+  const stemsFromEngine = (engineState: PlaybackState) => {
+    const stems: Record<string, StemState> = {};
+    for (const stem of engineState.stems) {
+      stems[stem.name] = {
+        name: stem.name,
+        volume: Math.round(stem.gain * 100),
+        isMuted: stem.muted,
+      };
+    }
+    return stems;
+  };
 
-  // Keep refs in sync with state
+  // Load the song into the engine when analysis changes
   useEffect(() => {
-    loopStartRef.current = state.loopStart;
-    loopEndRef.current = state.loopEnd;
-    durationRef.current = state.duration;
-    stemsRef.current = state.stems;
-  }, [state.loopStart, state.loopEnd, state.duration, state.stems]);
+    if (!analysis || !songDir) return;
 
-  // Store active source nodes separately from state to avoid mutation issues
-  const sourceNodesRef = useRef<Record<string, AudioBufferSourceNode>>({});
+    let cancelled = false;
+    setState((prev) => ({ ...prev, isLoading: true }));
+    invoke<PlaybackState>("load_playback", { songDir, speed: "1.0x" })
+      .then((engineState) => {
+        if (cancelled) return;
+        setState((prev) => ({
+          ...prev,
+          isLoading: false,
+          isPlaying: false,
+          currentTime: 0,
+          duration: engineState.duration,
+          speed: "1.0x",
+          stretch: engineState.speed,
+          pitchShift: engineState.pitchShift,
+          stems: stemsFromEngine(engineState),
+          soloedStems: new Set(
+            engineState.stems.filter((s) => s.solo).map((s) => s.name)
+          ),
+          loopStart: null,
+          loopEnd: null,
+        }));
+      })
+      .catch((error) => {
+        console.error("Failed to load stems:", error);
+        if (!cancelled) setState((prev) => ({ ...prev, isLoading: false }));
+      });
 
-  // Helper to stop all source nodes
-  const stopAllSources = useCallback(() => {
-    Object.values(sourceNodesRef.current).forEach((source) => {
-      try {
-        source.stop();
-      } catch {
-        // Ignore if already stopped
-      }
+    const unlisten = listen<PlaybackPosition>("playback-position", (event) => {
+      const { position, playing } = event.payload;
+      setState((prev) => ({ ...prev, currentTime: position, isPlaying: playing }));
     });
-    sourceNodesRef.current = {};
-  }, []);
-
-  // Internal stop used by updateTime
-  const stopPlayback = useCallback(() => {
-    if (!audioContextRef.current) return;
-
-    stopAllSources();
-
-    isPlayingRef.current = false;
-    pauseTimeRef.current = 0;
-    setState((prev) => ({ ...prev, isPlaying: false, currentTime: 0 }));
-  }, [stopAllSources]);
-
-  // Restart audio sources at a specific position (used for looping)
-  const restartAudioAt = useCallback((position: number) => {
-    const ctx = audioContextRef.current;
-    if (!ctx) return;
-
-    // Stop all current sources
-    stopAllSources();
-
-    // Restart all stems at the new position
-    Object.entries(stemsRef.current).forEach(([name, stem]) => {
-      if (!stem.buffer || !stem.gainNode) return;
-
-      const source = ctx.createBufferSource();
-      source.buffer = stem.buffer;
-      source.connect(stem.gainNode);
-      source.start(0, position);
-
-      sourceNodesRef.current[name] = source;
+    const unlistenError = listen<CommandError>("playback-error", (event) => {
+      console.error("Playback error:", event.payload);
     });
 
-    // Update time tracking
-    pauseTimeRef.current = position;
-    startTimeRef.current = ctx.currentTime;
-  }, [stopAllSources]);
-
-  // Update playback position
-  const updateTime = useCallback(() => {
-    // Use ref for playing state to avoid race conditions
-    if (!audioContextRef.current || !isPlayingRef.current) return;
-
-    const elapsed = audioContextRef.current.currentTime - startTimeRef.current;
-    const currentTime = pauseTimeRef.current + elapsed;
-
-    // Handle looping - use refs for consistent values
-    if (loopEndRef.current !== null && currentTime >= loopEndRef.current) {
-      const loopStart = loopStartRef.current ?? 0;
-
-      // Actually restart the audio at loop start position
-      restartAudioAt(loopStart);
-      setState((prev) => ({ ...prev, currentTime: loopStart }));
-      rafIdRef.current = requestAnimationFrame(updateTime);
-      return;
-    }
-
-    // Handle end of track
-    if (currentTime >= durationRef.current) {
-      stopPlayback();
-      return;
-    }
-
-    setState((prev) => ({ ...prev, currentTime }));
-    rafIdRef.current = requestAnimationFrame(updateTime);
-  }, [stopPlayback, restartAudioAt]);
-
-  // Start playback position updates
-  useEffect(() => {
-    if (state.isPlaying) {
-      rafIdRef.current = requestAnimationFrame(updateTime);
-    }
     return () => {
-      if (rafIdRef.current) {
-        cancelAnimationFrame(rafIdRef.current);
-      }
+      cancelled = true;
+      unlisten.then((fn) => fn());
+      unlistenError.then((fn) => fn());
+      invoke("pause_playback").catch(() => {
+        // Nothing loaded
+      });
     };
-  }, [state.isPlaying, updateTime]);
+  }, [analysis, songDir]);
 
   // Play all stems
   const play = useCallback(async () => {
-    if (!audioContextRef.current) return;
-
-    const ctx = audioContextRef.current;
-    const offset = pauseTimeRef.current;
-
-    // Resume context if suspended (critical for macOS)
-    if (ctx.state === "suspended") {
-      await ctx.resume();
+    try {
+      await invoke("start_playback");
+      setState((prev) => ({ ...prev, isPlaying: true }));
+    } catch (error) {
+      console.error("Failed to start playback:", error);
     }
-
-    // Clear any existing source nodes
-    stopAllSources();
-
-    // Create and start source nodes for all stems
-    Object.entries(state.stems).forEach(([name, stem]) => {
-      if (!stem.buffer || !stem.gainNode) return;
-
-      // Ensure gain is set correctly (Pro Tools style: solo mutes non-soloed stems)
-      const isSoloActive = state.soloedStems.size > 0;
-      const shouldPlay = !stem.isMuted && (!isSoloActive || state.soloedStems.has(name));
-      stem.gainNode.gain.value = shouldPlay ? stem.volume / 100 : 0;
-
-      const source = ctx.createBufferSource();
-      source.buffer = stem.buffer;
-      source.connect(stem.gainNode);
-      source.start(0, offset);
-
-      sourceNodesRef.current[name] = source;
-    });
-
-    startTimeRef.current = ctx.currentTime;
-    isPlayingRef.current = true;
-
-    setState((prev) => ({ ...prev, isPlaying: true }));
-  }, [state.stems, state.soloedStems, stopAllSources]);
+  }, []);
 
   // Pause playback
-  const pause = useCallback(() => {
-    if (!audioContextRef.current) return;
-    // Guard against being called when already paused
-    if (!isPlayingRef.current) return;
-
-    // Calculate current position
-    const elapsed = audioContextRef.current.currentTime - startTimeRef.current;
-    pauseTimeRef.current += elapsed;
-
-    // Stop all sources
-    stopAllSources();
-
-    isPlayingRef.current = false;
+  const pause = useCallback(async () => {
+    await invoke("pause_playback").catch(console.error);
     setState((prev) => ({ ...prev, isPlaying: false }));
-  }, [stopAllSources]);
-
-  // Stop playback and reset position (public API)
-  const stop = useCallback(() => {
-    stopPlayback();
-  }, [stopPlayback]);
+  }, []);
 
   // Seek to position
-  const seek = useCallback(
-    (time: number) => {
-      const wasPlaying = isPlayingRef.current;
-      const ctx = audioContextRef.current;
-
-      if (wasPlaying) {
-        stopAllSources();
-      }
-
-      // Update both refs together to maintain consistency
-      pauseTimeRef.current = time;
-      if (ctx) {
-        startTimeRef.current = ctx.currentTime;
-      }
-
-      setState((prev) => ({ ...prev, currentTime: time }));
+  const seek = useCallback(async (time: number) => {
+    setState((prev) => ({ ...prev, currentTime: time }));
+    await invoke("seek_playback", { position: time }).catch(console.error);
+  }, []);
 
-      if (wasPlaying) {
-        // Small delay to ensure state is updated, then restart playback
-        setTimeout(() => play(), 10);
-      }
+  // Stop playback and reset position
+  const stop = useCallback(async () => {
+    await pause();
+    await seek(0);
+  }, [pause, seek]);
+
+  // Send the loop range to the engine once it has an end
+  const applyLoop = useCallback(
+    (loopStart: number | null, loopEnd: number | null) => {
+      invoke("set_playback_loop", {
+        start: loopEnd !== null ? loopStart ?? 0 : null,
+        end: loopEnd,
+      }).catch(console.error);
     },
-    [play, stopAllSources]
+    []
   );
 
-  // Change playback speed
+  // Change playback speed by loading that speed's stems, keeping the
+  // relative position
   const setSpeed = useCallback(
     async (speed: PlaybackSpeed) => {
-      const wasPlaying = isPlayingRef.current;
-      const currentPosition = pauseTimeRef.current +
-        (wasPlaying && audioContextRef.current
-          ? audioContextRef.current.currentTime - startTimeRef.current
-          : 0);
-      const currentDuration = durationRef.current;
-
-      // Pause if playing
-      if (wasPlaying) {
-        pause();
-      }
-
-      // Calculate position ratio to maintain relative position
-      const newSpeedValue = parseFloat(speed.replace("x", ""));
-      const positionRatio = currentDuration > 0 ? currentPosition / currentDuration : 0;
-
-      setState((prev) => ({ ...prev, speed }));
-
-      // Load new stem files
-      await loadStemsForSpeed(speed);
-
-      // Calculate new position
-      const newDuration = analysis!.originalDuration / newSpeedValue;
-      const newPosition = positionRatio * newDuration;
+      if (!songDir) return;
+      const { isPlaying, currentTime, duration, loopStart, loopEnd } =
+        stateRef.current;
+      const ratio = duration > 0 ? currentTime / duration : 0;
 
-      pauseTimeRef.current = newPosition;
-      if (audioContextRef.current) {
-        startTimeRef.current = audioContextRef.current.currentTime;
-      }
-      setState((prev) => ({
-        ...prev,
-        currentTime: newPosition,
-        duration: newDuration,
-      }));
+      setState((prev) => ({ ...prev, isLoading: true }));
+      try {
+        const engineState = await invoke<PlaybackState>("load_playback", {
+          songDir,
+          speed,
+        });
+        const scale = (time: number | null) =>
+          time !== null && duration > 0
+            ? (time / duration) * engineState.duration
+            : null;
+        const position = ratio * engineState.duration;
+        await invoke("seek_playback", { position });
+        applyLoop(scale(loopStart), scale(loopEnd));
+        if (isPlaying) {
+          await invoke("start_playback");
+        }
 
-      // Resume if was playing
-      if (wasPlaying) {
-        setTimeout(() => play(), 100);
+        setState((prev) => ({
+          ...prev,
+          isLoading: false,
+          speed,
+          stretch: engineState.speed,
+          currentTime: position,
+          duration: engineState.duration,
+          stems: stemsFromEngine(engineState),
+          loopStart: scale(loopStart),
+          loopEnd: scale(loopEnd),
+        }));
+      } catch (error) {
+        console.error(`Failed to switch to ${speed}:`, error);
+        setState((prev) => ({ ...prev, isLoading: false }));
       }
     },
-    [analysis, pause, play, loadStemsForSpeed]
+    [songDir, applyLoop]
   );
 
-  // Set stem volume
+  // Time-stretch whatever is loaded to any speed from 0.25x to 2.0x
+  const setStretch = useCallback(async (stretch: number) => {
+    try {
+      await invoke("set_playback_speed", { speed: stretch });
+      setState((prev) => ({ ...prev, stretch }));
+    } catch (error) {
+      console.error(`Failed to stretch to ${stretch}x:`, error);
+    }
+  }, []);
+
+  // Transpose without changing speed
+  const setPitchShift = useCallback(async (semitones: number, cents = 0) => {
+    try {
+      await invoke("set_pitch_shift", { semitones, cents });
+      setState((prev) => ({ ...prev, pitchShift: semitones + cents / 100 }));
+    } catch (error) {
+      console.error("Failed to shift pitch:", error);
+    }
+  }, []);
+
+  // Set stem volume (0-100)
   const setStemVolume = useCallback((stemName: string, volume: number) => {
+    invoke("set_stem_gain", { stem: stemName, gain: volume / 100 }).catch(
+      console.error
+    );
     setState((prev) => {
       const stem = prev.stems[stemName];
-      if (!stem || !stem.gainNode) return prev;
-
-      // Apply volume (considering mute and solo states)
-      const isSoloActive = prev.soloedStems.size > 0;
-      const shouldPlay = !stem.isMuted && (!isSoloActive || prev.soloedStems.has(stemName));
-      stem.gainNode.gain.value = shouldPlay ? volume / 100 : 0;
-
+      if (!stem) return prev;
       return {
         ...prev,
-        stems: {
-          ...prev.stems,
-          [stemName]: { ...stem, volume },
-        },
+        stems: { ...prev.stems, [stemName]: { ...stem, volume } },
       };
     });
   }, []);
 
   // Toggle stem mute
   const toggleMute = useCallback((stemName: string) => {
-    setState((prev) => {
-      const stem = prev.stems[stemName];
-      if (!stem || !stem.gainNode) return prev;
-
-      const isMuted = !stem.isMuted;
-      const isSoloActive = prev.soloedStems.size > 0;
-      const shouldPlay = !isMuted && (!isSoloActive || prev.soloedStems.has(stemName));
-      stem.gainNode.gain.value = shouldPlay ? stem.volume / 100 : 0;
-
-      return {
-        ...prev,
-        stems: {
-          ...prev.stems,
-          [stemName]: { ...stem, isMuted },
-        },
-      };
-    });
+    const stem = stateRef.current.stems[stemName];
+    if (!stem) return;
+    const isMuted = !stem.isMuted;
+    invoke("set_stem_muted", { stem: stemName, muted: isMuted }).catch(
+      console.error
+    );
+    setState((prev) => ({
+      ...prev,
+      stems: { ...prev.stems, [stemName]: { ...prev.stems[stemName], isMuted } },
+    }));
   }, []);
 
   // Toggle stem solo (Pro Tools style: multiple stems can be soloed)
   const toggleSolo = useCallback((stemName: string) => {
+    const solo = !stateRef.current.soloedStems.has(stemName);
+    invoke("set_stem_solo", { stem: stemName, solo }).catch(console.error);
     setState((prev) => {
-      const newSoloedStems = new Set(prev.soloedStems);
-      if (newSoloedStems.has(stemName)) {
-        newSoloedStems.delete(stemName);
+      const soloedStems = new Set(prev.soloedStems);
+      if (solo) {
+        soloedStems.add(stemName);
       } else {
-        newSoloedStems.add(stemName);
-      }
-
-      // Update all stem volumes based on solo state
-      const newStems = { ...prev.stems };
-      for (const [name, stem] of Object.entries(newStems)) {
-        if (!stem.gainNode) continue;
-
-        if (newSoloedStems.size === 0) {
-          // No solo - respect mute state
-          stem.gainNode.gain.value = stem.isMuted ? 0 : stem.volume / 100;
-        } else {
-          // Solo active - only play soloed stems (mute others)
-          const isSoloed = newSoloedStems.has(name);
-          stem.gainNode.gain.value = isSoloed && !stem.isMuted ? stem.volume / 100 : 0;
-        }
+        soloedStems.delete(stemName);
       }
-
-      return {
-        ...prev,
-        stems: newStems,
-        soloedStems: newSoloedStems,
-      };
+      return { ...prev, soloedStems };
     });
   }, []);
 
   // Set loop points
   const setLoopStart = useCallback(() => {
-    setState((prev) => ({ ...prev, loopStart: prev.currentTime }));
-  }, []);
+    const { currentTime, loopEnd } = stateRef.current;
+    applyLoop(currentTime, loopEnd);
+    setState((prev) => ({ ...prev, loopStart: currentTime }));
+  }, [applyLoop]);
 
   const setLoopEnd = useCallback(() => {
-    setState((prev) => ({ ...prev, loopEnd: prev.currentTime }));
-  }, []);
+    const { currentTime, loopStart } = stateRef.current;
+    applyLoop(loopStart, currentTime);
+    setState((prev) => ({ ...prev, loopEnd: currentTime }));
+  }, [applyLoop]);
 
   const clearLoop = useCallback(() => {
+    applyLoop(null, null);
     setState((prev) => ({ ...prev, loopStart: null, loopEnd: null }));
-  }, []);
+  }, [applyLoop]);
 
   return {
     ...state,
@@ -500,6 +290,8 @@ export function useAudioEngine(analysis: SongAnalysis | null, songDir: string) {
     stop,
     seek,
     setSpeed,
+    setStretch,
+    setPitchShift,
     setStemVolume,
     toggleMute,
     toggleSolo,
@@ -1,10 +1,42 @@
 import { clsx, type ClassValue } from "clsx";
 import { twMerge } from "tailwind-merge";
+import type { CommandError } from "../types/analysis";
 
 export function cn(...inputs: ClassValue[]) {
   return twMerge(clsx(inputs));
 }
 
+/** Human-readable message for an error thrown by a Tauri command */
+export function describeError(error: unknown): string {
+  if (typeof error !== "object" || error === null || !("kind" in error)) {
+    return String(error);
+  }
+  const err = error as CommandError;
+  switch (err.kind) {
+    case "notFound":
+      return `Not found: ${err.path}`;
+    case "parse":
+      return `Could not parse ${err.path} (line ${err.line}, column ${err.column}): ${err.message}`;
+    case "schemaVersion":
+      return `${err.path} was made by an incompatible converter (${err.converterVersion ?? "unknown version"}). Re-analyze the song to update it.`;
+    case "toolMissing":
+      return `${err.tool} is not installed or not on your PATH`;
+    case "pipelineStageFailed":
+      return `${err.stage.replace(/_/g, " ")} failed: ${err.message}`;
+    case "permissionDenied":
+      return `Permission denied: ${err.path}`;
+    case "invalidRequest":
+    case "io":
+      return err.message;
+    case "decode":
+      return `Could not decode ${err.path}: ${err.message}`;
+    case "audioDevice":
+      return `Audio output error: ${err.message}`;
+    case "database":
+      return `Library index error: ${err.message}`;
+  }
+}
+
 /** Format time in MM:SS format */
 export function formatTime(seconds: number): string {
   const mins = Math.floor(seconds / 60);
@@ -56,18 +56,20 @@ export interface LyricLine {
 
 /** Lyrics data */
 export interface LyricsData {
-  source: "lrc" | "txt" | "transcribed";
+  source: "lrc" | "txt" | "transcribed" | "edited";
   lines: LyricLine[];
 }
 
 /** Full song analysis from analysis.json */
 export interface SongAnalysis {
+  schemaVersion: number; // older files are migrated to the current version on load
   title: string | null;
   artist: string | null;
   album: string | null;
   originalDuration: number;
   sampleRate: number;
   tempoBpm: number | null;
+  key?: string | null;
   timeSignature: [number, number] | null;
   stems: Record<string, StemInfo>;
   beats: BeatEvent[];
@@ -79,13 +81,412 @@ export interface SongAnalysis {
   converterVersion: string;
 }
 
+/** Whether a song can be opened, and if not, why */
+export type SongStatus =
+  | "ok"
+  | "unreadable"
+  | "incompatibleSchema"
+  | "missingStems";
+
+/** Library roots and scan settings */
+export interface LibraryConfig {
+  roots: string[];
+  maxDepth: number;
+  ignore: string[]; // directory name patterns with * and ? wildcards
+}
+
 /** Summary for song browser */
 export interface SongSummary {
   path: string;
+  root: string; // library root the song was found under
   title: string | null;
   artist: string | null;
+  album: string | null;
   duration: number;
+  tempoBpm: number | null;
+  key: string | null;
+  timeSignature: [number, number] | null;
   stemCount: number;
+  stemNames: string[];
+  hasLyrics: boolean;
+  hasDrumStrikes: boolean;
+  sourceFile: string | null;
+  processingDate: string | null;
+  status: SongStatus;
+  diagnostic: string | null;
+}
+
+export type SongSortField =
+  | "title"
+  | "artist"
+  | "album"
+  | "duration"
+  | "tempoBpm"
+  | "processingDate"
+  | "path";
+
+/** Arguments to `query_songs`; unset filters match every song */
+export interface SongQuery {
+  text?: string; // each word must match title, artist, album or source file
+  minTempo?: number;
+  maxTempo?: number;
+  timeSignature?: [number, number];
+  minDuration?: number;
+  maxDuration?: number;
+  hasLyrics?: boolean;
+  hasDrumStrikes?: boolean;
+  stems?: string[]; // all must be present
+  sort?: { field: SongSortField; descending?: boolean }[];
+  offset?: number;
+  limit?: number;
+}
+
+/** One page of `query_songs` results */
+export interface SongPage {
+  songs: SongSummary[];
+  total: number; // matches before paging
+}
+
+/** A semantic problem found in analysis.json by `validate_analysis` */
+export interface ValidationIssue {
+  severity: "warning" | "error";
+  path: string; // JSON path, e.g. "$.notes.bass[3].end"
+  message: string;
+  fix: string | null; // what the auto-fix does, if anything
+}
+
+/** Result of `load_analysis` */
+export interface LoadedAnalysis {
+  analysis: SongAnalysis;
+  issues: ValidationIssue[]; // repaired in the returned data when `fix` is set
+}
+
+export interface ValidationReport {
+  issues: ValidationIssue[];
+  backup: string | null; // original file, when fixes were written back
+}
+
+/** Format and levels of one stem file (`probe_stems`) */
+export interface AudioInfo {
+  codec: string;
+  sampleRate: number;
+  channels: number | null;
+  duration: number; // seconds, as decoded
+  peakDb: number;
+  rmsDb: number;
+}
+
+export interface StemProbe {
+  stem: string;
+  speed: string;
+  path: string;
+  info: AudioInfo | null; // null when the file couldn't be opened
+}
+
+/** Min/max/RMS of the mono mix per bucket (`get_waveform_peaks`) */
+export interface WaveformPeaks {
+  sampleRate: number;
+  duration: number; // seconds
+  bucketDuration: number; // seconds per bucket; the last may be shorter
+  min: number[];
+  max: number[];
+  rms: number[];
+}
+
+/** Options for `export_mix`; stems not listed are left out */
+export interface MixExport {
+  outputPath: string;
+  format?: "wav" | "flac"; // from the file extension when omitted
+  speed?: string; // e.g. "0.75x"; defaults to "1.0x"
+  stems: { name: string; gain?: number; muted?: boolean; solo?: boolean }[];
+  loopStart?: number; // seconds in the files at `speed`
+  loopEnd?: number;
+  countIn?: number; // beats of click before the music
+  normalizeDb?: number; // target peak in dBFS
+  bitDepth?: 16 | 24;
+}
+
+export interface MixExportResult {
+  path: string;
+  duration: number;
+  peakDb: number;
+  gainDb: number;
+  clippedSamples: number;
+}
+
+/** Result of `export_midi` */
+export interface MidiExportResult {
+  path: string;
+  /** Tracks written, the tempo track included */
+  tracks: number;
+  notes: number;
+  drumStrikes: number;
+  /** Drum names with no General MIDI equivalent, left out */
+  skippedDrums: string[];
+}
+
+/** Result of `export_musicxml` */
+export interface MusicXmlResult {
+  path: string;
+  measures: number;
+  notes: number;
+}
+
+/** Tuning preset, or open-string MIDI pitches from lowest to highest */
+export type Tuning = 'standard' | 'dropD' | 'bass' | 'fiveStringBass' | number[];
+
+export interface TabNote {
+  start: number;
+  end: number;
+  pitch: number;
+  /** 1 for the highest string */
+  string: number;
+  fret: number;
+}
+
+/** Result of `compute_tab` */
+export interface Tablature {
+  tuning: number[];
+  notes: TabNote[];
+  unplayable: number;
+}
+
+/** Result of `export_tab` */
+export interface TabExportResult {
+  path: string;
+  measures: number;
+  notes: number;
+  unplayable: number;
+}
+
+export type DrumGrid = 'eighths' | 'sixteenths' | 'triplets';
+
+export interface DrumHit {
+  drum: string;
+  /** Grid step within the bar */
+  step: number;
+  velocity: number;
+}
+
+export interface DrumMeasure {
+  number: number;
+  /** Seconds at which the bar starts */
+  start: number;
+  /** Bars with the same hits share a pattern number */
+  pattern: number;
+  hits: DrumHit[];
+}
+
+/** Result of `quantize_drums` */
+export interface DrumTranscription {
+  grid: DrumGrid;
+  stepsPerBar: number;
+  /** Position of swung off-beats in the beat (0.67 for a triplet shuffle) */
+  swing: number | null;
+  drums: string[];
+  measures: DrumMeasure[];
+  patterns: number;
+}
+
+/** Result of `export_drums` */
+export interface DrumExportResult {
+  path: string;
+  grid: DrumGrid;
+  swing: number | null;
+  measures: number;
+  patterns: number;
+}
+
+/** Request for `export_lyrics` */
+export interface LyricsExport {
+  outputPath: string;
+  /** Taken from the output extension when missing */
+  format?: 'lrc' | 'srt' | 'vtt';
+  /** Speed rendering the times should match, e.g. "0.75x" */
+  speed?: string;
+  /** Per-word timing where the format allows (default true) */
+  wordTiming?: boolean;
+}
+
+/** Result of `export_lyrics` */
+export interface LyricsExportResult {
+  path: string;
+  lines: number;
+  timedWords: number;
+}
+
+/** A correction for `edit_lyrics`; `line` and `word` are indexes */
+export type LyricsEdit =
+  | { op: 'setLineText'; line: number; text: string }
+  | { op: 'setWordText'; line: number; word: number; text: string }
+  /** Split before character `at`, at `time` or in proportion to the characters */
+  | { op: 'splitWord'; line: number; word: number; at: number; time?: number }
+  /** Join a word with the one after it */
+  | { op: 'mergeWords'; line: number; word: number }
+  | { op: 'shiftLine'; line: number; offset: number }
+  | { op: 'stretchLine'; line: number; start: number; end: number }
+  /** Every line when `line` is missing; `maxDistance` defaults to 0.15 s */
+  | { op: 'snapWords'; line?: number; target: 'beats' | 'onsets'; maxDistance?: number };
+
+/** Result of `edit_lyrics` and `undo_lyrics_edit` */
+export interface LyricsEditResult {
+  lyrics: LyricsData | null;
+  /** Edits that can still be undone */
+  undoSteps: number;
+}
+
+/** Request for `import_lyrics` */
+export interface LyricsImport {
+  inputPath: string;
+  /** LRC when the file has timestamped lines, plain text otherwise */
+  format?: 'lrc' | 'txt';
+}
+
+/** Result of `import_lyrics` */
+export interface LyricsImportResult {
+  format: 'lrc' | 'txt';
+  lines: number;
+  /** Words whose start came from the file rather than being spread */
+  timedWords: number;
+  /** Lyrics edits, including this import, that can be undone */
+  undoSteps: number;
+}
+
+/** Bar 1 starts on the first downbeat; a pickup is bar 0 */
+export interface TimelinePosition {
+  bar: number;
+  /** From 1 */
+  beat: number;
+  /** 480 ticks to a beat */
+  tick: number;
+}
+
+/** Event returned by `query_events`; `start`/`end` are at 1.0x */
+export type TimelineEvent = {
+  start: number;
+  end: number;
+  playbackStart: number;
+  playbackEnd: number;
+  position: TimelinePosition;
+} & (
+  | { type: 'beat'; downbeat: boolean }
+  | { type: 'note'; stem: string; pitch: number; velocity: number }
+  | { type: 'strike'; drum: string; velocity: number }
+  | { type: 'word'; line: number; word: number; text: string }
+);
+
+/** Request for `import_midi` */
+export interface MidiImport {
+  inputPath: string;
+  /** `file` uses the file's tempo events, `beats` the song's beat grid */
+  timing?: 'file' | 'beats';
+  /** Track index to stem (`"drums"` for drum strikes); empty maps by name */
+  tracks?: { track: number; stem: string }[];
+  /** Report the mapping without changing analysis.json */
+  dryRun?: boolean;
+}
+
+export interface MidiTrackInfo {
+  index: number;
+  name: string | null;
+  notes: number;
+  stem: string | null;
+}
+
+/** Result of `import_midi` */
+export interface MidiImportResult {
+  tracks: MidiTrackInfo[];
+  notes: number;
+  drumStrikes: number;
+  skippedDrumNotes: number;
+  backup: string | null;
+}
+
+/** Stem files checked against analysis.json (`probe_stems`) */
+export interface ProbeReport {
+  stems: StemProbe[];
+  issues: ValidationIssue[];
+}
+
+/** Playhead of the native engine (`playback-position` event) */
+export interface PlaybackPosition {
+  position: number; // seconds into the loaded stems
+  duration: number;
+  playing: boolean;
+}
+
+/** Full state of the native playback engine */
+export interface PlaybackState extends PlaybackPosition {
+  sampleRate: number;
+  loopStart: number | null;
+  loopEnd: number | null;
+  /** Real-time speed on top of the loaded files' own speed */
+  speed: number;
+  /** Transposition in semitones */
+  pitchShift: number;
+  stems: { name: string; gain: number; muted: boolean; solo: boolean }[];
+}
+
+/** Songs added, changed or removed on disk (`library-changed` event) */
+export interface LibraryChange {
+  changed: SongSummary[];
+  removed: string[];
+}
+
+/** Progress update for a processing job (`processing-progress` event) */
+export type ProcessingUpdate =
+  | { kind: "stageStarted"; stage: string }
+  | { kind: "stageFinished"; stage: string; seconds: number }
+  | { kind: "stageFailed"; stage: string; message: string }
+  | { kind: "warning"; message: string }
+  | { kind: "log"; line: string };
+
+export type ProcessingProgress = ProcessingUpdate & {
+  jobId: string;
+  percent: number;
+};
+
+/**
+ * Error returned by every Tauri command, and the `playback-error` and
+ * `library-error` event payload
+ */
+export type CommandError =
+  | { kind: "notFound"; path: string }
+  | { kind: "parse"; path: string; line: number; column: number; message: string }
+  | {
+      kind: "schemaVersion";
+      path: string;
+      converterVersion: string | null;
+      message: string;
+    }
+  | { kind: "toolMissing"; tool: string }
+  | { kind: "pipelineStageFailed"; stage: string; message: string }
+  | { kind: "permissionDenied"; path: string }
+  | { kind: "invalidRequest"; message: string }
+  | { kind: "io"; message: string }
+  | { kind: "decode"; path: string; message: string }
+  | { kind: "audioDevice"; message: string }
+  | { kind: "database"; message: string };
+
+/** Status of a queued processing job */
+export type JobStatus =
+  | { state: "queued" }
+  | { state: "running" }
+  | { state: "cancelling" } // stopping; retry is refused until it is cancelled
+  | { state: "completed" }
+  | { state: "failed"; error: CommandError }
+  | { state: "cancelled" };
+
+/** A song in the processing queue (`job-updated` event) */
+export interface Job {
+  id: string;
+  audioFile: string | null; // null when re-analyzing existing stems
+  outputDir: string;
+  separateDrums: boolean;
+  status: JobStatus;
+  stage: string | null;
+  percent: number;
+  warnings: string[];
 }
 
 /** Available playback speeds */
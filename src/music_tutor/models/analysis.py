@@ -7,6 +7,10 @@ alongside the separated stems.
 from dataclasses import dataclass, field
 from typing import Literal
 
+# Version of the analysis.json layout. Bump when the shape changes and add a
+# matching migration to the player (client/src-tauri/src/migration.rs).
+SCHEMA_VERSION = 1
+
 
 @dataclass
 class PitchBendPoint:
@@ -68,7 +72,7 @@ class LyricLine:
 class LyricsData:
     """Word-level lyrics with timing."""
 
-    source: Literal["lrc", "txt", "transcribed"]  # how lyrics were obtained
+    source: Literal["lrc", "txt", "transcribed", "edited"]  # how lyrics were obtained
     lines: list[LyricLine] = field(default_factory=list)
 
 
@@ -105,6 +109,7 @@ class SongAnalysis:
     source_file: str = ""  # original filename
     processing_date: str = ""  # ISO format
     converter_version: str = ""
+    schema_version: int = SCHEMA_VERSION
 
     # Stem availability (relative paths from analysis file)
     stems: dict[str, StemInfo] = field(default_factory=dict)